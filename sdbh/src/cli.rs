@@ -1,15 +1,33 @@
-use crate::db::{ensure_hash_index, import_from_db, insert_history, open_db};
-use crate::domain::{DbConfig, HistoryRow};
-use anyhow::Result;
-use clap::{Parser, Subcommand, ValueEnum};
+use crate::db::{
+    ImportOverrides, delete_history, ensure_hash_index, import_from_db, insert_history, open_db,
+    update_history_cmd,
+};
+use crate::domain::{
+    DbConfig, HashVersion, HistoryRecord, HistoryRow, RowDetails, SessionFields, StoredRecord,
+};
+use anyhow::{Context, Result};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use rusqlite::OptionalExtension;
+use std::collections::HashMap;
+use std::io::Write;
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(name = "sdbh", version, about = "Shell DB History (sdbh)")]
 pub struct Cli {
-    /// Path to SQLite database
+    /// Path to SQLite database. `stats top`/`stats daily` accept it repeatedly to
+    /// aggregate across several databases.
     #[arg(long, global = true)]
-    pub db: Option<PathBuf>,
+    pub db: Vec<PathBuf>,
+
+    /// Print the resolved database, config file and session to stderr before running
+    #[arg(long, global = true)]
+    pub db_info: bool,
+
+    /// Plain preview output: no emoji or rules. Also implied when stdout is not a
+    /// terminal (outside an fzf preview pane) or NO_COLOR is set
+    #[arg(long, global = true)]
+    pub no_color: bool,
 
     #[command(subcommand)]
     pub command: Commands,
@@ -29,9 +47,27 @@ pub enum Commands {
     /// Search history by substring (case-insensitive). Supports time filtering via --since-epoch/--days.
     Search(SearchArgs),
 
+    /// Exit 0 if any command matches the query, 1 otherwise (prints nothing)
+    Exists(ExistsArgs),
+
+    /// Print the most recent command (skipping sdbh's own invocations)
+    Last(LastArgs),
+
+    /// Print every stored column of one history entry by id, untruncated
+    Show(ShowArgs),
+
     /// Export history as JSON Lines (one JSON object per line)
     Export(ExportArgs),
 
+    /// Delete history entries by id or substring match
+    Delete(DeleteArgs),
+
+    /// Fix a logged command in $EDITOR (or an inline prompt)
+    Edit(EditArgs),
+
+    /// Rewrite every command matching a regex (e.g. to scrub a leaked secret)
+    Redact(RedactArgs),
+
     /// Aggregate statistics
     Stats(StatsArgs),
 
@@ -42,12 +78,19 @@ pub enum Commands {
     #[command(name = "import-history")]
     ImportHistory(ImportHistoryArgs),
 
+    /// Import/merge JSON Lines written by `export` (hash de-dup, like `import`)
+    #[command(name = "import-jsonl")]
+    ImportJsonl(ImportJsonlArgs),
+
     /// Diagnose shell integration / DB setup
     Doctor(DoctorArgs),
 
     /// Database operations
     Db(DbArgs),
 
+    /// Working directories from history (e.g. "where did I work on project X")
+    Dirs(DirsArgs),
+
     /// Print shell integration snippets
     Shell(ShellArgs),
 
@@ -57,6 +100,13 @@ pub enum Commands {
     /// Command template system for reusable command patterns
     Template(TemplateArgs),
 
+    /// Re-run one logged session's commands in order, each in its recorded directory,
+    /// or print (or run) a single command by its `list` id
+    Replay(ReplayArgs),
+
+    /// Print the JSON schema of the records emitted by `--format json` and `export`
+    Describe(DescribeArgs),
+
     /// Show version information
     Version,
 }
@@ -81,6 +131,41 @@ pub struct LogArgs {
     #[arg(long)]
     pub hist_id: Option<i64>,
 
+    /// Exit status of the command (`$?` in the shell hook)
+    #[arg(long)]
+    pub exit_code: Option<i64>,
+
+    /// How long the command ran, in milliseconds
+    #[arg(long)]
+    pub duration_ms: Option<i64>,
+
+    /// Skip the dedup-hash write for this row (faster; import won't dedup it)
+    #[arg(long)]
+    pub no_hash: bool,
+
+    /// Machine name to record (defaults to this host's name)
+    #[arg(long)]
+    pub host: Option<String>,
+
+    /// Terminal to record (defaults to $SSH_TTY, then the terminal on stdin)
+    #[arg(long)]
+    pub tty: Option<String>,
+
+    /// Git repository root to record (defaults to `git rev-parse --show-toplevel`
+    /// in --pwd when `[log] track_gitroot = true`)
+    #[arg(long)]
+    pub gitroot: Option<String>,
+
+    /// Position of the command within its session (the hooks count with
+    /// `$((++__SDBH_SEQ))`); orders commands that share an epoch
+    #[arg(long)]
+    pub seq: Option<i64>,
+
+    /// Record these environment variables (comma-separated names) with the command,
+    /// in addition to `[log] capture_env`; unset ones are left out
+    #[arg(long, value_name = "VAR1,VAR2", value_delimiter = ',')]
+    pub capture_env: Vec<String>,
+
     /// Disable default noisy-command filtering.
     /// Useful for debugging shell integration.
     #[arg(long)]
@@ -88,6 +173,7 @@ pub struct LogArgs {
 }
 
 #[derive(Parser, Debug)]
+#[command(group(ArgGroup::new("fzf_mode").args(["fzf", "multi_select"]).multiple(true)))]
 pub struct SummaryArgs {
     /// Query substring (or prefix if --starts)
     pub query: Option<String>,
@@ -119,22 +205,77 @@ pub struct SummaryArgs {
     #[arg(long, conflicts_with = "here")]
     pub under: bool,
 
+    /// Only commands run in a directory whose path contains this substring
+    #[arg(long, value_name = "SUBSTR")]
+    pub pwd_contains: Option<String>,
+
     /// Use fzf for interactive selection (outputs selected command to stdout)
     #[arg(long)]
     pub fzf: bool,
 
+    /// Give up on fzf after this many seconds and exit 130, as if cancelled
+    /// (default: `[fzf] timeout_secs`)
+    #[arg(long, value_name = "SECS", requires = "fzf_mode")]
+    pub fzf_timeout: Option<u64>,
+
     /// Allow selecting multiple commands with fzf (implies --fzf)
     #[arg(long)]
     pub multi_select: bool,
 
+    /// With --multi-select, exchange NUL-terminated records with fzf and print each
+    /// selection followed by NUL, so multi-line commands survive (newline output is lossy)
+    #[arg(long, requires = "multi_select")]
+    pub print0: bool,
+
+    /// With --fzf or --multi-select, print each selection as a JSON object with its metadata
+    /// (id, count, last_epoch) instead of the bare command
+    #[arg(long, requires = "fzf_mode")]
+    pub with_meta: bool,
+
+    /// With --fzf or --multi-select, log the picked command as if it had just been
+    /// run here, so reuse from history is recorded without the shell binding running
+    /// it again
+    #[arg(long, requires = "fzf_mode")]
+    pub log_selection: bool,
+
+    /// Rewrite leading aliases (from the [alias] config table) before grouping
+    #[arg(long)]
+    pub canonical: bool,
+
     #[arg(long)]
     pub verbose: bool,
+
+    /// Print a column header row in table output (default when stdout is a terminal)
+    #[arg(long, conflicts_with = "no_headers")]
+    pub headers: bool,
+
+    /// Never print the table header row
+    #[arg(long)]
+    pub no_headers: bool,
+
+    /// Only show commands logged on this host
+    #[arg(long)]
+    pub host: Option<String>,
+
+    /// Exit with status 3 when nothing matched (0 otherwise; errors exit 1)
+    #[arg(long)]
+    pub status: bool,
+
+    /// Stable script-friendly output: tab-separated, no header, raw epoch (see README)
+    #[arg(long, conflicts_with_all = ["fzf", "headers"])]
+    pub porcelain: bool,
+
+    /// Print only the number of matching rows (ignores --limit)
+    #[arg(long, conflicts_with_all = ["fzf", "porcelain"])]
+    pub count: bool,
 }
 
 #[derive(ValueEnum, Debug, Clone, Copy)]
 pub enum OutputFormat {
     Table,
     Json,
+    Csv,
+    Tsv,
 }
 
 impl std::fmt::Display for OutputFormat {
@@ -142,11 +283,73 @@ impl std::fmt::Display for OutputFormat {
         match self {
             OutputFormat::Table => write!(f, "table"),
             OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Csv => write!(f, "csv"),
+            OutputFormat::Tsv => write!(f, "tsv"),
         }
     }
 }
 
 #[derive(Parser, Debug)]
+pub struct ReplayArgs {
+    /// Session salt (`salt` in `export`/`--verbose-json` output)
+    #[arg(long, required_unless_present = "id", requires = "ppid")]
+    pub salt: Option<i64>,
+
+    /// Session shell pid (`ppid` in `export`/`--verbose-json` output)
+    #[arg(long, required_unless_present = "id", requires = "salt")]
+    pub ppid: Option<i64>,
+
+    /// Print the command with this history id (the `list` id column) instead of a session
+    #[arg(long, conflicts_with_all = ["salt", "ppid", "confirm_each", "dry_run", "yes"])]
+    pub id: Option<i64>,
+
+    /// With --id: run the command with `$SHELL -c` and log it, instead of printing it
+    #[arg(long, requires = "id")]
+    pub run: bool,
+
+    /// Run the session's commands (without it, or --confirm-each, replay only prints them)
+    #[arg(long, conflicts_with = "dry_run")]
+    pub yes: bool,
+
+    /// Ask before running each command
+    #[arg(long, conflicts_with = "dry_run")]
+    pub confirm_each: bool,
+
+    /// Only print the commands and directories that would run (the default)
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// Result order for `search --sort`.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortMode {
+    /// Newest first
+    Recency,
+    /// Exact, then prefix, then word-boundary, then other substring matches
+    Relevance,
+    /// Most frequently run commands first
+    Frequency,
+}
+
+#[derive(Parser, Debug)]
+pub struct DescribeArgs {
+    /// Record type to describe (all of them when omitted)
+    #[arg(value_enum)]
+    pub record: Option<RecordKind>,
+}
+
+/// Commands with a machine-readable output shape.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    List,
+    Search,
+    Export,
+    Doctor,
+    Show,
+}
+
+#[derive(Parser, Debug)]
+#[command(group(ArgGroup::new("fzf_mode").args(["fzf", "multi_select"]).multiple(true)))]
 pub struct ListArgs {
     /// Query substring
     pub query: Option<String>,
@@ -178,19 +381,116 @@ pub struct ListArgs {
     #[arg(long, conflicts_with = "here")]
     pub under: bool,
 
+    /// Only commands run in a directory whose path contains this substring
+    #[arg(long, value_name = "SUBSTR")]
+    pub pwd_contains: Option<String>,
+
     /// Use fzf for interactive selection (outputs selected command to stdout)
     #[arg(long)]
     pub fzf: bool,
 
+    /// Give up on fzf after this many seconds and exit 130, as if cancelled
+    /// (default: `[fzf] timeout_secs`)
+    #[arg(long, value_name = "SECS", requires = "fzf_mode")]
+    pub fzf_timeout: Option<u64>,
+
     /// Allow selecting multiple commands with fzf (implies --fzf)
     #[arg(long)]
     pub multi_select: bool,
+
+    /// With --multi-select, exchange NUL-terminated records with fzf and print each
+    /// selection followed by NUL, so multi-line commands survive (newline output is lossy)
+    #[arg(long, requires = "multi_select")]
+    pub print0: bool,
+
+    /// With --fzf or --multi-select, print each selection as a JSON object with its metadata
+    /// (id, epoch) instead of the bare command
+    #[arg(long, requires = "fzf_mode")]
+    pub with_meta: bool,
+
+    /// With --fzf or --multi-select, log the picked command as if it had just been
+    /// run here, so reuse from history is recorded without the shell binding running
+    /// it again
+    #[arg(long, requires = "fzf_mode")]
+    pub log_selection: bool,
+
+    /// Hide a row when its command repeats the previously printed row's command
+    #[arg(long)]
+    pub dedup_adjacent: bool,
+
+    /// Print the number of results to stderr after the output
+    #[arg(long)]
+    pub show_count: bool,
+
+    /// Only show commands that exited with a non-zero status
+    #[arg(long)]
+    pub failed_only: bool,
+
+    /// Drop commands containing this substring (repeatable; adds to `[search] default_exclude`)
+    #[arg(long)]
+    pub exclude: Vec<String>,
+
+    /// Add an ISO-8601 local-time "iso" field next to "epoch" in JSON output
+    #[arg(long)]
+    pub iso_dates: bool,
+
+    /// Also include the session fields `hist_id`, `salt` and `ppid` in JSON output
+    #[arg(long)]
+    pub verbose_json: bool,
+
+    /// Print a column header row in table output (default when stdout is a terminal)
+    #[arg(long, conflicts_with = "no_headers")]
+    pub headers: bool,
+
+    /// Never print the header row (table, CSV and TSV), e.g. to concatenate CSV/TSV output
+    #[arg(long, visible_alias = "no-header")]
+    pub no_headers: bool,
+
+    /// Only show commands logged on this host
+    #[arg(long)]
+    pub host: Option<String>,
+
+    /// Only include rows from this local date onwards (YYYY-MM-DD, inclusive)
+    #[arg(long)]
+    pub since: Option<String>,
+
+    /// Only include rows before this local date (YYYY-MM-DD, exclusive)
+    #[arg(long)]
+    pub until: Option<String>,
+
+    /// Exit with status 3 when nothing matched (0 otherwise; errors exit 1)
+    #[arg(long)]
+    pub status: bool,
+
+    /// Stable script-friendly output: tab-separated, no header, raw epoch (see README)
+    #[arg(long, conflicts_with_all = ["format", "fzf", "headers"])]
+    pub porcelain: bool,
+
+    /// Order by when sdbh recorded each row instead of when the command ran
+    /// (rows stored before sdbh tracked this come first)
+    #[arg(long)]
+    pub by_inserted: bool,
+
+    /// Show each distinct command once, at its most recent run (with that run's pwd)
+    #[arg(long)]
+    pub unique: bool,
+
+    /// Print only the number of matching rows (ignores --limit)
+    #[arg(long, conflicts_with_all = ["fzf", "porcelain"])]
+    pub count: bool,
+
+    /// Group rows by session, sessions in order of their first command, with a
+    /// header line before each session's commands (table output only)
+    #[arg(long, conflicts_with_all = ["fzf", "porcelain", "count", "by_inserted", "unique"])]
+    pub group_session: bool,
 }
 
 #[derive(Parser, Debug)]
+#[command(group(ArgGroup::new("fzf_mode").args(["fzf", "multi_select"]).multiple(true)))]
 pub struct SearchArgs {
     /// Query substring (case-insensitive)
-    pub query: String,
+    #[arg(required_unless_present_any = ["around_id", "near"])]
+    pub query: Option<String>,
 
     #[arg(long, default_value_t = 100)]
     pub limit: u32,
@@ -198,6 +498,11 @@ pub struct SearchArgs {
     #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
     pub format: OutputFormat,
 
+    /// Result order; relevance and frequency rank every match before applying --limit
+    #[arg(long, value_enum, default_value_t = SortMode::Recency,
+          conflicts_with_all = ["fzf", "around_id", "near"])]
+    pub sort: SortMode,
+
     /// Show all entries (no limit)
     #[arg(long)]
     pub all: bool,
@@ -214,6 +519,14 @@ pub struct SearchArgs {
     #[arg(long, conflicts_with = "since_epoch")]
     pub days: Option<u32>,
 
+    /// Only include rows from this local date onwards (YYYY-MM-DD, inclusive)
+    #[arg(long, conflicts_with_all = ["days", "since_epoch"])]
+    pub since: Option<String>,
+
+    /// Only include rows before this local date (YYYY-MM-DD, exclusive)
+    #[arg(long, conflicts_with_all = ["days", "since_epoch"])]
+    pub until: Option<String>,
+
     /// Override the working directory used by --here/--under (useful for tests)
     #[arg(long)]
     pub pwd_override: Option<String>,
@@ -224,30 +537,249 @@ pub struct SearchArgs {
     #[arg(long, conflicts_with = "here")]
     pub under: bool,
 
+    /// Only commands run in a directory whose path contains this substring
+    #[arg(long, value_name = "SUBSTR")]
+    pub pwd_contains: Option<String>,
+
     /// Use fzf for interactive selection (outputs selected command to stdout)
     #[arg(long)]
     pub fzf: bool,
 
+    /// Give up on fzf after this many seconds and exit 130, as if cancelled
+    /// (default: `[fzf] timeout_secs`)
+    #[arg(long, value_name = "SECS", requires = "fzf_mode")]
+    pub fzf_timeout: Option<u64>,
+
     /// Allow selecting multiple commands with fzf (implies --fzf)
     #[arg(long)]
     pub multi_select: bool,
+
+    /// With --multi-select, exchange NUL-terminated records with fzf and print each
+    /// selection followed by NUL, so multi-line commands survive (newline output is lossy)
+    #[arg(long, requires = "multi_select")]
+    pub print0: bool,
+
+    /// With --fzf or --multi-select, print each selection as a JSON object with its metadata
+    /// (id, epoch) instead of the bare command
+    #[arg(long, requires = "fzf_mode")]
+    pub with_meta: bool,
+
+    /// With --fzf or --multi-select, log the picked command as if it had just been
+    /// run here, so reuse from history is recorded without the shell binding running
+    /// it again
+    #[arg(long, requires = "fzf_mode")]
+    pub log_selection: bool,
+
+    /// Hide a row when its command repeats the previously printed row's command
+    #[arg(long)]
+    pub dedup_adjacent: bool,
+
+    /// Print the number of results to stderr after the output
+    #[arg(long)]
+    pub show_count: bool,
+
+    /// Only show commands that exited with a non-zero status
+    #[arg(long)]
+    pub failed_only: bool,
+
+    /// Drop commands containing this substring (repeatable; adds to `[search] default_exclude`)
+    #[arg(long)]
+    pub exclude: Vec<String>,
+
+    /// Add an ISO-8601 local-time "iso" field next to "epoch" in JSON output
+    #[arg(long)]
+    pub iso_dates: bool,
+
+    /// Also include the session fields `hist_id`, `salt` and `ppid` in JSON output
+    #[arg(long)]
+    pub verbose_json: bool,
+
+    /// Show commands that do NOT contain the query (like grep -v)
+    #[arg(long)]
+    pub invert: bool,
+
+    /// Show the rows logged just before and after this history id, in time order
+    /// (instead of a query; other filters are ignored)
+    #[arg(long, conflicts_with_all = ["query", "fzf", "multi_select"])]
+    pub around_id: Option<i64>,
+
+    /// Rows to show on each side of --around-id
+    #[arg(long, default_value_t = 5, requires = "around_id")]
+    pub context: u32,
+
+    /// Show every command logged within --window seconds of the most recent command
+    /// matching this query (instead of a query; other filters are ignored)
+    #[arg(long, conflicts_with_all = ["query", "fzf", "multi_select", "around_id"])]
+    pub near: Option<String>,
+
+    /// Seconds on each side of the --near match
+    #[arg(long, default_value_t = 600, requires = "near")]
+    pub window: u32,
+
+    /// Print a column header row in table output (default when stdout is a terminal)
+    #[arg(long, conflicts_with = "no_headers")]
+    pub headers: bool,
+
+    /// Never print the header row (table, CSV and TSV), e.g. to concatenate CSV/TSV output
+    #[arg(long, visible_alias = "no-header")]
+    pub no_headers: bool,
+
+    /// Only show commands logged on this host
+    #[arg(long)]
+    pub host: Option<String>,
+
+    /// Exit with status 3 when nothing matched (0 otherwise; errors exit 1)
+    #[arg(long)]
+    pub status: bool,
+
+    /// Stable script-friendly output: tab-separated, no header, raw epoch (see README)
+    #[arg(long, conflicts_with_all = ["format", "fzf", "headers"])]
+    pub porcelain: bool,
+
+    /// Print only the number of matching rows (ignores --limit)
+    #[arg(long, conflicts_with_all = ["fzf", "porcelain"])]
+    pub count: bool,
 }
 
 #[derive(Parser, Debug)]
-pub struct ExportArgs {
-    /// Show all entries (no limit)
+pub struct ShowArgs {
+    /// History id (the `list`/`search` id column)
     #[arg(long)]
-    pub all: bool,
+    pub id: i64,
+
+    /// Output format (table or json)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
+}
+
+#[derive(Parser, Debug)]
+pub struct LastArgs {
+    /// Only the current session's last command (needs SDBH_SALT/SDBH_PPID)
+    #[arg(long)]
+    pub session: bool,
+
+    /// Print the last N commands, oldest first
+    #[arg(short = 'n', long, default_value_t = 1)]
+    pub count: u32,
+
+    /// Override the working directory used by --here/--under (useful for tests)
+    #[arg(long)]
+    pub pwd_override: Option<String>,
+
+    #[arg(long, conflicts_with = "under")]
+    pub here: bool,
+
+    #[arg(long, conflicts_with = "here")]
+    pub under: bool,
+
+    /// Table prints just the command lines
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
+}
+
+#[derive(Parser, Debug)]
+pub struct ExistsArgs {
+    /// Query substring (case-insensitive)
+    pub query: String,
 
     /// Filter to current session only
     #[arg(long)]
     pub session: bool,
+
+    /// Only include rows with epoch >= since_epoch.
+    #[arg(long, conflicts_with = "days")]
+    pub since_epoch: Option<i64>,
+
+    /// Only include rows within the last N days.
+    #[arg(long, conflicts_with = "since_epoch")]
+    pub days: Option<u32>,
+
+    /// Override the working directory used by --here/--under (useful for tests)
+    #[arg(long)]
+    pub pwd_override: Option<String>,
+
+    #[arg(long, conflicts_with = "under")]
+    pub here: bool,
+
+    #[arg(long, conflicts_with = "here")]
+    pub under: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct DeleteArgs {
+    /// Delete the row with this id (as shown by list/search)
+    #[arg(long, conflicts_with = "query", required_unless_present = "query")]
+    pub id: Option<i64>,
+
+    /// Delete every row whose command contains this substring (case-insensitive)
+    #[arg(long)]
+    pub query: Option<String>,
+
+    /// Don't ask for confirmation (required when stdin is not a terminal)
+    #[arg(long)]
+    pub yes: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct RedactArgs {
+    /// Regular expression to search for in stored commands
+    #[arg(long, allow_hyphen_values = true)]
+    pub pattern: String,
+
+    /// Replacement text (`$1` etc. refer to capture groups)
+    #[arg(long, default_value = "***", allow_hyphen_values = true)]
+    pub replacement: String,
+
+    /// Only report what would change
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct EditArgs {
+    /// Row id to edit (as shown by `list`)
+    #[arg(long)]
+    pub id: i64,
+}
+
+#[derive(Parser, Debug)]
+pub struct ExportArgs {
+    /// Export every session, even from a shell with the hook installed
+    #[arg(long, conflicts_with = "session")]
+    pub all: bool,
+
+    /// Filter to current session only (the default when SDBH_SALT/SDBH_PPID are set)
+    #[arg(long)]
+    pub session: bool,
+
+    /// Skip rows whose timestamps were synthesized by import-history
+    #[arg(long)]
+    pub no_synthetic: bool,
+
+    /// Add an ISO-8601 local-time "iso" field next to "epoch" in JSON output
+    #[arg(long)]
+    pub iso_dates: bool,
+
+    #[arg(long, value_enum, default_value_t = ExportFormat::Jsonl)]
+    pub format: ExportFormat,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum ExportFormat {
+    /// One JSON object per line
+    Jsonl,
+    /// RFC 4180 CSV with a header row
+    Csv,
 }
 
 #[derive(Parser, Debug)]
 pub struct StatsArgs {
     #[command(subcommand)]
     pub command: StatsCommand,
+
+    /// Exit with status 3 when nothing matched (0 otherwise; errors exit 1)
+    #[arg(long, global = true)]
+    pub status: bool,
 }
 
 #[derive(Subcommand, Debug)]
@@ -260,13 +792,40 @@ pub enum StatsCommand {
 
     /// Command count per day within the last N days
     Daily(StatsDailyArgs),
+
+    /// Slowest commands by average duration within the last N days
+    Slow(StatsSlowArgs),
+
+    /// Command count per host within the last N days
+    ByHost(StatsByHostArgs),
+
+    /// Command count per git repository within the last N days (needs `[log] track_gitroot`)
+    ByGitroot(StatsByGitrootArgs),
+
+    /// Command count per hour of day (local time) within the last N days
+    Hourly(StatsHistogramArgs),
+
+    /// Command count per weekday (local time) within the last N days
+    Weekday(StatsHistogramArgs),
+
+    /// Commands first used within the last N days, oldest first
+    New(StatsNewArgs),
 }
 
 #[derive(Parser, Debug)]
+#[command(group(ArgGroup::new("fzf_mode").args(["fzf", "multi_select"]).multiple(true)))]
 pub struct StatsTopArgs {
     #[arg(long, default_value_t = 30)]
     pub days: u32,
 
+    /// Start of the window: epoch seconds, YYYY-MM-DD (local midnight), or a relative age like 12h/7d/2w
+    #[arg(long, conflicts_with = "days")]
+    pub since: Option<String>,
+
+    /// End of the window (inclusive): same formats as --since; a bare date covers that whole day
+    #[arg(long, conflicts_with = "days")]
+    pub until: Option<String>,
+
     #[arg(long, default_value_t = 50)]
     pub limit: u32,
 
@@ -278,20 +837,58 @@ pub struct StatsTopArgs {
     #[arg(long)]
     pub session: bool,
 
+    /// Rewrite leading aliases (from the [alias] config table) before grouping
+    #[arg(long)]
+    pub canonical: bool,
+
+    /// Group by program (first word of the command) instead of the full command
+    #[arg(long)]
+    pub by_program: bool,
+
+    /// Compare each command's use in the first and second half of the --days window
+    /// (e.g. `↑ +50%`)
+    #[arg(long, conflicts_with_all = ["since", "until", "fzf"])]
+    pub trend: bool,
+
     /// Use fzf for interactive selection (outputs selected command to stdout)
     #[arg(long)]
     pub fzf: bool,
 
+    /// Give up on fzf after this many seconds and exit 130, as if cancelled
+    /// (default: `[fzf] timeout_secs`)
+    #[arg(long, value_name = "SECS", requires = "fzf_mode")]
+    pub fzf_timeout: Option<u64>,
+
     /// Allow selecting multiple commands with fzf (implies --fzf)
     #[arg(long)]
     pub multi_select: bool,
+
+    /// With --multi-select, exchange NUL-terminated records with fzf and print each
+    /// selection followed by NUL, so multi-line commands survive (newline output is lossy)
+    #[arg(long, requires = "multi_select")]
+    pub print0: bool,
+
+    /// With --fzf or --multi-select, log the picked command as if it had just been
+    /// run here, so reuse from history is recorded without the shell binding running
+    /// it again
+    #[arg(long, requires = "fzf_mode", conflicts_with = "by_program")]
+    pub log_selection: bool,
 }
 
 #[derive(Parser, Debug)]
+#[command(group(ArgGroup::new("fzf_mode").args(["fzf", "multi_select"]).multiple(true)))]
 pub struct StatsByPwdArgs {
     #[arg(long, default_value_t = 30)]
     pub days: u32,
 
+    /// Start of the window: epoch seconds, YYYY-MM-DD (local midnight), or a relative age like 12h/7d/2w
+    #[arg(long, conflicts_with = "days")]
+    pub since: Option<String>,
+
+    /// End of the window (inclusive): same formats as --since; a bare date covers that whole day
+    #[arg(long, conflicts_with = "days")]
+    pub until: Option<String>,
+
     #[arg(long, default_value_t = 50)]
     pub limit: u32,
 
@@ -303,20 +900,53 @@ pub struct StatsByPwdArgs {
     #[arg(long)]
     pub session: bool,
 
+    /// Rewrite leading aliases (from the [alias] config table) before grouping
+    #[arg(long)]
+    pub canonical: bool,
+
     /// Use fzf for interactive selection (outputs selected command to stdout)
     #[arg(long)]
     pub fzf: bool,
 
+    /// Give up on fzf after this many seconds and exit 130, as if cancelled
+    /// (default: `[fzf] timeout_secs`)
+    #[arg(long, value_name = "SECS", requires = "fzf_mode")]
+    pub fzf_timeout: Option<u64>,
+
     /// Allow selecting multiple commands with fzf (implies --fzf)
     #[arg(long)]
     pub multi_select: bool,
+
+    /// With --multi-select, exchange NUL-terminated records with fzf and print each
+    /// selection followed by NUL, so multi-line commands survive (newline output is lossy)
+    #[arg(long, requires = "multi_select")]
+    pub print0: bool,
+
+    /// With --fzf or --multi-select, log the picked command as if it had just been
+    /// run here, so reuse from history is recorded without the shell binding running
+    /// it again
+    #[arg(long, requires = "fzf_mode")]
+    pub log_selection: bool,
 }
 
 #[derive(Parser, Debug)]
+#[command(group(ArgGroup::new("fzf_mode").args(["fzf", "multi_select"]).multiple(true)))]
 pub struct StatsDailyArgs {
     #[arg(long, default_value_t = 30)]
     pub days: u32,
 
+    /// Start of the window: epoch seconds, YYYY-MM-DD (local midnight), or a relative age like 12h/7d/2w
+    #[arg(long, conflicts_with = "days")]
+    pub since: Option<String>,
+
+    /// End of the window (inclusive): same formats as --since; a bare date covers that whole day
+    #[arg(long, conflicts_with = "days")]
+    pub until: Option<String>,
+
+    /// Only the most recent N days of the window
+    #[arg(long, default_value_t = 50)]
+    pub limit: u32,
+
     /// Show all entries (no limit)
     #[arg(long)]
     pub all: bool,
@@ -329,20 +959,168 @@ pub struct StatsDailyArgs {
     #[arg(long)]
     pub fzf: bool,
 
+    /// Give up on fzf after this many seconds and exit 130, as if cancelled
+    /// (default: `[fzf] timeout_secs`)
+    #[arg(long, value_name = "SECS", requires = "fzf_mode")]
+    pub fzf_timeout: Option<u64>,
+
     /// Allow selecting multiple commands with fzf (implies --fzf)
     #[arg(long)]
     pub multi_select: bool,
+
+    /// With --multi-select, exchange NUL-terminated records with fzf and print each
+    /// selection followed by NUL, so multi-line commands survive (newline output is lossy)
+    #[arg(long, requires = "multi_select")]
+    pub print0: bool,
 }
 
 #[derive(Parser, Debug)]
-pub struct ImportArgs {
-    /// Source SQLite path (dbhist compatible). Can be provided multiple times.
-    #[arg(long = "from")]
-    pub from_paths: Vec<PathBuf>,
+pub struct StatsSlowArgs {
+    #[arg(long, default_value_t = 30)]
+    pub days: u32,
+
+    /// Start of the window: epoch seconds, YYYY-MM-DD (local midnight), or a relative age like 12h/7d/2w
+    #[arg(long, conflicts_with = "days")]
+    pub since: Option<String>,
+
+    /// End of the window (inclusive): same formats as --since; a bare date covers that whole day
+    #[arg(long, conflicts_with = "days")]
+    pub until: Option<String>,
+
+    #[arg(long, default_value_t = 50)]
+    pub limit: u32,
+
+    /// Show all entries (no limit)
+    #[arg(long)]
+    pub all: bool,
+
+    /// Filter to current session only
+    #[arg(long)]
+    pub session: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct StatsNewArgs {
+    #[arg(long, default_value_t = 30)]
+    pub days: u32,
+
+    #[arg(long, default_value_t = 50)]
+    pub limit: u32,
+
+    /// Show all entries (no limit)
+    #[arg(long)]
+    pub all: bool,
+
+    /// Filter to current session only
+    #[arg(long)]
+    pub session: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct StatsByHostArgs {
+    #[arg(long, default_value_t = 30)]
+    pub days: u32,
+
+    /// Start of the window: epoch seconds, YYYY-MM-DD (local midnight), or a relative age like 12h/7d/2w
+    #[arg(long, conflicts_with = "days")]
+    pub since: Option<String>,
+
+    /// End of the window (inclusive): same formats as --since; a bare date covers that whole day
+    #[arg(long, conflicts_with = "days")]
+    pub until: Option<String>,
+
+    /// Show at most this many hosts, busiest first
+    #[arg(long, default_value_t = 50)]
+    pub limit: u32,
+
+    /// Show all entries (no limit)
+    #[arg(long)]
+    pub all: bool,
+
+    /// Filter to current session only
+    #[arg(long)]
+    pub session: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct StatsByGitrootArgs {
+    #[arg(long, default_value_t = 30)]
+    pub days: u32,
+
+    /// Start of the window: epoch seconds, YYYY-MM-DD (local midnight), or a relative age like 12h/7d/2w
+    #[arg(long, conflicts_with = "days")]
+    pub since: Option<String>,
+
+    /// End of the window (inclusive): same formats as --since; a bare date covers that whole day
+    #[arg(long, conflicts_with = "days")]
+    pub until: Option<String>,
+
+    #[arg(long, default_value_t = 50)]
+    pub limit: u32,
+
+    /// Show all entries (no limit)
+    #[arg(long)]
+    pub all: bool,
+
+    /// Filter to current session only
+    #[arg(long)]
+    pub session: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct StatsHistogramArgs {
+    #[arg(long, default_value_t = 30)]
+    pub days: u32,
+
+    /// Start of the window: epoch seconds, YYYY-MM-DD (local midnight), or a relative age like 12h/7d/2w
+    #[arg(long, conflicts_with = "days")]
+    pub since: Option<String>,
+
+    /// End of the window (inclusive): same formats as --since; a bare date covers that whole day
+    #[arg(long, conflicts_with = "days")]
+    pub until: Option<String>,
+
+    /// Filter to current session only
+    #[arg(long)]
+    pub session: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct ImportArgs {
+    /// Source SQLite path (dbhist compatible). Can be provided multiple times.
+    #[arg(long = "from")]
+    pub from_paths: Vec<PathBuf>,
 
     /// Destination db path (defaults to ~/.sdbh.sqlite)
     #[arg(long = "to")]
     pub to: Option<PathBuf>,
+
+    /// After each source, list how many already-present rows came from each of its sessions
+    #[arg(long)]
+    pub session_report: bool,
+
+    /// Store this pwd on every imported row
+    #[arg(long)]
+    pub set_pwd: Option<String>,
+
+    /// Store this host on every imported row
+    #[arg(long)]
+    pub set_host: Option<String>,
+
+    /// Store this salt on every imported row
+    #[arg(long)]
+    pub set_salt: Option<i64>,
+
+    /// Store this ppid on every imported row
+    #[arg(long)]
+    pub set_ppid: Option<i64>,
+}
+
+#[derive(Parser, Debug)]
+pub struct ImportJsonlArgs {
+    /// JSON Lines file from `sdbh export`. Can be provided multiple times.
+    #[arg(long = "from", required = true)]
+    pub from_paths: Vec<PathBuf>,
 }
 
 #[derive(Parser, Debug)]
@@ -368,6 +1146,38 @@ pub struct ImportHistoryArgs {
     pub ppid: i64,
 }
 
+#[derive(Parser, Debug)]
+pub struct DirsArgs {
+    #[command(subcommand)]
+    pub command: DirsCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DirsCommand {
+    /// Directories whose path contains the query, with command count and last activity
+    Search(DirsSearchArgs),
+}
+
+#[derive(Parser, Debug)]
+pub struct DirsSearchArgs {
+    /// Path substring (case-insensitive)
+    pub query: String,
+
+    #[arg(long, default_value_t = 100)]
+    pub limit: u32,
+
+    /// Show all matching directories (no limit)
+    #[arg(long)]
+    pub all: bool,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
+
+    /// Exit with status 3 when nothing matched (0 otherwise; errors exit 1)
+    #[arg(long)]
+    pub status: bool,
+}
+
 #[derive(Parser, Debug)]
 pub struct DbArgs {
     #[command(subcommand)]
@@ -377,13 +1187,71 @@ pub struct DbArgs {
 #[derive(Subcommand, Debug)]
 pub enum DbCommand {
     /// Check database health and statistics
-    Health,
+    Health(DbReportArgs),
     /// Optimize database (rebuild indexes, vacuum)
     Optimize,
     /// Show database statistics
-    Stats,
+    Stats(DbReportArgs),
     /// Show database schema information
-    Schema,
+    Schema(DbReportArgs),
+    /// Delete history older than N days
+    Prune(DbPruneArgs),
+    /// Remove exact duplicate rows (same dedup hash), keeping the oldest
+    Dedup(DbDedupArgs),
+    /// Write a compacted copy of the database (VACUUM INTO), leaving the original untouched
+    Compact(DbCompactArgs),
+    /// Copy the database to a consistent snapshot, even while it is in use
+    Backup(DbBackupArgs),
+    /// Build (or rebuild) the full-text index used to speed up `search`
+    #[command(name = "reindex-fts")]
+    ReindexFts,
+}
+
+#[derive(Parser, Debug)]
+pub struct DbReportArgs {
+    /// Output format (csv and tsv are not supported for db reports)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
+}
+
+#[derive(Parser, Debug)]
+pub struct DbCompactArgs {
+    /// Where to write the compacted copy (must not exist)
+    pub path: PathBuf,
+
+    /// Copy the compacted database back over the original afterwards (stop shells
+    /// from logging first)
+    #[arg(long)]
+    pub replace: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct DbBackupArgs {
+    /// Destination file for the snapshot
+    #[arg(long)]
+    pub to: PathBuf,
+
+    /// Overwrite the destination if it already exists
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct DbPruneArgs {
+    /// Remove rows logged more than this many days ago
+    #[arg(long)]
+    pub older_than_days: u32,
+
+    /// Only report how many rows would be removed
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct DbDedupArgs {
+    /// Only report how many rows would be removed
+    #[arg(long)]
+    pub dry_run: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -410,6 +1278,14 @@ pub struct ShellArgs {
     #[arg(long)]
     pub zsh: bool,
 
+    /// Print Nushell integration (hooks in config.nu; only printed when asked for)
+    #[arg(long, conflicts_with = "intercept")]
+    pub nu: bool,
+
+    /// Print PowerShell integration (prompt function for $PROFILE; only printed when asked for)
+    #[arg(long, conflicts_with = "intercept")]
+    pub powershell: bool,
+
     /// Print intercept-style integration (more invasive)
     #[arg(long)]
     pub intercept: bool,
@@ -419,9 +1295,14 @@ pub struct ShellArgs {
 pub struct PreviewArgs {
     /// Command to preview
     pub command: String,
+
+    /// Render for this many columns instead of the detected terminal width
+    #[arg(long)]
+    pub width: Option<usize>,
 }
 
 #[derive(Parser, Debug)]
+#[command(group(ArgGroup::new("fzf_mode").args(["fzf", "multi_select"]).multiple(true)))]
 pub struct TemplateArgs {
     /// Template name to execute (if not provided, lists all templates)
     pub name: Option<String>,
@@ -442,33 +1323,147 @@ pub struct TemplateArgs {
     #[arg(long)]
     pub delete: Option<String>,
 
+    /// Skip the confirmation prompt for --delete
+    #[arg(long, requires = "delete")]
+    pub yes: bool,
+
+    /// With --delete, move the template to `.trash` in the templates directory instead of removing it
+    #[arg(long, requires = "delete")]
+    pub backup: bool,
+
     /// Use fzf for interactive template selection
     #[arg(long)]
     pub fzf: bool,
 
+    /// Give up on fzf after this many seconds and exit 130, as if cancelled
+    /// (default: `[fzf] timeout_secs`)
+    #[arg(long, value_name = "SECS", requires = "fzf_mode")]
+    pub fzf_timeout: Option<u64>,
+
     /// Allow selecting multiple templates with fzf (implies --fzf)
     #[arg(long)]
     pub multi_select: bool,
+
+    /// Run the resolved command with `$SHELL -c` and log it, instead of printing it
+    #[arg(long, requires = "name")]
+    pub run: bool,
+
+    /// Don't prefill prompts with, or save, the values entered last time
+    #[arg(long)]
+    pub no_remember: bool,
+
+    /// Write all templates into one TOML bundle file
+    #[arg(long, value_name = "FILE", conflicts_with = "import")]
+    pub export: Option<PathBuf>,
+
+    /// Save every template from a bundle written by --export
+    #[arg(long, value_name = "FILE")]
+    pub import: Option<PathBuf>,
+
+    /// With --import, overwrite templates that already exist
+    #[arg(long, requires = "import")]
+    pub force: bool,
+}
+
+/// Whether `err` comes from writing to a pipe whose reader has gone away
+/// (e.g. `sdbh export --all | head -1`).
+pub fn is_broken_pipe(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        let kind = cause
+            .downcast_ref::<std::io::Error>()
+            .map(std::io::Error::kind)
+            .or_else(|| cause.downcast_ref::<serde_json::Error>()?.io_error_kind());
+        kind == Some(std::io::ErrorKind::BrokenPipe)
+    })
 }
 
 pub fn run(cli: Cli) -> Result<()> {
-    let db_path = cli.db.unwrap_or_else(DbConfig::default_path);
-    let cfg = DbConfig { path: db_path };
+    let db_source = if cli.db.is_empty() { "default" } else { "--db" };
+    let mut db_paths = cli.db.into_iter();
+    let db_path = match db_paths.next() {
+        Some(path) => path,
+        None => {
+            let path = DbConfig::default_path();
+            // The XDG location lives in a directory of its own that may not exist yet.
+            if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("creating {}", dir.display()))?;
+            }
+            path
+        }
+    };
+    let extra_paths: Vec<PathBuf> = db_paths.collect();
+
+    let aggregates = matches!(
+        &cli.command,
+        Commands::Stats(StatsArgs {
+            command: StatsCommand::Top(StatsTopArgs { fzf: false, .. })
+                | StatsCommand::Daily(StatsDailyArgs { fzf: false, .. }),
+            ..
+        })
+    );
+    if !extra_paths.is_empty() && !aggregates {
+        anyhow::bail!("multiple --db paths are only supported by `stats top` and `stats daily`");
+    }
+    // Parsed once here; `log` runs on every prompt and reuses it.
+    let file_cfg = load_config_file().unwrap_or_default();
+    let db_file_cfg = &file_cfg.db;
+    let hash_version = match db_file_cfg.hash_version {
+        Some(v) => HashVersion::from_number(v)
+            .with_context(|| format!("unsupported [db] hash_version {v} (expected 1 or 2)"))?,
+        None => HashVersion::default(),
+    };
+    let pragmas = db_file_cfg
+        .pragmas
+        .iter()
+        .filter_map(|(name, value)| match value {
+            toml::Value::Integer(n) => Some((name.clone(), n.to_string())),
+            toml::Value::String(s) => Some((name.clone(), s.clone())),
+            other => {
+                eprintln!(
+                    "warning: [db] pragmas: skipping {name}: must be an integer or string, got {other}"
+                );
+                None
+            }
+        })
+        .collect();
+    let cfg = DbConfig {
+        path: db_path,
+        extra_paths,
+        hash_version,
+        track_hash: db_file_cfg.track_hash.unwrap_or(true),
+        pragmas,
+    };
+
+    if cli.db_info {
+        print_db_info(&cfg, db_source);
+    }
+    let decorate = decorations_enabled(cli.no_color);
 
     match cli.command {
-        Commands::Log(args) => cmd_log(cfg, args),
+        Commands::Log(args) => cmd_log(cfg, args, &file_cfg),
         Commands::Summary(args) => cmd_summary(cfg, args),
         Commands::List(args) => cmd_list(cfg, args),
         Commands::Search(args) => cmd_search(cfg, args),
+        Commands::Exists(args) => cmd_exists(cfg, args),
+        Commands::Last(args) => cmd_last(cfg, args),
+        Commands::Show(args) => cmd_show(cfg, args),
         Commands::Export(args) => cmd_export(cfg, args),
+        Commands::Delete(args) => cmd_delete(cfg, args),
+        Commands::Edit(args) => cmd_edit(cfg, args),
+        Commands::Redact(args) => cmd_redact(cfg, args),
         Commands::Stats(args) => cmd_stats(cfg, args),
         Commands::Import(args) => cmd_import(cfg, args),
         Commands::ImportHistory(args) => cmd_import_history(cfg, args),
+        Commands::ImportJsonl(args) => cmd_import_jsonl(cfg, args),
         Commands::Doctor(args) => cmd_doctor(cfg, args),
         Commands::Db(args) => cmd_db(cfg, args),
         Commands::Shell(args) => cmd_shell(args),
-        Commands::Preview(args) => cmd_preview(cfg, args),
+        Commands::Preview(args) => cmd_preview(cfg, args, decorate),
         Commands::Template(args) => cmd_template(cfg, args),
+        Commands::Replay(args) => cmd_replay(cfg, args),
+        Commands::Dirs(args) => cmd_dirs(cfg, args),
+        Commands::Describe(args) => cmd_describe(args),
         Commands::Version => {
             println!("sdbh {}", env!("CARGO_PKG_VERSION"));
             Ok(())
@@ -476,31 +1471,138 @@ pub fn run(cli: Cli) -> Result<()> {
     }
 }
 
-fn cmd_log(cfg: DbConfig, args: LogArgs) -> Result<()> {
+fn print_db_info(cfg: &DbConfig, db_source: &str) {
+    eprintln!("db: {} (from {db_source})", cfg.path.display());
+    for extra in &cfg.extra_paths {
+        eprintln!("db: {} (from --db)", extra.display());
+    }
+
+    match config_path() {
+        Some(path) if path.exists() => {
+            let status = match std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|text| toml::from_str::<ConfigFile>(&text).map_err(Into::into))
+            {
+                Ok(_) => "loaded".to_string(),
+                Err(e) => format!("ignored: {e}"),
+            };
+            eprintln!("config: {} ({})", path.display(), status.trim_end());
+        }
+        Some(path) => eprintln!("config: none ({} not found)", path.display()),
+        None => eprintln!("config: none (HOME not set)"),
+    }
+
+    match current_session() {
+        Some((salt, ppid)) => eprintln!("session: salt={salt} ppid={ppid}"),
+        None => eprintln!("session: none (SDBH_SALT/SDBH_PPID not set)"),
+    }
+}
+
+fn cmd_log(mut cfg: DbConfig, args: LogArgs, file_cfg: &ConfigFile) -> Result<()> {
+    let log_cfg = &file_cfg.log;
     if !args.no_filter {
-        let filter = LogFilter::load_default();
+        let filter = LogFilter::from_config(log_cfg);
         if filter.should_skip(&args.cmd) {
             return Ok(());
         }
     }
 
+    if args.no_hash {
+        cfg.track_hash = false;
+    }
+
     let mut conn = open_db(&cfg)?;
     ensure_hash_index(&conn)?;
 
+    // Spawning git on every prompt costs a few ms, so it is opt-in.
+    let gitroot = args
+        .gitroot
+        .or_else(|| log_cfg.track_gitroot.then(|| git_toplevel(&args.pwd))?);
+
     let row = HistoryRow {
         hist_id: args.hist_id,
-        cmd: args.cmd,
+        cmd: sanitize_nul(args.cmd, log_cfg.strip_nul),
         epoch: args.epoch,
         ppid: args.ppid,
         pwd: args.pwd,
         salt: args.salt,
+        exit_code: args.exit_code,
+        duration_ms: args.duration_ms,
+        host: args.host.or_else(default_host),
+        tty: args.tty.or_else(default_tty),
+        gitroot,
+        seq: args.seq,
+        env: capture_env(args.capture_env.iter().chain(&log_cfg.capture_env)),
     };
 
-    insert_history(&mut conn, &row)?;
+    insert_history(&mut conn, &row, &cfg)?;
+
+    if let Some(every) = file_cfg.db.auto_optimize_every.filter(|&n| n > 0) {
+        // Housekeeping must never make logging a command fail.
+        if let Err(e) = crate::db::tick_auto_optimize(&conn, every) {
+            eprintln!("sdbh: auto optimize skipped: {e:#}");
+        }
+    }
     Ok(())
 }
 
-#[derive(Debug, Default, serde::Deserialize)]
+/// JSON object of the named variables that are set in sdbh's environment (the hook
+/// runs `sdbh log` as a child of the shell, so that means exported variables), or
+/// `None` when there is nothing to record.
+fn capture_env<'a>(names: impl Iterator<Item = &'a String>) -> Option<String> {
+    let vars: std::collections::BTreeMap<&str, String> = names
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| Some((name, std::env::var(name).ok()?)))
+        .collect();
+    if vars.is_empty() {
+        return None;
+    }
+    serde_json::to_string(&vars).ok()
+}
+
+/// With `[log] strip_nul` (the default), replace NUL bytes, which SQLite keeps but
+/// C-string tooling and terminals choke on, with a visible `\0` marker.
+fn sanitize_nul(cmd: String, strip_nul: bool) -> String {
+    if strip_nul && cmd.contains('\0') {
+        cmd.replace('\0', "\\0")
+    } else {
+        cmd
+    }
+}
+
+fn default_host() -> Option<String> {
+    whoami::fallible::hostname().ok().filter(|h| !h.is_empty())
+}
+
+/// Top level of the git work tree containing `pwd`; `None` outside a repository
+/// or when git is unavailable.
+fn git_toplevel(pwd: &str) -> Option<String> {
+    let out = std::process::Command::new("git")
+        .args(["-C", pwd, "rev-parse", "--show-toplevel"])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let root = String::from_utf8(out.stdout).ok()?;
+    let root = root.trim_end_matches(['\n', '\r']);
+    (!root.is_empty()).then(|| root.to_string())
+}
+
+fn default_tty() -> Option<String> {
+    if let Some(tty) = std::env::var("SSH_TTY").ok().filter(|t| !t.is_empty()) {
+        return Some(tty);
+    }
+    // What ttyname(0) reports on Linux; elsewhere pass --tty from the hook.
+    std::fs::read_link("/proc/self/fd/0")
+        .ok()
+        .map(|p| p.to_string_lossy().into_owned())
+        .filter(|p| p.starts_with("/dev/") && p != "/dev/null")
+}
+
+#[derive(Debug, serde::Deserialize)]
 struct LogConfig {
     #[serde(default)]
     ignore_exact: Vec<String>,
@@ -508,14 +1610,45 @@ struct LogConfig {
     #[serde(default)]
     ignore_prefix: Vec<String>,
 
+    /// Skip commands matching any of these regexes (invalid ones are reported and ignored)
+    #[serde(default)]
+    ignore_regex: Vec<String>,
+
     #[serde(default = "default_true")]
     use_builtin_ignores: bool,
+
+    /// Record the git repository root of each command's pwd
+    #[serde(default)]
+    track_gitroot: bool,
+
+    /// Store NUL bytes in commands as the two characters `\0` (default: true)
+    #[serde(default = "default_true")]
+    strip_nul: bool,
+
+    /// Environment variables to record with every command (`log --capture-env` adds more)
+    #[serde(default)]
+    capture_env: Vec<String>,
 }
 
 fn default_true() -> bool {
     true
 }
 
+// Spelled out so a missing `[log]` table gets the same defaults as an empty one.
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            ignore_exact: vec![],
+            ignore_prefix: vec![],
+            ignore_regex: vec![],
+            use_builtin_ignores: true,
+            track_gitroot: false,
+            strip_nul: true,
+            capture_env: vec![],
+        }
+    }
+}
+
 #[derive(Debug, Default, serde::Deserialize)]
 struct ConfigFile {
     #[serde(default)]
@@ -523,29 +1656,109 @@ struct ConfigFile {
 
     #[serde(default)]
     fzf: FzfConfig,
-}
 
-#[derive(Debug, Default, serde::Deserialize)]
-struct FzfConfig {
-    /// Height of fzf window (e.g., "50%", "20")
-    height: Option<String>,
+    #[serde(default)]
+    preview: PreviewConfig,
 
-    /// Layout style ("default", "reverse")
-    layout: Option<String>,
+    #[serde(default)]
+    db: DbFileConfig,
 
-    /// Border style ("rounded", "sharp", "bold", "double", "block", "thinblock")
-    border: Option<String>,
+    #[serde(default)]
+    run: RunConfig,
 
-    /// Color scheme (fzf color string)
-    color: Option<String>,
+    #[serde(default)]
+    import: ImportConfig,
 
-    /// Color for header text
-    color_header: Option<String>,
+    #[serde(default)]
+    search: SearchConfig,
 
-    /// Color for pointer
-    color_pointer: Option<String>,
+    /// Alias -> canonical program (e.g. `g = "git"`), applied by `--canonical`
+    #[serde(default)]
+    alias: HashMap<String, String>,
+}
 
-    /// Color for marker
+#[derive(Debug, Default, serde::Deserialize)]
+struct DbFileConfig {
+    /// Dedup hash scheme: 1 (default) includes the shell hist_id, 2 ignores it
+    hash_version: Option<u32>,
+
+    /// Write a history_hash entry for every logged row (default: true)
+    track_hash: Option<bool>,
+
+    /// After every N logged commands, run `PRAGMA optimize` and create missing
+    /// indexes (unset or 0: never)
+    auto_optimize_every: Option<u32>,
+
+    /// Extra SQLite pragmas applied to every connection, e.g. `cache_size = -20000`
+    /// (limited to an allowlist, see `db::apply_pragmas`)
+    #[serde(default)]
+    pragmas: std::collections::BTreeMap<String, toml::Value>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct ImportConfig {
+    /// Skip lines that look like a password typed at the shell after a prompting
+    /// command such as `ssh` or `sudo` (see `looks_like_typed_secret`)
+    #[serde(default)]
+    drop_suspected_secrets: bool,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct SearchConfig {
+    /// Substrings always excluded by `list` and `search`, as if passed via `--exclude`
+    #[serde(default)]
+    default_exclude: Vec<String>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct RunConfig {
+    /// What to do when a stored command's pwd no longer exists
+    #[serde(default)]
+    missing_pwd: MissingPwdPolicy,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum MissingPwdPolicy {
+    /// Refuse to run the command
+    #[default]
+    Error,
+    /// Run it in the current directory instead
+    Cwd,
+    /// Run it in the closest parent of the recorded pwd that still exists
+    NearestExistingAncestor,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct PreviewConfig {
+    /// Fixed preview width in columns (overrides terminal detection)
+    width: Option<usize>,
+
+    /// Show the time-of-day histogram section (default: true)
+    show_hours: Option<bool>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct FzfConfig {
+    /// Height of fzf window (e.g., "50%", "20")
+    height: Option<String>,
+
+    /// Layout style ("default", "reverse")
+    layout: Option<String>,
+
+    /// Border style ("rounded", "sharp", "bold", "double", "block", "thinblock")
+    border: Option<String>,
+
+    /// Color scheme (fzf color string)
+    color: Option<String>,
+
+    /// Color for header text
+    color_header: Option<String>,
+
+    /// Color for pointer
+    color_pointer: Option<String>,
+
+    /// Color for marker
     color_marker: Option<String>,
 
     /// Preview window settings (e.g., "right:50%")
@@ -560,6 +1773,9 @@ struct FzfConfig {
 
     /// Custom fzf binary path
     binary_path: Option<String>,
+
+    /// Kill fzf after this many seconds without a selection (`--fzf-timeout` overrides)
+    timeout_secs: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -567,23 +1783,21 @@ struct LogFilter {
     use_builtin_ignores: bool,
     ignore_exact: Vec<String>,
     ignore_prefix: Vec<String>,
+    ignore_regex: Vec<regex::Regex>,
 }
 
 impl LogFilter {
     fn load_default() -> Self {
-        let mut filter = Self {
-            use_builtin_ignores: true,
-            ignore_exact: vec![],
-            ignore_prefix: vec![],
-        };
+        Self::from_config(&load_log_config())
+    }
 
-        if let Some(cfg) = load_config_file() {
-            filter.use_builtin_ignores = cfg.log.use_builtin_ignores;
-            filter.ignore_exact = cfg.log.ignore_exact;
-            filter.ignore_prefix = cfg.log.ignore_prefix;
+    fn from_config(cfg: &LogConfig) -> Self {
+        Self {
+            use_builtin_ignores: cfg.use_builtin_ignores,
+            ignore_exact: cfg.ignore_exact.clone(),
+            ignore_prefix: cfg.ignore_prefix.clone(),
+            ignore_regex: compile_ignore_regexes(&cfg.ignore_regex),
         }
-
-        filter
     }
 
     fn should_skip(&self, cmd: &str) -> bool {
@@ -607,16 +1821,36 @@ impl LogFilter {
             }
         }
 
+        if self.ignore_regex.iter().any(|re| re.is_match(trimmed)) {
+            return true;
+        }
+
         false
     }
 }
 
+/// Compile `[log] ignore_regex`. A bad pattern must not stop commands from being
+/// logged, so it is reported on stderr and left out.
+fn compile_ignore_regexes(patterns: &[String]) -> Vec<regex::Regex> {
+    patterns
+        .iter()
+        .filter_map(|pattern| match regex::Regex::new(pattern) {
+            Ok(re) => Some(re),
+            Err(e) => {
+                eprintln!("sdbh: ignoring invalid [log] ignore_regex {pattern:?}: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// `~/.sdbh.toml`, or `$XDG_CONFIG_HOME/sdbh/config.toml` when that is set and no
+/// legacy config exists.
 fn config_path() -> Option<std::path::PathBuf> {
-    // User-requested location: ~/.sdbh.toml
-    let home = std::env::var_os("HOME").or_else(|| dirs::home_dir().map(|p| p.into_os_string()))?;
-    let mut p = std::path::PathBuf::from(home);
-    p.push(".sdbh.toml");
-    Some(p)
+    let home = std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .or_else(dirs::home_dir);
+    crate::domain::resolve_home_file(home, ".sdbh.toml", "XDG_CONFIG_HOME", "config.toml")
 }
 
 fn load_config_file() -> Option<ConfigFile> {
@@ -625,10 +1859,136 @@ fn load_config_file() -> Option<ConfigFile> {
     toml::from_str::<ConfigFile>(&text).ok()
 }
 
+fn load_log_config() -> LogConfig {
+    load_config_file().map(|cfg| cfg.log).unwrap_or_default()
+}
+
+fn load_search_config() -> SearchConfig {
+    load_config_file().map(|cfg| cfg.search).unwrap_or_default()
+}
+
+fn load_import_config() -> ImportConfig {
+    load_config_file().map(|cfg| cfg.import).unwrap_or_default()
+}
+
 fn load_fzf_config() -> FzfConfig {
     load_config_file().map(|cfg| cfg.fzf).unwrap_or_default()
 }
 
+fn load_preview_config() -> PreviewConfig {
+    load_config_file()
+        .map(|cfg| cfg.preview)
+        .unwrap_or_default()
+}
+
+fn load_run_config() -> RunConfig {
+    load_config_file().map(|cfg| cfg.run).unwrap_or_default()
+}
+
+/// Directory to run a stored command in, applying `[run] missing_pwd` when the
+/// recorded pwd has been deleted (e.g. a removed worktree).
+fn resolve_run_dir(pwd: &std::path::Path, policy: MissingPwdPolicy) -> Result<PathBuf> {
+    if pwd.is_dir() {
+        return Ok(pwd.to_path_buf());
+    }
+    match policy {
+        MissingPwdPolicy::Error => anyhow::bail!(
+            "recorded directory {} no longer exists (see [run] missing_pwd in ~/.sdbh.toml)",
+            pwd.display()
+        ),
+        MissingPwdPolicy::Cwd => Ok(std::env::current_dir()?),
+        MissingPwdPolicy::NearestExistingAncestor => match nearest_existing_ancestor(pwd) {
+            Some(dir) => Ok(dir),
+            None => Ok(std::env::current_dir()?),
+        },
+    }
+}
+
+/// Closest directory at or above `path` that exists; `None` for a relative path
+/// with no existing prefix.
+fn nearest_existing_ancestor(path: &std::path::Path) -> Option<PathBuf> {
+    path.ancestors()
+        .find(|p| !p.as_os_str().is_empty() && p.is_dir())
+        .map(|p| p.to_path_buf())
+}
+
+fn load_alias_config() -> HashMap<String, String> {
+    load_config_file().map(|cfg| cfg.alias).unwrap_or_default()
+}
+
+/// fzf's own exit status for an interrupted selection; a timed-out run exits with it too.
+const FZF_CANCELLED: i32 = 130;
+
+/// How an fzf run ended.
+#[derive(Debug)]
+enum FzfRun {
+    Finished(std::process::Output),
+    /// Killed after `--fzf-timeout` / `[fzf] timeout_secs`
+    TimedOut,
+}
+
+impl FzfRun {
+    /// fzf's output, or `None` if it was killed at the timeout.
+    fn into_output(self) -> Option<std::process::Output> {
+        match self {
+            FzfRun::Finished(output) => Some(output),
+            FzfRun::TimedOut => None,
+        }
+    }
+}
+
+/// Feed `input` to fzf and collect its output, killing it after `timeout_secs`.
+fn run_fzf(
+    fzf_cmd: &mut std::process::Command,
+    input: &[u8],
+    timeout_secs: Option<u64>,
+) -> Result<FzfRun> {
+    fzf_cmd
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped());
+    let mut child = fzf_cmd.spawn()?;
+
+    let Some(secs) = timeout_secs else {
+        if let Some(mut stdin) = child.stdin.take() {
+            std::io::Write::write_all(&mut stdin, input)?;
+        }
+        return Ok(FzfRun::Finished(child.wait_with_output()?));
+    };
+
+    // A hung fzf may never drain its pipes, so feed and read them off-thread while
+    // polling for exit. Write errors just mean fzf went away early.
+    if let Some(mut stdin) = child.stdin.take() {
+        let input = input.to_vec();
+        std::thread::spawn(move || std::io::Write::write_all(&mut stdin, &input));
+    }
+    let mut stdout = child.stdout.take().context("fzf stdout not captured")?;
+    let reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        std::io::Read::read_to_end(&mut stdout, &mut buf).map(|_| buf)
+    });
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(secs);
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if std::time::Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Ok(FzfRun::TimedOut);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    };
+    let stdout = reader
+        .join()
+        .map_err(|_| anyhow::anyhow!("fzf output reader panicked"))??;
+    Ok(FzfRun::Finished(std::process::Output {
+        status,
+        stdout,
+        stderr: vec![],
+    }))
+}
+
 fn build_fzf_command(base_cmd: &mut std::process::Command, fzf_config: &FzfConfig) {
     // Apply configuration options to the fzf command
 
@@ -724,15 +2084,40 @@ fn is_builtin_noisy_command(cmd: &str) -> bool {
     false
 }
 
-fn session_filter(session_only: bool) -> Option<(i64, i64)> {
-    if session_only {
-        // Filter to current session only
-        let salt = std::env::var("SDBH_SALT").ok()?.parse::<i64>().ok()?;
-        let ppid = std::env::var("SDBH_PPID").ok()?.parse::<i64>().ok()?;
-        Some((salt, ppid))
-    } else {
-        // No session filtering (show all sessions)
-        None
+/// The shell hook's session identity from `SDBH_SALT`/`SDBH_PPID`, if both are set.
+fn current_session() -> Option<(i64, i64)> {
+    let salt = std::env::var("SDBH_SALT").ok()?.parse::<i64>().ok()?;
+    let ppid = std::env::var("SDBH_PPID").ok()?.parse::<i64>().ok()?;
+    Some((salt, ppid))
+}
+
+/// `(salt, ppid)` to filter on for `--session`; an error rather than unfiltered
+/// output when the hook's environment is missing.
+fn session_filter(session_only: bool) -> Result<Option<(i64, i64)>> {
+    if !session_only {
+        return Ok(None);
+    }
+    match current_session() {
+        Some(session) => Ok(Some(session)),
+        None => anyhow::bail!(
+            "--session requires SDBH_SALT and SDBH_PPID to be set; is the shell hook installed?"
+        ),
+    }
+}
+
+/// One `cmd NOT LIKE` clause per `--exclude` substring.
+fn push_excludes(sql: &mut String, bind: &mut Vec<String>, excludes: &[String]) {
+    for exclude in excludes {
+        sql.push_str("AND cmd NOT LIKE ? ESCAPE '\\' ");
+        bind.push(format!("%{}%", escape_like(exclude)));
+    }
+}
+
+/// `--pwd-contains`: a substring match anywhere in `pwd`; composes with --here/--under.
+fn push_pwd_contains(sql: &mut String, bind: &mut Vec<String>, pwd_contains: &Option<String>) {
+    if let Some(substr) = pwd_contains {
+        sql.push_str("AND pwd LIKE ? ESCAPE '\\' ");
+        bind.push(format!("%{}%", escape_like(substr)));
     }
 }
 
@@ -752,6 +2137,57 @@ fn location_filter(
     Some((pwd, under))
 }
 
+/// Chronological row order. `seq` only means something within one session, so rows
+/// sharing an epoch are grouped by session before it breaks the tie.
+const CHRONOLOGICAL_SQL: &str = "epoch ASC, salt ASC, ppid ASC, seq ASC, id ASC";
+/// [`CHRONOLOGICAL_SQL`], newest first.
+const NEWEST_FIRST_SQL: &str = "epoch DESC, salt DESC, ppid DESC, seq DESC, id DESC";
+
+/// First whitespace-delimited word of `cmd`, as an SQL expression.
+const LEADING_TOKEN_SQL: &str =
+    "(CASE WHEN instr(cmd, ' ') > 0 THEN substr(cmd, 1, instr(cmd, ' ') - 1) ELSE cmd END)";
+
+/// SQL expression used as the grouping key for a command.
+///
+/// With `aliases`, the leading token is rewritten to its canonical program at
+/// query time (`g status` groups with `git status`); stored rows stay raw.
+/// With `by_program`, only the (canonicalized) leading token is kept.
+fn command_key_sql(
+    aliases: &HashMap<String, String>,
+    by_program: bool,
+    bind: &mut Vec<String>,
+) -> String {
+    let mut names: Vec<&String> = aliases.keys().collect();
+    names.sort();
+
+    if names.is_empty() {
+        return if by_program {
+            LEADING_TOKEN_SQL.to_string()
+        } else {
+            "cmd".to_string()
+        };
+    }
+
+    let mut expr = format!("(CASE {LEADING_TOKEN_SQL} ");
+    for name in names {
+        bind.push(name.clone());
+        bind.push(aliases[name].clone());
+        if by_program {
+            expr.push_str("WHEN ? THEN ? ");
+        } else {
+            expr.push_str(&format!(
+                "WHEN ? THEN ? || substr(cmd, length({LEADING_TOKEN_SQL}) + 1) "
+            ));
+        }
+    }
+    if by_program {
+        expr.push_str(&format!("ELSE {LEADING_TOKEN_SQL} END)"));
+    } else {
+        expr.push_str("ELSE cmd END)");
+    }
+    expr
+}
+
 fn cmd_summary(cfg: DbConfig, args: SummaryArgs) -> Result<()> {
     // Check if multi_select was requested but not fzf
     if args.multi_select && !args.fzf {
@@ -769,35 +2205,59 @@ fn cmd_summary(cfg: DbConfig, args: SummaryArgs) -> Result<()> {
         eprintln!("db: {}", cfg.path.display());
         eprintln!("sql: {}", sql);
     }
+    if args.count {
+        return print_count(&conn, &sql, &bind, false, args.status);
+    }
 
     let mut stmt = conn.prepare(&sql)?;
 
     let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    if !args.porcelain && want_headers(args.headers, args.no_headers) {
+        let cmd_header = if args.pwd { "PWD > CMD" } else { "CMD" };
+        write_table_header(
+            &mut out,
+            &format!(
+                "{:>6} | {:<19} | {:>6} | {cmd_header}",
+                "ID", "LAST SEEN", "COUNT"
+            ),
+        )?;
+    }
+    let mut shown = 0usize;
     while let Some(r) = rows.next()? {
+        shown += 1;
         let id_max: i64 = r.get(0)?;
         let dt: String = r.get(1)?;
         let count: i64 = r.get(2)?;
         let cmd: String = r.get(3)?;
-        if args.pwd {
+        if args.porcelain {
+            let pwd: String = if args.pwd { r.get(4)? } else { String::new() };
+            let last_epoch: i64 = r.get(if args.pwd { 5 } else { 4 })?;
+            write_porcelain_row(&mut out, id_max, last_epoch, &pwd, &cmd)?;
+        } else if args.pwd {
             let pwd: String = r.get(4)?;
-            println!(
+            writeln!(
+                out,
                 "{id:>6} | {dt} | {count:>6} | {pwd} > {cmd}",
                 id = id_max,
                 dt = dt,
                 count = count,
                 pwd = pwd,
                 cmd = cmd
-            );
+            )?;
         } else {
-            println!(
+            writeln!(
+                out,
                 "{id:>6} | {dt} | {count:>6} | {cmd}",
                 id = id_max,
                 dt = dt,
                 count = count,
                 cmd = cmd
-            );
+            )?;
         }
     }
+    out.flush()?;
+    exit_if_empty(args.status, shown);
 
     Ok(())
 }
@@ -805,21 +2265,34 @@ fn cmd_summary(cfg: DbConfig, args: SummaryArgs) -> Result<()> {
 fn build_summary_sql(args: &SummaryArgs) -> Result<(String, Vec<String>)> {
     let mut bind: Vec<String> = vec![];
 
-    let mut select = String::from(
-        "SELECT max(id) as mid, datetime(max(epoch), 'unixepoch', 'localtime') as dt, count(*) as cnt, cmd",
+    let aliases = if args.canonical {
+        load_alias_config()
+    } else {
+        HashMap::new()
+    };
+    let key = command_key_sql(&aliases, false, &mut bind);
+
+    let mut select = format!(
+        "SELECT max(id) as mid, datetime(max(epoch), 'unixepoch', 'localtime') as dt, count(*) as cnt, {key} as cmd_key",
     );
     if args.pwd {
         select.push_str(", pwd");
     }
+    select.push_str(", max(epoch) as last_epoch");
 
     let mut sql = format!("{select} FROM history WHERE 1=1 ");
 
-    if let Some((salt, ppid)) = session_filter(args.session) {
+    if let Some((salt, ppid)) = session_filter(args.session)? {
         sql.push_str("AND salt=? AND ppid=? ");
         bind.push(salt.to_string());
         bind.push(ppid.to_string());
     }
 
+    if let Some(host) = &args.host {
+        sql.push_str("AND host = ? ");
+        bind.push(host.clone());
+    }
+
     if let Some(q) = &args.query {
         let like = if args.starts {
             format!("{}%", q)
@@ -842,86 +2315,254 @@ fn build_summary_sql(args: &SummaryArgs) -> Result<(String, Vec<String>)> {
         }
     }
 
-    sql.push_str("GROUP BY cmd ");
+    push_pwd_contains(&mut sql, &mut bind, &args.pwd_contains);
+
+    sql.push_str("GROUP BY cmd_key ");
     if args.pwd {
         sql.push_str(", pwd ");
     }
 
     sql.push_str("ORDER BY max(id) DESC ");
     sql.push_str("LIMIT ?");
-    let limit = if args.all { u32::MAX } else { args.limit };
+    let limit = if args.all || args.count {
+        u32::MAX
+    } else {
+        args.limit
+    };
     bind.push(limit.to_string());
 
     Ok((sql, bind))
 }
 
-fn cmd_list(cfg: DbConfig, args: ListArgs) -> Result<()> {
-    if args.fzf {
+/// `id, dt, pwd, cmd` of a list/search table row.
+type TableRow = (i64, String, String, String);
+
+fn cmd_list(cfg: DbConfig, mut args: ListArgs) -> Result<()> {
+    args.exclude.extend(load_search_config().default_exclude);
+    // --multi-select implies --fzf
+    if args.fzf || args.multi_select {
         return cmd_list_fzf(cfg, args);
     }
 
+    if args.group_session && !matches!(args.format, OutputFormat::Table) {
+        anyhow::bail!("--group-session only supports table output");
+    }
+
     let conn = open_db(&cfg)?;
     let (sql, bind) = build_list_sql(&args)?;
+    if args.count {
+        let json = matches!(args.format, OutputFormat::Json);
+        return print_count(&conn, &sql, &bind, json, args.status);
+    }
 
     let mut stmt = conn.prepare(&sql)?;
     let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+    // `list --all` can be huge: buffer, and let a closed pipe surface as an error.
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+
+    // Display-only filter: the query still returns every run.
+    let mut prev_cmd: Option<String> = None;
+    let mut is_adjacent_dup = |cmd: &str| {
+        let dup = args.dedup_adjacent && prev_cmd.as_deref() == Some(cmd);
+        prev_cmd = Some(cmd.to_string());
+        dup
+    };
+    let mut shown = 0usize;
 
     match args.format {
+        _ if args.porcelain => {
+            while let Some(r) = rows.next()? {
+                let id: i64 = r.get(0)?;
+                let epoch: i64 = r.get(4)?;
+                let pwd: String = r.get(2)?;
+                let cmd: String = r.get(3)?;
+                if is_adjacent_dup(&cmd) {
+                    continue;
+                }
+                shown += 1;
+                write_porcelain_row(&mut out, id, epoch, &pwd, &cmd)?;
+            }
+        }
+        OutputFormat::Table if args.group_session => {
+            if want_headers(args.headers, args.no_headers) {
+                write_table_header(&mut out, HISTORY_TABLE_HEADER)?;
+            }
+            // Buffered so each header can show its session's time span.
+            let mut sessions: Vec<((i64, i64), Vec<TableRow>)> = vec![];
+            while let Some(r) = rows.next()? {
+                let key = (r.get(6)?, r.get(7)?);
+                let row: TableRow = (r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?);
+                match sessions.last_mut() {
+                    // Repeats are dropped here so the header counts what is shown
+                    Some((last, rows)) if *last == key => {
+                        if !(args.dedup_adjacent && rows.last().is_some_and(|p| p.3 == row.3)) {
+                            rows.push(row);
+                        }
+                    }
+                    _ => sessions.push((key, vec![row])),
+                }
+            }
+            for (i, ((salt, ppid), rows)) in sessions.iter().enumerate() {
+                if i > 0 {
+                    writeln!(out)?;
+                }
+                let (first, last) = (&rows[0].1, &rows[rows.len() - 1].1);
+                writeln!(
+                    out,
+                    "── session salt={salt} ppid={ppid} · {first} – {last} · {} command(s)",
+                    rows.len()
+                )?;
+                for (id, dt, pwd, cmd) in rows {
+                    shown += 1;
+                    writeln!(out, "{id:>6} | {dt} | {pwd} | {cmd}")?;
+                }
+            }
+        }
         OutputFormat::Table => {
+            if want_headers(args.headers, args.no_headers) {
+                write_table_header(&mut out, HISTORY_TABLE_HEADER)?;
+            }
             while let Some(r) = rows.next()? {
                 let id: i64 = r.get(0)?;
                 let dt: String = r.get(1)?;
                 let pwd: String = r.get(2)?;
                 let cmd: String = r.get(3)?;
-                println!("{id:>6} | {dt} | {pwd} | {cmd}");
+                if is_adjacent_dup(&cmd) {
+                    continue;
+                }
+                shown += 1;
+                writeln!(out, "{id:>6} | {dt} | {pwd} | {cmd}")?;
             }
         }
         OutputFormat::Json => {
-            // Minimal JSON without serde_json dependency for now.
-            // (We can add serde_json later.)
-            print!("[");
+            write!(out, "[")?;
             let mut first = true;
+            while let Some(r) = rows.next()? {
+                let record = history_record(r, args.iso_dates, args.verbose_json)?;
+                if is_adjacent_dup(&record.cmd) {
+                    continue;
+                }
+                shown += 1;
+
+                if !first {
+                    write!(out, ",")?;
+                }
+                first = false;
+                serde_json::to_writer(&mut out, &record)?;
+            }
+            writeln!(out, "]")?;
+        }
+        format @ (OutputFormat::Csv | OutputFormat::Tsv) => {
+            if !args.no_headers {
+                writeln!(out, "{}", history_delimited_header(format, args.iso_dates))?;
+            }
             while let Some(r) = rows.next()? {
                 let id: i64 = r.get(0)?;
                 let epoch: i64 = r.get(4)?;
                 let pwd: String = r.get(2)?;
                 let cmd: String = r.get(3)?;
-
-                if !first {
-                    print!(",");
+                if is_adjacent_dup(&cmd) {
+                    continue;
                 }
-                first = false;
-                print!(
-                    "{{\"id\":{},\"epoch\":{},\"pwd\":{},\"cmd\":{}}}",
-                    id,
-                    epoch,
-                    json_string(&pwd),
-                    json_string(&cmd)
-                );
+                shown += 1;
+                writeln!(
+                    out,
+                    "{}",
+                    history_delimited_row(format, id, epoch, args.iso_dates, &pwd, &cmd)
+                )?;
             }
-            println!("]");
         }
     }
 
+    out.flush()?;
+
+    if args.show_count {
+        eprintln!("{shown} results");
+    }
+    exit_if_empty(args.status, shown);
+
     Ok(())
 }
 
+/// Columns read by the `list`/`search` printers; `session_fields` appends
+/// `hist_id, salt, ppid` (columns 5-7) for `--verbose-json`.
+fn history_columns(session_fields: bool) -> &'static str {
+    if session_fields {
+        "id, datetime(epoch, 'unixepoch', 'localtime') as dt, pwd, cmd, epoch, hist_id, salt, ppid"
+    } else {
+        "id, datetime(epoch, 'unixepoch', 'localtime') as dt, pwd, cmd, epoch"
+    }
+}
+
+/// hist_id/salt/ppid for a row selected with `history_columns(true)`, or `None`
+/// without `--verbose-json`.
+fn session_fields(
+    r: &rusqlite::Row,
+    verbose_json: bool,
+) -> rusqlite::Result<Option<SessionFields>> {
+    if !verbose_json {
+        return Ok(None);
+    }
+    Ok(Some(SessionFields {
+        hist_id: r.get(5)?,
+        salt: r.get(6)?,
+        ppid: r.get(7)?,
+    }))
+}
+
+/// `HistoryRecord` for an `id, dt, pwd, cmd, epoch[, session]` row.
+fn history_record(
+    r: &rusqlite::Row,
+    iso_dates: bool,
+    verbose_json: bool,
+) -> rusqlite::Result<HistoryRecord> {
+    let epoch: i64 = r.get(4)?;
+    Ok(HistoryRecord {
+        id: r.get(0)?,
+        epoch,
+        iso: iso_dates.then(|| iso_datetime(epoch)),
+        pwd: r.get(2)?,
+        cmd: r.get(3)?,
+        session: session_fields(r, verbose_json)?,
+        details: None,
+    })
+}
+
 fn build_list_sql(args: &ListArgs) -> Result<(String, Vec<String>)> {
     let mut bind: Vec<String> = vec![];
-    let mut sql = String::from(
-        "SELECT id, datetime(epoch, 'unixepoch', 'localtime') as dt, pwd, cmd, epoch FROM history WHERE 1=1 ",
-    );
+    // --unique: with a single max() aggregate, SQLite fills the other columns
+    // from the row holding it, i.e. each command's latest run.
+    let mut sql = if args.unique {
+        String::from("SELECT *, max(epoch) FROM history WHERE 1=1 ")
+    } else {
+        format!(
+            "SELECT {} FROM history WHERE 1=1 ",
+            history_columns(args.verbose_json || args.group_session)
+        )
+    };
 
-    if let Some((salt, ppid)) = session_filter(args.session) {
+    if let Some((salt, ppid)) = session_filter(args.session)? {
         sql.push_str("AND salt=? AND ppid=? ");
         bind.push(salt.to_string());
         bind.push(ppid.to_string());
     }
 
+    if let Some(host) = &args.host {
+        sql.push_str("AND host = ? ");
+        bind.push(host.clone());
+    }
+
+    push_date_range(&mut sql, &mut bind, &args.since, &args.until)?;
+
     if let Some(q) = &args.query {
         sql.push_str("AND cmd LIKE ? ESCAPE '\\' ");
         bind.push(escape_like(&format!("%{}%", q)));
     }
+    push_excludes(&mut sql, &mut bind, &args.exclude);
+
+    if args.failed_only {
+        sql.push_str("AND exit_code != 0 ");
+    }
 
     if let Some((pwd, under)) = location_filter(args.here, args.under, &args.pwd_override) {
         if under {
@@ -933,74 +2574,256 @@ fn build_list_sql(args: &ListArgs) -> Result<(String, Vec<String>)> {
         }
     }
 
-    sql.push_str("ORDER BY epoch ASC, id ASC ");
+    push_pwd_contains(&mut sql, &mut bind, &args.pwd_contains);
+
+    if args.unique {
+        sql = format!(
+            "SELECT {} FROM ({sql}GROUP BY cmd) ",
+            history_columns(args.verbose_json)
+        );
+    }
+
+    if args.by_inserted {
+        sql.push_str("ORDER BY inserted_at ASC, id ASC ");
+    } else if args.group_session {
+        // Sessions by when they started, so --limit keeps the earliest ones whole
+        sql.push_str(
+            "ORDER BY min(epoch) OVER (PARTITION BY salt, ppid) ASC, \
+             salt ASC, ppid ASC, epoch ASC, seq ASC, id ASC ",
+        );
+    } else {
+        sql.push_str(&format!("ORDER BY {CHRONOLOGICAL_SQL} "));
+    }
     sql.push_str("LIMIT ? OFFSET ?");
-    let limit = if args.all { u32::MAX } else { args.limit };
+    let limit = if args.all || args.count {
+        u32::MAX
+    } else {
+        args.limit
+    };
     bind.push(limit.to_string());
     bind.push(args.offset.to_string());
 
     Ok((sql, bind))
 }
 
-fn cmd_search(cfg: DbConfig, args: SearchArgs) -> Result<()> {
-    if args.fzf {
+fn cmd_search(cfg: DbConfig, mut args: SearchArgs) -> Result<()> {
+    args.exclude.extend(load_search_config().default_exclude);
+    // --multi-select implies --fzf
+    if args.fzf || args.multi_select {
         return cmd_search_fzf(cfg, args);
     }
 
     let conn = open_db(&cfg)?;
 
-    let (sql, bind) = build_search_sql(&args)?;
+    let (sql, bind) = match (args.around_id, &args.near) {
+        (_, Some(query)) => {
+            let anchor: Option<i64> = conn
+                .query_row(
+                    "SELECT epoch FROM history WHERE cmd LIKE ?1 ESCAPE '\\' \
+                     ORDER BY epoch DESC, id DESC LIMIT 1",
+                    [format!("%{}%", escape_like(query))],
+                    |r| r.get(0),
+                )
+                .optional()?;
+            let Some(epoch) = anchor else {
+                anyhow::bail!("no history entry matches {query:?}");
+            };
+            build_near_sql(epoch, args.window, args.verbose_json)
+        }
+        (Some(id), None) => {
+            let found: bool = conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM history WHERE id = ?1)",
+                [id],
+                |r| r.get(0),
+            )?;
+            if !found {
+                anyhow::bail!("no history entry with id {id}");
+            }
+            build_around_sql(id, args.context, args.verbose_json)
+        }
+        (None, None) => build_search_sql(&args, crate::db::has_fts(&conn)?)?,
+    };
     // Debugging aid: enable with SDBH_DEBUG=1
     if std::env::var("SDBH_DEBUG").ok().as_deref() == Some("1") {
         eprintln!("sql: {sql}");
         eprintln!("bind: {:?}", bind);
     }
+    if args.count {
+        let json = matches!(args.format, OutputFormat::Json);
+        return print_count(&conn, &sql, &bind, json, args.status);
+    }
 
     let mut stmt = conn.prepare(&sql)?;
-    let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+    let mut hits = stmt
+        .query_map(rusqlite::params_from_iter(bind.iter()), |r| {
+            Ok(SearchHit {
+                id: r.get(0)?,
+                dt: r.get(1)?,
+                pwd: r.get(2)?,
+                cmd: r.get(3)?,
+                epoch: r.get(4)?,
+                session: session_fields(r, args.verbose_json)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if args.sort != SortMode::Recency {
+        sort_hits(
+            &mut hits,
+            args.sort,
+            args.query.as_deref().unwrap_or_default(),
+        );
+        if !args.all {
+            hits.truncate(args.limit as usize);
+        }
+    }
+
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+
+    // Display-only filter: the query still returns every run.
+    let mut prev_cmd: Option<String> = None;
+    let mut is_adjacent_dup = |cmd: &str| {
+        let dup = args.dedup_adjacent && prev_cmd.as_deref() == Some(cmd);
+        prev_cmd = Some(cmd.to_string());
+        dup
+    };
+    let mut shown = 0usize;
 
     match args.format {
+        _ if args.porcelain => {
+            for h in &hits {
+                if is_adjacent_dup(&h.cmd) {
+                    continue;
+                }
+                shown += 1;
+                write_porcelain_row(&mut out, h.id, h.epoch, &h.pwd, &h.cmd)?;
+            }
+        }
         OutputFormat::Table => {
-            while let Some(r) = rows.next()? {
-                let id: i64 = r.get(0)?;
-                let dt: String = r.get(1)?;
-                let pwd: String = r.get(2)?;
-                let cmd: String = r.get(3)?;
-                println!("{id:>6} | {dt} | {pwd} | {cmd}");
+            if want_headers(args.headers, args.no_headers) {
+                write_table_header(&mut out, HISTORY_TABLE_HEADER)?;
+            }
+            for h in &hits {
+                if is_adjacent_dup(&h.cmd) {
+                    continue;
+                }
+                shown += 1;
+                writeln!(out, "{:>6} | {} | {} | {}", h.id, h.dt, h.pwd, h.cmd)?;
             }
         }
         OutputFormat::Json => {
-            print!("[");
+            write!(out, "[")?;
             let mut first = true;
-            while let Some(r) = rows.next()? {
-                let id: i64 = r.get(0)?;
-                let epoch: i64 = r.get(4)?;
-                let pwd: String = r.get(2)?;
-                let cmd: String = r.get(3)?;
+            for h in &hits {
+                if is_adjacent_dup(&h.cmd) {
+                    continue;
+                }
+                shown += 1;
 
                 if !first {
-                    print!(",");
+                    write!(out, ",")?;
                 }
                 first = false;
-                print!(
-                    "{{\"id\":{},\"epoch\":{},\"pwd\":{},\"cmd\":{}}}",
-                    id,
-                    epoch,
-                    json_string(&pwd),
-                    json_string(&cmd)
-                );
+                let record = HistoryRecord {
+                    id: h.id,
+                    epoch: h.epoch,
+                    iso: args.iso_dates.then(|| iso_datetime(h.epoch)),
+                    pwd: h.pwd.clone(),
+                    cmd: h.cmd.clone(),
+                    session: h.session.clone(),
+                    details: None,
+                };
+                serde_json::to_writer(&mut out, &record)?;
+            }
+            writeln!(out, "]")?;
+        }
+        format @ (OutputFormat::Csv | OutputFormat::Tsv) => {
+            if !args.no_headers {
+                writeln!(out, "{}", history_delimited_header(format, args.iso_dates))?;
+            }
+            for h in &hits {
+                if is_adjacent_dup(&h.cmd) {
+                    continue;
+                }
+                shown += 1;
+                writeln!(
+                    out,
+                    "{}",
+                    history_delimited_row(format, h.id, h.epoch, args.iso_dates, &h.pwd, &h.cmd)
+                )?;
             }
-            println!("]");
         }
     }
 
+    out.flush()?;
+
+    if args.show_count {
+        eprintln!("{shown} results");
+    }
+    exit_if_empty(args.status, shown);
+
     Ok(())
 }
 
-fn build_search_sql(args: &SearchArgs) -> Result<(String, Vec<String>)> {
+/// One `search` result row, collected so `--sort` can reorder it.
+struct SearchHit {
+    id: i64,
+    dt: String,
+    pwd: String,
+    cmd: String,
+    epoch: i64,
+    /// `--verbose-json` fields (`None` without the flag)
+    session: Option<SessionFields>,
+}
+
+/// How closely `cmd` matches `query`, case-insensitively: 0 exact, 1 prefix,
+/// 2 at a word boundary, 3 anywhere else (or not at all, e.g. with --invert).
+fn relevance_tier(cmd: &str, query: &str) -> u8 {
+    let cmd = cmd.to_lowercase();
+    let query = query.to_lowercase();
+    if cmd == query {
+        0
+    } else if cmd.starts_with(&query) {
+        1
+    } else if cmd
+        .match_indices(&query)
+        .any(|(i, _)| !cmd[..i].ends_with(|c: char| c.is_alphanumeric()))
+    {
+        2
+    } else {
+        3
+    }
+}
+
+/// Reorder recency-ordered `hits` for `--sort`. Both sorts are stable, so ties
+/// keep the most recent first.
+fn sort_hits(hits: &mut [SearchHit], mode: SortMode, query: &str) {
+    match mode {
+        SortMode::Recency => {}
+        SortMode::Relevance => hits.sort_by_key(|h| relevance_tier(&h.cmd, query)),
+        SortMode::Frequency => {
+            let mut counts: HashMap<String, usize> = HashMap::new();
+            for h in hits.iter() {
+                *counts.entry(h.cmd.clone()).or_default() += 1;
+            }
+            hits.sort_by_key(|h| std::cmp::Reverse(counts[&h.cmd]));
+        }
+    }
+}
+
+/// FTS5 phrase for `query` when the trigram index gives the same rows as LIKE:
+/// at least three characters (trigram minimum), ASCII only (LIKE folds ASCII case
+/// only), and no LIKE wildcard or escape characters.
+fn fts_phrase(query: &str) -> Option<String> {
+    let usable = query.len() >= 3 && query.is_ascii() && !query.contains(['%', '_', '\\']);
+    usable.then(|| format!("\"{}\"", query.replace('"', "\"\"")))
+}
+
+fn build_search_sql(args: &SearchArgs, fts: bool) -> Result<(String, Vec<String>)> {
+    let query = args.query.as_deref().unwrap_or_default();
     let mut bind: Vec<String> = vec![];
-    let mut sql = String::from(
-        "SELECT id, datetime(epoch, 'unixepoch', 'localtime') as dt, pwd, cmd, epoch FROM history WHERE 1=1 ",
+    let mut sql = format!(
+        "SELECT {} FROM history WHERE 1=1 ",
+        history_columns(args.verbose_json)
     );
 
     // Optional time filtering
@@ -1011,23 +2834,332 @@ fn build_search_sql(args: &SearchArgs) -> Result<(String, Vec<String>)> {
         sql.push_str("AND epoch >= ? ");
         bind.push(days_cutoff_epoch(days).to_string());
     }
+    push_date_range(&mut sql, &mut bind, &args.since, &args.until)?;
 
     // WORKAROUND: In some SQLite builds / PRAGMA settings, `COLLATE NOCASE` can behave
     // unexpectedly with LIKE. Instead we normalize both sides with lower(), which is
     // deterministic for ASCII (our common use case) and matches our tests.
     // Note: the query string is lowercased for binding below.
 
-    if let Some((salt, ppid)) = session_filter(args.session) {
+    if let Some((salt, ppid)) = session_filter(args.session)? {
         sql.push_str("AND salt=? AND ppid=? ");
         bind.push(salt.to_string());
         bind.push(ppid.to_string());
     }
 
+    if let Some(host) = &args.host {
+        sql.push_str("AND host = ? ");
+        bind.push(host.clone());
+    }
+
     // Case-insensitive substring match.
     // Use a NOCASE collation on the command column rather than applying lower()
     // to avoid surprises with expression collation + LIKE in some SQLite builds.
+    // With `db reindex-fts`, route through the trigram index instead of a table scan.
+    if let Some(phrase) = fts.then(|| fts_phrase(query)).flatten() {
+        let not = if args.invert { "NOT " } else { "" };
+        sql.push_str(&format!(
+            "AND id {not}IN (SELECT rowid FROM history_fts WHERE history_fts MATCH ?) "
+        ));
+        bind.push(phrase);
+    } else {
+        if args.invert {
+            sql.push_str("AND cmd NOT LIKE ? ESCAPE '\\' ");
+        } else {
+            sql.push_str("AND cmd LIKE ? ESCAPE '\\' ");
+        }
+        // Do NOT escape the surrounding wildcards; only escape user-provided text.
+        bind.push(format!("%{}%", escape_like(query)));
+    }
+    push_excludes(&mut sql, &mut bind, &args.exclude);
+
+    if args.failed_only {
+        sql.push_str("AND exit_code != 0 ");
+    }
+
+    if let Some((pwd, under)) = location_filter(args.here, args.under, &args.pwd_override) {
+        if under {
+            sql.push_str("AND pwd LIKE ? ESCAPE '\\' ");
+            bind.push(format!("{}%", escape_like(&pwd)));
+        } else {
+            sql.push_str("AND pwd = ? ");
+            bind.push(pwd);
+        }
+    }
+
+    push_pwd_contains(&mut sql, &mut bind, &args.pwd_contains);
+
+    sql.push_str(&format!("ORDER BY {NEWEST_FIRST_SQL} "));
+    sql.push_str("LIMIT ?");
+    // --sort ranks in Rust, so it needs every candidate before applying --limit.
+    let unlimited = args.all || args.count || args.sort != SortMode::Recency;
+    let limit = if unlimited { u32::MAX } else { args.limit };
+    bind.push(limit.to_string());
+
+    Ok((sql, bind))
+}
+
+/// Up to `context` rows on each side of `id` by (epoch, id), plus the row itself,
+/// oldest first. Same columns as `build_search_sql` so the output code is shared.
+fn build_around_sql(id: i64, context: u32, session_fields: bool) -> (String, Vec<String>) {
+    let sql = format!(
+        "WITH anchor AS (SELECT id AS aid, epoch AS aepoch FROM history WHERE id = ?1), \
+         before AS (SELECT h.id FROM history h, anchor \
+           WHERE h.epoch < aepoch OR (h.epoch = aepoch AND h.id < aid) \
+           ORDER BY h.epoch DESC, h.id DESC LIMIT ?2), \
+         after AS (SELECT h.id FROM history h, anchor \
+           WHERE h.epoch > aepoch OR (h.epoch = aepoch AND h.id > aid) \
+           ORDER BY h.epoch ASC, h.id ASC LIMIT ?2) \
+         SELECT {} FROM history \
+         WHERE id IN (SELECT aid FROM anchor UNION ALL SELECT id FROM before UNION ALL SELECT id FROM after) \
+         ORDER BY epoch ASC, id ASC",
+        history_columns(session_fields)
+    );
+    (sql, vec![id.to_string(), context.to_string()])
+}
+
+/// Every row within `window` seconds of `epoch`, in time order (`search --near`).
+fn build_near_sql(epoch: i64, window: u32, session_fields: bool) -> (String, Vec<String>) {
+    let sql = format!(
+        "SELECT {} FROM history \
+         WHERE epoch BETWEEN ?1 AND ?2 \
+         ORDER BY {CHRONOLOGICAL_SQL}",
+        history_columns(session_fields)
+    );
+    let window = i64::from(window);
+    (
+        sql,
+        vec![(epoch - window).to_string(), (epoch + window).to_string()],
+    )
+}
+
+/// Decode the JSON object `log --capture-env` stores in the `env` column.
+fn parse_env_column(
+    id: i64,
+    env: Option<String>,
+) -> Result<Option<std::collections::BTreeMap<String, String>>> {
+    env.map(|env| {
+        serde_json::from_str(&env)
+            .with_context(|| format!("history entry {id} has a malformed env column"))
+    })
+    .transpose()
+}
+
+fn cmd_show(cfg: DbConfig, args: ShowArgs) -> Result<()> {
+    reject_csv_report(args.format, "show")?;
+    let conn = open_db(&cfg)?;
+    let record = conn
+        .query_row(
+            "SELECT id, hist_id, epoch, ppid, pwd, salt, cmd, exit_code, duration_ms, host, tty, \
+                    gitroot, seq, env, inserted_at, synthetic \
+             FROM history WHERE id = ?1",
+            [args.id],
+            |r| {
+                Ok((
+                    StoredRecord {
+                        id: r.get(0)?,
+                        hist_id: r.get(1)?,
+                        epoch: r.get(2)?,
+                        ppid: r.get(3)?,
+                        pwd: r.get(4)?,
+                        salt: r.get(5)?,
+                        cmd: r.get(6)?,
+                        exit_code: r.get(7)?,
+                        duration_ms: r.get(8)?,
+                        host: r.get(9)?,
+                        tty: r.get(10)?,
+                        gitroot: r.get(11)?,
+                        seq: r.get(12)?,
+                        env: None,
+                        inserted_at: r.get(14)?,
+                        synthetic: r.get::<_, Option<i64>>(15)?.unwrap_or(0) != 0,
+                    },
+                    r.get::<_, Option<String>>(13)?,
+                ))
+            },
+        )
+        .optional()?;
+    let Some((mut record, env)) = record else {
+        anyhow::bail!("no history entry with id {}", args.id);
+    };
+    record.env = parse_env_column(record.id, env)?;
+
+    if let OutputFormat::Json = args.format {
+        println!("{}", serde_json::to_string(&record)?);
+        return Ok(());
+    }
+
+    let opt = |v: Option<String>| v.unwrap_or_else(|| "-".to_string());
+    let fields = [
+        ("id", record.id.to_string()),
+        ("hist_id", opt(record.hist_id.map(|v| v.to_string()))),
+        (
+            "epoch",
+            format!("{} ({})", record.epoch, local_datetime(record.epoch)),
+        ),
+        ("ppid", record.ppid.to_string()),
+        ("salt", record.salt.to_string()),
+        ("pwd", record.pwd),
+        ("cmd", record.cmd),
+        ("exit_code", opt(record.exit_code.map(|v| v.to_string()))),
+        (
+            "duration_ms",
+            opt(record.duration_ms.map(|v| v.to_string())),
+        ),
+        ("host", opt(record.host)),
+        ("tty", opt(record.tty)),
+        ("gitroot", opt(record.gitroot)),
+        ("seq", opt(record.seq.map(|v| v.to_string()))),
+        (
+            "inserted_at",
+            opt(record
+                .inserted_at
+                .map(|v| format!("{v} ({})", local_datetime(v)))),
+        ),
+        ("synthetic", record.synthetic.to_string()),
+    ];
+    for (name, value) in fields {
+        println!("{name:<12} {value}");
+    }
+    match record.env {
+        Some(vars) => {
+            println!("env");
+            for (name, value) in vars {
+                println!("  {name}={value}");
+            }
+        }
+        None => println!("{:<12} -", "env"),
+    }
+    Ok(())
+}
+
+/// `YYYY-MM-DD HH:MM:SS` in local time, like the `list` table's date column.
+fn local_datetime(epoch: i64) -> String {
+    use time::{OffsetDateTime, UtcOffset, macros::format_description};
+
+    let Ok(utc) = OffsetDateTime::from_unix_timestamp(epoch) else {
+        return format_timestamp(epoch);
+    };
+    let offset = UtcOffset::local_offset_at(utc).unwrap_or(UtcOffset::UTC);
+    utc.to_offset(offset)
+        .format(format_description!(
+            "[year]-[month]-[day] [hour]:[minute]:[second]"
+        ))
+        .unwrap_or_else(|_| format_timestamp(epoch))
+}
+
+fn cmd_last(cfg: DbConfig, args: LastArgs) -> Result<()> {
+    let (sql, bind) = build_last_sql(&args)?;
+    let conn = open_db(&cfg)?;
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows: Vec<(i64, i64, String, String)> = stmt
+        .query_map(rusqlite::params_from_iter(bind.iter()), |r| {
+            Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    // Fetched newest first; print oldest first like `list`.
+    rows.reverse();
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+
+    match args.format {
+        OutputFormat::Table => {
+            for (_, _, _, cmd) in &rows {
+                writeln!(out, "{cmd}")?;
+            }
+        }
+        OutputFormat::Json => {
+            let records: Vec<HistoryRecord> = rows
+                .into_iter()
+                .map(|(id, epoch, pwd, cmd)| HistoryRecord {
+                    id,
+                    epoch,
+                    iso: None,
+                    pwd,
+                    cmd,
+                    session: None,
+                    details: None,
+                })
+                .collect();
+            serde_json::to_writer(&mut out, &records)?;
+            writeln!(out)?;
+        }
+        format @ (OutputFormat::Csv | OutputFormat::Tsv) => {
+            writeln!(out, "{}", history_delimited_header(format, false))?;
+            for (id, epoch, pwd, cmd) in &rows {
+                writeln!(
+                    out,
+                    "{}",
+                    history_delimited_row(format, *id, *epoch, false, pwd, cmd)
+                )?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Newest `--count` rows, optionally within one session or directory. Rows logged
+/// for `sdbh` itself are skipped so a prompt calling `sdbh last` reports the user's
+/// previous command.
+fn build_last_sql(args: &LastArgs) -> Result<(String, Vec<String>)> {
+    let mut bind: Vec<String> = vec![];
+    let mut sql = String::from(
+        "SELECT id, epoch, pwd, cmd FROM history WHERE cmd != 'sdbh' AND cmd NOT LIKE 'sdbh %' ",
+    );
+
+    if let Some((salt, ppid)) = session_filter(args.session)? {
+        sql.push_str("AND salt=? AND ppid=? ");
+        bind.push(salt.to_string());
+        bind.push(ppid.to_string());
+    }
+
+    if let Some((pwd, under)) = location_filter(args.here, args.under, &args.pwd_override) {
+        if under {
+            sql.push_str("AND pwd LIKE ? ESCAPE '\\' ");
+            bind.push(format!("{}%", escape_like(&pwd)));
+        } else {
+            sql.push_str("AND pwd = ? ");
+            bind.push(pwd);
+        }
+    }
+
+    sql.push_str(&format!("ORDER BY {NEWEST_FIRST_SQL} LIMIT ?"));
+    bind.push(args.count.to_string());
+    Ok((sql, bind))
+}
+
+fn cmd_exists(cfg: DbConfig, args: ExistsArgs) -> Result<()> {
+    let conn = open_db(&cfg)?;
+    let (sql, bind) = build_exists_sql(&args)?;
+    let found: bool = conn.query_row(&sql, rusqlite::params_from_iter(bind.iter()), |r| {
+        r.get::<_, i64>(0)
+    })? == 1;
+
+    if !found {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn build_exists_sql(args: &ExistsArgs) -> Result<(String, Vec<String>)> {
+    let mut bind: Vec<String> = vec![];
+    let mut sql = String::from("SELECT EXISTS(SELECT 1 FROM history WHERE 1=1 ");
+
+    if let Some(since) = args.since_epoch {
+        sql.push_str("AND epoch >= ? ");
+        bind.push(since.to_string());
+    } else if let Some(days) = args.days {
+        sql.push_str("AND epoch >= ? ");
+        bind.push(days_cutoff_epoch(days).to_string());
+    }
+
+    if let Some((salt, ppid)) = session_filter(args.session)? {
+        sql.push_str("AND salt=? AND ppid=? ");
+        bind.push(salt.to_string());
+        bind.push(ppid.to_string());
+    }
+
     sql.push_str("AND cmd LIKE ? ESCAPE '\\' ");
-    // Do NOT escape the surrounding wildcards; only escape user-provided text.
     bind.push(format!("%{}%", escape_like(&args.query)));
 
     if let Some((pwd, under)) = location_filter(args.here, args.under, &args.pwd_override) {
@@ -1040,193 +3172,914 @@ fn build_search_sql(args: &SearchArgs) -> Result<(String, Vec<String>)> {
         }
     }
 
-    sql.push_str("ORDER BY epoch DESC, id DESC ");
-    sql.push_str("LIMIT ?");
+    sql.push(')');
+    Ok((sql, bind))
+}
+
+fn cmd_export(cfg: DbConfig, args: ExportArgs) -> Result<()> {
+    let conn = open_db(&cfg)?;
+
+    let mut bind: Vec<String> = vec![];
+
+    let mut sql = String::from(
+        "SELECT id, hist_id, cmd, epoch, ppid, pwd, salt, exit_code, duration_ms, host, tty, \
+                gitroot, seq, env \
+         FROM history WHERE 1=1 ",
+    );
+
+    // Like the hook's own view of history: a hooked shell exports its session
+    // unless --all asks for everything.
+    let session = match session_filter(args.session)? {
+        Some(session) => Some(session),
+        None if !args.all => current_session().inspect(|(salt, ppid)| {
+            eprintln!(
+                "sdbh: exporting only the current session (salt {salt}, ppid {ppid}); pass --all for everything"
+            );
+        }),
+        None => None,
+    };
+    if let Some((salt, ppid)) = session {
+        sql.push_str("AND salt=? AND ppid=? ");
+        bind.push(salt.to_string());
+        bind.push(ppid.to_string());
+    }
+
+    if args.no_synthetic {
+        sql.push_str("AND COALESCE(synthetic, 0) = 0 ");
+    }
+
+    sql.push_str(&format!("ORDER BY {CHRONOLOGICAL_SQL}"));
+
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+
+    if let ExportFormat::Csv = args.format {
+        let iso = if args.iso_dates { ",iso" } else { "" };
+        writeln!(out, "id,hist_id,epoch{iso},ppid,pwd,salt,cmd")?;
+    }
+
+    while let Some(r) = rows.next()? {
+        let id: i64 = r.get(0)?;
+        let hist_id: Option<i64> = r.get(1)?;
+        let cmd: String = r.get(2)?;
+        let epoch: i64 = r.get(3)?;
+        let ppid: i64 = r.get(4)?;
+        let pwd: String = r.get(5)?;
+        let salt: i64 = r.get(6)?;
+
+        if let ExportFormat::Csv = args.format {
+            let mut fields = vec![
+                id.to_string(),
+                hist_id.map(|v| v.to_string()).unwrap_or_default(),
+                epoch.to_string(),
+            ];
+            if args.iso_dates {
+                fields.push(iso_datetime(epoch));
+            }
+            fields.extend([ppid.to_string(), pwd, salt.to_string(), cmd]);
+            writeln!(out, "{}", csv_row(&fields))?;
+            continue;
+        }
+
+        let record = HistoryRecord {
+            id,
+            epoch,
+            iso: args.iso_dates.then(|| iso_datetime(epoch)),
+            pwd,
+            cmd,
+            session: Some(SessionFields {
+                hist_id,
+                salt,
+                ppid,
+            }),
+            details: Some(RowDetails {
+                exit_code: r.get(7)?,
+                duration_ms: r.get(8)?,
+                host: r.get(9)?,
+                tty: r.get(10)?,
+                gitroot: r.get(11)?,
+                seq: r.get(12)?,
+                env: parse_env_column(id, r.get(13)?)?,
+            }),
+        };
+        serde_json::to_writer(&mut out, &record)?;
+        writeln!(out)?;
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+fn cmd_delete(cfg: DbConfig, args: DeleteArgs) -> Result<()> {
+    let mut conn = open_db(&cfg)?;
+
+    let (sql, bind) = match (&args.id, &args.query) {
+        (Some(id), _) => ("SELECT id FROM history WHERE id = ?", id.to_string()),
+        (None, Some(q)) => (
+            "SELECT id FROM history WHERE cmd LIKE ? ESCAPE '\\' ORDER BY id",
+            format!("%{}%", escape_like(q)),
+        ),
+        (None, None) => anyhow::bail!("either --id or --query must be specified"),
+    };
+    let ids: Vec<i64> = conn
+        .prepare(sql)?
+        .query_map([bind], |r| r.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    if ids.is_empty() {
+        println!("No matching history entries");
+        return Ok(());
+    }
+
+    if !args.yes {
+        if !atty::is(atty::Stream::Stdin) {
+            anyhow::bail!(
+                "refusing to delete {} row(s) without confirmation; pass --yes",
+                ids.len()
+            );
+        }
+        let confirmed = dialoguer::Confirm::new()
+            .with_prompt(format!("Delete {} history row(s)?", ids.len()))
+            .default(false)
+            .interact()?;
+        if !confirmed {
+            println!("Aborted");
+            return Ok(());
+        }
+    }
+
+    let removed = delete_history(&mut conn, &ids)?;
+    println!("Deleted {removed} row(s)");
+    Ok(())
+}
+
+fn cmd_edit(cfg: DbConfig, args: EditArgs) -> Result<()> {
+    let mut conn = open_db(&cfg)?;
+    let before: String = conn
+        .query_row("SELECT cmd FROM history WHERE id = ?1", [args.id], |r| {
+            r.get(0)
+        })
+        .optional()?
+        .with_context(|| format!("no history entry with id {}", args.id))?;
+
+    let after = match std::env::var("EDITOR")
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+    {
+        Some(editor) => edit_in_editor(&editor, args.id, &before)?,
+        None => {
+            if !atty::is(atty::Stream::Stdin) {
+                anyhow::bail!("set $EDITOR to edit without a terminal");
+            }
+            dialoguer::Input::<String>::new()
+                .with_prompt("Command")
+                .with_initial_text(before.clone())
+                .interact_text()?
+        }
+    };
+
+    if after.trim().is_empty() {
+        anyhow::bail!(
+            "command cannot be empty; use `sdbh delete --id {}`",
+            args.id
+        );
+    }
+    if after == before {
+        println!("No changes");
+        return Ok(());
+    }
+
+    update_history_cmd(&mut conn, args.id, &after, cfg.hash_version)?;
+    println!("Before: {before}");
+    println!("After:  {after}");
+    Ok(())
+}
+
+/// Round-trip `cmd` through `$EDITOR` (which may carry arguments, e.g. `code -w`)
+/// via a temp file. Trailing newlines added by the editor are dropped.
+fn edit_in_editor(editor: &str, id: i64, cmd: &str) -> Result<String> {
+    // Private (0600) file with an unpredictable name: it holds history contents.
+    let mut file = tempfile::Builder::new()
+        .prefix(&format!("sdbh-edit-{id}-"))
+        .suffix(".sh")
+        .tempfile()
+        .context("creating a temp file to edit")?;
+    writeln!(file, "{cmd}").with_context(|| format!("writing {}", file.path().display()))?;
+    let path = file.path().to_path_buf();
+
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or(editor);
+    let status = std::process::Command::new(program)
+        .args(parts)
+        .arg(&path)
+        .status()
+        .with_context(|| format!("running editor {program}"));
+    let edited = std::fs::read_to_string(&path);
+    drop(file);
+
+    let status = status?;
+    if !status.success() {
+        anyhow::bail!("editor exited with {status}; row left unchanged");
+    }
+    Ok(edited?.trim_end_matches(['\n', '\r']).to_string())
+}
+
+fn cmd_redact(cfg: DbConfig, args: RedactArgs) -> Result<()> {
+    let pattern = regex::Regex::new(&args.pattern)
+        .with_context(|| format!("invalid --pattern {:?}", args.pattern))?;
+    let mut conn = open_db(&cfg)?;
+    let changed = crate::db::redact_history(
+        &mut conn,
+        &pattern,
+        &args.replacement,
+        cfg.hash_version,
+        args.dry_run,
+    )?;
+
+    if !args.dry_run {
+        println!("Redacted {} row(s)", changed.len());
+        return Ok(());
+    }
+
+    const SAMPLE: usize = 5;
+    println!("{} row(s) would change", changed.len());
+    for (id, before, after) in changed.iter().take(SAMPLE) {
+        println!("{id:>6} | before: {before}");
+        println!("{:>6} | after:  {after}", "");
+    }
+    if changed.len() > SAMPLE {
+        println!("... and {} more", changed.len() - SAMPLE);
+    }
+    Ok(())
+}
+
+fn cmd_stats(cfg: DbConfig, args: StatsArgs) -> Result<()> {
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    let shown = match args.command {
+        StatsCommand::Top(a) => {
+            // Check if multi_select was requested but not fzf
+            if a.multi_select && !a.fzf {
+                anyhow::bail!("--multi-select requires --fzf flag");
+            }
+            if a.fzf {
+                return cmd_stats_top_fzf(cfg, a);
+            }
+            let (sql, mut bind) = build_stats_top_sql(&a)?;
+            if !cfg.extra_paths.is_empty() {
+                if a.trend {
+                    anyhow::bail!("--trend is not supported with multiple --db paths");
+                }
+                // The limit has to apply after merging, so fetch every group per database.
+                let limit = if a.all { u32::MAX } else { a.limit };
+                bind.pop();
+                bind.push(u32::MAX.to_string());
+                let shown = print_merged_stats_top(&mut out, &cfg, &sql, &bind, limit as usize)?;
+                out.flush()?;
+                exit_if_empty(args.status, shown);
+                return Ok(());
+            }
+            let conn = open_db(&cfg)?;
+            let mut stmt = conn.prepare(&sql)?;
+            let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+            let mut shown = 0usize;
+            while let Some(r) = rows.next()? {
+                shown += 1;
+                let cnt: i64 = r.get(0)?;
+                let cmd: String = r.get(1)?;
+                if a.trend {
+                    let first_half: i64 = r.get(3)?;
+                    let trend = trend_label(first_half, cnt - first_half);
+                    writeln!(out, "{cnt:>6} | {trend:<8} | {cmd}")?;
+                } else {
+                    writeln!(out, "{cnt:>6} | {cmd}")?;
+                }
+            }
+            shown
+        }
+        StatsCommand::ByPwd(a) => {
+            // Check if multi_select was requested but not fzf
+            if a.multi_select && !a.fzf {
+                anyhow::bail!("--multi-select requires --fzf flag");
+            }
+            if a.fzf {
+                return cmd_stats_by_pwd_fzf(cfg, a);
+            }
+            let conn = open_db(&cfg)?;
+            let (sql, bind) = build_stats_by_pwd_sql(&a)?;
+            let mut stmt = conn.prepare(&sql)?;
+            let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+            let mut shown = 0usize;
+            while let Some(r) = rows.next()? {
+                shown += 1;
+                let cnt: i64 = r.get(0)?;
+                let pwd: String = r.get(1)?;
+                let cmd: String = r.get(2)?;
+                writeln!(out, "{cnt:>6} | {pwd} | {cmd}")?;
+            }
+            shown
+        }
+        StatsCommand::Daily(a) => {
+            // Check if multi_select was requested but not fzf
+            if a.multi_select && !a.fzf {
+                anyhow::bail!("--multi-select requires --fzf flag");
+            }
+            if a.fzf {
+                return cmd_stats_daily_fzf(cfg, a);
+            }
+            let (sql, bind) = build_stats_daily_sql(&a)?;
+            if !cfg.extra_paths.is_empty() {
+                let limit = if a.all { u32::MAX } else { a.limit };
+                let shown = print_merged_stats_daily(&mut out, &cfg, &sql, &bind, limit as usize)?;
+                out.flush()?;
+                exit_if_empty(args.status, shown);
+                return Ok(());
+            }
+            let conn = open_db(&cfg)?;
+            let mut stmt = conn.prepare(&sql)?;
+            let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+            let mut shown = 0usize;
+            while let Some(r) = rows.next()? {
+                shown += 1;
+                let day: String = r.get(0)?;
+                let cnt: i64 = r.get(1)?;
+                writeln!(out, "{day} | {cnt:>6}")?;
+            }
+            shown
+        }
+        StatsCommand::Slow(a) => {
+            let conn = open_db(&cfg)?;
+            let (sql, bind) = build_stats_slow_sql(&a)?;
+            let mut stmt = conn.prepare(&sql)?;
+            let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+            let mut shown = 0usize;
+            while let Some(r) = rows.next()? {
+                shown += 1;
+                let avg_ms: f64 = r.get(0)?;
+                let runs: i64 = r.get(1)?;
+                let cmd: String = r.get(2)?;
+                writeln!(out, "{:>8} | {runs:>6} | {cmd}", avg_ms.round() as i64)?;
+            }
+            shown
+        }
+        StatsCommand::ByHost(a) => {
+            let conn = open_db(&cfg)?;
+            let (sql, bind) = build_stats_by_host_sql(&a)?;
+            let mut stmt = conn.prepare(&sql)?;
+            let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+            let mut shown = 0usize;
+            while let Some(r) = rows.next()? {
+                shown += 1;
+                let cnt: i64 = r.get(0)?;
+                let host: Option<String> = r.get(1)?;
+                writeln!(out, "{cnt:>6} | {}", host.as_deref().unwrap_or("(unknown)"))?;
+            }
+            shown
+        }
+        StatsCommand::ByGitroot(a) => {
+            let conn = open_db(&cfg)?;
+            let (sql, bind) = build_stats_by_gitroot_sql(&a)?;
+            let mut stmt = conn.prepare(&sql)?;
+            let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+            let mut shown = 0usize;
+            while let Some(r) = rows.next()? {
+                shown += 1;
+                let cnt: i64 = r.get(0)?;
+                let gitroot: Option<String> = r.get(1)?;
+                writeln!(
+                    out,
+                    "{cnt:>6} | {}",
+                    gitroot.as_deref().unwrap_or("(no repo)")
+                )?;
+            }
+            shown
+        }
+        StatsCommand::Hourly(a) => {
+            let conn = open_db(&cfg)?;
+            let (sql, bind) = build_stats_histogram_sql(&a, "%H")?;
+            let counts = histogram_counts::<24>(&conn, &sql, &bind)?;
+            let labels: Vec<String> = (0..24).map(|h| format!("{h:02}h")).collect();
+            print_histogram(&mut out, &labels, &counts)?;
+            counts.iter().sum::<i64>() as usize
+        }
+        StatsCommand::Weekday(a) => {
+            let conn = open_db(&cfg)?;
+            let (sql, bind) = build_stats_histogram_sql(&a, "%w")?;
+            let counts = histogram_counts::<7>(&conn, &sql, &bind)?;
+            let labels = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"].map(String::from);
+            print_histogram(&mut out, &labels, &counts)?;
+            counts.iter().sum::<i64>() as usize
+        }
+        StatsCommand::New(a) => {
+            let conn = open_db(&cfg)?;
+            let (sql, bind) = build_stats_new_sql(&a)?;
+            let mut stmt = conn.prepare(&sql)?;
+            let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+            let mut shown = 0usize;
+            while let Some(r) = rows.next()? {
+                shown += 1;
+                let first_seen: String = r.get(0)?;
+                let uses: i64 = r.get(1)?;
+                let cmd: String = r.get(2)?;
+                writeln!(out, "{first_seen} | {uses:>6} | {cmd}")?;
+            }
+            shown
+        }
+    };
+
+    out.flush()?;
+    exit_if_empty(args.status, shown);
+    Ok(())
+}
+
+/// `--count`: run `sql` as a subquery and print how many rows it returns,
+/// as a bare integer or `{"count":N}`.
+fn print_count(
+    conn: &rusqlite::Connection,
+    sql: &str,
+    bind: &[String],
+    json: bool,
+    status: bool,
+) -> Result<()> {
+    let n: i64 = conn.query_row(
+        &format!("SELECT count(*) FROM ({sql})"),
+        rusqlite::params_from_iter(bind.iter()),
+        |r| r.get(0),
+    )?;
+    if json {
+        println!("{{\"count\":{n}}}");
+    } else {
+        println!("{n}");
+    }
+    exit_if_empty(status, n as usize);
+    Ok(())
+}
+
+/// `--status` exit code for "nothing matched". Distinct from 1 (any error) and 2
+/// (clap's usage errors) so scripts can tell an empty result from a failure.
+const NO_RESULTS_EXIT_CODE: i32 = 3;
+
+/// With `--status`, exit [`NO_RESULTS_EXIT_CODE`] when a query produced no rows, so
+/// scripts can branch on "no matches" without counting output.
+fn exit_if_empty(status: bool, rows: usize) {
+    if status && rows == 0 {
+        std::process::exit(NO_RESULTS_EXIT_CODE);
+    }
+}
+
+/// Count rows per local-time `strftime` bucket (`%H` for hours, `%w` for weekdays).
+fn build_stats_histogram_sql(
+    args: &StatsHistogramArgs,
+    bucket: &str,
+) -> Result<(String, Vec<String>)> {
+    let mut bind: Vec<String> = vec![bucket.to_string()];
+    let mut sql = String::from(
+        "SELECT CAST(strftime(?, epoch, 'unixepoch', 'localtime') AS INTEGER) as bucket, count(*) as cnt FROM history WHERE 1=1 ",
+    );
+
+    if let Some((salt, ppid)) = session_filter(args.session)? {
+        sql.push_str("AND salt=? AND ppid=? ");
+        bind.push(salt.to_string());
+        bind.push(ppid.to_string());
+    }
+
+    push_stats_window(&mut sql, &mut bind, args.days, &args.since, &args.until)?;
+
+    sql.push_str("GROUP BY bucket");
+
+    Ok((sql, bind))
+}
+
+/// Run a histogram query into `N` buckets; buckets without rows stay at zero.
+fn histogram_counts<const N: usize>(
+    conn: &rusqlite::Connection,
+    sql: &str,
+    bind: &[String],
+) -> Result<[i64; N]> {
+    let mut counts = [0i64; N];
+    let mut stmt = conn.prepare(sql)?;
+    let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+    while let Some(r) = rows.next()? {
+        let bucket: i64 = r.get(0)?;
+        let cnt: i64 = r.get(1)?;
+        if let Some(slot) = counts.get_mut(bucket as usize) {
+            *slot = cnt;
+        }
+    }
+    Ok(counts)
+}
+
+fn print_histogram(out: &mut impl Write, labels: &[String], counts: &[i64]) -> Result<()> {
+    let width = get_terminal_width().unwrap_or(80);
+    for line in histogram_lines(labels, counts, width) {
+        writeln!(out, "{line}")?;
+    }
+    Ok(())
+}
+
+/// Render `label | count | ####` rows, scaling the longest bar to fill `width` columns.
+fn histogram_lines(labels: &[String], counts: &[i64], width: usize) -> Vec<String> {
+    let label_width = labels.iter().map(|l| l.len()).max().unwrap_or(0);
+    // label + " | " + 6-wide count + " | "
+    let bar_width = width.saturating_sub(label_width + 12).max(10);
+    let max = counts.iter().copied().max().unwrap_or(0).max(1);
+
+    labels
+        .iter()
+        .zip(counts)
+        .map(|(label, &cnt)| {
+            let mut bar = (cnt as usize * bar_width) / max as usize;
+            if cnt > 0 && bar == 0 {
+                bar = 1;
+            }
+            format!("{label:<label_width$} | {cnt:>6} | {}", "#".repeat(bar))
+                .trim_end()
+                .to_string()
+        })
+        .collect()
+}
+
+/// Run the `stats top` query against every --db and merge counts per command.
+fn print_merged_stats_top(
+    out: &mut impl Write,
+    cfg: &DbConfig,
+    sql: &str,
+    bind: &[String],
+    limit: usize,
+) -> Result<usize> {
+    // cmd -> (count, last epoch)
+    let mut merged: HashMap<String, (i64, i64)> = HashMap::new();
+    for db in cfg.each_db() {
+        let conn = open_db(&db)?;
+        let mut stmt = conn.prepare(sql)?;
+        let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+        while let Some(r) = rows.next()? {
+            let cnt: i64 = r.get(0)?;
+            let cmd: String = r.get(1)?;
+            let last: i64 = r.get(2)?;
+            let entry = merged.entry(cmd).or_insert((0, i64::MIN));
+            entry.0 += cnt;
+            entry.1 = entry.1.max(last);
+        }
+    }
+
+    let mut merged: Vec<(String, (i64, i64))> = merged.into_iter().collect();
+    merged.sort_by(|a, b| {
+        b.1.0
+            .cmp(&a.1.0)
+            .then(b.1.1.cmp(&a.1.1))
+            .then(a.0.cmp(&b.0))
+    });
+    let shown = merged.len().min(limit);
+    for (cmd, (cnt, _)) in merged.into_iter().take(limit) {
+        writeln!(out, "{cnt:>6} | {cmd}")?;
+    }
+    Ok(shown)
+}
+
+/// Run the `stats daily` query against every --db and sum counts per day.
+fn print_merged_stats_daily(
+    out: &mut impl Write,
+    cfg: &DbConfig,
+    sql: &str,
+    bind: &[String],
+    limit: usize,
+) -> Result<usize> {
+    let mut merged: std::collections::BTreeMap<String, i64> = Default::default();
+    for db in cfg.each_db() {
+        let conn = open_db(&db)?;
+        let mut stmt = conn.prepare(sql)?;
+        let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+        while let Some(r) = rows.next()? {
+            let day: String = r.get(0)?;
+            let cnt: i64 = r.get(1)?;
+            *merged.entry(day).or_insert(0) += cnt;
+        }
+    }
+
+    // Each database already returned its most recent `limit` days; keep the newest overall.
+    for (day, cnt) in merged.iter().skip(merged.len().saturating_sub(limit)) {
+        writeln!(out, "{day} | {cnt:>6}")?;
+    }
+    Ok(merged.len().min(limit))
+}
+
+fn days_cutoff_epoch(days: u32) -> i64 {
+    let now = std::time::SystemTime::now();
+    let now_epoch = now
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let secs = (days as i64) * 86400;
+    now_epoch - secs
+}
+
+/// Parse a point in time for --since/--until.
+///
+/// Accepts raw epoch seconds, a `YYYY-MM-DD` date (local midnight, like list/search
+/// `--since`), or a relative age such as
+/// `90m`, `12h`, `7d` or `2w` (counted back from now). With `end_of_day`, a bare
+/// date resolves to its last second so `--until 2024-03-31` includes that day.
+fn parse_time_spec(spec: &str, end_of_day: bool) -> Result<i64> {
+    let spec = spec.trim();
+
+    if let Ok(epoch) = spec.parse::<i64>() {
+        return Ok(epoch);
+    }
+
+    if let Some(unit) = spec.chars().last()
+        && let Ok(n) = spec[..spec.len() - unit.len_utf8()].parse::<i64>()
+    {
+        let secs = match unit {
+            's' => Some(1),
+            'm' => Some(60),
+            'h' => Some(3600),
+            'd' => Some(86400),
+            'w' => Some(7 * 86400),
+            _ => None,
+        };
+        if let Some(secs) = secs {
+            return Ok(time::OffsetDateTime::now_utc().unix_timestamp() - n * secs);
+        }
+    }
+
+    if let Some(date) = parse_calendar_date(spec) {
+        let date = date?;
+        return Ok(if end_of_day {
+            // The next local midnight, not +86399, so DST-change days stay whole
+            let next = date
+                .next_day()
+                .ok_or_else(|| anyhow::anyhow!("date out of range: {spec}"))?;
+            local_midnight(next) - 1
+        } else {
+            local_midnight(date)
+        });
+    }
+
+    anyhow::bail!("invalid time spec: {spec} (expected epoch seconds, YYYY-MM-DD, or e.g. 7d)")
+}
+
+/// Parse `YYYY-MM-DD`; `None` when the spec is not shaped like a date at all.
+fn parse_calendar_date(spec: &str) -> Option<Result<time::Date>> {
+    let parts: Vec<&str> = spec.split('-').collect();
+    let [y, m, d] = parts.as_slice() else {
+        return None;
+    };
+    let (Ok(y), Ok(m), Ok(d)) = (y.parse::<i32>(), m.parse::<u8>(), d.parse::<u8>()) else {
+        return None;
+    };
+    Some(
+        time::Month::try_from(m)
+            .map_err(|_| anyhow::anyhow!("invalid month in date: {spec}"))
+            .and_then(|month| {
+                time::Date::from_calendar_date(y, month, d)
+                    .map_err(|e| anyhow::anyhow!("invalid date {spec}: {e}"))
+            }),
+    )
+}
+
+/// Resolve a `YYYY-MM-DD` date to the epoch of its local midnight.
+///
+/// Falls back to UTC when the local offset cannot be determined.
+fn local_midnight_epoch(date: &str) -> Result<i64> {
+    let date = date.trim();
+    let date = parse_calendar_date(date)
+        .unwrap_or_else(|| Err(anyhow::anyhow!("invalid date {date} (expected YYYY-MM-DD)")))?;
+    Ok(local_midnight(date))
+}
+
+/// Epoch of `date`'s midnight in the local time zone (UTC when it is unknown).
+fn local_midnight(date: time::Date) -> i64 {
+    let midnight = date.midnight();
+    let offset =
+        time::UtcOffset::local_offset_at(midnight.assume_utc()).unwrap_or(time::UtcOffset::UTC);
+    midnight.assume_offset(offset).unix_timestamp()
+}
+
+/// Restrict list/search to local dates: `since` is inclusive, `until` exclusive.
+fn push_date_range(
+    sql: &mut String,
+    bind: &mut Vec<String>,
+    since: &Option<String>,
+    until: &Option<String>,
+) -> Result<()> {
+    if let Some(since) = since {
+        sql.push_str("AND epoch >= ? ");
+        bind.push(local_midnight_epoch(since)?.to_string());
+    }
+    if let Some(until) = until {
+        sql.push_str("AND epoch < ? ");
+        bind.push(local_midnight_epoch(until)?.to_string());
+    }
+    Ok(())
+}
+
+/// Restrict stats to `--since`/`--until` when given, otherwise to the last `days` days.
+fn push_stats_window(
+    sql: &mut String,
+    bind: &mut Vec<String>,
+    days: u32,
+    since: &Option<String>,
+    until: &Option<String>,
+) -> Result<()> {
+    if since.is_none() && until.is_none() {
+        sql.push_str("AND epoch >= ? ");
+        bind.push(days_cutoff_epoch(days).to_string());
+        return Ok(());
+    }
+
+    if let Some(since) = since {
+        sql.push_str("AND epoch >= ? ");
+        bind.push(parse_time_spec(since, false)?.to_string());
+    }
+    if let Some(until) = until {
+        sql.push_str("AND epoch <= ? ");
+        bind.push(parse_time_spec(until, true)?.to_string());
+    }
+    Ok(())
+}
+
+fn build_stats_top_sql(args: &StatsTopArgs) -> Result<(String, Vec<String>)> {
+    let mut bind: Vec<String> = vec![];
+    let aliases = if args.canonical {
+        load_alias_config()
+    } else {
+        HashMap::new()
+    };
+    let key = command_key_sql(&aliases, args.by_program, &mut bind);
+    let mut sql = format!("SELECT count(*) as cnt, {key} as cmd_key, max(epoch) as last_epoch ");
+    if args.trend {
+        sql.push_str(", sum(epoch < CAST(? AS INTEGER)) as first_half ");
+        bind.push(window_midpoint(days_cutoff_epoch(args.days), args.days).to_string());
+    }
+    sql.push_str("FROM history WHERE 1=1 ");
+
+    if let Some((salt, ppid)) = session_filter(args.session)? {
+        sql.push_str("AND salt=? AND ppid=? ");
+        bind.push(salt.to_string());
+        bind.push(ppid.to_string());
+    }
+
+    push_stats_window(&mut sql, &mut bind, args.days, &args.since, &args.until)?;
+
+    sql.push_str("GROUP BY cmd_key ORDER BY cnt DESC, max(epoch) DESC LIMIT ?");
+    let limit = if args.all { u32::MAX } else { args.limit };
+    bind.push(limit.to_string());
+
+    Ok((sql, bind))
+}
+
+/// Epoch splitting a `days`-long window starting at `cutoff` into two equal halves.
+fn window_midpoint(cutoff: i64, days: u32) -> i64 {
+    cutoff + days as i64 * 86400 / 2
+}
+
+/// `stats top --trend` label for a command used `first` times in the first half of
+/// the window and `second` times in the second: an arrow plus the percentage change.
+fn trend_label(first: i64, second: i64) -> String {
+    if first == second {
+        return "→ 0%".to_string();
+    }
+    if first == 0 {
+        return "↑ new".to_string();
+    }
+    let change = (second - first) as f64 * 100.0 / first as f64;
+    let arrow = if second > first { '↑' } else { '↓' };
+    format!("{arrow} {change:+.0}%")
+}
+
+fn build_stats_by_pwd_sql(args: &StatsByPwdArgs) -> Result<(String, Vec<String>)> {
+    let mut bind: Vec<String> = vec![];
+    let aliases = if args.canonical {
+        load_alias_config()
+    } else {
+        HashMap::new()
+    };
+    let key = command_key_sql(&aliases, false, &mut bind);
+    let mut sql = format!("SELECT count(*) as cnt, pwd, {key} as cmd_key FROM history WHERE 1=1 ");
+
+    if let Some((salt, ppid)) = session_filter(args.session)? {
+        sql.push_str("AND salt=? AND ppid=? ");
+        bind.push(salt.to_string());
+        bind.push(ppid.to_string());
+    }
+
+    push_stats_window(&mut sql, &mut bind, args.days, &args.since, &args.until)?;
+
+    sql.push_str("GROUP BY pwd, cmd_key ORDER BY cnt DESC, max(epoch) DESC LIMIT ?");
     let limit = if args.all { u32::MAX } else { args.limit };
     bind.push(limit.to_string());
 
     Ok((sql, bind))
 }
 
-fn cmd_export(cfg: DbConfig, args: ExportArgs) -> Result<()> {
-    let conn = open_db(&cfg)?;
-
+fn build_stats_daily_sql(args: &StatsDailyArgs) -> Result<(String, Vec<String>)> {
     let mut bind: Vec<String> = vec![];
+    let mut sql = String::from(
+        "SELECT date(epoch, 'unixepoch', 'localtime') as day, count(*) as cnt FROM history WHERE 1=1 ",
+    );
 
-    let mut sql =
-        String::from("SELECT id, hist_id, cmd, epoch, ppid, pwd, salt FROM history WHERE 1=1 ");
-
-    if let Some((salt, ppid)) = session_filter(args.session) {
+    if let Some((salt, ppid)) = session_filter(args.session)? {
         sql.push_str("AND salt=? AND ppid=? ");
         bind.push(salt.to_string());
         bind.push(ppid.to_string());
     }
 
-    sql.push_str("ORDER BY epoch ASC, id ASC");
+    push_stats_window(&mut sql, &mut bind, args.days, &args.since, &args.until)?;
 
-    let mut stmt = conn.prepare(&sql)?;
-    let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+    // Keep the most recent days, then flip back to chronological order for display.
+    sql.push_str("GROUP BY day ORDER BY day DESC LIMIT ?");
+    let limit = if args.all { u32::MAX } else { args.limit };
+    bind.push(limit.to_string());
+    let sql = format!("SELECT day, cnt FROM ({sql}) ORDER BY day ASC");
 
-    while let Some(r) = rows.next()? {
-        let id: i64 = r.get(0)?;
-        let hist_id: Option<i64> = r.get(1)?;
-        let cmd: String = r.get(2)?;
-        let epoch: i64 = r.get(3)?;
-        let ppid: i64 = r.get(4)?;
-        let pwd: String = r.get(5)?;
-        let salt: i64 = r.get(6)?;
+    Ok((sql, bind))
+}
 
-        // JSONL without serde.
-        // Keep fields simple and stable.
-        let hist_id_json = match hist_id {
-            Some(v) => v.to_string(),
-            None => "null".to_string(),
-        };
+fn build_stats_slow_sql(args: &StatsSlowArgs) -> Result<(String, Vec<String>)> {
+    let mut bind: Vec<String> = vec![];
+    // Rows logged without a duration (hook mode, imports) don't count towards the average.
+    let mut sql = String::from(
+        "SELECT avg(duration_ms) as avg_ms, count(*) as runs, cmd FROM history WHERE duration_ms IS NOT NULL ",
+    );
 
-        println!(
-            "{{\"id\":{},\"hist_id\":{},\"epoch\":{},\"ppid\":{},\"pwd\":{},\"salt\":{},\"cmd\":{}}}",
-            id,
-            hist_id_json,
-            epoch,
-            ppid,
-            json_string(&pwd),
-            salt,
-            json_string(&cmd)
-        );
+    if let Some((salt, ppid)) = session_filter(args.session)? {
+        sql.push_str("AND salt=? AND ppid=? ");
+        bind.push(salt.to_string());
+        bind.push(ppid.to_string());
     }
 
-    Ok(())
-}
+    push_stats_window(&mut sql, &mut bind, args.days, &args.since, &args.until)?;
 
-fn cmd_stats(cfg: DbConfig, args: StatsArgs) -> Result<()> {
-    match args.command {
-        StatsCommand::Top(a) => {
-            // Check if multi_select was requested but not fzf
-            if a.multi_select && !a.fzf {
-                anyhow::bail!("--multi-select requires --fzf flag");
-            }
-            if a.fzf {
-                return cmd_stats_top_fzf(cfg, a);
-            }
-            let conn = open_db(&cfg)?;
-            let (sql, bind) = build_stats_top_sql(&a)?;
-            let mut stmt = conn.prepare(&sql)?;
-            let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
-            while let Some(r) = rows.next()? {
-                let cnt: i64 = r.get(0)?;
-                let cmd: String = r.get(1)?;
-                println!("{cnt:>6} | {cmd}");
-            }
-            Ok(())
-        }
-        StatsCommand::ByPwd(a) => {
-            // Check if multi_select was requested but not fzf
-            if a.multi_select && !a.fzf {
-                anyhow::bail!("--multi-select requires --fzf flag");
-            }
-            if a.fzf {
-                return cmd_stats_by_pwd_fzf(cfg, a);
-            }
-            let conn = open_db(&cfg)?;
-            let (sql, bind) = build_stats_by_pwd_sql(&a)?;
-            let mut stmt = conn.prepare(&sql)?;
-            let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
-            while let Some(r) = rows.next()? {
-                let cnt: i64 = r.get(0)?;
-                let pwd: String = r.get(1)?;
-                let cmd: String = r.get(2)?;
-                println!("{cnt:>6} | {pwd} | {cmd}");
-            }
-            Ok(())
-        }
-        StatsCommand::Daily(a) => {
-            // Check if multi_select was requested but not fzf
-            if a.multi_select && !a.fzf {
-                anyhow::bail!("--multi-select requires --fzf flag");
-            }
-            if a.fzf {
-                return cmd_stats_daily_fzf(cfg, a);
-            }
-            let conn = open_db(&cfg)?;
-            let (sql, bind) = build_stats_daily_sql(&a)?;
-            let mut stmt = conn.prepare(&sql)?;
-            let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
-            while let Some(r) = rows.next()? {
-                let day: String = r.get(0)?;
-                let cnt: i64 = r.get(1)?;
-                println!("{day} | {cnt:>6}");
-            }
-            Ok(())
-        }
-    }
-}
+    sql.push_str("GROUP BY cmd ORDER BY avg_ms DESC, runs DESC LIMIT ?");
+    let limit = if args.all { u32::MAX } else { args.limit };
+    bind.push(limit.to_string());
 
-fn days_cutoff_epoch(days: u32) -> i64 {
-    let now = std::time::SystemTime::now();
-    let now_epoch = now
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs() as i64;
-    let secs = (days as i64) * 86400;
-    now_epoch - secs
+    Ok((sql, bind))
 }
 
-fn build_stats_top_sql(args: &StatsTopArgs) -> Result<(String, Vec<String>)> {
+/// Commands whose first run in the whole history (not just the window) falls within
+/// the last `days` days.
+fn build_stats_new_sql(args: &StatsNewArgs) -> Result<(String, Vec<String>)> {
     let mut bind: Vec<String> = vec![];
-    let mut sql = String::from("SELECT count(*) as cnt, cmd FROM history WHERE 1=1 ");
+    let mut sql = String::from(
+        "SELECT date(min(epoch), 'unixepoch', 'localtime') as first_seen, count(*) as uses, cmd \
+         FROM history WHERE 1=1 ",
+    );
 
-    if let Some((salt, ppid)) = session_filter(args.session) {
+    if let Some((salt, ppid)) = session_filter(args.session)? {
         sql.push_str("AND salt=? AND ppid=? ");
         bind.push(salt.to_string());
         bind.push(ppid.to_string());
     }
 
-    sql.push_str("AND epoch >= ? ");
+    // Binds are text and min(epoch) carries no column affinity, so cast explicitly.
+    sql.push_str(
+        "GROUP BY cmd HAVING min(epoch) >= CAST(? AS INTEGER) ORDER BY min(epoch) ASC, cmd ASC LIMIT ?",
+    );
     bind.push(days_cutoff_epoch(args.days).to_string());
-
-    sql.push_str("GROUP BY cmd ORDER BY cnt DESC, max(epoch) DESC LIMIT ?");
     let limit = if args.all { u32::MAX } else { args.limit };
     bind.push(limit.to_string());
 
     Ok((sql, bind))
 }
 
-fn build_stats_by_pwd_sql(args: &StatsByPwdArgs) -> Result<(String, Vec<String>)> {
+fn build_stats_by_host_sql(args: &StatsByHostArgs) -> Result<(String, Vec<String>)> {
     let mut bind: Vec<String> = vec![];
-    let mut sql = String::from("SELECT count(*) as cnt, pwd, cmd FROM history WHERE 1=1 ");
+    // Rows logged before host tracking (or imported from history files) group under NULL.
+    let mut sql = String::from("SELECT count(*) as cnt, host FROM history WHERE 1=1 ");
 
-    if let Some((salt, ppid)) = session_filter(args.session) {
+    if let Some((salt, ppid)) = session_filter(args.session)? {
         sql.push_str("AND salt=? AND ppid=? ");
         bind.push(salt.to_string());
         bind.push(ppid.to_string());
     }
 
-    sql.push_str("AND epoch >= ? ");
-    bind.push(days_cutoff_epoch(args.days).to_string());
+    push_stats_window(&mut sql, &mut bind, args.days, &args.since, &args.until)?;
 
-    sql.push_str("GROUP BY pwd, cmd ORDER BY cnt DESC, max(epoch) DESC LIMIT ?");
+    sql.push_str("GROUP BY host ORDER BY cnt DESC, host ASC LIMIT ?");
     let limit = if args.all { u32::MAX } else { args.limit };
     bind.push(limit.to_string());
 
     Ok((sql, bind))
 }
 
-fn build_stats_daily_sql(args: &StatsDailyArgs) -> Result<(String, Vec<String>)> {
+fn build_stats_by_gitroot_sql(args: &StatsByGitrootArgs) -> Result<(String, Vec<String>)> {
     let mut bind: Vec<String> = vec![];
-    let mut sql = String::from(
-        "SELECT date(epoch, 'unixepoch', 'localtime') as day, count(*) as cnt FROM history WHERE 1=1 ",
-    );
+    // Commands outside a repository (or logged with tracking off) group under NULL.
+    let mut sql = String::from("SELECT count(*) as cnt, gitroot FROM history WHERE 1=1 ");
 
-    if let Some((salt, ppid)) = session_filter(args.session) {
+    if let Some((salt, ppid)) = session_filter(args.session)? {
         sql.push_str("AND salt=? AND ppid=? ");
         bind.push(salt.to_string());
         bind.push(ppid.to_string());
     }
 
-    sql.push_str("AND epoch >= ? ");
-    bind.push(days_cutoff_epoch(args.days).to_string());
+    push_stats_window(&mut sql, &mut bind, args.days, &args.since, &args.until)?;
 
-    sql.push_str("GROUP BY day ORDER BY day ASC");
+    sql.push_str("GROUP BY gitroot ORDER BY cnt DESC, gitroot ASC LIMIT ?");
+    let limit = if args.all { u32::MAX } else { args.limit };
+    bind.push(limit.to_string());
 
     Ok((sql, bind))
 }
@@ -1243,19 +4096,69 @@ fn cmd_import(mut cfg: DbConfig, args: ImportArgs) -> Result<()> {
         anyhow::bail!("--from must be specified at least once");
     }
 
+    let overrides = ImportOverrides {
+        pwd: args.set_pwd,
+        host: args.set_host,
+        salt: args.set_salt,
+        ppid: args.set_ppid,
+    };
+
+    let mut total_considered = 0u64;
+    let mut total_inserted = 0u64;
+
+    for p in &args.from_paths {
+        let outcome = import_from_db(&mut conn, p, cfg.hash_version, &overrides)?;
+        eprintln!(
+            "imported from {}: considered {}, inserted {}",
+            p.display(),
+            outcome.considered,
+            outcome.inserted
+        );
+        if args.session_report {
+            print_session_report(&outcome);
+        }
+        total_considered += outcome.considered;
+        total_inserted += outcome.inserted;
+    }
+
+    eprintln!(
+        "total: considered {}, inserted {}",
+        total_considered, total_inserted
+    );
+
+    Ok(())
+}
+
+/// Duplicate rows per source session, most overlapping first.
+fn print_session_report(outcome: &crate::db::ImportOutcome) {
+    if outcome.duplicates_by_session.is_empty() {
+        eprintln!("  no duplicate rows");
+        return;
+    }
+    let mut sessions: Vec<_> = outcome.duplicates_by_session.iter().collect();
+    sessions.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    for ((salt, ppid), dups) in sessions {
+        eprintln!("  session salt={salt} ppid={ppid}: {dups} duplicate(s)");
+    }
+}
+
+fn cmd_import_jsonl(cfg: DbConfig, args: ImportJsonlArgs) -> Result<()> {
+    let mut conn = open_db(&cfg)?;
+    ensure_hash_index(&conn)?;
+
     let mut total_considered = 0u64;
     let mut total_inserted = 0u64;
 
     for p in &args.from_paths {
-        let (considered, inserted) = import_from_db(&mut conn, p)?;
+        let outcome = crate::db::import_from_jsonl(&mut conn, p, cfg.hash_version)?;
         eprintln!(
             "imported from {}: considered {}, inserted {}",
             p.display(),
-            considered,
-            inserted
+            outcome.considered,
+            outcome.inserted
         );
-        total_considered += considered;
-        total_inserted += inserted;
+        total_considered += outcome.considered;
+        total_inserted += outcome.inserted;
     }
 
     eprintln!(
@@ -1291,10 +4194,21 @@ fn cmd_import_history(cfg: DbConfig, args: ImportHistoryArgs) -> Result<()> {
     let missing = entries.iter().filter(|e| e.epoch.is_none()).count() as i64;
     let mut next_synth_epoch = 1_000_000_000i64 - missing;
 
+    let drop_secrets = load_import_config().drop_suspected_secrets;
+    let strip_nul = load_log_config().strip_nul;
     let mut considered = 0u64;
     let mut inserted = 0u64;
+    let mut dropped = 0u64;
+    let mut prev_cmd: Option<String> = None;
 
     for e in entries {
+        let after_prompt = prev_cmd.as_deref().is_some_and(prompts_for_password);
+        prev_cmd = Some(e.cmd.clone());
+        if drop_secrets && after_prompt && looks_like_typed_secret(&e.cmd) {
+            dropped += 1;
+            continue;
+        }
+
         let epoch = match e.epoch {
             Some(v) => v,
             None => {
@@ -1305,16 +4219,23 @@ fn cmd_import_history(cfg: DbConfig, args: ImportHistoryArgs) -> Result<()> {
 
         let row = HistoryRow {
             hist_id: None,
-            cmd: e.cmd,
+            cmd: sanitize_nul(e.cmd, strip_nul),
             epoch,
             ppid: args.ppid,
             pwd: pwd.clone(),
             salt: args.salt,
+            exit_code: None,
+            duration_ms: None,
+            host: None,
+            tty: None,
+            gitroot: None,
+            seq: None,
+            env: None,
         };
         considered += 1;
 
         // Dedup using history_hash
-        let hash = crate::db::row_hash(&row);
+        let hash = crate::db::row_hash(&row, cfg.hash_version);
         let exists: bool = conn.query_row(
             "SELECT EXISTS(SELECT 1 FROM history_hash WHERE hash=?1)",
             rusqlite::params![hash],
@@ -1326,14 +4247,73 @@ fn cmd_import_history(cfg: DbConfig, args: ImportHistoryArgs) -> Result<()> {
         }
 
         // insert_history also populates history_hash.
-        insert_history(&mut conn, &row)?;
+        let id = insert_history(&mut conn, &row, &cfg)?;
+        if e.epoch.is_none() {
+            conn.execute(
+                "UPDATE history SET synthetic = 1 WHERE id = ?1",
+                rusqlite::params![id],
+            )?;
+        }
         inserted += 1;
     }
 
-    eprintln!("import-history: considered {considered}, inserted {inserted}");
+    if drop_secrets {
+        eprintln!(
+            "import-history: considered {considered}, inserted {inserted}, dropped {dropped} suspected secrets"
+        );
+    } else {
+        eprintln!("import-history: considered {considered}, inserted {inserted}");
+    }
     Ok(())
 }
 
+/// Commands that read a password from the terminal, so the next history line may be
+/// one typed blind when echo wasn't suppressed.
+fn prompts_for_password(cmd: &str) -> bool {
+    const PROMPTING: &[&str] = &[
+        "ssh", "scp", "sftp", "sudo", "su", "passwd", "mysql", "ssh-add", "gpg", "kinit", "doas",
+        "ftp",
+    ];
+    cmd.split_whitespace()
+        .next()
+        .and_then(|word| word.rsplit('/').next())
+        .is_some_and(|prog| PROMPTING.contains(&prog))
+}
+
+/// A single token of 8+ characters mixing at least three character classes with
+/// high entropy: shaped like a password rather than a command.
+fn looks_like_typed_secret(cmd: &str) -> bool {
+    let token = cmd.trim();
+    if token.chars().count() < 8 || token.contains(char::is_whitespace) {
+        return false;
+    }
+
+    let classes = [
+        token.chars().any(|c| c.is_ascii_lowercase()),
+        token.chars().any(|c| c.is_ascii_uppercase()),
+        token.chars().any(|c| c.is_ascii_digit()),
+        token.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    if classes.iter().filter(|&&present| present).count() < 3 {
+        return false;
+    }
+
+    // Shannon entropy in bits per character
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in token.chars() {
+        *counts.entry(c).or_default() += 1;
+    }
+    let len = token.chars().count() as f64;
+    let entropy: f64 = counts
+        .values()
+        .map(|&n| {
+            let p = n as f64 / len;
+            -p * p.log2()
+        })
+        .sum();
+    entropy >= 3.0
+}
+
 fn cmd_doctor(cfg: DbConfig, args: DoctorArgs) -> Result<()> {
     let mut checks: Vec<DoctorCheck> = vec![];
 
@@ -1503,24 +4483,33 @@ fn cmd_doctor(cfg: DbConfig, args: DoctorArgs) -> Result<()> {
         }
     }
 
+    if !cfg.track_hash {
+        checks.push(DoctorCheck::info(
+            "db.hash",
+            "dedup hashing disabled ([db] track_hash = false); import won't dedup newly logged rows"
+                .to_string(),
+        ));
+    }
+
     // --- Env vars ---
     checks.extend(check_env_i64("SDBH_SALT"));
     checks.extend(check_env_i64("SDBH_PPID"));
 
+    // --- fzf ---
+    checks.push(check_fzf_version(&load_fzf_config()));
+
+    // --- sdbh on PATH (what the shell hooks actually run) ---
+    checks.push(check_binary_path());
+
     // --- Env-only shell detection ---
     if !args.spawn_only {
         if let Ok(pc) = std::env::var("PROMPT_COMMAND") {
-            if pc.contains("__sdbh_prompt") {
-                checks.push(DoctorCheck::ok(
-                    "bash.hook.env",
-                    "PROMPT_COMMAND contains __sdbh_prompt".to_string(),
-                ));
-            } else {
-                checks.push(DoctorCheck::info(
-                    "bash.hook.env",
-                    "PROMPT_COMMAND does not contain __sdbh_prompt".to_string(),
-                ));
-            }
+            checks.push(hook_check(
+                "bash.hook.env",
+                "PROMPT_COMMAND",
+                &pc,
+                "__sdbh_prompt",
+            ));
         } else {
             checks.push(DoctorCheck::info(
                 "bash.hook.env",
@@ -1538,17 +4527,18 @@ fn cmd_doctor(cfg: DbConfig, args: DoctorArgs) -> Result<()> {
                         "bash.spawn",
                         format!("ok: {}", rep.summary()),
                     ));
-                    if rep.prompt_command.contains("__sdbh_prompt") {
-                        checks.push(DoctorCheck::ok(
-                            "bash.hook.spawn",
-                            "PROMPT_COMMAND contains __sdbh_prompt".to_string(),
-                        ));
-                    } else {
-                        checks.push(DoctorCheck::info(
-                            "bash.hook.spawn",
-                            "PROMPT_COMMAND missing __sdbh_prompt".to_string(),
-                        ));
-                    }
+                    checks.push(hook_check(
+                        "bash.hook.spawn",
+                        "PROMPT_COMMAND",
+                        &rep.prompt_command,
+                        "__sdbh_prompt",
+                    ));
+                    checks.push(function_check(
+                        "bash.function.spawn",
+                        "__sdbh_prompt",
+                        rep.prompt_fn_defined,
+                    ));
+                    checks.push(histtimeformat_check(rep.histtimeformat.as_deref()));
 
                     if rep.trap_debug.contains("__sdbh_debug_trap") {
                         checks.push(DoctorCheck::ok(
@@ -1582,17 +4572,17 @@ fn cmd_doctor(cfg: DbConfig, args: DoctorArgs) -> Result<()> {
                         format!("ok: {}", rep.summary()),
                     ));
 
-                    if rep.precmd_functions.contains("sdbh_precmd") {
-                        checks.push(DoctorCheck::ok(
-                            "zsh.hook.spawn",
-                            "precmd_functions contains sdbh_precmd".to_string(),
-                        ));
-                    } else {
-                        checks.push(DoctorCheck::info(
-                            "zsh.hook.spawn",
-                            "precmd_functions missing sdbh_precmd".to_string(),
-                        ));
-                    }
+                    checks.push(hook_check(
+                        "zsh.hook.spawn",
+                        "precmd_functions",
+                        &rep.precmd_functions,
+                        "sdbh_precmd",
+                    ));
+                    checks.push(function_check(
+                        "zsh.function.spawn",
+                        "sdbh_precmd",
+                        rep.precmd_fn_defined,
+                    ));
 
                     if rep.preexec_functions.contains("sdbh_preexec") {
                         checks.push(DoctorCheck::ok(
@@ -1617,22 +4607,270 @@ fn cmd_doctor(cfg: DbConfig, args: DoctorArgs) -> Result<()> {
                 "zsh not found on PATH".to_string(),
             ));
         }
+
+        if let Some(nu) = which("nu") {
+            checks.push(match spawn_nu_hooks(&nu) {
+                Ok(hooks) => hook_check("nu.spawn", "config hooks", &hooks, "sdbh log"),
+                Err(e) => DoctorCheck::warn("nu.spawn", format!("failed to inspect nu: {e}")),
+            });
+        } else {
+            checks.push(DoctorCheck::info(
+                "nu.spawn",
+                "nu not found on PATH".to_string(),
+            ));
+        }
+    }
+
+    output_doctor(&checks, args.format);
+    Ok(())
+}
+
+fn cmd_dirs(cfg: DbConfig, args: DirsArgs) -> Result<()> {
+    match args.command {
+        DirsCommand::Search(args) => cmd_dirs_search(cfg, args),
+    }
+}
+
+fn cmd_dirs_search(cfg: DbConfig, args: DirsSearchArgs) -> Result<()> {
+    let conn = open_db(&cfg)?;
+    let (sql, bind) = build_dirs_search_sql(&args);
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
+
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    let mut shown = 0usize;
+    match args.format {
+        OutputFormat::Table => {
+            while let Some(r) = rows.next()? {
+                shown += 1;
+                let pwd: String = r.get(0)?;
+                let cnt: i64 = r.get(1)?;
+                let dt: String = r.get(3)?;
+                writeln!(out, "{cnt:>6} | {dt} | {pwd}")?;
+            }
+        }
+        OutputFormat::Json => {
+            let mut items = Vec::new();
+            while let Some(r) = rows.next()? {
+                let pwd: String = r.get(0)?;
+                let cnt: i64 = r.get(1)?;
+                let last_epoch: i64 = r.get(2)?;
+                items.push(format!(
+                    "{{\"pwd\":{},\"count\":{cnt},\"last_epoch\":{last_epoch}}}",
+                    json_string(&pwd)
+                ));
+            }
+            shown = items.len();
+            writeln!(out, "[{}]", items.join(","))?;
+        }
+        format @ (OutputFormat::Csv | OutputFormat::Tsv) => {
+            let tsv = matches!(format, OutputFormat::Tsv);
+            writeln!(
+                out,
+                "{}",
+                if tsv {
+                    "pwd\tcount\tlast_epoch"
+                } else {
+                    "pwd,count,last_epoch"
+                }
+            )?;
+            while let Some(r) = rows.next()? {
+                shown += 1;
+                let pwd: String = r.get(0)?;
+                let cnt: i64 = r.get(1)?;
+                let last_epoch: i64 = r.get(2)?;
+                let fields = [pwd, cnt.to_string(), last_epoch.to_string()];
+                writeln!(
+                    out,
+                    "{}",
+                    if tsv {
+                        tsv_row(&fields)
+                    } else {
+                        csv_row(&fields)
+                    }
+                )?;
+            }
+        }
+    }
+    out.flush()?;
+    exit_if_empty(args.status, shown);
+    Ok(())
+}
+
+/// Distinct working directories containing the query, most recently active first.
+fn build_dirs_search_sql(args: &DirsSearchArgs) -> (String, Vec<String>) {
+    let sql = String::from(
+        "SELECT pwd, count(*) as cnt, max(epoch) as last_epoch, \
+         datetime(max(epoch), 'unixepoch', 'localtime') as dt \
+         FROM history GROUP BY pwd HAVING pwd LIKE ? ESCAPE '\\' \
+         ORDER BY last_epoch DESC, pwd ASC LIMIT ?",
+    );
+    let limit = if args.all { u32::MAX } else { args.limit };
+    (
+        sql,
+        vec![format!("%{}%", escape_like(&args.query)), limit.to_string()],
+    )
+}
+
+fn cmd_db(cfg: DbConfig, args: DbArgs) -> Result<()> {
+    match args.command {
+        DbCommand::Health(args) => cmd_db_health(cfg, args.format),
+        DbCommand::Optimize => cmd_db_optimize(cfg),
+        DbCommand::Stats(args) => cmd_db_stats(cfg, args.format),
+        DbCommand::Schema(args) => cmd_db_schema(cfg, args.format),
+        DbCommand::Prune(args) => cmd_db_prune(cfg, args),
+        DbCommand::Dedup(args) => cmd_db_dedup(cfg, args),
+        DbCommand::Compact(args) => cmd_db_compact(cfg, args),
+        DbCommand::Backup(args) => cmd_db_backup(cfg, args),
+        DbCommand::ReindexFts => cmd_db_reindex_fts(cfg),
+    }
+}
+
+fn cmd_db_prune(cfg: DbConfig, args: DbPruneArgs) -> Result<()> {
+    let mut conn = open_db(&cfg)?;
+    let cutoff = days_cutoff_epoch(args.older_than_days);
+    let removed = crate::db::prune_older_than(&mut conn, cutoff, args.dry_run)?;
+
+    if args.dry_run {
+        println!(
+            "Would remove {removed} row(s) older than {} days",
+            args.older_than_days
+        );
+    } else {
+        println!(
+            "Removed {removed} row(s) older than {} days",
+            args.older_than_days
+        );
+        if removed > 0 {
+            println!("Run `sdbh db optimize` to reclaim disk space.");
+        }
     }
+    Ok(())
+}
 
-    output_doctor(&checks, args.format);
+fn cmd_db_dedup(cfg: DbConfig, args: DbDedupArgs) -> Result<()> {
+    let mut conn = open_db(&cfg)?;
+    let removed = crate::db::dedup_history(&mut conn, &cfg, args.dry_run)?;
+
+    if args.dry_run {
+        println!("Would remove {removed} duplicate row(s)");
+    } else {
+        println!("Removed {removed} duplicate row(s)");
+        if removed > 0 {
+            println!("Run `sdbh db optimize` to reclaim disk space.");
+        }
+    }
     Ok(())
 }
 
-fn cmd_db(cfg: DbConfig, args: DbArgs) -> Result<()> {
-    match args.command {
-        DbCommand::Health => cmd_db_health(cfg),
-        DbCommand::Optimize => cmd_db_optimize(cfg),
-        DbCommand::Stats => cmd_db_stats(cfg),
-        DbCommand::Schema => cmd_db_schema(cfg),
+fn cmd_db_compact(cfg: DbConfig, args: DbCompactArgs) -> Result<()> {
+    if args.path.exists() {
+        anyhow::bail!("{} already exists", args.path.display());
+    }
+
+    let mut conn = open_db(&cfg)?;
+    // With --replace, keep writers out while the copy is made so nothing logged in
+    // the meantime is missing from it. Readers (the copy itself) are not blocked.
+    let writer_lock = if args.replace {
+        let lock = rusqlite::Connection::open(&cfg.path)?;
+        lock.execute_batch("BEGIN IMMEDIATE")
+            .context("locking the database for --replace (is a shell hook writing?)")?;
+        Some(lock)
+    } else {
+        None
+    };
+    crate::db::vacuum_into(&conn, &args.path)?;
+    // Bumped whenever another connection commits, so it tells us whether anything
+    // was written between releasing the lock and copying back.
+    let data_version = |c: &rusqlite::Connection| -> Result<i64> {
+        Ok(c.query_row("PRAGMA data_version", [], |r| r.get(0))?)
+    };
+    let compacted_version = data_version(&conn)?;
+
+    let size = |p: &std::path::Path| std::fs::metadata(p).map(|m| m.len()).unwrap_or(0);
+    println!(
+        "Compacted {} ({:.1} MB) into {} ({:.1} MB)",
+        cfg.path.display(),
+        size(&cfg.path) as f64 / 1_000_000.0,
+        args.path.display(),
+        size(&args.path) as f64 / 1_000_000.0
+    );
+
+    if args.replace {
+        // Copy the pages back into the live file rather than renaming over it: a hook
+        // holding the old file open would otherwise write into an unlinked inode.
+        // The backup can't write through the connection holding the lock, so release
+        // it and refuse to copy back if a writer got in first.
+        drop(writer_lock);
+        if data_version(&conn)? != compacted_version {
+            std::fs::remove_file(&args.path)
+                .with_context(|| format!("removing {}", args.path.display()))?;
+            anyhow::bail!(
+                "{} changed while it was being compacted; retry",
+                cfg.path.display()
+            );
+        }
+        crate::db::restore_from(&mut conn, &args.path).with_context(|| {
+            format!(
+                "replacing {} (the compacted copy was kept)",
+                cfg.path.display()
+            )
+        })?;
+        std::fs::remove_file(&args.path)
+            .with_context(|| format!("removing {}", args.path.display()))?;
+        println!("Replaced {} with the compacted copy", cfg.path.display());
     }
+    Ok(())
+}
+
+fn cmd_db_backup(cfg: DbConfig, args: DbBackupArgs) -> Result<()> {
+    let conn = open_db(&cfg)?;
+
+    if args.to.exists() {
+        let same_file = match (cfg.path.canonicalize(), args.to.canonicalize()) {
+            (Ok(source), Ok(dest)) => source == dest,
+            _ => false,
+        };
+        if same_file {
+            anyhow::bail!(
+                "{} is the database being backed up; choose another destination",
+                args.to.display()
+            );
+        }
+        if !args.force {
+            anyhow::bail!(
+                "{} already exists (use --force to overwrite)",
+                args.to.display()
+            );
+        }
+    }
+
+    // Snapshot into a temp file beside the target and only move it into place once
+    // the backup completed, so a failure never clobbers an existing backup.
+    let dir = match args.to.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => std::path::Path::new("."),
+    };
+    let tmp = tempfile::Builder::new()
+        .prefix(".sdbh-backup-")
+        .tempfile_in(dir)
+        .with_context(|| format!("creating a temp file in {}", dir.display()))?;
+    crate::db::backup_to(&conn, tmp.path())?;
+    tmp.persist(&args.to)
+        .with_context(|| format!("moving the backup into {}", args.to.display()))?;
+
+    let count_rows = |conn: &rusqlite::Connection| -> Result<i64> {
+        Ok(conn.query_row("SELECT count(*) FROM history", [], |r| r.get(0))?)
+    };
+    let source_rows = count_rows(&conn)?;
+    let backup_rows = count_rows(&rusqlite::Connection::open(&args.to)?)?;
+    println!("Source:      {} ({source_rows} rows)", cfg.path.display());
+    println!("Destination: {} ({backup_rows} rows)", args.to.display());
+    Ok(())
 }
 
-fn cmd_db_health(cfg: DbConfig) -> Result<()> {
+fn cmd_db_health(cfg: DbConfig, format: OutputFormat) -> Result<()> {
+    reject_csv_report(format, "db health")?;
     let conn = open_db(&cfg)?;
 
     // Database integrity check
@@ -1641,12 +4879,6 @@ fn cmd_db_health(cfg: DbConfig) -> Result<()> {
         .map(|result| result == "ok")
         .unwrap_or(false);
 
-    if integrity_ok {
-        println!("✓ Database integrity check passed");
-    } else {
-        println!("✗ Database integrity check failed");
-    }
-
     // Get database statistics
     let page_count: i64 = conn.query_row("PRAGMA page_count", [], |r| r.get(0))?;
     let freelist_count: i64 = conn.query_row("PRAGMA freelist_count", [], |r| r.get(0))?;
@@ -1661,12 +4893,6 @@ fn cmd_db_health(cfg: DbConfig) -> Result<()> {
         0.0
     };
 
-    println!("Database Statistics:");
-    println!("  Rows: {}", row_count);
-    println!("  Size: {:.1} MB", db_size_mb);
-    println!("  Free space: {:.1} MB", free_space_mb);
-    println!("  Fragmentation: {:.1}%", fragmentation_ratio * 100.0);
-
     // Check for missing indexes
     let mut missing_indexes = Vec::new();
     let indexes = [
@@ -1699,6 +4925,32 @@ fn cmd_db_health(cfg: DbConfig) -> Result<()> {
         }
     }
 
+    if let OutputFormat::Json = format {
+        let missing: Vec<String> = missing_indexes.iter().map(|n| json_string(n)).collect();
+        println!(
+            "{{\"integrity\":{},\"rows\":{},\"size_mb\":{:.3},\"free_mb\":{:.3},\"fragmentation\":{:.4},\"missing_indexes\":[{}]}}",
+            integrity_ok,
+            row_count,
+            db_size_mb,
+            free_space_mb,
+            fragmentation_ratio,
+            missing.join(",")
+        );
+        return Ok(());
+    }
+
+    if integrity_ok {
+        println!("✓ Database integrity check passed");
+    } else {
+        println!("✗ Database integrity check failed");
+    }
+
+    println!("Database Statistics:");
+    println!("  Rows: {}", row_count);
+    println!("  Size: {:.1} MB", db_size_mb);
+    println!("  Free space: {:.1} MB", free_space_mb);
+    println!("  Fragmentation: {:.1}%", fragmentation_ratio * 100.0);
+
     if missing_indexes.is_empty() {
         println!("✓ All performance indexes present");
     } else {
@@ -1719,6 +4971,14 @@ fn cmd_db_health(cfg: DbConfig) -> Result<()> {
     Ok(())
 }
 
+/// `db health/stats/schema` reports are nested, so only table and JSON are offered.
+fn reject_csv_report(format: OutputFormat, command: &str) -> Result<()> {
+    if let OutputFormat::Csv | OutputFormat::Tsv = format {
+        anyhow::bail!("{command} does not support --format {format}; use table or json");
+    }
+    Ok(())
+}
+
 fn cmd_db_optimize(cfg: DbConfig) -> Result<()> {
     let conn = open_db(&cfg)?;
 
@@ -1732,6 +4992,9 @@ fn cmd_db_optimize(cfg: DbConfig) -> Result<()> {
     conn.execute_batch("REINDEX;")?;
     println!("✓ Reindexed database");
 
+    crate::db::rebuild_fts(&conn)?;
+    println!("✓ Rebuilt full-text search index");
+
     // Vacuum to reclaim space
     conn.execute_batch("VACUUM;")?;
     println!("✓ Vacuumed database");
@@ -1740,7 +5003,16 @@ fn cmd_db_optimize(cfg: DbConfig) -> Result<()> {
     Ok(())
 }
 
-fn cmd_db_stats(cfg: DbConfig) -> Result<()> {
+fn cmd_db_reindex_fts(cfg: DbConfig) -> Result<()> {
+    let conn = open_db(&cfg)?;
+    crate::db::rebuild_fts(&conn)?;
+    let rows: i64 = conn.query_row("SELECT count(*) FROM history", [], |r| r.get(0))?;
+    println!("✓ Full-text index built ({rows} rows)");
+    Ok(())
+}
+
+fn cmd_db_stats(cfg: DbConfig, format: OutputFormat) -> Result<()> {
+    reject_csv_report(format, "db stats")?;
     let conn = open_db(&cfg)?;
 
     // Basic statistics
@@ -1750,6 +5022,25 @@ fn cmd_db_stats(cfg: DbConfig) -> Result<()> {
 
     let db_size_mb = (page_count * page_size) as f64 / 1_000_000.0;
 
+    let mut stmt =
+        conn.prepare("SELECT name FROM sqlite_master WHERE type='index' AND name LIKE 'idx_%'")?;
+    let indexes = stmt
+        .query_map([], |r| r.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    if let OutputFormat::Json = format {
+        let names: Vec<String> = indexes.iter().map(|n| json_string(n)).collect();
+        println!(
+            "{{\"rows\":{},\"size_mb\":{:.3},\"page_count\":{},\"page_size\":{},\"indexes\":[{}]}}",
+            row_count,
+            db_size_mb,
+            page_count,
+            page_size,
+            names.join(",")
+        );
+        return Ok(());
+    }
+
     println!("Database Statistics:");
     println!("  Total rows: {}", row_count);
     println!("  Database size: {:.1} MB", db_size_mb);
@@ -1758,49 +5049,115 @@ fn cmd_db_stats(cfg: DbConfig) -> Result<()> {
 
     // Index information
     println!("\nIndexes:");
-    let mut stmt =
-        conn.prepare("SELECT name FROM sqlite_master WHERE type='index' AND name LIKE 'idx_%'")?;
-    let rows = stmt.query_map([], |r| r.get::<_, String>(0))?;
-    for row in rows {
-        let name = row?;
+    for name in &indexes {
         println!("  {}", name);
     }
 
     Ok(())
 }
 
-fn cmd_db_schema(cfg: DbConfig) -> Result<()> {
-    let conn = open_db(&cfg)?;
+/// One column from `PRAGMA table_info`
+struct SchemaColumn {
+    name: String,
+    ty: String,
+    notnull: bool,
+    pk: bool,
+}
 
-    println!("Database Schema:");
-    println!("================");
+fn cmd_db_schema(cfg: DbConfig, format: OutputFormat) -> Result<()> {
+    reject_csv_report(format, "db schema")?;
+    let conn = open_db(&cfg)?;
 
     // Tables
-    println!("\nTables:");
     let mut stmt =
         conn.prepare("SELECT name FROM sqlite_master WHERE type='table' ORDER BY name")?;
-    let tables = stmt.query_map([], |r| r.get::<_, String>(0))?;
-    for table in tables {
-        let table_name = table?;
-        println!("  {}", table_name);
-
-        // Show table schema
+    let table_names = stmt
+        .query_map([], |r| r.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut tables: Vec<(String, Vec<SchemaColumn>)> = vec![];
+    for table_name in table_names {
+        // Table schema
         let mut schema_stmt = conn.prepare(&format!("PRAGMA table_info({})", table_name))?;
-        let columns = schema_stmt.query_map([], |r| {
-            let name: String = r.get(1)?;
-            let type_: String = r.get(2)?;
-            let notnull: i64 = r.get(3)?;
-            let pk: i64 = r.get(5)?;
-            Ok((name, type_, notnull, pk))
-        })?;
+        let columns = schema_stmt
+            .query_map([], |r| {
+                Ok(SchemaColumn {
+                    name: r.get(1)?,
+                    ty: r.get(2)?,
+                    notnull: r.get::<_, i64>(3)? == 1,
+                    pk: r.get::<_, i64>(5)? == 1,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        tables.push((table_name, columns));
+    }
+
+    // Indexes
+    let mut stmt = conn.prepare(
+        "SELECT name, tbl_name, sql FROM sqlite_master WHERE type='index' AND sql IS NOT NULL ORDER BY name"
+    )?;
+    let indexes = stmt
+        .query_map([], |r| {
+            let name: String = r.get(0)?;
+            let table: String = r.get(1)?;
+            let sql: String = r.get(2)?;
+            Ok((name, table, sql))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    if let OutputFormat::Json = format {
+        let tables: Vec<String> = tables
+            .iter()
+            .map(|(name, columns)| {
+                let columns: Vec<String> = columns
+                    .iter()
+                    .map(|c| {
+                        format!(
+                            "{{\"name\":{},\"type\":{},\"notnull\":{},\"pk\":{}}}",
+                            json_string(&c.name),
+                            json_string(&c.ty),
+                            c.notnull,
+                            c.pk
+                        )
+                    })
+                    .collect();
+                format!(
+                    "{{\"name\":{},\"columns\":[{}]}}",
+                    json_string(name),
+                    columns.join(",")
+                )
+            })
+            .collect();
+        let indexes: Vec<String> = indexes
+            .iter()
+            .map(|(name, table, sql)| {
+                format!(
+                    "{{\"name\":{},\"table\":{},\"sql\":{}}}",
+                    json_string(name),
+                    json_string(table),
+                    json_string(sql)
+                )
+            })
+            .collect();
+        println!(
+            "{{\"tables\":[{}],\"indexes\":[{}]}}",
+            tables.join(","),
+            indexes.join(",")
+        );
+        return Ok(());
+    }
+
+    println!("Database Schema:");
+    println!("================");
 
+    println!("\nTables:");
+    for (table_name, columns) in &tables {
+        println!("  {}", table_name);
         for column in columns {
-            let (name, type_, notnull, pk) = column?;
             let mut flags = Vec::new();
-            if pk == 1 {
+            if column.pk {
                 flags.push("PRIMARY KEY");
             }
-            if notnull == 1 {
+            if column.notnull {
                 flags.push("NOT NULL");
             }
             let flags_str = if flags.is_empty() {
@@ -1808,24 +5165,12 @@ fn cmd_db_schema(cfg: DbConfig) -> Result<()> {
             } else {
                 format!(" ({})", flags.join(", "))
             };
-            println!("    {} {}{}", name, type_, flags_str);
+            println!("    {} {}{}", column.name, column.ty, flags_str);
         }
     }
 
-    // Indexes
     println!("\nIndexes:");
-    let mut stmt = conn.prepare(
-        "SELECT name, tbl_name, sql FROM sqlite_master WHERE type='index' AND sql IS NOT NULL ORDER BY name"
-    )?;
-    let indexes = stmt.query_map([], |r| {
-        let name: String = r.get(0)?;
-        let table: String = r.get(1)?;
-        let sql: String = r.get(2)?;
-        Ok((name, table, sql))
-    })?;
-
-    for index in indexes {
-        let (name, table, sql) = index?;
+    for (name, table, sql) in &indexes {
         println!("  {} on {}: {}", name, table, sql);
     }
 
@@ -1898,103 +5243,531 @@ impl DoctorCheck {
         }
     }
 
-    fn warn(name: &'static str, detail: String) -> Self {
-        Self {
-            name,
-            status: DoctorStatus::Warn,
-            detail,
+    fn warn(name: &'static str, detail: String) -> Self {
+        Self {
+            name,
+            status: DoctorStatus::Warn,
+            detail,
+        }
+    }
+
+    fn fail(name: &'static str, detail: String) -> Self {
+        Self {
+            name,
+            status: DoctorStatus::Fail,
+            detail,
+        }
+    }
+
+    fn info(name: &'static str, detail: String) -> Self {
+        Self {
+            name,
+            status: DoctorStatus::Info,
+            detail,
+        }
+    }
+}
+
+fn check_env_i64(key: &'static str) -> Vec<DoctorCheck> {
+    match std::env::var(key) {
+        Ok(v) => match v.parse::<i64>() {
+            Ok(_) => vec![DoctorCheck::ok(key, format!("{key}={v}"))],
+            Err(_) => vec![DoctorCheck::warn(
+                key,
+                format!("{key} is set but not an integer: {v}"),
+            )],
+        },
+        Err(_) => vec![DoctorCheck::warn(key, format!("{key} is not set"))],
+    }
+}
+
+fn status_str(s: DoctorStatus) -> &'static str {
+    match s {
+        DoctorStatus::Ok => "ok",
+        DoctorStatus::Warn => "warn",
+        DoctorStatus::Fail => "fail",
+        DoctorStatus::Info => "info",
+    }
+}
+
+fn output_doctor(checks: &[DoctorCheck], format: OutputFormat) {
+    match format {
+        OutputFormat::Table => {
+            for c in checks {
+                println!("{:18} | {:5} | {}", c.name, status_str(c.status), c.detail);
+            }
+        }
+        OutputFormat::Json => {
+            print!("{{\"checks\":[");
+            let mut first = true;
+            for c in checks {
+                if !first {
+                    print!(",");
+                }
+                first = false;
+                print!(
+                    "{{\"check\":{},\"status\":{},\"detail\":{}}}",
+                    json_string(c.name),
+                    json_string(status_str(c.status)),
+                    json_string(&c.detail)
+                );
+            }
+            let suggestions: Vec<String> = doctor_suggestions(checks)
+                .into_iter()
+                .map(json_string)
+                .collect();
+            println!("],\"suggestions\":[{}]}}", suggestions.join(","));
+        }
+        format @ (OutputFormat::Csv | OutputFormat::Tsv) => {
+            let tsv = matches!(format, OutputFormat::Tsv);
+            println!(
+                "{}",
+                if tsv {
+                    "check\tstatus\tdetail"
+                } else {
+                    "check,status,detail"
+                }
+            );
+            for c in checks {
+                let fields = [c.name, status_str(c.status), c.detail.as_str()];
+                println!(
+                    "{}",
+                    if tsv {
+                        tsv_row(&fields)
+                    } else {
+                        csv_row(&fields)
+                    }
+                );
+            }
+        }
+    }
+}
+
+/// Remediation for a check that warned or failed; `None` when there is nothing
+/// specific to do.
+fn doctor_suggestion(check: &DoctorCheck) -> Option<&'static str> {
+    if !matches!(check.status, DoctorStatus::Warn | DoctorStatus::Fail) {
+        return None;
+    }
+    match check.name {
+        "db.open" => Some("check the --db path"),
+        "db.write" => Some("check database file permissions"),
+        "db.integrity" => Some("restore the database from a backup"),
+        "db.indexes" | "db.fragmentation" => Some("db optimize"),
+        "SDBH_SALT" | "SDBH_PPID" => Some("add hook to shell rc (see sdbh shell --help)"),
+        "bash.hook.env" | "bash.hook.spawn" => Some("remove duplicate sdbh setup from .bashrc"),
+        "zsh.hook.spawn" => Some("remove duplicate sdbh setup from .zshrc"),
+        "nu.spawn" => Some("check sdbh hooks in nushell config"),
+        "fzf.version" => Some("upgrade fzf"),
+        "bash.histtimeformat" => Some("export HISTTIMEFORMAT=\"%s \" in .bashrc"),
+        "binary.path" => Some("put the current sdbh first in PATH or remove the stale copy"),
+        _ => None,
+    }
+}
+
+/// Suggestions for every warned/failed check, in check order, without repeats.
+fn doctor_suggestions(checks: &[DoctorCheck]) -> Vec<&'static str> {
+    let mut out: Vec<&'static str> = vec![];
+    for s in checks.iter().filter_map(doctor_suggestion) {
+        if !out.contains(&s) {
+            out.push(s);
+        }
+    }
+    out
+}
+
+/// One key of a JSON output record, as documented by `sdbh describe`.
+#[derive(Clone, Copy)]
+struct RecordField {
+    name: &'static str,
+    /// JSON Schema type name
+    ty: &'static str,
+    nullable: bool,
+    /// Flag the key is gated behind, such as `--iso-dates`; `None` if always present
+    only_with: Option<&'static str>,
+    description: &'static str,
+}
+
+const fn field(name: &'static str, ty: &'static str, description: &'static str) -> RecordField {
+    RecordField {
+        name,
+        ty,
+        nullable: false,
+        only_with: None,
+        description,
+    }
+}
+
+const fn nullable(name: &'static str, ty: &'static str, description: &'static str) -> RecordField {
+    RecordField {
+        nullable: true,
+        ..field(name, ty, description)
+    }
+}
+
+const ISO_FIELD: RecordField = RecordField {
+    only_with: Some("--iso-dates"),
+    ..field("iso", "string", "RFC 3339 local time")
+};
+
+/// Every stored column of a history row, as `export` and `show` print them. The
+/// other row schemas are built from this, and unit tests hold each one to the keys
+/// its serde struct (`HistoryRecord`, `StoredRecord`) actually serializes.
+const STORED_FIELDS: &[RecordField] = &[
+    field("id", "integer", "Row id in the history table"),
+    nullable("hist_id", "integer", "Shell history number, if known"),
+    field("epoch", "integer", "Unix timestamp the command ran at"),
+    field("ppid", "integer", "Process id of the session's shell"),
+    field("pwd", "string", "Working directory"),
+    field("salt", "integer", "Per-session random salt"),
+    field("cmd", "string", "Command line"),
+    nullable(
+        "exit_code",
+        "integer",
+        "Exit status, if the hook reported it",
+    ),
+    nullable(
+        "duration_ms",
+        "integer",
+        "Run time in milliseconds, if known",
+    ),
+    nullable("host", "string", "Machine the command ran on"),
+    nullable("tty", "string", "Terminal the command ran in"),
+    nullable(
+        "gitroot",
+        "string",
+        "Git repository root of pwd ([log] track_gitroot)",
+    ),
+    nullable(
+        "seq",
+        "integer",
+        "Position of the command within its session",
+    ),
+    nullable(
+        "env",
+        "object",
+        "Variables recorded with log --capture-env, name to value",
+    ),
+];
+
+/// Session columns, which `list`/`search` only print with `--verbose-json`.
+const SESSION_FIELDS: [&str; 3] = ["hist_id", "salt", "ppid"];
+
+/// Bookkeeping columns only `show` prints.
+const SHOW_EXTRA_FIELDS: &[RecordField] = &[
+    nullable(
+        "inserted_at",
+        "integer",
+        "Unix timestamp sdbh stored the row at",
+    ),
+    field(
+        "synthetic",
+        "boolean",
+        "Whether import-history made up the epoch",
+    ),
+];
+
+const DOCTOR_FIELDS: &[RecordField] = &[
+    field("check", "string", "Check name"),
+    field("status", "string", "One of ok, warn, fail, info"),
+    field("detail", "string", "Human-readable result"),
+];
+
+impl RecordKind {
+    const ALL: [RecordKind; 5] = [
+        Self::List,
+        Self::Search,
+        Self::Export,
+        Self::Doctor,
+        Self::Show,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::List => "list",
+            Self::Search => "search",
+            Self::Export => "export",
+            Self::Doctor => "doctor",
+            Self::Show => "show",
+        }
+    }
+
+    fn fields(self) -> Vec<RecordField> {
+        match self {
+            Self::List | Self::Search => STORED_FIELDS
+                .iter()
+                .filter(|f| ["id", "epoch", "pwd", "cmd"].contains(&f.name))
+                .copied()
+                .chain(STORED_FIELDS.iter().filter_map(|f| {
+                    SESSION_FIELDS.contains(&f.name).then_some(RecordField {
+                        only_with: Some("--verbose-json"),
+                        ..*f
+                    })
+                }))
+                .chain([ISO_FIELD])
+                .collect(),
+            Self::Export => STORED_FIELDS.iter().copied().chain([ISO_FIELD]).collect(),
+            Self::Doctor => DOCTOR_FIELDS.to_vec(),
+            Self::Show => [STORED_FIELDS, SHOW_EXTRA_FIELDS].concat(),
+        }
+    }
+
+    /// `list`/`search` print one JSON array; `export` writes JSON Lines,
+    /// `doctor` wraps its records in an object and `show` prints a single object.
+    fn is_array(self) -> bool {
+        matches!(self, Self::List | Self::Search)
+    }
+}
+
+fn record_schema(kind: RecordKind) -> String {
+    let fields = kind.fields();
+    let properties: Vec<String> = fields
+        .iter()
+        .map(|f| {
+            let ty = if f.nullable {
+                format!("[{},\"null\"]", json_string(f.ty))
+            } else {
+                json_string(f.ty)
+            };
+            let description = match f.only_with {
+                Some(flag) => format!("{} (only with {flag})", f.description),
+                None => f.description.to_string(),
+            };
+            format!(
+                "{}:{{\"type\":{},\"description\":{}}}",
+                json_string(f.name),
+                ty,
+                json_string(&description)
+            )
+        })
+        .collect();
+    let required: Vec<String> = fields
+        .iter()
+        .filter(|f| f.only_with.is_none())
+        .map(|f| json_string(f.name))
+        .collect();
+    let object = format!(
+        "{{\"type\":\"object\",\"properties\":{{{}}},\"required\":[{}],\"additionalProperties\":false}}",
+        properties.join(","),
+        required.join(",")
+    );
+    let title = json_string(&format!("sdbh {} output", kind.name()));
+    if kind == RecordKind::Doctor {
+        format!(
+            "{{\"$schema\":\"https://json-schema.org/draft/2020-12/schema\",\"title\":{title},\"type\":\"object\",\"properties\":{{\"checks\":{{\"type\":\"array\",\"items\":{object}}},\"suggestions\":{{\"type\":\"array\",\"items\":{{\"type\":\"string\"}},\"description\":\"Remediation actions for checks that warned or failed\"}}}},\"required\":[\"checks\",\"suggestions\"],\"additionalProperties\":false}}"
+        )
+    } else if kind.is_array() {
+        format!(
+            "{{\"$schema\":\"https://json-schema.org/draft/2020-12/schema\",\"title\":{title},\"type\":\"array\",\"items\":{object}}}"
+        )
+    } else if kind == RecordKind::Show {
+        format!(
+            "{{\"$schema\":\"https://json-schema.org/draft/2020-12/schema\",\"title\":{title},{}",
+            &object[1..]
+        )
+    } else {
+        format!(
+            "{{\"$schema\":\"https://json-schema.org/draft/2020-12/schema\",\"title\":{title},\"description\":\"One object per line (JSON Lines)\",{}",
+            &object[1..]
+        )
+    }
+}
+
+fn cmd_describe(args: DescribeArgs) -> Result<()> {
+    match args.record {
+        Some(kind) => println!("{}", record_schema(kind)),
+        None => {
+            let all: Vec<String> = RecordKind::ALL
+                .iter()
+                .map(|&k| format!("{}:{}", json_string(k.name()), record_schema(k)))
+                .collect();
+            println!("{{{}}}", all.join(","));
         }
     }
+    Ok(())
+}
 
-    fn fail(name: &'static str, detail: String) -> Self {
-        Self {
-            name,
-            status: DoctorStatus::Fail,
-            detail,
+/// Oldest fzf we know handles every flag sdbh passes (`--preview-window`, `--bind`, `--height`, ...).
+const MIN_FZF_VERSION: (u32, u32, u32) = (0, 30, 0);
+
+/// Parse the leading `X.Y[.Z]` out of `fzf --version` output, e.g. `0.44.1 (d7d2ac3)`.
+fn parse_fzf_version(output: &str) -> Option<(u32, u32, u32)> {
+    let token = output.split_whitespace().next()?;
+    let mut parts = token.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    // Patch may carry a suffix (e.g. `0.20.0-devel`) or be missing entirely
+    let patch = parts
+        .next()
+        .map(|p| {
+            p.chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect::<String>()
+        })
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+fn check_fzf_version(fzf_config: &FzfConfig) -> DoctorCheck {
+    let fzf_binary = fzf_config.binary_path.as_deref().unwrap_or("fzf");
+    let Some(path) = which(fzf_binary) else {
+        return DoctorCheck::info(
+            "fzf.version",
+            format!("{fzf_binary} not found (only needed for --fzf)"),
+        );
+    };
+
+    let output = match std::process::Command::new(&path).arg("--version").output() {
+        Ok(o) => String::from_utf8_lossy(&o.stdout).to_string(),
+        Err(e) => {
+            return DoctorCheck::warn(
+                "fzf.version",
+                format!("failed to run {} --version: {e}", path.display()),
+            );
         }
-    }
+    };
 
-    fn info(name: &'static str, detail: String) -> Self {
-        Self {
-            name,
-            status: DoctorStatus::Info,
-            detail,
+    let (maj, min, patch) = MIN_FZF_VERSION;
+    match parse_fzf_version(&output) {
+        Some(v) if v >= MIN_FZF_VERSION => {
+            DoctorCheck::ok("fzf.version", format!("fzf {}.{}.{}", v.0, v.1, v.2))
         }
+        Some(v) => DoctorCheck::warn(
+            "fzf.version",
+            format!(
+                "fzf {}.{}.{} is older than {maj}.{min}.{patch}; some --fzf features may misbehave, please upgrade fzf",
+                v.0, v.1, v.2
+            ),
+        ),
+        None => DoctorCheck::warn(
+            "fzf.version",
+            format!("could not parse fzf version from {:?}", output.trim()),
+        ),
     }
 }
 
-fn check_env_i64(key: &'static str) -> Vec<DoctorCheck> {
-    match std::env::var(key) {
-        Ok(v) => match v.parse::<i64>() {
-            Ok(_) => vec![DoctorCheck::ok(key, format!("{key}={v}"))],
-            Err(_) => vec![DoctorCheck::warn(
-                key,
-                format!("{key} is set but not an integer: {v}"),
-            )],
-        },
-        Err(_) => vec![DoctorCheck::warn(key, format!("{key} is not set"))],
+/// Compare the `sdbh` found on PATH with this binary: after an upgrade a stale copy
+/// earlier in PATH keeps receiving the shell hook's `sdbh log` calls.
+fn check_binary_path() -> DoctorCheck {
+    let running = env!("CARGO_PKG_VERSION");
+    let Some(path) = which("sdbh") else {
+        return DoctorCheck::info(
+            "binary.path",
+            "sdbh not found on PATH (shell hooks need it)".to_string(),
+        );
+    };
+
+    let output = match std::process::Command::new(&path).arg("version").output() {
+        Ok(o) => String::from_utf8_lossy(&o.stdout).to_string(),
+        Err(e) => {
+            return DoctorCheck::warn(
+                "binary.path",
+                format!("failed to run {} version: {e}", path.display()),
+            );
+        }
+    };
+
+    // `sdbh version` prints e.g. `sdbh 0.13.0`
+    let found = output.split_whitespace().nth(1).unwrap_or("").to_string();
+    if found == running {
+        DoctorCheck::ok(
+            "binary.path",
+            format!("{} is version {running}", path.display()),
+        )
+    } else {
+        DoctorCheck::warn(
+            "binary.path",
+            format!(
+                "{} reports {:?} but this sdbh is {running}; shell hooks may log with a stale binary",
+                path.display(),
+                output.trim()
+            ),
+        )
     }
 }
 
-fn status_str(s: DoctorStatus) -> &'static str {
-    match s {
-        DoctorStatus::Ok => "ok",
-        DoctorStatus::Warn => "warn",
-        DoctorStatus::Fail => "fail",
-        DoctorStatus::Info => "info",
-    }
+fn which(bin: &str) -> Option<std::path::PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    which_in(bin, &path)
 }
 
-fn output_doctor(checks: &[DoctorCheck], format: OutputFormat) {
-    match format {
-        OutputFormat::Table => {
-            for c in checks {
-                println!("{:18} | {:5} | {}", c.name, status_str(c.status), c.detail);
-            }
-        }
-        OutputFormat::Json => {
-            print!("[");
-            let mut first = true;
-            for c in checks {
-                if !first {
-                    print!(",");
-                }
-                first = false;
-                print!(
-                    "{{\"check\":{},\"status\":{},\"detail\":{}}}",
-                    json_string(c.name),
-                    json_string(status_str(c.status)),
-                    json_string(&c.detail)
-                );
+fn which_in(bin: &str, path: &std::ffi::OsStr) -> Option<std::path::PathBuf> {
+    // On Windows `fzf` is really `fzf.exe`, so try each PATHEXT suffix as well.
+    let mut candidates = vec![bin.to_string()];
+    if cfg!(windows) && std::path::Path::new(bin).extension().is_none() {
+        let pathext = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into());
+        candidates.extend(
+            pathext
+                .split(';')
+                .filter(|ext| !ext.is_empty())
+                .map(|ext| format!("{bin}{}", ext.to_lowercase())),
+        );
+    }
+
+    for dir in std::env::split_paths(path) {
+        for name in &candidates {
+            let p = dir.join(name);
+            if is_executable(&p) {
+                return Some(p);
             }
-            println!("]");
         }
     }
+    None
 }
 
-fn which(bin: &str) -> Option<std::path::PathBuf> {
-    let path = std::env::var_os("PATH")?;
-    for dir in std::env::split_paths(&path) {
-        let p = dir.join(bin);
-        if p.exists() {
-            return Some(p);
-        }
+#[cfg(unix)]
+fn is_executable(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &std::path::Path) -> bool {
+    path.is_file()
+}
+
+/// Report how many times `hook` appears in `value`; more than once usually
+/// means the integration snippet was sourced twice (every command gets logged twice).
+fn hook_check(name: &'static str, source: &str, value: &str, hook: &str) -> DoctorCheck {
+    match value.matches(hook).count() {
+        0 => DoctorCheck::info(name, format!("{source} does not contain {hook}")),
+        1 => DoctorCheck::ok(name, format!("{source} contains {hook} (1 occurrence)")),
+        n => DoctorCheck::warn(
+            name,
+            format!(
+                "{source} contains {hook} {n} times (duplicate hook; check your shell rc for repeated sdbh setup)"
+            ),
+        ),
+    }
+}
+
+fn function_check(name: &'static str, func: &str, defined: bool) -> DoctorCheck {
+    if defined {
+        DoctorCheck::ok(name, format!("function {func} is defined"))
+    } else {
+        DoctorCheck::info(name, format!("function {func} is not defined"))
     }
-    None
 }
 
 #[derive(Debug)]
 struct BashInspect {
     prompt_command: String,
     trap_debug: String,
+    prompt_fn_defined: bool,
+    /// `None` when HISTTIMEFORMAT is unset
+    histtimeformat: Option<String>,
 }
 
 impl BashInspect {
     fn summary(&self) -> String {
         format!(
-            "prompt_command_len={}, trap_debug_len={}",
+            "prompt_command_len={}, trap_debug_len={}, prompt_hook_count={}",
             self.prompt_command.len(),
-            self.trap_debug.len()
+            self.trap_debug.len(),
+            self.prompt_command.matches("__sdbh_prompt").count()
         )
     }
 }
@@ -2003,13 +5776,17 @@ fn spawn_bash_inspect(bash: &std::path::Path) -> Result<BashInspect> {
     let out = std::process::Command::new(bash)
         .args([
             "-lc",
-            "echo __SDBH_PROMPT_COMMAND__=$PROMPT_COMMAND; echo __SDBH_TRAP_DEBUG__=$(trap -p DEBUG)",
+            "echo __SDBH_PROMPT_COMMAND__=$PROMPT_COMMAND; echo __SDBH_TRAP_DEBUG__=$(trap -p DEBUG); \
+             if declare -F __sdbh_prompt >/dev/null; then echo __SDBH_PROMPT_FN__=1; else echo __SDBH_PROMPT_FN__=0; fi; \
+             if [ -n \"${HISTTIMEFORMAT+set}\" ]; then echo \"__SDBH_HISTTIMEFORMAT__=$HISTTIMEFORMAT\"; fi",
         ])
         .output()?;
 
     let stdout = String::from_utf8_lossy(&out.stdout);
     let mut prompt_command = String::new();
     let mut trap_debug = String::new();
+    let mut prompt_fn_defined = false;
+    let mut histtimeformat = None;
 
     for line in stdout.lines() {
         if let Some(v) = line.strip_prefix("__SDBH_PROMPT_COMMAND__=") {
@@ -2018,26 +5795,56 @@ fn spawn_bash_inspect(bash: &std::path::Path) -> Result<BashInspect> {
         if let Some(v) = line.strip_prefix("__SDBH_TRAP_DEBUG__=") {
             trap_debug = v.to_string();
         }
+        if let Some(v) = line.strip_prefix("__SDBH_PROMPT_FN__=") {
+            prompt_fn_defined = v == "1";
+        }
+        if let Some(v) = line.strip_prefix("__SDBH_HISTTIMEFORMAT__=") {
+            histtimeformat = Some(v.to_string());
+        }
     }
 
     Ok(BashInspect {
         prompt_command,
         trap_debug,
+        prompt_fn_defined,
+        histtimeformat,
     })
 }
 
+/// The bash hook reads the epoch from `history 1`, which only carries one when
+/// HISTTIMEFORMAT starts with `%s`.
+fn histtimeformat_check(value: Option<&str>) -> DoctorCheck {
+    match value {
+        Some(v) if v.starts_with("%s") => {
+            DoctorCheck::ok("bash.histtimeformat", format!("HISTTIMEFORMAT={v:?}"))
+        }
+        Some(v) => DoctorCheck::warn(
+            "bash.histtimeformat",
+            format!(
+                "HISTTIMEFORMAT={v:?} does not start with %s; bash hook timestamps will be wrong"
+            ),
+        ),
+        None => DoctorCheck::warn(
+            "bash.histtimeformat",
+            "HISTTIMEFORMAT is not set; bash hook timestamps will be wrong".to_string(),
+        ),
+    }
+}
+
 #[derive(Debug)]
 struct ZshInspect {
     precmd_functions: String,
     preexec_functions: String,
+    precmd_fn_defined: bool,
 }
 
 impl ZshInspect {
     fn summary(&self) -> String {
         format!(
-            "precmd_len={}, preexec_len={}",
+            "precmd_len={}, preexec_len={}, precmd_hook_count={}",
             self.precmd_functions.len(),
-            self.preexec_functions.len()
+            self.preexec_functions.len(),
+            self.precmd_functions.matches("sdbh_precmd").count()
         )
     }
 }
@@ -2046,13 +5853,15 @@ fn spawn_zsh_inspect(zsh: &std::path::Path) -> Result<ZshInspect> {
     let out = std::process::Command::new(zsh)
         .args([
             "-lc",
-            "echo __SDBH_PRECMD__=${precmd_functions[*]}; echo __SDBH_PREEXEC__=${preexec_functions[*]}",
+            "echo __SDBH_PRECMD__=${precmd_functions[*]}; echo __SDBH_PREEXEC__=${preexec_functions[*]}; \
+             if (( ${+functions[sdbh_precmd]} )); then echo __SDBH_PRECMD_FN__=1; else echo __SDBH_PRECMD_FN__=0; fi",
         ])
         .output()?;
 
     let stdout = String::from_utf8_lossy(&out.stdout);
     let mut precmd_functions = String::new();
     let mut preexec_functions = String::new();
+    let mut precmd_fn_defined = false;
 
     for line in stdout.lines() {
         if let Some(v) = line.strip_prefix("__SDBH_PRECMD__=") {
@@ -2061,14 +5870,30 @@ fn spawn_zsh_inspect(zsh: &std::path::Path) -> Result<ZshInspect> {
         if let Some(v) = line.strip_prefix("__SDBH_PREEXEC__=") {
             preexec_functions = v.to_string();
         }
+        if let Some(v) = line.strip_prefix("__SDBH_PRECMD_FN__=") {
+            precmd_fn_defined = v == "1";
+        }
     }
 
     Ok(ZshInspect {
         precmd_functions,
         preexec_functions,
+        precmd_fn_defined,
     })
 }
 
+/// Nushell's configured hooks, with closures serialized to their source so we can
+/// look for the `sdbh log` call from `sdbh shell --nu`.
+fn spawn_nu_hooks(nu: &std::path::Path) -> Result<String> {
+    let out = std::process::Command::new(nu)
+        .args(["-l", "-c", "$env.config.hooks | to nuon --serialize"])
+        .output()?;
+    if !out.status.success() {
+        anyhow::bail!("{}", String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
 #[derive(Debug, Clone)]
 struct HistoryEntry {
     epoch: Option<i64>,
@@ -2143,7 +5968,24 @@ fn read_zsh_history(path: &std::path::Path) -> Result<Vec<HistoryEntry>> {
     Ok(out)
 }
 
-fn cmd_preview(cfg: DbConfig, args: PreviewArgs) -> Result<()> {
+/// Emoji and rules in preview output: only for a person reading a terminal or an
+/// fzf preview pane (which is a pipe, but sets FZF_PREVIEW_COLUMNS).
+fn decorations_enabled(no_color: bool) -> bool {
+    !no_color
+        && std::env::var_os("NO_COLOR").is_none()
+        && (atty::is(atty::Stream::Stdout) || std::env::var_os("FZF_PREVIEW_COLUMNS").is_some())
+}
+
+/// A preview section heading, with its emoji prefix only when decorating.
+fn heading(decorate: bool, emoji: &str, text: &str) -> String {
+    if decorate {
+        format!("{emoji}{text}")
+    } else {
+        text.to_string()
+    }
+}
+
+fn cmd_preview(cfg: DbConfig, args: PreviewArgs, decorate: bool) -> Result<()> {
     let conn = open_db(&cfg)?;
 
     // Get command statistics
@@ -2173,8 +6015,9 @@ fn cmd_preview(cfg: DbConfig, args: PreviewArgs) -> Result<()> {
             return Ok(());
         }
 
-        // Detect terminal width for responsive design
-        let term_width = get_terminal_width().unwrap_or(80);
+        // Detect terminal width for responsive design (fzf preview panes often misreport it)
+        let preview_cfg = load_preview_config();
+        let term_width = resolve_preview_width(args.width, preview_cfg.width, get_terminal_width());
 
         // Format timestamps
         let last_used = last_used_epoch
@@ -2188,14 +6031,19 @@ fn cmd_preview(cfg: DbConfig, args: PreviewArgs) -> Result<()> {
         let cmd_type = CommandType::detect(&args.command);
 
         // Phase 3: Professional Layout with Organized Sections
-        println!(
-            "🔍 Command Analysis: {}",
-            truncate_for_display(&args.command, term_width - 25)
+        let title = format!(
+            "Command Analysis: {}",
+            truncate_for_display(&args.command, term_width.saturating_sub(25))
         );
-        println!("{}", "━".repeat(term_width.min(80)));
+        println!("{}", heading(decorate, "🔍 ", &title));
+        if decorate {
+            println!("{}", "━".repeat(term_width.min(80)));
+        }
+        // Stable anchor for scripts: the full command, never truncated
+        println!("Command: {}", args.command);
 
         // 📊 Usage Statistics Section
-        println!("📊 Usage Statistics");
+        println!("{}", heading(decorate, "📊 ", "Usage Statistics"));
         println!("  Total uses: {}", total_uses);
         println!("  First used: {}", first_used);
         println!("  Last used: {}", last_used);
@@ -2203,17 +6051,21 @@ fn cmd_preview(cfg: DbConfig, args: PreviewArgs) -> Result<()> {
 
         // ℹ️ Context Information Section
         if let Some(context) = get_command_context(&args.command, cmd_type) {
-            println!("\nℹ️  Context: {}", context);
+            let context = format!("Context: {context}");
+            println!("\n{}", heading(decorate, "ℹ️  ", &context));
         }
 
         // 📁 Directory Usage Section
         if let Some(dirs) = dirs {
             let dir_list: Vec<&str> = dirs.split(',').collect();
             if !dir_list.is_empty() {
-                println!("\n📁 Directory Usage:");
+                println!("\n{}", heading(decorate, "📁 ", "Directory Usage:"));
                 let max_dirs = if term_width > 120 { 8 } else { 5 };
                 for dir in dir_list.iter().take(max_dirs) {
-                    println!("  • {}", truncate_for_display(dir, term_width - 6));
+                    println!(
+                        "  • {}",
+                        truncate_for_display(dir, term_width.saturating_sub(6))
+                    );
                 }
                 if dir_list.len() > max_dirs {
                     println!("  … and {} more", dir_list.len() - max_dirs);
@@ -2222,7 +6074,10 @@ fn cmd_preview(cfg: DbConfig, args: PreviewArgs) -> Result<()> {
         }
 
         // 🕒 Recent Activity Section
-        println!("\n🕒 Recent Activity (Last 5 executions):");
+        println!(
+            "\n{}",
+            heading(decorate, "🕒 ", "Recent Activity (Last 5 executions):")
+        );
         let mut recent_stmt = conn.prepare(
             "SELECT id, epoch, pwd, cmd
              FROM history
@@ -2287,7 +6142,12 @@ fn cmd_preview(cfg: DbConfig, args: PreviewArgs) -> Result<()> {
         }
 
         // 🔗 Related Commands Section
-        show_related_commands(&conn, &args.command, cmd_type)?;
+        // 🕐 Time of Day Section
+        if preview_cfg.show_hours.unwrap_or(true) {
+            show_hour_histogram(&conn, &args.command, decorate)?;
+        }
+
+        show_related_commands(&conn, &args.command, cmd_type, decorate)?;
     } else {
         println!("Command '{}' not found in history", args.command);
     }
@@ -2295,9 +6155,23 @@ fn cmd_preview(cfg: DbConfig, args: PreviewArgs) -> Result<()> {
     Ok(())
 }
 
+/// `YYYY-MM-DD` (UTC) for epochs `time` cannot format, e.g. years past 9999.
+/// Plain integer arithmetic (days-to-civil), so it works for every `i64`.
 fn format_timestamp(epoch: i64) -> String {
-    // Simple timestamp formatting - could be enhanced
-    format!("{}", epoch)
+    if std::env::var("SDBH_DEBUG").ok().as_deref() == Some("1") {
+        eprintln!("time: formatting epoch {epoch} without the time crate");
+    }
+    let z = i128::from(epoch.div_euclid(86_400)) + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i128::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
 }
 
 fn format_relative_time(epoch: i64) -> String {
@@ -2306,7 +6180,7 @@ fn format_relative_time(epoch: i64) -> String {
     let now = OffsetDateTime::now_utc();
     let now_epoch = now.unix_timestamp();
 
-    let diff_secs = now_epoch - epoch;
+    let diff_secs = now_epoch.saturating_sub(epoch);
 
     if diff_secs < 0 {
         return "in the future".to_string();
@@ -2512,51 +6386,77 @@ fn show_related_commands(
     conn: &rusqlite::Connection,
     base_cmd: &str,
     cmd_type: CommandType,
+    decorate: bool,
 ) -> Result<()> {
-    let mut suggestions = Vec::new();
-
-    // 1. Semantic similarity: Find commands with related purposes
-    let semantic_suggestions = find_semantic_related_commands(base_cmd, cmd_type);
-    suggestions.extend(semantic_suggestions);
-
-    // 2. Same tool variations: Commands starting with same tool (current behavior)
-    let tool_suggestions = find_tool_related_commands(conn, base_cmd)?;
-    suggestions.extend(tool_suggestions);
-
-    // 3. Workflow patterns: Commands commonly used in same sessions
-    let workflow_suggestions = find_workflow_related_commands(conn, base_cmd)?;
-    suggestions.extend(workflow_suggestions);
+    // Gather candidates per source; rank_related_commands orders them by source.
+    let sources = vec![
+        (
+            RelatedSource::Semantic,
+            find_semantic_related_commands(base_cmd, cmd_type),
+        ),
+        (
+            RelatedSource::Tool,
+            find_tool_related_commands(conn, base_cmd)?,
+        ),
+        (
+            RelatedSource::Workflow,
+            find_workflow_related_commands(conn, base_cmd)?,
+        ),
+        (
+            RelatedSource::Directory,
+            find_directory_related_commands(conn, base_cmd)?,
+        ),
+    ];
+    let ranked = rank_related_commands(base_cmd, sources, 5);
 
-    // 4. Directory-based: Commands used in same directories
-    let directory_suggestions = find_directory_related_commands(conn, base_cmd)?;
-    suggestions.extend(directory_suggestions);
+    if !ranked.is_empty() {
+        println!("\n{}", heading(decorate, "🔗 ", "Related Commands"));
+        for (cmd, source) in ranked.iter() {
+            // Truncate long commands for display
+            println!("  {} {}", truncate_for_display(cmd, 60), source.label());
+        }
+    }
 
-    // Remove duplicates and the base command itself
-    let mut unique_suggestions: Vec<String> = suggestions
-        .into_iter()
-        .filter(|cmd| cmd != base_cmd)
-        .collect::<std::collections::HashSet<_>>()
-        .into_iter()
-        .collect();
+    Ok(())
+}
 
-    // Sort by relevance (semantic first, then tool, workflow, directory)
-    // For now, just limit to 5 most relevant
-    unique_suggestions.truncate(5);
+/// Why a command shows up under "Related Commands", in ranking order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum RelatedSource {
+    Semantic,
+    Workflow,
+    Tool,
+    Directory,
+}
 
-    if !unique_suggestions.is_empty() {
-        println!("\n🔗 Related Commands");
-        for cmd in unique_suggestions.iter() {
-            // Truncate long commands for display
-            let display_cmd = if cmd.len() > 60 {
-                format!("{}...", &cmd[..57])
-            } else {
-                cmd.clone()
-            };
-            println!("  {}", display_cmd);
+impl RelatedSource {
+    fn label(self) -> &'static str {
+        match self {
+            RelatedSource::Semantic => "(related task)",
+            RelatedSource::Workflow => "(often used together)",
+            RelatedSource::Tool => "(same tool)",
+            RelatedSource::Directory => "(same directory)",
         }
     }
+}
 
-    Ok(())
+/// Order suggestions by source priority (keeping each source's own order), drop
+/// the base command and duplicates (a command keeps its best-ranked source), and
+/// keep at most `limit`.
+fn rank_related_commands(
+    base_cmd: &str,
+    mut sources: Vec<(RelatedSource, Vec<String>)>,
+    limit: usize,
+) -> Vec<(String, RelatedSource)> {
+    sources.sort_by_key(|(source, _)| *source);
+
+    let mut seen = std::collections::HashSet::new();
+    sources
+        .into_iter()
+        .flat_map(|(source, cmds)| cmds.into_iter().map(move |cmd| (cmd, source)))
+        .filter(|(cmd, _)| cmd != base_cmd && seen.insert(cmd.clone()))
+        .take(limit)
+        .collect()
 }
 
 fn find_semantic_related_commands(base_cmd: &str, cmd_type: CommandType) -> Vec<String> {
@@ -2732,18 +6632,108 @@ fn find_directory_related_commands(
 
 // Phase 3: Helper functions for responsive design and enhanced display
 
+/// Render counts as a one-character-per-value bar chart; zero values stay blank.
+fn sparkline(values: &[i64]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = values.iter().copied().max().unwrap_or(0);
+    values
+        .iter()
+        .map(|&v| {
+            if v <= 0 || max <= 0 {
+                ' '
+            } else {
+                BARS[((v * 7) / max) as usize]
+            }
+        })
+        .collect()
+}
+
+/// The busiest window of up to three consecutive hours, trimmed of empty edges.
+fn peak_hours(hours: &[i64; 24]) -> Option<(usize, usize)> {
+    let start = (0..24).max_by_key(|&h| {
+        let window: i64 = (h..h + 3).map(|i| hours[i % 24]).sum();
+        // Prefer the earliest start on ties
+        (window, std::cmp::Reverse(h))
+    })?;
+    let mut span: Vec<usize> = (start..start + 3).map(|i| i % 24).collect();
+    while span.first().is_some_and(|&h| hours[h] == 0) {
+        span.remove(0);
+    }
+    while span.last().is_some_and(|&h| hours[h] == 0) {
+        span.pop();
+    }
+    Some((*span.first()?, *span.last()?))
+}
+
+fn show_hour_histogram(conn: &rusqlite::Connection, cmd: &str, decorate: bool) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "SELECT CAST(strftime('%H', epoch, 'unixepoch', 'localtime') AS INTEGER) as hour, COUNT(*)
+         FROM history
+         WHERE cmd = ?1
+         GROUP BY hour",
+    )?;
+    let mut rows = stmt.query([cmd])?;
+    let mut hours = [0i64; 24];
+    while let Some(r) = rows.next()? {
+        let hour: i64 = r.get(0)?;
+        let cnt: i64 = r.get(1)?;
+        if let Some(slot) = hours.get_mut(hour as usize) {
+            *slot = cnt;
+        }
+    }
+
+    let Some((start, end)) = peak_hours(&hours) else {
+        return Ok(());
+    };
+
+    println!("\n{}", heading(decorate, "🕐 ", "Time of Day:"));
+    println!("  │{}│", sparkline(&hours));
+    println!("   0     6     12    18  23");
+    if start == end {
+        println!("  Usually run: {start:02}h");
+    } else {
+        println!("  Usually run: {start:02}–{end:02}h");
+    }
+    Ok(())
+}
+
 fn get_terminal_width() -> Option<usize> {
     terminal_size::terminal_size().map(|(terminal_size::Width(w), _)| w as usize)
 }
 
+/// `--width` wins over `[preview] width`, which wins over the detected terminal width.
+fn resolve_preview_width(
+    cli: Option<usize>,
+    config: Option<usize>,
+    detected: Option<usize>,
+) -> usize {
+    cli.or(config).or(detected).unwrap_or(80)
+}
+
+/// Truncate to `max_width` terminal columns, cutting on a char boundary so
+/// multibyte commands/paths never panic.
 fn truncate_for_display(text: &str, max_width: usize) -> String {
-    if text.len() <= max_width {
-        text.to_string()
-    } else if max_width <= 3 {
-        "...".to_string()
-    } else {
-        format!("{}...", &text[..max_width.saturating_sub(3)])
+    use unicode_width::UnicodeWidthChar;
+
+    if unicode_width::UnicodeWidthStr::width(text) <= max_width {
+        return text.to_string();
+    }
+    if max_width <= 3 {
+        return "...".to_string();
+    }
+
+    let budget = max_width - 3;
+    let mut used = 0;
+    let mut end = 0;
+    for (idx, ch) in text.char_indices() {
+        let w = ch.width().unwrap_or(0);
+        if used + w > budget {
+            break;
+        }
+        used += w;
+        end = idx + ch.len_utf8();
     }
+    format!("{}...", &text[..end])
 }
 
 fn get_command_context(cmd: &str, cmd_type: CommandType) -> Option<String> {
@@ -2810,9 +6800,10 @@ fn get_command_context(cmd: &str, cmd_type: CommandType) -> Option<String> {
 }
 
 fn cmd_shell(args: ShellArgs) -> Result<()> {
-    // Default: print both if neither specified
-    let want_bash = args.bash || !args.zsh;
-    let want_zsh = args.zsh || !args.bash;
+    // Default: print bash and zsh if no shell specified
+    let none = !args.bash && !args.zsh && !args.nu && !args.powershell;
+    let want_bash = args.bash || none;
+    let want_zsh = args.zsh || none;
 
     if args.intercept {
         if want_bash {
@@ -2830,6 +6821,12 @@ fn cmd_shell(args: ShellArgs) -> Result<()> {
     if want_zsh {
         println!("{}", zsh_hook_snippet());
     }
+    if args.nu {
+        println!("{}", nu_hook_snippet());
+    }
+    if args.powershell {
+        println!("{}", pwsh_hook_snippet());
+    }
 
     Ok(())
 }
@@ -2842,6 +6839,8 @@ export SDBH_SALT=${RANDOM}
 export SDBH_PPID=$PPID
 
 __sdbh_prompt() {
+  # Must be read first, before anything else overwrites $?
+  local exit_code=$?
   [[ -n "${COMP_LINE}" ]] && return
 
   local line
@@ -2867,7 +6866,7 @@ __sdbh_prompt() {
   [[ -z "${cmd}" ]] && return
   [[ ! "${epoch}" =~ ^[0-9]+$ ]] && return
 
-  sdbh log --hist-id "${hist_id}" --epoch "${epoch}" --ppid "${PPID}" --pwd "${PWD}" --salt "${SDBH_SALT}" --cmd "${cmd}" 2>/dev/null || true
+  sdbh log --hist-id "${hist_id}" --epoch "${epoch}" --ppid "${PPID}" --pwd "${PWD}" --salt "${SDBH_SALT}" --seq "$((++__SDBH_SEQ))" --capture-env "${SDBH_CAPTURE_ENV:-}" --exit-code "${exit_code}" --cmd "${cmd}" 2>/dev/null || true
 }
 
 if ! [[ "${PROMPT_COMMAND}" =~ __sdbh_prompt ]]; then
@@ -2885,11 +6884,12 @@ export SDBH_SALT=$RANDOM
 export SDBH_PPID=$$
 
 sdbh_precmd() {
+  local exit_code=$?
   local cmd epoch
   cmd="$(fc -ln -1)"
   epoch="$(date +%s)"
   [[ -z "${cmd}" ]] && return
-  sdbh log --epoch "${epoch}" --ppid "$$" --pwd "${PWD}" --salt "${SDBH_SALT}" --cmd "${cmd}" 2>/dev/null || true
+  sdbh log --epoch "${epoch}" --ppid "$$" --pwd "${PWD}" --salt "${SDBH_SALT}" --seq "$((++__SDBH_SEQ))" --capture-env "${SDBH_CAPTURE_ENV:-}" --exit-code "${exit_code}" --cmd "${cmd}" 2>/dev/null || true
 }
 
 autoload -Uz add-zsh-hook
@@ -2898,6 +6898,83 @@ add-zsh-hook precmd sdbh_precmd
     .to_string()
 }
 
+fn nu_hook_snippet() -> String {
+    r#"# sdbh nushell hook mode
+# Add to config.nu (`config nu` opens it).
+# pre_execution remembers the command line; pre_prompt logs it once it has finished,
+# which is equivalent to:
+#   sdbh log --cmd <line> --epoch <start, unix seconds> --ppid <$nu.pid> --pwd <dir>
+#            --salt $env.SDBH_SALT --exit-code $env.LAST_EXIT_CODE --duration-ms <ms>
+#            --capture-env $env.SDBH_CAPTURE_ENV
+
+$env.SDBH_SALT = (random int 0..32767 | into string)
+$env.SDBH_PPID = ($nu.pid | into string)
+
+$env.config = ($env.config | upsert hooks.pre_execution (
+  ($env.config.hooks.pre_execution? | default []) | append {||
+    $env.__SDBH_CMD = (commandline)
+    $env.__SDBH_PWD = $env.PWD
+    $env.__SDBH_START = (date now)
+  }
+))
+
+$env.config = ($env.config | upsert hooks.pre_prompt (
+  ($env.config.hooks.pre_prompt? | default []) | append {||
+    let cmd = ($env.__SDBH_CMD? | default "")
+    $env.__SDBH_CMD = ""
+    if ($cmd | str trim | is-empty) or ($cmd | str starts-with "sdbh") { return }
+    let start = $env.__SDBH_START
+    (^sdbh log
+      --cmd $cmd
+      --epoch ($start | format date "%s")
+      --ppid $env.SDBH_PPID
+      --pwd $env.__SDBH_PWD
+      --salt $env.SDBH_SALT
+      --exit-code ($env.LAST_EXIT_CODE | into string)
+      --duration-ms (((date now) - $start) / 1ms | math floor | into string)
+      --capture-env ($env.SDBH_CAPTURE_ENV? | default "")
+    ) | complete | ignore
+  }
+))
+"#
+    .to_string()
+}
+
+fn pwsh_hook_snippet() -> String {
+    r#"# sdbh PowerShell hook mode
+# Add to $PROFILE (`notepad $PROFILE` opens it).
+# Wraps the prompt function and logs the last history item once it has finished.
+# Windows has no parent-shell pid like bash's $PPID, so this shell's own $PID
+# groups commands into a session (SDBH_PPID), next to a random SDBH_SALT.
+
+$env:SDBH_SALT = [string](Get-Random -Maximum 32768)
+$env:SDBH_PPID = [string]$PID
+
+$global:__SdbhLastId = $null
+$global:__SdbhPrompt = $function:prompt
+
+function global:prompt {
+  # Must be read first, before anything else overwrites them
+  $ok = $?
+  $lastExit = $global:LASTEXITCODE
+  $code = if ($ok) { 0 } elseif ($lastExit) { $lastExit } else { 1 }
+
+  $item = Get-History -Count 1
+  if ($item -and $item.Id -ne $global:__SdbhLastId -and $item.CommandLine -notlike 'sdbh*') {
+    $global:__SdbhLastId = $item.Id
+    $epoch = ([DateTimeOffset]$item.StartExecutionTime).ToUnixTimeSeconds()
+    $ms = [int64]($item.EndExecutionTime - $item.StartExecutionTime).TotalMilliseconds
+    sdbh log --hist-id $item.Id --epoch $epoch --ppid $PID --pwd $PWD.Path --salt $env:SDBH_SALT --exit-code $code --duration-ms $ms "--capture-env=$env:SDBH_CAPTURE_ENV" --cmd $item.CommandLine 2>$null | Out-Null
+    # Keep the user's $LASTEXITCODE rather than sdbh's
+    $global:LASTEXITCODE = $lastExit
+  }
+
+  & $global:__SdbhPrompt
+}
+"#
+    .to_string()
+}
+
 fn bash_intercept_snippet() -> String {
     r#"# sdbh bash intercept mode (more invasive)
 # Uses DEBUG trap to log each command before it runs.
@@ -2909,6 +6986,14 @@ export SDBH_PPID=$PPID
 __sdbh_debug_trap() {
   # Avoid recursion
   [[ -n "${__SDBH_IN_TRAP}" ]] && return
+
+  # Skip scripts and subshells (non-interactive or BASH_SUBSHELL > 0) so their
+  # internal commands don't flood history. Set SDBH_LOG_NONINTERACTIVE=1 to log them anyway.
+  if [[ -z "${SDBH_LOG_NONINTERACTIVE}" ]]; then
+    [[ $- != *i* ]] && return
+    (( BASH_SUBSHELL > 0 )) && return
+  fi
+
   __SDBH_IN_TRAP=1
 
   local cmd epoch
@@ -2919,42 +7004,184 @@ __sdbh_debug_trap() {
   [[ -z "${cmd}" ]] && __SDBH_IN_TRAP= && return
   [[ "${cmd}" == sdbh* ]] && __SDBH_IN_TRAP= && return
 
-  sdbh log --epoch "${epoch}" --ppid "${PPID}" --pwd "${PWD}" --salt "${SDBH_SALT}" --cmd "${cmd}" 2>/dev/null || true
-  __SDBH_IN_TRAP=
+  sdbh log --epoch "${epoch}" --ppid "${PPID}" --pwd "${PWD}" --salt "${SDBH_SALT}" --seq "$((++__SDBH_SEQ))" --capture-env "${SDBH_CAPTURE_ENV:-}" --cmd "${cmd}" 2>/dev/null || true
+  __SDBH_IN_TRAP=
+}
+
+trap '__sdbh_debug_trap' DEBUG
+"#
+    .to_string()
+}
+
+fn zsh_intercept_snippet() -> String {
+    r#"# sdbh zsh intercept mode (more invasive)
+# Uses preexec/precmd to log each command with its exit status and duration.
+# Add to ~/.zshrc
+
+export SDBH_SALT=$RANDOM
+export SDBH_PPID=$$
+
+zmodload zsh/datetime
+
+# preexec captures the command and start time; precmd logs it once it has
+# finished so the exit status and duration can be recorded.
+function sdbh_preexec() {
+  local cmd="$1"
+  [[ -z "${cmd}" ]] && return
+  [[ "${cmd}" == sdbh* ]] && return
+  __sdbh_cmd="${cmd}"
+  __sdbh_pwd="${PWD}"
+  __sdbh_start="${EPOCHREALTIME}"
+}
+
+function sdbh_intercept_precmd() {
+  local exit_code=$?
+  [[ -z "${__sdbh_cmd}" ]] && return
+  local -i duration_ms=$(( (EPOCHREALTIME - __sdbh_start) * 1000 ))
+  sdbh log --epoch "${__sdbh_start%.*}" --ppid "$$" --pwd "${__sdbh_pwd}" --salt "${SDBH_SALT}" --seq "$((++__SDBH_SEQ))" --capture-env "${SDBH_CAPTURE_ENV:-}" --exit-code "${exit_code}" --duration-ms "${duration_ms}" --cmd "${__sdbh_cmd}" 2>/dev/null || true
+  __sdbh_cmd=
+}
+
+autoload -Uz add-zsh-hook
+add-zsh-hook preexec sdbh_preexec
+add-zsh-hook precmd sdbh_intercept_precmd
+"#
+    .to_string()
+}
+
+fn escape_like(s: &str) -> String {
+    // Escape LIKE wildcards and backslash itself
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// ISO-8601 timestamp in local time, falling back to UTC when the local offset
+/// can't be determined.
+fn iso_datetime(epoch: i64) -> String {
+    use time::{OffsetDateTime, UtcOffset, format_description::well_known::Rfc3339};
+
+    let Ok(utc) = OffsetDateTime::from_unix_timestamp(epoch) else {
+        return epoch.to_string();
+    };
+    let offset = UtcOffset::local_offset_at(utc).unwrap_or(UtcOffset::UTC);
+    utc.to_offset(offset)
+        .format(&Rfc3339)
+        .unwrap_or_else(|_| epoch.to_string())
+}
+
+/// Column header for the `id | dt | pwd | cmd` table rows of list/search.
+const HISTORY_TABLE_HEADER: &str = "    ID | DATE                | PWD | CMD";
+
+/// Table headers: forced by --headers, suppressed by --no-headers, otherwise only
+/// when a person is likely reading (stdout is a terminal).
+fn want_headers(headers: bool, no_headers: bool) -> bool {
+    !no_headers && (headers || atty::is(atty::Stream::Stdout))
+}
+
+fn write_table_header(out: &mut impl Write, header: &str) -> Result<()> {
+    writeln!(out, "{header}")?;
+    writeln!(out, "{}", "-".repeat(header.len()))?;
+    Ok(())
+}
+
+/// Write one `--porcelain` row. `list`, `search` and `summary` all emit exactly
+/// `id\tepoch\tpwd\tcmd` through this, so the contract lives in one place.
+fn write_porcelain_row(
+    out: &mut impl Write,
+    id: i64,
+    epoch: i64,
+    pwd: &str,
+    cmd: &str,
+) -> Result<()> {
+    writeln!(
+        out,
+        "{}",
+        porcelain_row(&[&id.to_string(), &epoch.to_string(), pwd, cmd])
+    )?;
+    Ok(())
+}
+
+/// One `--porcelain` line: fields joined by tabs. Backslash, tab, CR and LF inside a
+/// field are written as `\\`, `\t`, `\r` and `\n` so every row stays on one line.
+/// This format is a compatibility contract: fields are only ever appended.
+fn porcelain_row(fields: &[&str]) -> String {
+    fields
+        .iter()
+        .map(|f| {
+            f.replace('\\', "\\\\")
+                .replace('\t', "\\t")
+                .replace('\r', "\\r")
+                .replace('\n', "\\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\t")
 }
 
-trap '__sdbh_debug_trap' DEBUG
-"#
-    .to_string()
+/// Quote a CSV field per RFC 4180: wrap in double quotes when it contains a comma,
+/// quote, CR or LF, doubling any embedded quotes.
+fn csv_field(s: &str) -> std::borrow::Cow<'_, str> {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\"")).into()
+    } else {
+        s.into()
+    }
 }
 
-fn zsh_intercept_snippet() -> String {
-    r#"# sdbh zsh intercept mode (more invasive)
-# Uses preexec to log each command before it runs.
-# Add to ~/.zshrc
+fn csv_row<S: AsRef<str>>(fields: &[S]) -> String {
+    fields
+        .iter()
+        .map(|f| csv_field(f.as_ref()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
 
-export SDBH_SALT=$RANDOM
-export SDBH_PPID=$$
+/// `--format tsv` line: fields joined by tabs. Tabs and line breaks inside a field
+/// become spaces, so each row is one line that splits cleanly on `\t` (e.g. `awk -F'\t'`).
+fn tsv_row<S: AsRef<str>>(fields: &[S]) -> String {
+    fields
+        .iter()
+        .map(|f| f.as_ref().replace(['\t', '\r', '\n'], " "))
+        .collect::<Vec<_>>()
+        .join("\t")
+}
 
-function sdbh_preexec() {
-  local cmd="$1"
-  local epoch="$(date +%s)"
-  [[ -z "${cmd}" ]] && return
-  [[ "${cmd}" == sdbh* ]] && return
-  sdbh log --epoch "${epoch}" --ppid "$$" --pwd "${PWD}" --salt "${SDBH_SALT}" --cmd "${cmd}" 2>/dev/null || true
+/// Header for list/search/last CSV or TSV output.
+fn history_delimited_header(format: OutputFormat, iso_dates: bool) -> String {
+    let header = history_csv_header(iso_dates);
+    match format {
+        OutputFormat::Tsv => header.replace(',', "\t"),
+        _ => header.to_string(),
+    }
 }
 
-autoload -Uz add-zsh-hook
-add-zsh-hook preexec sdbh_preexec
-"#
-    .to_string()
+/// One list/search/last row as CSV or TSV.
+fn history_delimited_row(
+    format: OutputFormat,
+    id: i64,
+    epoch: i64,
+    iso_dates: bool,
+    pwd: &str,
+    cmd: &str,
+) -> String {
+    let mut fields = vec![id.to_string(), epoch.to_string()];
+    if iso_dates {
+        fields.push(iso_datetime(epoch));
+    }
+    fields.extend([pwd.to_string(), cmd.to_string()]);
+    match format {
+        OutputFormat::Tsv => tsv_row(&fields),
+        _ => csv_row(&fields),
+    }
 }
 
-fn escape_like(s: &str) -> String {
-    // Escape LIKE wildcards and backslash itself
-    s.replace('\\', "\\\\")
-        .replace('%', "\\%")
-        .replace('_', "\\_")
+/// Header for list/search CSV output; `iso` follows `epoch` like the JSON field.
+fn history_csv_header(iso_dates: bool) -> &'static str {
+    if iso_dates {
+        "id,epoch,iso,pwd,cmd"
+    } else {
+        "id,epoch,pwd,cmd"
+    }
 }
 
 fn json_string(s: &str) -> String {
@@ -2967,6 +7194,8 @@ fn json_string(s: &str) -> String {
             '\n' => out.push_str("\\n"),
             '\r' => out.push_str("\\r"),
             '\t' => out.push_str("\\t"),
+            // JSON forbids raw control characters; everything without a short escape gets \u00XX
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
             _ => out.push(c),
         }
     }
@@ -2974,6 +7203,76 @@ fn json_string(s: &str) -> String {
     out
 }
 
+/// Split fzf's output into selected records: NUL-terminated with `--print0`, else lines.
+fn fzf_records(output: &str, print0: bool) -> Vec<&str> {
+    if print0 {
+        output.split('\0').filter(|r| !r.is_empty()).collect()
+    } else {
+        output.lines().collect()
+    }
+}
+
+/// Print one fzf selection, NUL-terminated under `--print0` so embedded newlines
+/// stay unambiguous.
+fn write_selection(out: &mut impl std::io::Write, item: &str, print0: bool) -> Result<()> {
+    if print0 {
+        write!(out, "{item}\0")?;
+    } else {
+        writeln!(out, "{item}")?;
+    }
+    Ok(())
+}
+
+/// fzf field separator for our selection lines: the tab after each `--with-meta`
+/// column, or the two spaces before the `(…)`/`[…]` decoration after the command.
+const FZF_DELIMITER: &str = "\t|  [(\\[]";
+
+/// fzf `--preview` command for the command field, which follows `meta_fields`
+/// hidden metadata columns.
+fn fzf_preview_command(meta_fields: usize) -> String {
+    format!("sdbh preview -- {{{}}}", meta_fields + 1)
+}
+
+/// Preview the command alone, without its `(timestamp) [pwd]` decoration.
+fn fzf_preview(fzf_cmd: &mut std::process::Command, meta_fields: usize) {
+    fzf_cmd
+        .arg("--delimiter")
+        .arg(FZF_DELIMITER)
+        .arg("--preview")
+        .arg(fzf_preview_command(meta_fields));
+}
+
+/// Hide the first `fields` tab-separated columns of each fzf line (the `--with-meta`
+/// prefix) from display and matching; fzf still prints the whole line.
+fn fzf_hide_meta(fzf_cmd: &mut std::process::Command, fields: usize) {
+    fzf_cmd
+        .arg("--delimiter")
+        .arg(FZF_DELIMITER)
+        .arg("--with-nth")
+        .arg(format!("{}..", fields + 1));
+}
+
+/// Split a selected fzf line into its `fields` numeric metadata columns and the
+/// displayed remainder.
+fn split_meta(line: &str, fields: usize) -> Option<(Vec<i64>, &str)> {
+    let mut parts = line.splitn(fields + 1, '\t');
+    let meta = (&mut parts)
+        .take(fields)
+        .map(|f| f.parse().ok())
+        .collect::<Option<Vec<i64>>>()?;
+    Some((meta, parts.next()?))
+}
+
+/// `--with-meta` output for one selection: the metadata fields, then the command.
+fn selection_json(meta: &[(&str, i64)], cmd: &str) -> String {
+    let mut out = String::from("{");
+    for (key, value) in meta {
+        out.push_str(&format!("{}:{value},", json_string(key)));
+    }
+    out.push_str(&format!("\"cmd\":{}}}", json_string(cmd)));
+    out
+}
+
 fn cmd_list_fzf(cfg: DbConfig, args: ListArgs) -> Result<()> {
     // Load fzf configuration
     let fzf_config = load_fzf_config();
@@ -2993,15 +7292,21 @@ fn cmd_list_fzf(cfg: DbConfig, args: ListArgs) -> Result<()> {
     let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
 
     // Collect items for fzf in a compact format
+    let sep = if args.print0 { '\0' } else { '\n' };
     let mut fzf_input = String::new();
     while let Some(r) = rows.next()? {
+        let id: i64 = r.get(0)?;
         let dt: String = r.get(1)?;
         let pwd: String = r.get(2)?;
         let cmd: String = r.get(3)?;
+        let epoch: i64 = r.get(4)?;
 
+        if args.with_meta {
+            fzf_input.push_str(&format!("{id}\t{epoch}\t"));
+        }
         // Format: "cmd  (timestamp) [pwd]"
         // We put cmd first so it's the primary search target
-        fzf_input.push_str(&format!("{}  ({}) [{}]\n", cmd, dt, pwd));
+        fzf_input.push_str(&format!("{}  ({}) [{}]{sep}", cmd, dt, pwd));
     }
 
     if fzf_input.is_empty() {
@@ -3013,7 +7318,7 @@ fn cmd_list_fzf(cfg: DbConfig, args: ListArgs) -> Result<()> {
     build_fzf_command(&mut fzf_cmd, &fzf_config);
 
     // Override defaults with our specific settings
-    fzf_cmd.arg("--preview").arg("sdbh preview --command {{}}");
+    fzf_preview(&mut fzf_cmd, if args.with_meta { 2 } else { 0 });
 
     // Enable multi-select if requested
     if args.multi_select {
@@ -3021,21 +7326,18 @@ fn cmd_list_fzf(cfg: DbConfig, args: ListArgs) -> Result<()> {
     } else {
         fzf_cmd.arg("--no-multi");
     }
-
-    fzf_cmd
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped());
-
-    let mut fzf_process = fzf_cmd.spawn()?;
-
-    // Write input to fzf's stdin
-    if let Some(mut stdin) = fzf_process.stdin.take() {
-        std::io::Write::write_all(&mut stdin, fzf_input.as_bytes())?;
-        drop(stdin); // Close stdin to signal EOF
+    if args.print0 {
+        fzf_cmd.arg("--read0").arg("--print0");
+    }
+    if args.with_meta {
+        fzf_hide_meta(&mut fzf_cmd, 2);
     }
 
-    // Wait for fzf to complete and get output
-    let output = fzf_process.wait_with_output()?;
+    let timeout = args.fzf_timeout.or(fzf_config.timeout_secs);
+    let Some(output) = run_fzf(&mut fzf_cmd, fzf_input.as_bytes(), timeout)?.into_output() else {
+        eprintln!("fzf timed out without a selection");
+        std::process::exit(FZF_CANCELLED);
+    };
 
     if !output.status.success() {
         // User cancelled selection (Ctrl+C) or fzf failed
@@ -3044,12 +7346,13 @@ fn cmd_list_fzf(cfg: DbConfig, args: ListArgs) -> Result<()> {
 
     // Extract the selected command(s)
     let selected = String::from_utf8_lossy(&output.stdout);
-    let selected_lines: Vec<&str> = selected.lines().collect();
+    let selected_lines = fzf_records(&selected, args.print0);
 
     if selected_lines.is_empty() {
         return Ok(());
     }
 
+    let log_filter = LogFilter::load_default();
     // Process each selected line
     for line in selected_lines {
         let line = line.trim();
@@ -3057,10 +7360,27 @@ fn cmd_list_fzf(cfg: DbConfig, args: ListArgs) -> Result<()> {
             continue;
         }
 
+        let (meta, line) = if args.with_meta {
+            let Some(split) = split_meta(line, 2) else {
+                continue;
+            };
+            split
+        } else {
+            (vec![], line)
+        };
+
         // Extract command from the fzf format: "cmd  (timestamp) [pwd]"
         if let Some(cmd_end) = line.find("  (") {
             let cmd = &line[..cmd_end];
-            println!("{}", cmd);
+            if args.with_meta {
+                let json = selection_json(&[("id", meta[0]), ("epoch", meta[1])], cmd);
+                write_selection(&mut std::io::stdout(), &json, args.print0)?;
+            } else {
+                write_selection(&mut std::io::stdout(), cmd, args.print0)?;
+            }
+            if args.log_selection {
+                log_selection(&cfg, &log_filter, cmd)?;
+            }
         }
     }
 
@@ -3080,21 +7400,27 @@ fn cmd_search_fzf(cfg: DbConfig, args: SearchArgs) -> Result<()> {
     }
 
     let conn = open_db(&cfg)?;
-    let (sql, bind) = build_search_sql(&args)?;
+    let (sql, bind) = build_search_sql(&args, crate::db::has_fts(&conn)?)?;
 
     let mut stmt = conn.prepare(&sql)?;
     let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
 
     // Collect items for fzf in a compact format
+    let sep = if args.print0 { '\0' } else { '\n' };
     let mut fzf_input = String::new();
     while let Some(r) = rows.next()? {
+        let id: i64 = r.get(0)?;
         let dt: String = r.get(1)?;
         let pwd: String = r.get(2)?;
         let cmd: String = r.get(3)?;
+        let epoch: i64 = r.get(4)?;
 
+        if args.with_meta {
+            fzf_input.push_str(&format!("{id}\t{epoch}\t"));
+        }
         // Format: "cmd  (timestamp) [pwd]"
         // We put cmd first so it's the primary search target
-        fzf_input.push_str(&format!("{}  ({}) [{}]\n", cmd, dt, pwd));
+        fzf_input.push_str(&format!("{}  ({}) [{}]{sep}", cmd, dt, pwd));
     }
 
     if fzf_input.is_empty() {
@@ -3106,7 +7432,7 @@ fn cmd_search_fzf(cfg: DbConfig, args: SearchArgs) -> Result<()> {
     build_fzf_command(&mut fzf_cmd, &fzf_config);
 
     // Override defaults with our specific settings
-    fzf_cmd.arg("--preview").arg("sdbh preview --command {{}}");
+    fzf_preview(&mut fzf_cmd, if args.with_meta { 2 } else { 0 });
 
     // Enable multi-select if requested
     if args.multi_select {
@@ -3114,21 +7440,18 @@ fn cmd_search_fzf(cfg: DbConfig, args: SearchArgs) -> Result<()> {
     } else {
         fzf_cmd.arg("--no-multi");
     }
-
-    fzf_cmd
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped());
-
-    let mut fzf_process = fzf_cmd.spawn()?;
-
-    // Write input to fzf's stdin
-    if let Some(mut stdin) = fzf_process.stdin.take() {
-        std::io::Write::write_all(&mut stdin, fzf_input.as_bytes())?;
-        drop(stdin); // Close stdin to signal EOF
+    if args.print0 {
+        fzf_cmd.arg("--read0").arg("--print0");
+    }
+    if args.with_meta {
+        fzf_hide_meta(&mut fzf_cmd, 2);
     }
 
-    // Wait for fzf to complete and get output
-    let output = fzf_process.wait_with_output()?;
+    let timeout = args.fzf_timeout.or(fzf_config.timeout_secs);
+    let Some(output) = run_fzf(&mut fzf_cmd, fzf_input.as_bytes(), timeout)?.into_output() else {
+        eprintln!("fzf timed out without a selection");
+        std::process::exit(FZF_CANCELLED);
+    };
 
     if !output.status.success() {
         // User cancelled selection (Ctrl+C) or fzf failed
@@ -3137,12 +7460,13 @@ fn cmd_search_fzf(cfg: DbConfig, args: SearchArgs) -> Result<()> {
 
     // Extract the selected command(s)
     let selected = String::from_utf8_lossy(&output.stdout);
-    let selected_lines: Vec<&str> = selected.lines().collect();
+    let selected_lines = fzf_records(&selected, args.print0);
 
     if selected_lines.is_empty() {
         return Ok(());
     }
 
+    let log_filter = LogFilter::load_default();
     // Process each selected line
     for line in selected_lines {
         let line = line.trim();
@@ -3150,10 +7474,27 @@ fn cmd_search_fzf(cfg: DbConfig, args: SearchArgs) -> Result<()> {
             continue;
         }
 
+        let (meta, line) = if args.with_meta {
+            let Some(split) = split_meta(line, 2) else {
+                continue;
+            };
+            split
+        } else {
+            (vec![], line)
+        };
+
         // Extract command from the fzf format: "cmd  (timestamp) [pwd]"
         if let Some(cmd_end) = line.find("  (") {
             let cmd = &line[..cmd_end];
-            println!("{}", cmd);
+            if args.with_meta {
+                let json = selection_json(&[("id", meta[0]), ("epoch", meta[1])], cmd);
+                write_selection(&mut std::io::stdout(), &json, args.print0)?;
+            } else {
+                write_selection(&mut std::io::stdout(), cmd, args.print0)?;
+            }
+            if args.log_selection {
+                log_selection(&cfg, &log_filter, cmd)?;
+            }
         }
     }
 
@@ -3184,15 +7525,16 @@ fn cmd_summary_fzf(cfg: DbConfig, args: SummaryArgs) -> Result<()> {
     let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
 
     // Collect items for fzf in a compact format
+    let sep = if args.print0 { '\0' } else { '\n' };
     let mut fzf_input = String::new();
     while let Some(r) = rows.next()? {
-        let _id_max: i64 = r.get(0)?;
+        let id_max: i64 = r.get(0)?;
         let dt: String = r.get(1)?;
         let count: i64 = r.get(2)?;
         let cmd: String = r.get(3)?;
         let pwd_part = if args.pwd {
             if let Ok(pwd) = r.get::<_, String>(4) {
-                format!(" [{}]", pwd)
+                format!("  [{}]", pwd)
             } else {
                 String::new()
             }
@@ -3200,9 +7542,13 @@ fn cmd_summary_fzf(cfg: DbConfig, args: SummaryArgs) -> Result<()> {
             String::new()
         };
 
+        if args.with_meta {
+            let last_epoch: i64 = r.get(if args.pwd { 5 } else { 4 })?;
+            fzf_input.push_str(&format!("{id_max}\t{count}\t{last_epoch}\t"));
+        }
         // Format: "cmd  (count uses, last: timestamp) [pwd]"
         fzf_input.push_str(&format!(
-            "{}{}  ({} uses, last: {})\n",
+            "{}{}  ({} uses, last: {}){sep}",
             cmd, pwd_part, count, dt
         ));
     }
@@ -3216,7 +7562,7 @@ fn cmd_summary_fzf(cfg: DbConfig, args: SummaryArgs) -> Result<()> {
     build_fzf_command(&mut fzf_cmd, &fzf_config);
 
     // Override defaults with our specific settings
-    fzf_cmd.arg("--preview").arg("sdbh preview --command {{}}");
+    fzf_preview(&mut fzf_cmd, if args.with_meta { 3 } else { 0 });
 
     // Enable multi-select if requested
     if args.multi_select {
@@ -3224,21 +7570,18 @@ fn cmd_summary_fzf(cfg: DbConfig, args: SummaryArgs) -> Result<()> {
     } else {
         fzf_cmd.arg("--no-multi");
     }
-
-    fzf_cmd
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped());
-
-    let mut fzf_process = fzf_cmd.spawn()?;
-
-    // Write input to fzf's stdin
-    if let Some(mut stdin) = fzf_process.stdin.take() {
-        std::io::Write::write_all(&mut stdin, fzf_input.as_bytes())?;
-        drop(stdin); // Close stdin to signal EOF
+    if args.print0 {
+        fzf_cmd.arg("--read0").arg("--print0");
+    }
+    if args.with_meta {
+        fzf_hide_meta(&mut fzf_cmd, 3);
     }
 
-    // Wait for fzf to complete and get output
-    let output = fzf_process.wait_with_output()?;
+    let timeout = args.fzf_timeout.or(fzf_config.timeout_secs);
+    let Some(output) = run_fzf(&mut fzf_cmd, fzf_input.as_bytes(), timeout)?.into_output() else {
+        eprintln!("fzf timed out without a selection");
+        std::process::exit(FZF_CANCELLED);
+    };
 
     if !output.status.success() {
         // User cancelled selection (Ctrl+C) or fzf failed
@@ -3247,12 +7590,13 @@ fn cmd_summary_fzf(cfg: DbConfig, args: SummaryArgs) -> Result<()> {
 
     // Extract the selected command(s)
     let selected = String::from_utf8_lossy(&output.stdout);
-    let selected_lines: Vec<&str> = selected.lines().collect();
+    let selected_lines = fzf_records(&selected, args.print0);
 
     if selected_lines.is_empty() {
         return Ok(());
     }
 
+    let log_filter = LogFilter::load_default();
     // Process each selected line
     for line in selected_lines {
         let line = line.trim();
@@ -3260,16 +7604,36 @@ fn cmd_summary_fzf(cfg: DbConfig, args: SummaryArgs) -> Result<()> {
             continue;
         }
 
-        // Extract command from the fzf format: "cmd [pwd]  (count uses, last: timestamp)"
+        let (meta, line) = if args.with_meta {
+            let Some(split) = split_meta(line, 3) else {
+                continue;
+            };
+            split
+        } else {
+            (vec![], line)
+        };
+
+        // Extract command from the fzf format: "cmd  [pwd]  (count uses, last: timestamp)"
         if let Some(cmd_end) = line.find("  (") {
             let cmd_part = &line[..cmd_end];
-            // Remove pwd part if present: "cmd [pwd]" -> "cmd"
+            // Remove pwd part if present: "cmd  [pwd]" -> "cmd"
             let cmd = if let Some(bracket_start) = cmd_part.find(" [") {
                 cmd_part[..bracket_start].trim()
             } else {
                 cmd_part.trim()
             };
-            println!("{}", cmd);
+            if args.with_meta {
+                let json = selection_json(
+                    &[("id", meta[0]), ("count", meta[1]), ("last_epoch", meta[2])],
+                    cmd,
+                );
+                write_selection(&mut std::io::stdout(), &json, args.print0)?;
+            } else {
+                write_selection(&mut std::io::stdout(), cmd, args.print0)?;
+            }
+            if args.log_selection {
+                log_selection(&cfg, &log_filter, cmd)?;
+            }
         }
     }
 
@@ -3300,13 +7664,14 @@ fn cmd_stats_top_fzf(cfg: DbConfig, args: StatsTopArgs) -> Result<()> {
     let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
 
     // Collect items for fzf in a compact format
+    let sep = if args.print0 { '\0' } else { '\n' };
     let mut fzf_input = String::new();
     while let Some(r) = rows.next()? {
         let cnt: i64 = r.get(0)?;
         let cmd: String = r.get(1)?;
 
         // Format: "cmd  (count uses)"
-        fzf_input.push_str(&format!("{}  ({} uses)\n", cmd, cnt));
+        fzf_input.push_str(&format!("{}  ({} uses){sep}", cmd, cnt));
     }
 
     if fzf_input.is_empty() {
@@ -3318,7 +7683,7 @@ fn cmd_stats_top_fzf(cfg: DbConfig, args: StatsTopArgs) -> Result<()> {
     build_fzf_command(&mut fzf_cmd, &fzf_config);
 
     // Override defaults with our specific settings
-    fzf_cmd.arg("--preview").arg("sdbh preview --command {{}}");
+    fzf_preview(&mut fzf_cmd, 0);
 
     // Enable multi-select if requested
     if args.multi_select {
@@ -3326,21 +7691,15 @@ fn cmd_stats_top_fzf(cfg: DbConfig, args: StatsTopArgs) -> Result<()> {
     } else {
         fzf_cmd.arg("--no-multi");
     }
-
-    fzf_cmd
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped());
-
-    let mut fzf_process = fzf_cmd.spawn()?;
-
-    // Write input to fzf's stdin
-    if let Some(mut stdin) = fzf_process.stdin.take() {
-        std::io::Write::write_all(&mut stdin, fzf_input.as_bytes())?;
-        drop(stdin); // Close stdin to signal EOF
+    if args.print0 {
+        fzf_cmd.arg("--read0").arg("--print0");
     }
 
-    // Wait for fzf to complete and get output
-    let output = fzf_process.wait_with_output()?;
+    let timeout = args.fzf_timeout.or(fzf_config.timeout_secs);
+    let Some(output) = run_fzf(&mut fzf_cmd, fzf_input.as_bytes(), timeout)?.into_output() else {
+        eprintln!("fzf timed out without a selection");
+        std::process::exit(FZF_CANCELLED);
+    };
 
     if !output.status.success() {
         // User cancelled selection (Ctrl+C) or fzf failed
@@ -3349,12 +7708,13 @@ fn cmd_stats_top_fzf(cfg: DbConfig, args: StatsTopArgs) -> Result<()> {
 
     // Extract the selected command(s)
     let selected = String::from_utf8_lossy(&output.stdout);
-    let selected_lines: Vec<&str> = selected.lines().collect();
+    let selected_lines = fzf_records(&selected, args.print0);
 
     if selected_lines.is_empty() {
         return Ok(());
     }
 
+    let log_filter = LogFilter::load_default();
     // Process each selected line
     for line in selected_lines {
         let line = line.trim();
@@ -3365,7 +7725,10 @@ fn cmd_stats_top_fzf(cfg: DbConfig, args: StatsTopArgs) -> Result<()> {
         // Extract command from the fzf format: "cmd  (count uses)"
         if let Some(cmd_end) = line.find("  (") {
             let cmd = &line[..cmd_end];
-            println!("{}", cmd);
+            write_selection(&mut std::io::stdout(), cmd, args.print0)?;
+            if args.log_selection {
+                log_selection(&cfg, &log_filter, cmd)?;
+            }
         }
     }
 
@@ -3396,6 +7759,7 @@ fn cmd_stats_by_pwd_fzf(cfg: DbConfig, args: StatsByPwdArgs) -> Result<()> {
     let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
 
     // Collect items for fzf in a compact format
+    let sep = if args.print0 { '\0' } else { '\n' };
     let mut fzf_input = String::new();
     while let Some(r) = rows.next()? {
         let cnt: i64 = r.get(0)?;
@@ -3403,7 +7767,7 @@ fn cmd_stats_by_pwd_fzf(cfg: DbConfig, args: StatsByPwdArgs) -> Result<()> {
         let cmd: String = r.get(2)?;
 
         // Format: "cmd  [pwd]  (count uses)"
-        fzf_input.push_str(&format!("{}  [{}]  ({} uses)\n", cmd, pwd, cnt));
+        fzf_input.push_str(&format!("{}  [{}]  ({} uses){sep}", cmd, pwd, cnt));
     }
 
     if fzf_input.is_empty() {
@@ -3415,7 +7779,7 @@ fn cmd_stats_by_pwd_fzf(cfg: DbConfig, args: StatsByPwdArgs) -> Result<()> {
     build_fzf_command(&mut fzf_cmd, &fzf_config);
 
     // Override defaults with our specific settings
-    fzf_cmd.arg("--preview").arg("sdbh preview --command {{}}");
+    fzf_preview(&mut fzf_cmd, 0);
 
     // Enable multi-select if requested
     if args.multi_select {
@@ -3423,21 +7787,15 @@ fn cmd_stats_by_pwd_fzf(cfg: DbConfig, args: StatsByPwdArgs) -> Result<()> {
     } else {
         fzf_cmd.arg("--no-multi");
     }
-
-    fzf_cmd
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped());
-
-    let mut fzf_process = fzf_cmd.spawn()?;
-
-    // Write input to fzf's stdin
-    if let Some(mut stdin) = fzf_process.stdin.take() {
-        std::io::Write::write_all(&mut stdin, fzf_input.as_bytes())?;
-        drop(stdin); // Close stdin to signal EOF
+    if args.print0 {
+        fzf_cmd.arg("--read0").arg("--print0");
     }
 
-    // Wait for fzf to complete and get output
-    let output = fzf_process.wait_with_output()?;
+    let timeout = args.fzf_timeout.or(fzf_config.timeout_secs);
+    let Some(output) = run_fzf(&mut fzf_cmd, fzf_input.as_bytes(), timeout)?.into_output() else {
+        eprintln!("fzf timed out without a selection");
+        std::process::exit(FZF_CANCELLED);
+    };
 
     if !output.status.success() {
         // User cancelled selection (Ctrl+C) or fzf failed
@@ -3446,12 +7804,13 @@ fn cmd_stats_by_pwd_fzf(cfg: DbConfig, args: StatsByPwdArgs) -> Result<()> {
 
     // Extract the selected command(s)
     let selected = String::from_utf8_lossy(&output.stdout);
-    let selected_lines: Vec<&str> = selected.lines().collect();
+    let selected_lines = fzf_records(&selected, args.print0);
 
     if selected_lines.is_empty() {
         return Ok(());
     }
 
+    let log_filter = LogFilter::load_default();
     // Process each selected line
     for line in selected_lines {
         let line = line.trim();
@@ -3462,7 +7821,10 @@ fn cmd_stats_by_pwd_fzf(cfg: DbConfig, args: StatsByPwdArgs) -> Result<()> {
         // Extract command from the fzf format: "cmd  [pwd]  (count uses)"
         if let Some(cmd_end) = line.find("  [") {
             let cmd = &line[..cmd_end];
-            println!("{}", cmd);
+            write_selection(&mut std::io::stdout(), cmd, args.print0)?;
+            if args.log_selection {
+                log_selection(&cfg, &log_filter, cmd)?;
+            }
         }
     }
 
@@ -3493,13 +7855,14 @@ fn cmd_stats_daily_fzf(cfg: DbConfig, args: StatsDailyArgs) -> Result<()> {
     let mut rows = stmt.query(rusqlite::params_from_iter(bind.iter()))?;
 
     // Collect items for fzf in a compact format
+    let sep = if args.print0 { '\0' } else { '\n' };
     let mut fzf_input = String::new();
     while let Some(r) = rows.next()? {
         let day: String = r.get(0)?;
         let cnt: i64 = r.get(1)?;
 
         // Format: "day  (count commands)"
-        fzf_input.push_str(&format!("{}  ({} commands)\n", day, cnt));
+        fzf_input.push_str(&format!("{}  ({} commands){sep}", day, cnt));
     }
 
     if fzf_input.is_empty() {
@@ -3519,21 +7882,15 @@ fn cmd_stats_daily_fzf(cfg: DbConfig, args: StatsDailyArgs) -> Result<()> {
     } else {
         fzf_cmd.arg("--no-multi");
     }
-
-    fzf_cmd
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped());
-
-    let mut fzf_process = fzf_cmd.spawn()?;
-
-    // Write input to fzf's stdin
-    if let Some(mut stdin) = fzf_process.stdin.take() {
-        std::io::Write::write_all(&mut stdin, fzf_input.as_bytes())?;
-        drop(stdin); // Close stdin to signal EOF
+    if args.print0 {
+        fzf_cmd.arg("--read0").arg("--print0");
     }
 
-    // Wait for fzf to complete and get output
-    let output = fzf_process.wait_with_output()?;
+    let timeout = args.fzf_timeout.or(fzf_config.timeout_secs);
+    let Some(output) = run_fzf(&mut fzf_cmd, fzf_input.as_bytes(), timeout)?.into_output() else {
+        eprintln!("fzf timed out without a selection");
+        std::process::exit(FZF_CANCELLED);
+    };
 
     if !output.status.success() {
         // User cancelled selection (Ctrl+C) or fzf failed
@@ -3542,7 +7899,7 @@ fn cmd_stats_daily_fzf(cfg: DbConfig, args: StatsDailyArgs) -> Result<()> {
 
     // Extract the selected command(s)
     let selected = String::from_utf8_lossy(&output.stdout);
-    let selected_lines: Vec<&str> = selected.lines().collect();
+    let selected_lines = fzf_records(&selected, args.print0);
 
     if selected_lines.is_empty() {
         return Ok(());
@@ -3558,14 +7915,340 @@ fn cmd_stats_daily_fzf(cfg: DbConfig, args: StatsDailyArgs) -> Result<()> {
         // Extract day from the fzf format: "day  (count commands)"
         if let Some(day_end) = line.find("  (") {
             let day = &line[..day_end];
-            println!("{}", day);
+            write_selection(&mut std::io::stdout(), day, args.print0)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Run `cmd` through `$SHELL -c` (falling back to `sh`) with inherited stdio, in
+/// `dir` or the current directory. Returns the exit code like the shell's `$?`.
+fn run_in_shell(cmd: &str, dir: Option<&std::path::Path>) -> Result<i32> {
+    let shell = std::env::var("SHELL")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "sh".to_string());
+    let mut command = std::process::Command::new(&shell);
+    command.arg("-c").arg(cmd);
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+    let status = command
+        .status()
+        .with_context(|| format!("running {shell} -c"))?;
+    Ok(status
+        .code()
+        .or_else(|| signal_exit_code(&status))
+        .unwrap_or(1))
+}
+
+/// 128 + signal number when the process was killed by a signal.
+#[cfg(unix)]
+fn signal_exit_code(status: &std::process::ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal().map(|sig| 128 + sig)
+}
+
+#[cfg(not(unix))]
+fn signal_exit_code(_status: &std::process::ExitStatus) -> Option<i32> {
+    None
+}
+
+/// The invoking shell's pid, used as the session ppid when no hook session is set.
+#[cfg(unix)]
+fn parent_pid() -> i64 {
+    std::os::unix::process::parent_id() as i64
+}
+
+#[cfg(not(unix))]
+fn parent_pid() -> i64 {
+    std::process::id() as i64
+}
+
+fn cmd_replay(cfg: DbConfig, args: ReplayArgs) -> Result<()> {
+    let conn = open_db(&cfg)?;
+    if let Some(id) = args.id {
+        let cmd: String = conn
+            .query_row("SELECT cmd FROM history WHERE id = ?1", [id], |r| r.get(0))
+            .optional()?
+            .with_context(|| format!("no history entry with id {id}"))?;
+        drop(conn);
+        if args.run {
+            let code = run_and_log(&cfg, &cmd)?;
+            if code != 0 {
+                std::process::exit(code);
+            }
+        } else {
+            println!("{cmd}");
+        }
+        return Ok(());
+    }
+
+    let (Some(salt), Some(ppid)) = (args.salt, args.ppid) else {
+        anyhow::bail!("--salt and --ppid are required without --id");
+    };
+    let mut stmt = conn.prepare(
+        "SELECT id, pwd, cmd FROM history WHERE salt = ?1 AND ppid = ?2 \
+         ORDER BY epoch ASC, seq ASC, id ASC",
+    )?;
+    let rows: Vec<(i64, String, String)> = stmt
+        .query_map([salt, ppid], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    // Like `last`, skip sdbh's own invocations (e.g. a `replay` inside the session).
+    let rows: Vec<_> = rows
+        .into_iter()
+        .filter(|(_, _, cmd)| cmd != "sdbh" && !cmd.starts_with("sdbh "))
+        .collect();
+
+    if rows.is_empty() {
+        anyhow::bail!("no commands logged for session salt={salt} ppid={ppid}");
+    }
+    if args.confirm_each && !atty::is(atty::Stream::Stdin) {
+        anyhow::bail!("--confirm-each needs an interactive terminal");
+    }
+
+    // Running a whole session is never the default: it needs --yes or --confirm-each.
+    let dry_run = !(args.yes || args.confirm_each);
+    let policy = load_run_config().missing_pwd;
+    if dry_run {
+        let mut missing = 0usize;
+        for (id, pwd, cmd) in &rows {
+            let recorded = std::path::Path::new(pwd);
+            if recorded.is_dir() {
+                println!("{id:>6} | {pwd} | {cmd}");
+                continue;
+            }
+            missing += 1;
+            match resolve_run_dir(recorded, policy) {
+                Ok(dir) => println!("{id:>6} | {} (missing: {pwd}) | {cmd}", dir.display()),
+                Err(_) => println!("{id:>6} | {pwd} (missing) | {cmd}"),
+            }
+        }
+        if missing > 0 {
+            eprintln!(
+                "{missing} recorded director{} no longer exist (see [run] missing_pwd in ~/.sdbh.toml)",
+                if missing == 1 { "y" } else { "ies" }
+            );
+        }
+        if !args.dry_run {
+            eprintln!("Dry run: pass --yes to run these {} command(s)", rows.len());
+        }
+        return Ok(());
+    }
+
+    let (mut ran, mut failed) = (0usize, 0usize);
+    for (_, pwd, cmd) in &rows {
+        let dir = resolve_run_dir(std::path::Path::new(pwd), policy)?;
+        if args.confirm_each {
+            let run = dialoguer::Confirm::new()
+                .with_prompt(format!("Run `{cmd}` in {}?", dir.display()))
+                .default(true)
+                .interact()?;
+            if !run {
+                println!("skipped: {cmd}");
+                continue;
+            }
         }
+        let code = run_in_shell(cmd, Some(&dir))?;
+        ran += 1;
+        if code != 0 {
+            failed += 1;
+        }
+        println!("exit {code}: {cmd}");
     }
 
+    println!("Replayed {ran} command(s), {failed} failed");
     Ok(())
 }
 
-fn cmd_template(_cfg: DbConfig, args: TemplateArgs) -> Result<()> {
+/// Run `cmd` through `$SHELL -c` (falling back to `sh`) with inherited stdio, then
+/// log it like the shell hook would. Returns the command's exit code.
+fn run_and_log(cfg: &DbConfig, cmd: &str) -> Result<i32> {
+    let pwd = std::env::current_dir()?.to_string_lossy().into_owned();
+    let epoch = time::OffsetDateTime::now_utc().unix_timestamp();
+    let started = std::time::Instant::now();
+
+    let code = run_in_shell(cmd, None)?;
+
+    if !LogFilter::load_default().should_skip(cmd) {
+        let row = HistoryRow {
+            exit_code: Some(code as i64),
+            duration_ms: Some(started.elapsed().as_millis() as i64),
+            ..fresh_row(cmd, pwd, epoch)
+        };
+        let mut conn = open_db(cfg)?;
+        ensure_hash_index(&conn)?;
+        insert_history(&mut conn, &row, cfg)?;
+    }
+
+    Ok(code)
+}
+
+/// A row for `cmd` run in `pwd` at `epoch` from this shell session, with the same
+/// host/tty/gitroot/env the shell hook would record.
+fn fresh_row(cmd: &str, pwd: String, epoch: i64) -> HistoryRow {
+    let (salt, ppid) = current_session().unwrap_or((0, parent_pid()));
+    let log_cfg = load_log_config();
+    // The hooks pass $SDBH_CAPTURE_ENV as --capture-env; honour it the same way.
+    let hook_names: Vec<String> = std::env::var("SDBH_CAPTURE_ENV")
+        .unwrap_or_default()
+        .split(',')
+        .map(String::from)
+        .collect();
+    HistoryRow {
+        hist_id: None,
+        cmd: cmd.to_string(),
+        epoch,
+        ppid,
+        gitroot: log_cfg.track_gitroot.then(|| git_toplevel(&pwd)).flatten(),
+        pwd,
+        salt,
+        exit_code: None,
+        duration_ms: None,
+        host: default_host(),
+        tty: default_tty(),
+        seq: None,
+        env: capture_env(hook_names.iter().chain(&log_cfg.capture_env)),
+    }
+}
+
+/// `--log-selection`: record a command picked in fzf as run here and now. Returns
+/// whether a row was written; empty or filtered commands are skipped.
+fn log_selection(cfg: &DbConfig, filter: &LogFilter, cmd: &str) -> Result<bool> {
+    if filter.should_skip(cmd) {
+        return Ok(false);
+    }
+    let pwd = std::env::current_dir()?.to_string_lossy().into_owned();
+    let epoch = time::OffsetDateTime::now_utc().unix_timestamp();
+    let mut conn = open_db(cfg)?;
+    ensure_hash_index(&conn)?;
+    insert_history(&mut conn, &fresh_row(cmd, pwd, epoch), cfg)?;
+    Ok(true)
+}
+
+/// One-line summary of a template variable, e.g. `env (required)` or
+/// `tag (optional, default: latest)`.
+fn describe_template_variable(
+    template: &crate::domain::Template,
+    var: &crate::domain::Variable,
+) -> String {
+    let kind = if var.required { "required" } else { "optional" };
+    match var
+        .default
+        .as_ref()
+        .or_else(|| template.defaults.get(&var.name))
+    {
+        Some(default) => format!("{} ({kind}, default: {default})", var.name),
+        None => format!("{} ({kind})", var.name),
+    }
+}
+
+/// Parse repeated `--var key=value` assignments
+fn parse_template_vars(assignments: &[String]) -> Result<HashMap<String, String>> {
+    let mut provided_vars = HashMap::new();
+    for var_assignment in assignments {
+        if let Some((key, value)) = var_assignment.split_once('=') {
+            provided_vars.insert(key.to_string(), value.to_string());
+        } else {
+            anyhow::bail!(
+                "Invalid variable assignment: {}. Use format: key=value",
+                var_assignment
+            );
+        }
+    }
+    Ok(provided_vars)
+}
+
+/// Resolve a template, offering and then saving last-used values unless `--no-remember`
+fn resolve_template_remembered(
+    engine: &crate::template::TemplateEngine,
+    template: &crate::domain::Template,
+    provided_vars: &HashMap<String, String>,
+    args: &TemplateArgs,
+) -> Result<crate::domain::ResolvedTemplate> {
+    let remembered = if args.no_remember {
+        HashMap::new()
+    } else {
+        engine.last_values(&template.id).unwrap_or_else(|e| {
+            eprintln!("warning: ignoring remembered template values: {e:#}");
+            HashMap::new()
+        })
+    };
+    let resolved = engine.resolve_template_interactive(template, provided_vars, &remembered)?;
+    if !args.no_remember
+        && let Err(e) = engine.remember_values(template, &resolved.variables_used)
+    {
+        eprintln!("warning: could not save template values: {e:#}");
+    }
+    Ok(resolved)
+}
+
+/// Let the user pick templates in fzf; returns the selected template ids.
+fn select_templates_fzf(
+    engine: &crate::template::TemplateEngine,
+    multi_select: bool,
+    fzf_timeout: Option<u64>,
+) -> Result<Vec<String>> {
+    let fzf_config = load_fzf_config();
+
+    // Check if fzf is available
+    let fzf_binary = fzf_config.binary_path.as_deref().unwrap_or("fzf");
+    if which(fzf_binary).is_none() {
+        anyhow::bail!(
+            "fzf is not installed or not found in PATH. Please install fzf to use --fzf flag."
+        );
+    }
+
+    let templates = engine.list_templates()?;
+    if templates.is_empty() {
+        println!("No templates found. Create one with: sdbh template --create <name>");
+        return Ok(vec![]);
+    }
+
+    // Format: "id  [category] - description"
+    let mut fzf_input = String::new();
+    for template in &templates {
+        fzf_input.push_str(&template.id);
+        fzf_input.push_str("  ");
+        if let Some(category) = &template.category {
+            fzf_input.push_str(&format!("[{}] ", category));
+        }
+        fzf_input.push_str(&format!(
+            "- {}\n",
+            template.description.as_deref().unwrap_or(&template.name)
+        ));
+    }
+
+    let mut fzf_cmd = std::process::Command::new(fzf_binary);
+    build_fzf_command(&mut fzf_cmd, &fzf_config);
+
+    if multi_select {
+        fzf_cmd.arg("--multi");
+    } else {
+        fzf_cmd.arg("--no-multi");
+    }
+
+    let timeout = fzf_timeout.or(fzf_config.timeout_secs);
+    let Some(output) = run_fzf(&mut fzf_cmd, fzf_input.as_bytes(), timeout)?.into_output() else {
+        eprintln!("fzf timed out without a selection");
+        std::process::exit(FZF_CANCELLED);
+    };
+    if !output.status.success() {
+        // User cancelled selection (Ctrl+C) or fzf failed
+        return Ok(vec![]);
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split("  ").next())
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+fn cmd_template(cfg: DbConfig, args: TemplateArgs) -> Result<()> {
     let engine = crate::template::TemplateEngine::new()?;
 
     if args.list {
@@ -3587,12 +8270,41 @@ fn cmd_template(_cfg: DbConfig, args: TemplateArgs) -> Result<()> {
             if let Some(category) = &template.category {
                 println!("  Category: {}", category);
             }
-            println!("  Variables: {}", template.variables.len());
+            if template.variables.is_empty() {
+                println!("  Variables: none");
+            } else {
+                println!("  Variables:");
+                for var in &template.variables {
+                    println!("    - {}", describe_template_variable(&template, var));
+                }
+            }
             println!();
         }
         return Ok(());
     }
 
+    if let Some(path) = &args.export {
+        let count = engine.export_bundle(path)?;
+        println!("Exported {} template(s) to {}", count, path.display());
+        return Ok(());
+    }
+
+    if let Some(path) = &args.import {
+        let outcome = engine.import_bundle(path, args.force)?;
+        for id in &outcome.skipped {
+            eprintln!(
+                "warning: template '{}' already exists; skipped (use --force to overwrite)",
+                id
+            );
+        }
+        println!(
+            "Imported {} template(s) from {}",
+            outcome.imported.len(),
+            path.display()
+        );
+        return Ok(());
+    }
+
     if let Some(name) = &args.create {
         // Create a new template interactively
         return create_template_interactive(&engine, name);
@@ -3600,34 +8312,60 @@ fn cmd_template(_cfg: DbConfig, args: TemplateArgs) -> Result<()> {
 
     if let Some(name) = &args.delete {
         // Delete a template
-        engine.delete_template(name)?;
-        println!("Deleted template: {}", name);
+        if !engine.template_exists(name) {
+            anyhow::bail!("Template '{}' not found", name);
+        }
+
+        if !args.yes {
+            if !atty::is(atty::Stream::Stdin) {
+                anyhow::bail!(
+                    "refusing to delete template '{}' without confirmation; pass --yes",
+                    name
+                );
+            }
+            let confirmed = dialoguer::Confirm::new()
+                .with_prompt(format!("Delete template '{}'?", name))
+                .default(false)
+                .interact()?;
+            if !confirmed {
+                println!("Aborted");
+                return Ok(());
+            }
+        }
+
+        if args.backup {
+            let trashed = engine.trash_template(name)?;
+            println!("Moved template {} to {}", name, trashed.display());
+        } else {
+            engine.delete_template(name)?;
+            println!("Deleted template: {}", name);
+        }
         return Ok(());
     }
 
     // Execute a template
     if let Some(template_name) = &args.name {
         let template = engine.load_template(template_name)?;
+        let provided_vars = parse_template_vars(&args.var)?;
 
-        // Parse variable assignments from command line
-        let mut provided_vars = std::collections::HashMap::new();
-        for var_assignment in &args.var {
-            if let Some((key, value)) = var_assignment.split_once('=') {
-                provided_vars.insert(key.to_string(), value.to_string());
-            } else {
-                anyhow::bail!(
-                    "Invalid variable assignment: {}. Use format: key=value",
-                    var_assignment
-                );
+        // Resolve and execute the template with interactive prompting if needed
+        let resolved = resolve_template_remembered(&engine, &template, &provided_vars, &args)?;
+        if args.run {
+            let code = run_and_log(&cfg, &resolved.resolved_command)?;
+            if code != 0 {
+                std::process::exit(code);
             }
+        } else {
+            println!("{}", resolved.resolved_command);
+        }
+    } else if args.fzf || args.multi_select {
+        // Pick one or more templates with fzf, then resolve each in turn
+        let provided_vars = parse_template_vars(&args.var)?;
+        for template_id in select_templates_fzf(&engine, args.multi_select, args.fzf_timeout)? {
+            let template = engine.load_template(&template_id)?;
+            let resolved = resolve_template_remembered(&engine, &template, &provided_vars, &args)?;
+            println!("{}", resolved.resolved_command);
         }
-
-        // Resolve and execute the template with interactive prompting if needed
-        let resolved = engine.resolve_template_interactive(&template, &provided_vars)?;
-        println!("{}", resolved.resolved_command);
-    } else if args.fzf {
-        // fzf integration for template selection
-        println!("fzf template selection will be available in v0.13.0");
         return Ok(());
     } else {
         // No specific action, show help
@@ -3640,6 +8378,12 @@ fn cmd_template(_cfg: DbConfig, args: TemplateArgs) -> Result<()> {
         println!("  sdbh template --delete <name>           # Delete a template");
         println!("  sdbh template <name>                    # Execute a template");
         println!("  sdbh template <name> --var key=value    # Execute with variables");
+        println!("  sdbh template <name> --run              # Run it and log it to history");
+        println!("  sdbh template --fzf                     # Pick a template with fzf");
+        println!("  sdbh template --export <file.toml>      # Bundle all templates into one file");
+        println!(
+            "  sdbh template --import <file.toml>      # Import a bundle (--force overwrites)"
+        );
         println!();
         println!(
             "Templates are stored in: {}",
@@ -3732,6 +8476,7 @@ fn create_template_interactive(engine: &crate::template::TemplateEngine, name: &
                 },
                 required,
                 default,
+                var_type: None,
             });
         }
     }
@@ -3748,7 +8493,7 @@ fn create_template_interactive(engine: &crate::template::TemplateEngine, name: &
         command,
         category,
         variables,
-        defaults: std::collections::HashMap::new(), // Individual defaults are in variables
+        defaults: HashMap::new(), // Individual defaults are in variables
     };
 
     // Validate and save
@@ -3759,6 +8504,7 @@ fn create_template_interactive(engine: &crate::template::TemplateEngine, name: &
 }
 
 #[cfg(test)]
+#[allow(clippy::len_zero)]
 mod tests {
     use super::*;
 
@@ -3779,9 +8525,21 @@ mod tests {
             pwd_override: None,
             here: false,
             under: false,
+            pwd_contains: None,
             verbose: false,
             fzf: false,
+            log_selection: false,
+            fzf_timeout: None,
             multi_select: false,
+            print0: false,
+            with_meta: false,
+            canonical: false,
+            headers: false,
+            no_headers: false,
+            host: None,
+            status: false,
+            porcelain: false,
+            count: false,
         };
         let (_sql, bind) = build_summary_sql(&args).unwrap();
         // --all means unlimited, so limit should be u32::MAX
@@ -3800,9 +8558,21 @@ mod tests {
             pwd_override: None,
             here: false,
             under: false,
+            pwd_contains: None,
             verbose: false,
             fzf: false,
+            log_selection: false,
+            fzf_timeout: None,
             multi_select: false,
+            print0: false,
+            with_meta: false,
+            canonical: false,
+            headers: false,
+            no_headers: false,
+            host: None,
+            status: false,
+            porcelain: false,
+            count: false,
         };
         let (_sql, bind) = build_summary_sql(&args).unwrap();
         assert_eq!(bind.last().unwrap(), "5");
@@ -3812,11 +8582,19 @@ mod tests {
     fn build_stats_top_sql_basic() {
         let args = StatsTopArgs {
             days: 30,
+            since: None,
+            until: None,
             limit: 50,
             all: false,
             session: false,
+            canonical: false,
+            by_program: false,
+            trend: false,
             fzf: false,
+            log_selection: false,
+            fzf_timeout: None,
             multi_select: false,
+            print0: false,
         };
         let (sql, bind) = build_stats_top_sql(&args).unwrap();
         assert!(sql.contains("GROUP BY cmd"));
@@ -3824,15 +8602,33 @@ mod tests {
         assert!(bind.len() > 0);
     }
 
+    #[test]
+    fn stats_top_trend_splits_the_window_in_half() {
+        assert_eq!(window_midpoint(1_000_000, 30), 1_000_000 + 15 * 86400);
+        assert_eq!(window_midpoint(1_000_000, 1), 1_000_000 + 43200);
+
+        assert_eq!(trend_label(10, 15), "↑ +50%");
+        assert_eq!(trend_label(10, 5), "↓ -50%");
+        assert_eq!(trend_label(4, 4), "→ 0%");
+        assert_eq!(trend_label(3, 0), "↓ -100%");
+        assert_eq!(trend_label(0, 2), "↑ new");
+    }
+
     #[test]
     fn build_stats_by_pwd_sql_basic() {
         let args = StatsByPwdArgs {
             days: 30,
+            since: None,
+            until: None,
             limit: 50,
             all: false,
             session: false,
+            canonical: false,
             fzf: false,
+            log_selection: false,
+            fzf_timeout: None,
             multi_select: false,
+            print0: false,
         };
         let (sql, bind) = build_stats_by_pwd_sql(&args).unwrap();
         assert!(sql.contains("GROUP BY pwd, cmd"));
@@ -3840,18 +8636,778 @@ mod tests {
         assert!(bind.len() > 0);
     }
 
+    #[test]
+    fn build_stats_new_sql_filters_on_first_seen() {
+        let args = StatsNewArgs {
+            days: 7,
+            limit: 10,
+            all: false,
+            session: false,
+        };
+        let (sql, bind) = build_stats_new_sql(&args).unwrap();
+        assert!(sql.contains("GROUP BY cmd HAVING min(epoch) >= CAST(? AS INTEGER)"));
+        assert!(sql.contains("ORDER BY min(epoch) ASC"));
+        // The window must not be a WHERE filter, or every old command would look new
+        assert!(!sql.contains("AND epoch >= ?"));
+        assert_eq!(
+            bind,
+            vec![days_cutoff_epoch(7).to_string(), "10".to_string()]
+        );
+    }
+
     #[test]
     fn build_stats_daily_sql_basic() {
         let args = StatsDailyArgs {
             days: 30,
+            since: None,
+            until: None,
+            limit: 50,
             all: false,
             session: false,
             fzf: false,
+            fzf_timeout: None,
             multi_select: false,
+            print0: false,
         };
         let (sql, bind) = build_stats_daily_sql(&args).unwrap();
         assert!(sql.contains("GROUP BY day"));
         assert!(sql.contains("ORDER BY day ASC"));
         assert!(bind.len() > 0);
     }
+
+    #[test]
+    fn build_stats_daily_sql_limits_to_most_recent_days() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE history (cmd TEXT, epoch INTEGER)", [])
+            .unwrap();
+        for day in 0..5i64 {
+            conn.execute(
+                "INSERT INTO history (cmd, epoch) VALUES ('ls', ?1)",
+                [1700000000 + day * 86400],
+            )
+            .unwrap();
+        }
+
+        let mut args = StatsDailyArgs {
+            days: 30,
+            since: Some("1699900000".to_string()),
+            until: None,
+            limit: 3,
+            all: false,
+            session: false,
+            fzf: false,
+            fzf_timeout: None,
+            multi_select: false,
+            print0: false,
+        };
+        let days = |args: &StatsDailyArgs| -> Vec<String> {
+            let (sql, bind) = build_stats_daily_sql(args).unwrap();
+            let mut stmt = conn.prepare(&sql).unwrap();
+            stmt.query_map(rusqlite::params_from_iter(bind.iter()), |r| r.get(0))
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect()
+        };
+
+        let limited = days(&args);
+        assert_eq!(limited.len(), 3);
+        assert!(limited.windows(2).all(|w| w[0] < w[1]));
+
+        args.all = true;
+        let all = days(&args);
+        assert_eq!(all.len(), 5);
+        assert_eq!(&all[2..], limited.as_slice());
+    }
+
+    fn search_args(query: &str) -> SearchArgs {
+        SearchArgs {
+            query: Some(query.to_string()),
+            limit: 100,
+            format: OutputFormat::Table,
+            sort: SortMode::Recency,
+            all: false,
+            session: false,
+            since_epoch: None,
+            days: None,
+            pwd_override: None,
+            here: false,
+            under: false,
+            pwd_contains: None,
+            fzf: false,
+            log_selection: false,
+            fzf_timeout: None,
+            multi_select: false,
+            print0: false,
+            with_meta: false,
+            dedup_adjacent: false,
+            show_count: false,
+            failed_only: false,
+            exclude: vec![],
+            iso_dates: false,
+            verbose_json: false,
+            invert: false,
+            around_id: None,
+            near: None,
+            window: 600,
+            context: 5,
+            headers: false,
+            no_headers: false,
+            host: None,
+            since: None,
+            until: None,
+            status: false,
+            porcelain: false,
+            count: false,
+        }
+    }
+
+    #[test]
+    fn build_search_sql_invert_negates_match() {
+        let (sql, _) = build_search_sql(&search_args("git"), false).unwrap();
+        assert!(sql.contains("AND cmd LIKE ?"));
+        assert!(!sql.contains("NOT LIKE"));
+
+        let mut args = search_args("git");
+        args.invert = true;
+        args.days = Some(7);
+        let (sql, bind) = build_search_sql(&args, false).unwrap();
+        assert!(sql.contains("AND cmd NOT LIKE ? ESCAPE"));
+        assert!(sql.contains("AND epoch >= ?"));
+        assert_eq!(bind[1], "%git%");
+    }
+
+    #[test]
+    fn build_search_sql_uses_fts_only_when_equivalent() {
+        let (sql, bind) = build_search_sql(&search_args("git"), true).unwrap();
+        assert!(
+            sql.contains("AND id IN (SELECT rowid FROM history_fts WHERE history_fts MATCH ?)")
+        );
+        assert_eq!(bind[0], "\"git\"");
+
+        // Too short for trigrams, or containing LIKE metacharacters: plain LIKE
+        for query in ["gi", "50%", "my_var", "café"] {
+            let (sql, _) = build_search_sql(&search_args(query), true).unwrap();
+            assert!(sql.contains("AND cmd LIKE ?"), "{query}");
+            assert!(!sql.contains("history_fts"), "{query}");
+        }
+
+        assert_eq!(
+            fts_phrase("say \"hi\""),
+            Some("\"say \"\"hi\"\"\"".to_string())
+        );
+    }
+
+    #[test]
+    fn resolve_preview_width_prefers_explicit_width() {
+        assert_eq!(resolve_preview_width(Some(40), Some(60), Some(200)), 40);
+        assert_eq!(resolve_preview_width(None, Some(60), Some(200)), 60);
+        assert_eq!(resolve_preview_width(None, None, Some(200)), 200);
+        assert_eq!(resolve_preview_width(None, None, None), 80);
+    }
+
+    #[test]
+    fn histogram_lines_scale_to_width() {
+        let labels = ["Sun", "Mon", "Tue"].map(String::from);
+        let lines = histogram_lines(&labels, &[0, 10, 5], 42);
+        assert_eq!(lines[0], "Sun |      0 |");
+        assert_eq!(lines[1], format!("Mon |     10 | {}", "#".repeat(27)));
+        assert_eq!(lines[2], format!("Tue |      5 | {}", "#".repeat(13)));
+
+        // A tiny non-zero count still shows up
+        let lines = histogram_lines(&labels, &[1, 1000, 0], 42);
+        assert!(lines[0].ends_with("| #"));
+    }
+
+    #[test]
+    fn build_search_sql_date_range_is_half_open() {
+        let mut args = search_args("git");
+        args.since = Some("2024-03-01".to_string());
+        args.until = Some("2024-03-02".to_string());
+        let (sql, bind) = build_search_sql(&args, false).unwrap();
+        assert!(sql.contains("AND epoch >= ? AND epoch < ? "));
+        let since: i64 = bind[0].parse().unwrap();
+        let until: i64 = bind[1].parse().unwrap();
+        assert_eq!(until - since, 86400);
+        assert_eq!(since, local_midnight_epoch("2024-03-01").unwrap());
+
+        args.until = Some("03/02/2024".to_string());
+        assert!(build_search_sql(&args, false).is_err());
+    }
+
+    #[test]
+    fn parse_time_spec_accepts_epoch_dates_and_ages() {
+        assert_eq!(parse_time_spec("1700000000", false).unwrap(), 1700000000);
+        // Dates mean local midnight, the same as list/search --since
+        assert_eq!(
+            parse_time_spec("2024-03-01", false).unwrap(),
+            local_midnight_epoch("2024-03-01").unwrap()
+        );
+        assert_eq!(
+            parse_time_spec("2024-03-31", true).unwrap(),
+            local_midnight_epoch("2024-04-01").unwrap() - 1
+        );
+
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let week_ago = parse_time_spec("7d", false).unwrap();
+        assert!((now - 7 * 86400 - week_ago).abs() <= 1);
+
+        assert!(parse_time_spec("2024-13-01", false).is_err());
+        assert!(parse_time_spec("yesterday", false).is_err());
+    }
+
+    #[test]
+    fn stats_sql_uses_explicit_window() {
+        let args = StatsTopArgs {
+            days: 30,
+            since: Some("2024-03-01".to_string()),
+            until: Some("2024-03-31".to_string()),
+            limit: 50,
+            all: false,
+            session: false,
+            canonical: false,
+            by_program: false,
+            trend: false,
+            fzf: false,
+            log_selection: false,
+            fzf_timeout: None,
+            multi_select: false,
+            print0: false,
+        };
+        let (sql, bind) = build_stats_top_sql(&args).unwrap();
+        assert!(sql.contains("AND epoch >= ? AND epoch <= ? "));
+        let since = local_midnight_epoch("2024-03-01").unwrap();
+        let until = local_midnight_epoch("2024-04-01").unwrap() - 1;
+        assert_eq!(
+            bind,
+            vec![since.to_string(), until.to_string(), "50".to_string()]
+        );
+
+        let args = StatsDailyArgs {
+            days: 30,
+            since: None,
+            until: Some("1700000000".to_string()),
+            limit: 50,
+            all: false,
+            session: false,
+            fzf: false,
+            fzf_timeout: None,
+            multi_select: false,
+            print0: false,
+        };
+        let (sql, bind) = build_stats_daily_sql(&args).unwrap();
+        assert!(sql.contains("AND epoch <= ? "));
+        assert!(!sql.contains("epoch >= ?"));
+        assert_eq!(bind, vec!["1700000000", "50"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn which_skips_non_executable_matches() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let first = tempfile::TempDir::new().unwrap();
+        let second = tempfile::TempDir::new().unwrap();
+
+        // A plain file and a directory named like the binary must be skipped...
+        let plain = first.path().join("fakebin");
+        std::fs::write(&plain, "not a program").unwrap();
+        std::fs::set_permissions(&plain, std::fs::Permissions::from_mode(0o644)).unwrap();
+        std::fs::create_dir(first.path().join("fakedir")).unwrap();
+
+        // ...in favour of an executable later on PATH.
+        let exe = second.path().join("fakebin");
+        std::fs::write(&exe, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let path = std::env::join_paths([first.path(), second.path()]).unwrap();
+        assert_eq!(which_in("fakebin", &path), Some(exe));
+        assert_eq!(which_in("fakedir", &path), None);
+
+        let only_plain = std::env::join_paths([first.path()]).unwrap();
+        assert_eq!(which_in("fakebin", &only_plain), None);
+    }
+
+    #[test]
+    fn truncate_for_display_respects_char_boundaries() {
+        assert_eq!(truncate_for_display("echo hi", 20), "echo hi");
+        // Byte slicing at 7 would land inside 'é'
+        assert_eq!(truncate_for_display("cd café/bin", 10), "cd café...");
+        assert_eq!(truncate_for_display("cd café/bin", 9), "cd caf...");
+        // Wide CJK chars take two columns each
+        assert_eq!(
+            truncate_for_display("git commit -m 日本語です", 20),
+            "git commit -m 日..."
+        );
+        assert_eq!(truncate_for_display("日本語", 4), "...");
+        assert_eq!(truncate_for_display("日本語", 2), "...");
+    }
+
+    #[test]
+    fn table_header_lines_up_with_rows() {
+        assert_eq!(
+            format!("{:>6} | {:<19} | {} | {}", "ID", "DATE", "PWD", "CMD"),
+            HISTORY_TABLE_HEADER
+        );
+        assert!(!want_headers(false, true));
+        assert!(want_headers(true, false));
+    }
+
+    #[test]
+    fn log_selection_records_picked_commands_unless_filtered() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let cfg = DbConfig {
+            path: tmp.path().join("test.sqlite"),
+            extra_paths: vec![],
+            hash_version: HashVersion::V1,
+            track_hash: true,
+            pragmas: vec![],
+        };
+        let filter = LogFilter {
+            use_builtin_ignores: true,
+            ignore_exact: vec![],
+            ignore_prefix: vec!["secret ".to_string()],
+            ignore_regex: vec![],
+        };
+
+        assert!(log_selection(&cfg, &filter, "cargo test").unwrap());
+        assert!(!log_selection(&cfg, &filter, "").unwrap());
+        assert!(!log_selection(&cfg, &filter, "ls").unwrap());
+        assert!(!log_selection(&cfg, &filter, "secret thing").unwrap());
+
+        let conn = open_db(&cfg).unwrap();
+        let (cmd, pwd): (String, String) = conn
+            .query_row("SELECT cmd, pwd FROM history", [], |r| {
+                Ok((r.get(0)?, r.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(cmd, "cargo test");
+        assert_eq!(pwd, std::env::current_dir().unwrap().to_string_lossy());
+    }
+
+    #[test]
+    fn nearest_existing_ancestor_walks_up_deleted_dirs() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let worktree = tmp.path().join("repo");
+        std::fs::create_dir(&worktree).unwrap();
+        let deep = worktree.join("gone/feature-x/src/deeply/nested");
+
+        assert_eq!(nearest_existing_ancestor(&deep), Some(worktree.clone()));
+        assert_eq!(
+            resolve_run_dir(&deep, MissingPwdPolicy::NearestExistingAncestor).unwrap(),
+            worktree
+        );
+        assert!(resolve_run_dir(&deep, MissingPwdPolicy::Error).is_err());
+        assert_eq!(
+            resolve_run_dir(&worktree, MissingPwdPolicy::Error).unwrap(),
+            worktree
+        );
+        assert_eq!(
+            nearest_existing_ancestor(std::path::Path::new("no/such/dir")),
+            None
+        );
+
+        let cfg: ConfigFile =
+            toml::from_str("[run]\nmissing_pwd = \"nearest-existing-ancestor\"").unwrap();
+        assert_eq!(
+            cfg.run.missing_pwd,
+            MissingPwdPolicy::NearestExistingAncestor
+        );
+    }
+
+    #[test]
+    fn rank_related_commands_orders_by_source_and_labels() {
+        let cmds = |list: &[&str]| list.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        let ranked = rank_related_commands(
+            "git commit",
+            vec![
+                (RelatedSource::Directory, cmds(&["make", "ls"])),
+                (
+                    RelatedSource::Tool,
+                    cmds(&["git commit", "git diff", "git push"]),
+                ),
+                (RelatedSource::Semantic, cmds(&["git status", "git push"])),
+                (RelatedSource::Workflow, cmds(&["cargo test", "git status"])),
+            ],
+            5,
+        );
+        assert_eq!(
+            ranked,
+            vec![
+                ("git status".to_string(), RelatedSource::Semantic),
+                ("git push".to_string(), RelatedSource::Semantic),
+                ("cargo test".to_string(), RelatedSource::Workflow),
+                ("git diff".to_string(), RelatedSource::Tool),
+                ("make".to_string(), RelatedSource::Directory),
+            ]
+        );
+        assert_eq!(ranked[2].1.label(), "(often used together)");
+    }
+
+    #[test]
+    fn porcelain_row_keeps_one_line_per_row() {
+        assert_eq!(
+            porcelain_row(&["1", "1700000000", "/my dir", "echo a b"]),
+            "1\t1700000000\t/my dir\techo a b"
+        );
+        assert_eq!(
+            porcelain_row(&["a\tb", "c\nd", "e\\f"]),
+            "a\\tb\tc\\nd\te\\\\f"
+        );
+    }
+
+    #[test]
+    fn csv_field_quotes_only_when_needed() {
+        assert_eq!(csv_field("git status"), "git status");
+        assert_eq!(csv_field("echo a,b"), "\"echo a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("line1\nline2"), "\"line1\nline2\"");
+        assert_eq!(csv_row(&["1", "", "x,y"]), "1,,\"x,y\"");
+    }
+
+    #[test]
+    fn sanitize_nul_marks_nul_bytes() {
+        assert_eq!(sanitize_nul("printf 'a\0b'".into(), true), "printf 'a\\0b'");
+        assert_eq!(sanitize_nul("printf 'a\0b'".into(), false), "printf 'a\0b'");
+        assert_eq!(sanitize_nul("ls".into(), true), "ls");
+    }
+
+    #[test]
+    fn log_config_defaults_match_with_and_without_a_log_table() {
+        for text in ["", "[log]\n"] {
+            let cfg: ConfigFile = toml::from_str(text).unwrap();
+            assert!(cfg.log.strip_nul, "{text:?}");
+            assert!(cfg.log.use_builtin_ignores, "{text:?}");
+        }
+        let cfg: ConfigFile = toml::from_str("[log]\nstrip_nul = false\n").unwrap();
+        assert!(!cfg.log.strip_nul);
+    }
+
+    #[test]
+    fn record_schemas_describe_exactly_the_serialized_keys() {
+        let described = |kind: RecordKind| -> Vec<String> {
+            let mut names: Vec<String> = kind.fields().iter().map(|f| f.name.to_string()).collect();
+            names.sort();
+            names
+        };
+        let emitted = |value: serde_json::Value| -> Vec<String> {
+            value.as_object().unwrap().keys().cloned().collect()
+        };
+
+        let mut record = HistoryRecord {
+            id: 1,
+            epoch: 1700000000,
+            iso: Some("2023-11-14T22:13:20+00:00".to_string()),
+            pwd: "/tmp".to_string(),
+            cmd: "ls".to_string(),
+            session: Some(SessionFields {
+                hist_id: None,
+                salt: 1,
+                ppid: 2,
+            }),
+            details: None,
+        };
+        let list = emitted(serde_json::to_value(&record).unwrap());
+        assert_eq!(list, described(RecordKind::List));
+        assert_eq!(list, described(RecordKind::Search));
+
+        record.details = Some(RowDetails {
+            exit_code: None,
+            duration_ms: None,
+            host: None,
+            tty: None,
+            gitroot: None,
+            seq: None,
+            env: None,
+        });
+        let export = emitted(serde_json::to_value(&record).unwrap());
+        assert_eq!(export, described(RecordKind::Export));
+
+        let stored = StoredRecord {
+            id: 1,
+            hist_id: None,
+            epoch: 1700000000,
+            ppid: 2,
+            pwd: "/tmp".to_string(),
+            salt: 1,
+            cmd: "ls".to_string(),
+            exit_code: None,
+            duration_ms: None,
+            host: None,
+            tty: None,
+            gitroot: None,
+            seq: None,
+            env: None,
+            inserted_at: None,
+            synthetic: false,
+        };
+        let show = emitted(serde_json::to_value(&stored).unwrap());
+        assert_eq!(show, described(RecordKind::Show));
+    }
+
+    #[test]
+    fn json_string_escapes_control_characters() {
+        assert_eq!(json_string("a\"b\\c\n\t"), "\"a\\\"b\\\\c\\n\\t\"");
+        assert_eq!(json_string("\x1b[31mred\x00"), "\"\\u001b[31mred\\u0000\"");
+        let parsed: String = serde_json::from_str(&json_string("\x01\x1f\x7f é")).unwrap();
+        assert_eq!(parsed, "\x01\x1f\x7f é");
+    }
+
+    #[test]
+    fn fzf_timeout_kills_a_hung_fzf() {
+        let finished = FzfRun::Finished(std::process::Command::new("true").output().unwrap());
+        assert!(finished.into_output().is_some());
+        assert!(FzfRun::TimedOut.into_output().is_none());
+        assert_eq!(FZF_CANCELLED, 130);
+
+        // A stand-in for a hung fzf is killed at the deadline
+        let mut hung = std::process::Command::new("sleep");
+        hung.arg("30");
+        let start = std::time::Instant::now();
+        let run = run_fzf(&mut hung, b"ls\n", Some(1)).unwrap();
+        assert!(matches!(run, FzfRun::TimedOut));
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+
+        let mut quick = std::process::Command::new("head");
+        quick.arg("-n1");
+        let run = run_fzf(&mut quick, b"ls\npwd\n", Some(5)).unwrap();
+        assert_eq!(run.into_output().unwrap().stdout, b"ls\n");
+    }
+
+    #[test]
+    fn fzf_preview_receives_only_the_command() {
+        assert_eq!(fzf_preview_command(0), "sdbh preview -- {1}");
+        assert_eq!(fzf_preview_command(3), "sdbh preview -- {4}");
+
+        // fzf splits fields on FZF_DELIMITER; the preview field must be the bare command
+        let delimiter = regex::Regex::new(FZF_DELIMITER).unwrap();
+        for (line, meta_fields) in [
+            ("git commit -m 'x'  (2024-01-01 10:00:00) [/repo]", 0),
+            (
+                "12\t1700000000\tgit status  (2024-01-01 10:00:00) [/repo]",
+                2,
+            ),
+            (
+                "12\t3\t1700000000\tmake test  [/repo]  (3 uses, last: 2024-01-01)",
+                3,
+            ),
+            ("cargo build  [/repo]  (5 uses)", 0),
+            ("ls -la  (7 uses)", 0),
+        ] {
+            let fields: Vec<&str> = delimiter.split(line).collect();
+            let cmd = fields[meta_fields];
+            assert!(
+                [
+                    "git commit -m 'x'",
+                    "git status",
+                    "make test",
+                    "cargo build",
+                    "ls -la"
+                ]
+                .contains(&cmd),
+                "{line:?} -> {cmd:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn format_relative_time_never_panics_on_extreme_epochs() {
+        assert_eq!(format_relative_time(0), "1970-01-01");
+        assert_eq!(format_relative_time(1_700_000_000), "2023-11-14");
+        assert_eq!(format_relative_time(i64::MAX), "in the future");
+        // Beyond what `time` can represent, the manual fallback still gives a date
+        assert!(format_relative_time(i64::MIN).ends_with("-01-27"));
+
+        assert_eq!(format_timestamp(0), "1970-01-01");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29");
+        assert_eq!(format_timestamp(-86_400), "1969-12-31");
+        assert_eq!(format_timestamp(253_402_300_800), "10000-01-01");
+    }
+
+    #[test]
+    fn parse_fzf_version_handles_common_formats() {
+        assert_eq!(parse_fzf_version("0.44.1 (d7d2ac3)\n"), Some((0, 44, 1)));
+        assert_eq!(parse_fzf_version("0.56.3 (brew)"), Some((0, 56, 3)));
+        assert_eq!(parse_fzf_version("0.20.0-devel"), Some((0, 20, 0)));
+        assert_eq!(parse_fzf_version("0.29"), Some((0, 29, 0)));
+        assert_eq!(parse_fzf_version(""), None);
+        assert_eq!(parse_fzf_version("fzf: command not found"), None);
+
+        assert!(parse_fzf_version("0.44.1").unwrap() >= MIN_FZF_VERSION);
+        assert!(parse_fzf_version("0.30.0").unwrap() >= MIN_FZF_VERSION);
+        assert!(parse_fzf_version("0.29.9").unwrap() < MIN_FZF_VERSION);
+        assert!(parse_fzf_version("1.0.0").unwrap() >= MIN_FZF_VERSION);
+    }
+
+    #[test]
+    fn sparkline_scales_to_max() {
+        assert_eq!(sparkline(&[0, 1, 2, 4, 8]), " ▁▂▄█");
+        assert_eq!(sparkline(&[0, 0]), "  ");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn peak_hours_finds_busiest_window() {
+        let mut hours = [0i64; 24];
+        assert_eq!(peak_hours(&hours), None);
+
+        hours[9] = 3;
+        hours[10] = 2;
+        hours[14] = 1;
+        assert_eq!(peak_hours(&hours), Some((9, 10)));
+
+        let mut late = [0i64; 24];
+        late[23] = 2;
+        late[0] = 2;
+        assert_eq!(peak_hours(&late), Some((23, 0)));
+    }
+
+    #[test]
+    fn command_key_sql_without_aliases_is_raw_cmd() {
+        let mut bind = vec![];
+        assert_eq!(command_key_sql(&HashMap::new(), false, &mut bind), "cmd");
+        assert_eq!(
+            command_key_sql(&HashMap::new(), true, &mut bind),
+            LEADING_TOKEN_SQL
+        );
+        assert!(bind.is_empty());
+    }
+
+    #[test]
+    fn command_key_sql_rewrites_aliases() {
+        let mut aliases = HashMap::new();
+        aliases.insert("g".to_string(), "git".to_string());
+        aliases.insert("k".to_string(), "kubectl".to_string());
+
+        let mut bind = vec![];
+        let expr = command_key_sql(&aliases, false, &mut bind);
+        assert!(expr.contains("WHEN ? THEN ? || substr(cmd"));
+        assert_eq!(bind, vec!["g", "git", "k", "kubectl"]);
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let key: String = conn
+            .query_row(
+                &format!("SELECT {expr} FROM (SELECT 'g status' AS cmd)"),
+                rusqlite::params_from_iter(bind.iter()),
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(key, "git status");
+    }
+
+    #[test]
+    fn suspected_secret_heuristic() {
+        assert!(prompts_for_password("ssh admin@db1"));
+        assert!(prompts_for_password("/usr/bin/sudo -i"));
+        assert!(!prompts_for_password("sshfs host:/ /mnt"));
+        assert!(!prompts_for_password(""));
+
+        assert!(looks_like_typed_secret("S3cr3t!Pass"));
+        assert!(looks_like_typed_secret("xK9#mQ2$vL"));
+        // Commands, short words and low-entropy tokens are kept
+        assert!(!looks_like_typed_secret("ls -la"));
+        assert!(!looks_like_typed_secret("hunter2"));
+        assert!(!looks_like_typed_secret("./configure"));
+        assert!(!looks_like_typed_secret("Aa1Aa1Aa1Aa1"));
+    }
+
+    #[test]
+    fn histtimeformat_check_requires_epoch_prefix() {
+        assert!(matches!(
+            histtimeformat_check(Some("%s ")).status,
+            DoctorStatus::Ok
+        ));
+        assert!(matches!(
+            histtimeformat_check(Some("%F %T ")).status,
+            DoctorStatus::Warn
+        ));
+        assert!(matches!(
+            histtimeformat_check(Some("")).status,
+            DoctorStatus::Warn
+        ));
+        assert!(matches!(
+            histtimeformat_check(None).status,
+            DoctorStatus::Warn
+        ));
+    }
+
+    #[test]
+    fn fzf_selection_print0_keeps_multiline_commands() {
+        let cmd = "for f in *.log; do\n  gzip \"$f\"\ndone";
+
+        let mut out = Vec::new();
+        write_selection(&mut out, cmd, true).unwrap();
+        write_selection(&mut out, "ls", true).unwrap();
+        assert_eq!(out, format!("{cmd}\0ls\0").into_bytes());
+
+        // Newline output can't tell the multi-line command from three selections
+        let mut out = Vec::new();
+        write_selection(&mut out, cmd, false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 3);
+
+        let fzf_out =
+            format!("{cmd}  (2024-01-01 10:00:00) [/tmp]\0ls  (2024-01-01 10:01:00) [/tmp]\0");
+        let records = fzf_records(&fzf_out, true);
+        assert_eq!(records.len(), 2);
+        assert!(records[0].starts_with(cmd));
+        assert_eq!(fzf_records("a\nb\n", false), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn relevance_tier_prefers_prefix_then_word_boundary() {
+        assert_eq!(relevance_tier("kubectl", "KUBECTL"), 0);
+        assert_eq!(relevance_tier("kubectl get pods", "kube"), 1);
+        assert_eq!(relevance_tier("watch kubectl get pods", "kube"), 2);
+        assert_eq!(relevance_tier("echo x|kubectl", "kube"), 2);
+        assert_eq!(relevance_tier("minikube start", "kube"), 3);
+    }
+
+    #[test]
+    fn sort_hits_is_stable_within_rank() {
+        let hit = |id: i64, cmd: &str| SearchHit {
+            id,
+            dt: String::new(),
+            pwd: String::new(),
+            cmd: cmd.to_string(),
+            epoch: 0,
+            session: None,
+        };
+        let ids = |hits: &[SearchHit]| hits.iter().map(|h| h.id).collect::<Vec<_>>();
+        // Most recent first, as the query returns them
+        let mut hits = vec![
+            hit(5, "minikube start"),
+            hit(4, "watch kubectl get pods"),
+            hit(3, "kubectl logs"),
+            hit(2, "minikube start"),
+            hit(1, "kubectl get pods"),
+        ];
+
+        sort_hits(&mut hits, SortMode::Relevance, "kube");
+        assert_eq!(ids(&hits), vec![3, 1, 4, 5, 2]);
+
+        sort_hits(&mut hits, SortMode::Frequency, "kube");
+        assert_eq!(ids(&hits)[..2], [5, 2]);
+    }
+
+    #[test]
+    fn with_meta_selection_is_json_with_hidden_fields() {
+        let line = "7\t3\t1700000000\tgit status  (3 uses, last: 2023-11-14 22:13:20)";
+        let (meta, display) = split_meta(line, 3).unwrap();
+        assert_eq!(meta, vec![7, 3, 1700000000]);
+        assert!(display.starts_with("git status  ("));
+
+        let json = selection_json(
+            &[("id", meta[0]), ("count", meta[1]), ("last_epoch", meta[2])],
+            "git status",
+        );
+        assert_eq!(
+            json,
+            r#"{"id":7,"count":3,"last_epoch":1700000000,"cmd":"git status"}"#
+        );
+        let v: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(v["cmd"], "git status");
+
+        // A display line without the metadata prefix is rejected
+        assert!(split_meta("git status  (3 uses)", 3).is_none());
+    }
 }
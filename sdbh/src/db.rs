@@ -1,15 +1,51 @@
-use crate::domain::{DbConfig, HistoryRow};
+use crate::domain::{DbConfig, HashVersion, HistoryRow};
 use anyhow::{Context, Result};
-use rusqlite::{Connection, params, types::Value};
+use rusqlite::{Connection, OptionalExtension, params, types::Value};
 use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 
 pub fn open_db(cfg: &DbConfig) -> Result<Connection> {
     let conn = Connection::open(&cfg.path)
         .with_context(|| format!("opening sqlite db at {}", cfg.path.display()))?;
+    apply_pragmas(&conn, &cfg.pragmas);
     init_schema(&conn)?;
+    migrate(&conn)?;
+    sync_hash_version(&conn, cfg.hash_version)?;
     Ok(conn)
 }
 
+/// Pragmas `[db] pragmas` may set: performance knobs only, nothing that can
+/// corrupt the file, weaken durability or change what queries see.
+const ALLOWED_PRAGMAS: &[&str] = &["busy_timeout", "cache_size", "mmap_size", "temp_store"];
+
+/// Apply `[db] pragmas`. A bad entry is reported and skipped so a typo in the
+/// config never makes the database unusable.
+fn apply_pragmas(conn: &Connection, pragmas: &[(String, String)]) {
+    for (name, value) in pragmas {
+        let applied = check_pragma(name, value).and_then(|()| {
+            conn.execute_batch(&format!("PRAGMA {name} = {value}"))
+                .with_context(|| format!("applying PRAGMA {name} = {value}"))
+        });
+        if let Err(e) = applied {
+            eprintln!("warning: [db] pragmas: skipping {name}: {e:#}");
+        }
+    }
+}
+
+fn check_pragma(name: &str, value: &str) -> Result<()> {
+    if !ALLOWED_PRAGMAS.contains(&name) {
+        anyhow::bail!("not allowed (allowed: {})", ALLOWED_PRAGMAS.join(", "));
+    }
+    // Values are spliced into the statement, so only accept plain
+    // integers and keywords such as MEMORY.
+    let plain = value.parse::<i64>().is_ok()
+        || (!value.is_empty() && value.chars().all(|c| c.is_ascii_alphabetic()));
+    if !plain {
+        anyhow::bail!("invalid value {value:?}");
+    }
+    Ok(())
+}
+
 fn init_schema(conn: &Connection) -> Result<()> {
     conn.execute_batch(
         r#"
@@ -35,6 +71,7 @@ fn init_schema(conn: &Connection) -> Result<()> {
         "#,
     )?;
 
+    // The tables above are schema version 1; `migrate` takes it from there.
     conn.execute(
         "INSERT OR IGNORE INTO meta(key,value) VALUES('schema_version','1')",
         [],
@@ -43,29 +80,497 @@ fn init_schema(conn: &Connection) -> Result<()> {
     Ok(())
 }
 
-pub fn insert_history(conn: &mut Connection, row: &HistoryRow) -> Result<i64> {
+type Migration = fn(&Connection) -> Result<()>;
+
+/// Forward migrations: `MIGRATIONS[i]` upgrades schema version `i + 1` to `i + 2`.
+/// Append new steps at the end; never reorder or edit released ones.
+const MIGRATIONS: &[Migration] = &[
+    // 2: per-command metadata. Builds before versioned migrations added these
+    // columns ad hoc while staying at version 1, so only add what is missing.
+    |conn| {
+        add_column_if_missing(conn, "history", "exit_code", "INTEGER")?;
+        add_column_if_missing(conn, "history", "duration_ms", "INTEGER")?;
+        add_column_if_missing(conn, "history", "host", "TEXT")?;
+        add_column_if_missing(conn, "history", "tty", "TEXT")?;
+        add_column_if_missing(conn, "history", "gitroot", "TEXT")
+    },
+    // 3: when sdbh recorded the row, as opposed to when the command ran (`epoch`).
+    // NULL for rows stored before this version.
+    |conn| add_column_if_missing(conn, "history", "inserted_at", "INTEGER"),
+    // 4: 1 when import-history made up the row's epoch (the source had no timestamp).
+    |conn| add_column_if_missing(conn, "history", "synthetic", "INTEGER"),
+    // 5: per-session command counter (`log --seq`), ordering rows that share an epoch.
+    |conn| add_column_if_missing(conn, "history", "seq", "INTEGER"),
+    // 6: environment variables captured with `log --capture-env`, as a JSON object.
+    |conn| add_column_if_missing(conn, "history", "env", "TEXT"),
+];
+
+/// Schema version this build writes (`meta.schema_version`).
+const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+/// Bring the database up to `SCHEMA_VERSION`, one migration per transaction so an
+/// interrupted upgrade resumes from the last completed step.
+fn migrate(conn: &Connection) -> Result<()> {
+    let current: u32 = conn
+        .query_row(
+            "SELECT value FROM meta WHERE key='schema_version'",
+            [],
+            |r| r.get::<_, String>(0),
+        )?
+        .parse()
+        .context("meta.schema_version is not a number")?;
+    if current == 0 {
+        anyhow::bail!("meta.schema_version is 0; schema versions start at 1");
+    }
+    if current > SCHEMA_VERSION {
+        anyhow::bail!(
+            "database schema version {current} is newer than this sdbh supports ({SCHEMA_VERSION}); please upgrade sdbh"
+        );
+    }
+
+    for (step, migration) in MIGRATIONS.iter().enumerate().skip(current as usize - 1) {
+        let version = step as u32 + 2;
+        conn.execute_batch("BEGIN")?;
+        let applied = migration(conn).and_then(|()| {
+            conn.execute(
+                "UPDATE meta SET value = ?1 WHERE key='schema_version'",
+                params![version.to_string()],
+            )?;
+            Ok(())
+        });
+        match applied {
+            Ok(()) => conn.execute_batch("COMMIT")?,
+            Err(e) => {
+                conn.execute_batch("ROLLBACK")?;
+                return Err(e.context(format!("migrating database to schema version {version}")));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, ty: &str) -> Result<()> {
+    if !has_column(conn, table, column)? {
+        conn.execute_batch(&format!("ALTER TABLE {table} ADD COLUMN {column} {ty};"))?;
+    }
+    Ok(())
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let mut rows = stmt.query([])?;
+    while let Some(r) = rows.next()? {
+        let name: String = r.get(1)?;
+        if name == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Rebuild `history_hash` when the configured hash scheme differs from the one
+/// the database was hashed with, so dedup keeps working after switching.
+fn sync_hash_version(conn: &Connection, version: HashVersion) -> Result<()> {
+    let stored: Option<HashVersion> = conn
+        .query_row("SELECT value FROM meta WHERE key='hash_version'", [], |r| {
+            r.get::<_, String>(0)
+        })
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .and_then(HashVersion::from_number);
+
+    if stored == Some(version) {
+        return Ok(());
+    }
+
+    // Databases created before hash_version existed were hashed with V1.
+    if stored.unwrap_or(HashVersion::V1) != version {
+        rehash_history(conn, version)?;
+    }
+
+    conn.execute(
+        "INSERT OR REPLACE INTO meta(key,value) VALUES('hash_version', ?1)",
+        params![version.number().to_string()],
+    )?;
+    Ok(())
+}
+
+fn rehash_history(conn: &Connection, version: HashVersion) -> Result<()> {
+    conn.execute_batch("BEGIN; DELETE FROM history_hash;")?;
+    {
+        let mut stmt = conn.prepare(
+            "SELECT id, hist_id, cmd, epoch, ppid, pwd, salt, host FROM history ORDER BY id",
+        )?;
+        let mut insert =
+            conn.prepare("INSERT OR IGNORE INTO history_hash(hash, history_id) VALUES (?1, ?2)")?;
+        let mut rows = stmt.query([])?;
+        while let Some(r) = rows.next()? {
+            let id: i64 = r.get(0)?;
+            let row = HistoryRow {
+                hist_id: r.get(1)?,
+                cmd: r.get(2)?,
+                epoch: r.get(3)?,
+                ppid: r.get(4)?,
+                pwd: r.get(5)?,
+                salt: r.get(6)?,
+                exit_code: None,
+                duration_ms: None,
+                host: r.get(7)?,
+                tty: None,
+                gitroot: None,
+                seq: None,
+                env: None,
+            };
+            insert.execute(params![row_hash(&row, version), id])?;
+        }
+    }
+    conn.execute_batch("COMMIT")?;
+    Ok(())
+}
+
+pub fn insert_history(conn: &mut Connection, row: &HistoryRow, cfg: &DbConfig) -> Result<i64> {
     let tx = conn.transaction()?;
     tx.execute(
         r#"
-        INSERT INTO history(hist_id, cmd, epoch, ppid, pwd, salt)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        INSERT INTO history(hist_id, cmd, epoch, ppid, pwd, salt, exit_code, duration_ms, host, tty, gitroot, seq, env, inserted_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, CAST(strftime('%s','now') AS INTEGER))
         "#,
-        params![row.hist_id, row.cmd, row.epoch, row.ppid, row.pwd, row.salt],
+        params![
+            row.hist_id,
+            row.cmd,
+            row.epoch,
+            row.ppid,
+            row.pwd,
+            row.salt,
+            row.exit_code,
+            row.duration_ms,
+            row.host,
+            row.tty,
+            row.gitroot,
+            row.seq,
+            row.env
+        ],
     )?;
 
     let id = tx.last_insert_rowid();
-    let hash = row_hash(row);
+
+    if cfg.track_hash {
+        let hash = row_hash(row, cfg.hash_version);
+        tx.execute(
+            "INSERT OR IGNORE INTO history_hash(hash, history_id) VALUES (?1, ?2)",
+            params![hash, id],
+        )?;
+    }
+
+    tx.commit()?;
+    Ok(id)
+}
+
+/// Remove history rows along with their `history_hash` entries. Returns the number
+/// of history rows removed.
+pub fn delete_history(conn: &mut Connection, ids: &[i64]) -> Result<usize> {
+    let tx = conn.transaction()?;
+    let mut removed = 0;
+    {
+        let mut del_hash = tx.prepare("DELETE FROM history_hash WHERE history_id = ?1")?;
+        let mut del_row = tx.prepare("DELETE FROM history WHERE id = ?1")?;
+        for id in ids {
+            del_hash.execute(params![id])?;
+            removed += del_row.execute(params![id])?;
+        }
+    }
+    tx.commit()?;
+    Ok(removed)
+}
+
+/// Replace the `cmd` of one row and move its `history_hash` entry to the new hash.
+/// Fails without changing anything if another row already has that hash.
+pub fn update_history_cmd(
+    conn: &mut Connection,
+    id: i64,
+    cmd: &str,
+    version: HashVersion,
+) -> Result<()> {
+    let tx = conn.transaction()?;
+    let row = tx
+        .query_row(
+            "SELECT hist_id, epoch, ppid, pwd, salt, host FROM history WHERE id = ?1",
+            params![id],
+            |r| {
+                Ok(HistoryRow {
+                    hist_id: r.get(0)?,
+                    cmd: cmd.to_string(),
+                    epoch: r.get(1)?,
+                    ppid: r.get(2)?,
+                    pwd: r.get(3)?,
+                    salt: r.get(4)?,
+                    exit_code: None,
+                    duration_ms: None,
+                    host: r.get(5)?,
+                    tty: None,
+                    gitroot: None,
+                    seq: None,
+                    env: None,
+                })
+            },
+        )
+        .optional()?
+        .with_context(|| format!("no history entry with id {id}"))?;
+
+    let hash = row_hash(&row, version);
+    let clash: Option<i64> = tx
+        .query_row(
+            "SELECT history_id FROM history_hash WHERE hash = ?1 AND history_id != ?2",
+            params![hash, id],
+            |r| r.get(0),
+        )
+        .optional()?;
+    if let Some(other) = clash {
+        anyhow::bail!("an identical entry already exists (id {other}); not updating");
+    }
 
     tx.execute(
-        "INSERT OR IGNORE INTO history_hash(hash, history_id) VALUES (?1, ?2)",
-        params![hash, id],
+        "UPDATE history SET cmd = ?1 WHERE id = ?2",
+        params![cmd, id],
     )?;
+    // Rows logged with track_hash off have no hash entry; keep it that way.
+    let had_hash = tx.execute(
+        "DELETE FROM history_hash WHERE history_id = ?1",
+        params![id],
+    )? > 0;
+    if had_hash {
+        tx.execute(
+            "INSERT INTO history_hash(hash, history_id) VALUES (?1, ?2)",
+            params![hash, id],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Apply `pattern` -> `replacement` (regex syntax, `$1` expands groups) to every
+/// `cmd` and every captured `env` value, rewriting `history_hash` for rows whose
+/// `cmd` changed. Runs in one transaction; with `dry_run` it is rolled back.
+/// Returns `(id, before, after)` for each changed row, with ` (env: {...})` appended
+/// when the row's env changed.
+pub fn redact_history(
+    conn: &mut Connection,
+    pattern: &regex::Regex,
+    replacement: &str,
+    version: HashVersion,
+    dry_run: bool,
+) -> Result<Vec<(i64, String, String)>> {
+    let tx = conn.transaction()?;
+    let mut changed = Vec::new();
+    {
+        let mut stmt =
+            tx.prepare("SELECT id, hist_id, cmd, epoch, ppid, pwd, salt, host, env FROM history")?;
+        let mut update = tx.prepare("UPDATE history SET cmd = ?1, env = ?2 WHERE id = ?3")?;
+        let mut del_hash = tx.prepare("DELETE FROM history_hash WHERE history_id = ?1")?;
+        // Redaction can make two rows identical; the first keeps the hash entry.
+        let mut ins_hash =
+            tx.prepare("INSERT OR IGNORE INTO history_hash(hash, history_id) VALUES (?1, ?2)")?;
 
+        let mut rows = stmt.query([])?;
+        while let Some(r) = rows.next()? {
+            let id: i64 = r.get(0)?;
+            let before: String = r.get(2)?;
+            let after = pattern.replace_all(&before, replacement).into_owned();
+            let env_before: Option<String> = r.get(8)?;
+            let env_after = match &env_before {
+                Some(env) => {
+                    Some(redact_env(env, pattern, replacement).with_context(|| {
+                        format!("history entry {id} has a malformed env column")
+                    })?)
+                }
+                None => None,
+            };
+            let env_changed = env_after != env_before;
+            if after == before && !env_changed {
+                continue;
+            }
+
+            update.execute(params![after, env_after, id])?;
+            if after != before {
+                let row = HistoryRow {
+                    hist_id: r.get(1)?,
+                    cmd: after.clone(),
+                    epoch: r.get(3)?,
+                    ppid: r.get(4)?,
+                    pwd: r.get(5)?,
+                    salt: r.get(6)?,
+                    exit_code: None,
+                    duration_ms: None,
+                    host: r.get(7)?,
+                    tty: None,
+                    gitroot: None,
+                    seq: None,
+                    env: None,
+                };
+                if del_hash.execute(params![id])? > 0 {
+                    ins_hash.execute(params![row_hash(&row, version), id])?;
+                }
+            }
+            match (env_changed, env_before, env_after) {
+                (true, Some(env_before), Some(env_after)) => changed.push((
+                    id,
+                    format!("{before} (env: {env_before})"),
+                    format!("{after} (env: {env_after})"),
+                )),
+                _ => changed.push((id, before, after)),
+            }
+        }
+    }
+
+    if dry_run {
+        tx.rollback()?;
+    } else {
+        tx.commit()?;
+    }
+    Ok(changed)
+}
+
+/// Apply a redaction to each value of a stored `env` JSON object.
+fn redact_env(env: &str, pattern: &regex::Regex, replacement: &str) -> Result<String> {
+    let vars: BTreeMap<String, String> = serde_json::from_str(env)?;
+    let vars: BTreeMap<String, String> = vars
+        .into_iter()
+        .map(|(name, value)| {
+            let value = pattern.replace_all(&value, replacement).into_owned();
+            (name, value)
+        })
+        .collect();
+    Ok(serde_json::to_string(&vars)?)
+}
+
+/// Delete rows with `epoch < cutoff_epoch` and their `history_hash` entries.
+/// With `dry_run`, only counts the rows that would be removed.
+pub fn prune_older_than(conn: &mut Connection, cutoff_epoch: i64, dry_run: bool) -> Result<u64> {
+    if dry_run {
+        let n: i64 = conn.query_row(
+            "SELECT count(*) FROM history WHERE epoch < ?1",
+            params![cutoff_epoch],
+            |r| r.get(0),
+        )?;
+        return Ok(n as u64);
+    }
+
+    let tx = conn.transaction()?;
+    tx.execute(
+        "DELETE FROM history_hash WHERE history_id IN (SELECT id FROM history WHERE epoch < ?1)",
+        params![cutoff_epoch],
+    )?;
+    let removed = tx.execute(
+        "DELETE FROM history WHERE epoch < ?1",
+        params![cutoff_epoch],
+    )?;
     tx.commit()?;
-    Ok(id)
+    Ok(removed as u64)
+}
+
+/// Delete rows whose `row_hash` matches an earlier row, keeping the lowest `id` of
+/// each group, then rebuild `history_hash` from the survivors (only with
+/// `track_hash`; otherwise just drop the removed rows' entries). Returns how many
+/// rows were (or, with `dry_run`, would be) removed.
+pub fn dedup_history(conn: &mut Connection, cfg: &DbConfig, dry_run: bool) -> Result<u64> {
+    let tx = conn.transaction()?;
+    let mut removed = 0u64;
+    {
+        let mut stmt = tx.prepare(
+            "SELECT id, hist_id, cmd, epoch, ppid, pwd, salt, host FROM history ORDER BY id",
+        )?;
+        let mut delete = tx.prepare("DELETE FROM history WHERE id = ?1")?;
+        let mut unhash = tx.prepare("DELETE FROM history_hash WHERE history_id = ?1")?;
+        let mut kept: Vec<(String, i64)> = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+
+        let mut rows = stmt.query([])?;
+        while let Some(r) = rows.next()? {
+            let id: i64 = r.get(0)?;
+            let row = HistoryRow {
+                hist_id: r.get(1)?,
+                cmd: r.get(2)?,
+                epoch: r.get(3)?,
+                ppid: r.get(4)?,
+                pwd: r.get(5)?,
+                salt: r.get(6)?,
+                exit_code: None,
+                duration_ms: None,
+                host: r.get(7)?,
+                tty: None,
+                gitroot: None,
+                seq: None,
+                env: None,
+            };
+            let hash = row_hash(&row, cfg.hash_version);
+            if seen.insert(hash.clone()) {
+                kept.push((hash, id));
+            } else {
+                delete.execute(params![id])?;
+                unhash.execute(params![id])?;
+                removed += 1;
+            }
+        }
+
+        if cfg.track_hash {
+            tx.execute("DELETE FROM history_hash", [])?;
+            let mut insert =
+                tx.prepare("INSERT INTO history_hash(hash, history_id) VALUES (?1, ?2)")?;
+            for (hash, id) in &kept {
+                insert.execute(params![hash, id])?;
+            }
+        }
+    }
+
+    if dry_run {
+        tx.rollback()?;
+    } else {
+        tx.commit()?;
+    }
+    Ok(removed)
 }
 
-pub fn row_hash(row: &HistoryRow) -> String {
+/// Write a compacted copy of the database to `dest` (`VACUUM INTO`), leaving the
+/// original untouched. `dest` must not exist yet.
+pub fn vacuum_into(conn: &Connection, dest: &std::path::Path) -> Result<()> {
+    conn.execute("VACUUM INTO ?1", params![dest.to_string_lossy()])
+        .with_context(|| format!("compacting into {}", dest.display()))?;
+    Ok(())
+}
+
+/// Copy the live database to a new file at `dest` with SQLite's online backup API,
+/// which yields a consistent snapshot even while other connections write.
+pub fn backup_to(conn: &Connection, dest: &std::path::Path) -> Result<()> {
+    let mut out = Connection::open(dest)
+        .with_context(|| format!("opening backup destination {}", dest.display()))?;
+    let backup = rusqlite::backup::Backup::new(conn, &mut out)?;
+    backup
+        .run_to_completion(256, std::time::Duration::from_millis(10), None)
+        .with_context(|| format!("backing up to {}", dest.display()))?;
+    Ok(())
+}
+
+/// Overwrite the database behind `conn` with the contents of `src` (backup API in
+/// reverse). One step copies every page, so the live file stays write-locked for the
+/// whole copy and connections waiting on it then continue on the new contents.
+pub fn restore_from(conn: &mut Connection, src: &std::path::Path) -> Result<()> {
+    let source = Connection::open(src).with_context(|| format!("opening {}", src.display()))?;
+    let backup = rusqlite::backup::Backup::new(&source, conn)?;
+    // Another connection holding a lock makes a step report busy; wait about as
+    // long as the default busy_timeout before giving up.
+    for _ in 0..500 {
+        let step = backup
+            .step(-1)
+            .with_context(|| format!("copying {} into the database", src.display()))?;
+        if step == rusqlite::backup::StepResult::Done {
+            return Ok(());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    anyhow::bail!("database is busy; stop shells that are logging and retry")
+}
+
+pub fn row_hash(row: &HistoryRow, version: HashVersion) -> String {
     // Stable: field separator is '\n'. Keep it simple & deterministic.
     let mut hasher = Sha256::new();
     hasher.update(row.epoch.to_string());
@@ -74,11 +579,18 @@ pub fn row_hash(row: &HistoryRow) -> String {
     hasher.update("\n");
     hasher.update(row.salt.to_string());
     hasher.update("\n");
-    hasher.update(row.hist_id.map(|v| v.to_string()).unwrap_or_default());
-    hasher.update("\n");
+    if version == HashVersion::V1 {
+        hasher.update(row.hist_id.map(|v| v.to_string()).unwrap_or_default());
+        hasher.update("\n");
+    }
     hasher.update(&row.pwd);
     hasher.update("\n");
     hasher.update(&row.cmd);
+    // Appended only when known so rows logged before host tracking keep their hash.
+    if let Some(host) = &row.host {
+        hasher.update("\n");
+        hasher.update(host);
+    }
     format!("{:x}", hasher.finalize())
 }
 
@@ -95,14 +607,117 @@ pub fn ensure_indexes(conn: &Connection) -> Result<()> {
     Ok(())
 }
 
+/// Whether the optional `history_fts` full-text index has been built.
+pub fn has_fts(conn: &Connection) -> Result<bool> {
+    let n: i64 = conn.query_row(
+        "SELECT count(*) FROM sqlite_master WHERE type='table' AND name='history_fts'",
+        [],
+        |r| r.get(0),
+    )?;
+    Ok(n > 0)
+}
+
+/// Create `history_fts` (FTS5, trigram tokenizer over `history.cmd`) and the triggers
+/// that keep it in sync, then repopulate it from `history`. Safe to run repeatedly.
+///
+/// The trigram tokenizer matches case-insensitive substrings, so a MATCH returns the
+/// same rows as `cmd LIKE '%query%'` for queries of three or more characters.
+pub fn rebuild_fts(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE VIRTUAL TABLE IF NOT EXISTS history_fts USING fts5(
+          cmd, content='history', content_rowid='id', tokenize='trigram'
+        );
+
+        CREATE TRIGGER IF NOT EXISTS history_fts_ai AFTER INSERT ON history BEGIN
+          INSERT INTO history_fts(rowid, cmd) VALUES (new.id, new.cmd);
+        END;
+        CREATE TRIGGER IF NOT EXISTS history_fts_ad AFTER DELETE ON history BEGIN
+          INSERT INTO history_fts(history_fts, rowid, cmd) VALUES ('delete', old.id, old.cmd);
+        END;
+        CREATE TRIGGER IF NOT EXISTS history_fts_au AFTER UPDATE OF cmd ON history BEGIN
+          INSERT INTO history_fts(history_fts, rowid, cmd) VALUES ('delete', old.id, old.cmd);
+          INSERT INTO history_fts(rowid, cmd) VALUES (new.id, new.cmd);
+        END;
+
+        INSERT INTO history_fts(history_fts) VALUES ('rebuild');
+        "#,
+    )
+    .context("building history_fts (requires SQLite with FTS5)")?;
+    Ok(())
+}
+
 // Keep the old function for backward compatibility
 pub fn ensure_hash_index(conn: &Connection) -> Result<()> {
     ensure_indexes(conn)
 }
 
-pub fn import_from_db(conn: &mut Connection, from_path: &std::path::Path) -> Result<(u64, u64)> {
-    // Returns (considered, inserted)
+/// Count one insert towards `[db] auto_optimize_every`; on every `every`th call run
+/// the cheap part of `db optimize` (`PRAGMA optimize` plus any missing indexes) and
+/// reset the counter. Returns whether it ran.
+pub fn tick_auto_optimize(conn: &Connection, every: u32) -> Result<bool> {
+    let count: i64 = conn.query_row(
+        "INSERT INTO meta(key,value) VALUES('inserts_since_optimize', '1') \
+         ON CONFLICT(key) DO UPDATE SET value = CAST(value AS INTEGER) + 1 \
+         RETURNING CAST(value AS INTEGER)",
+        [],
+        |r| r.get(0),
+    )?;
+    if count < i64::from(every) {
+        return Ok(false);
+    }
+
+    ensure_indexes(conn)?;
+    conn.execute_batch("PRAGMA optimize")?;
+    conn.execute_batch(
+        "INSERT OR REPLACE INTO meta(key,value) VALUES('inserts_since_optimize', '0'); \
+         INSERT OR REPLACE INTO meta(key,value) \
+           VALUES('last_auto_optimize', CAST(strftime('%s','now') AS TEXT));",
+    )?;
+    Ok(true)
+}
 
+/// What `import_from_db` did with one source database.
+#[derive(Debug, Default)]
+pub struct ImportOutcome {
+    pub considered: u64,
+    pub inserted: u64,
+    /// Rows skipped as already present, keyed by their source session `(salt, ppid)`
+    pub duplicates_by_session: BTreeMap<(i64, i64), u64>,
+}
+
+/// Field values forced onto every imported row, e.g. to namespace another machine's history.
+#[derive(Debug, Default, Clone)]
+pub struct ImportOverrides {
+    pub pwd: Option<String>,
+    pub host: Option<String>,
+    pub salt: Option<i64>,
+    pub ppid: Option<i64>,
+}
+
+impl ImportOverrides {
+    fn apply(&self, row: &mut HistoryRow) {
+        if let Some(pwd) = &self.pwd {
+            row.pwd = pwd.clone();
+        }
+        if let Some(host) = &self.host {
+            row.host = Some(host.clone());
+        }
+        if let Some(salt) = self.salt {
+            row.salt = salt;
+        }
+        if let Some(ppid) = self.ppid {
+            row.ppid = ppid;
+        }
+    }
+}
+
+pub fn import_from_db(
+    conn: &mut Connection,
+    from_path: &std::path::Path,
+    hash_version: HashVersion,
+    overrides: &ImportOverrides,
+) -> Result<ImportOutcome> {
     // ATTACH is convenient but can trigger locking edge cases on some platforms
     // and temp dir configurations. Instead, open the source DB as a separate
     // connection and stream rows into destination.
@@ -125,18 +740,29 @@ pub fn import_from_db(conn: &mut Connection, from_path: &std::path::Path) -> Res
         );
     }
 
-    let mut considered: u64 = 0;
-    let mut inserted: u64 = 0;
+    let mut outcome = ImportOutcome::default();
     let mut skipped_bad: u64 = 0;
 
+    // Older sources predate the metadata columns; read missing ones as NULL.
+    let optional = |column: &'static str| -> Result<&'static str> {
+        Ok(if has_column(&src, "history", column)? {
+            column
+        } else {
+            "NULL"
+        })
+    };
+    let (host_col, tty_col, gitroot_col) =
+        (optional("host")?, optional("tty")?, optional("gitroot")?);
+    let (exit_code_col, duration_col) = (optional("exit_code")?, optional("duration_ms")?);
+    let (seq_col, env_col, synthetic_col) =
+        (optional("seq")?, optional("env")?, optional("synthetic")?);
+
     {
-        let mut stmt = src.prepare(
-            r#"
-            SELECT hist_id, cmd, epoch, ppid, pwd, salt
-            FROM history
-            ORDER BY id ASC
-            "#,
-        )?;
+        let mut stmt = src.prepare(&format!(
+            "SELECT hist_id, cmd, epoch, ppid, pwd, salt, {host_col}, {tty_col}, {gitroot_col}, \
+             {exit_code_col}, {duration_col}, {seq_col}, {env_col}, {synthetic_col} \
+             FROM history ORDER BY id ASC"
+        ))?;
 
         let rows = stmt.query_map([], |r| {
             Ok((
@@ -146,12 +772,35 @@ pub fn import_from_db(conn: &mut Connection, from_path: &std::path::Path) -> Res
                 r.get::<_, Value>(3)?,
                 r.get::<_, String>(4)?,
                 r.get::<_, Value>(5)?,
+                r.get::<_, Option<String>>(6)?,
+                r.get::<_, Option<String>>(7)?,
+                r.get::<_, Option<String>>(8)?,
+                r.get::<_, Value>(9)?,
+                r.get::<_, Value>(10)?,
+                r.get::<_, Value>(11)?,
+                r.get::<_, Option<String>>(12)?,
+                r.get::<_, Value>(13)?,
             ))
         })?;
 
         for row in rows {
-            let (hist_id_v, cmd, epoch_v, ppid_v, pwd, salt_v) = row?;
-            considered += 1;
+            let (
+                hist_id_v,
+                cmd,
+                epoch_v,
+                ppid_v,
+                pwd,
+                salt_v,
+                host,
+                tty,
+                gitroot,
+                exit_code_v,
+                duration_v,
+                seq_v,
+                env,
+                synthetic_v,
+            ) = row?;
+            outcome.considered += 1;
 
             let hist_id = value_to_i64(&hist_id_v);
             let epoch = match value_to_i64(&epoch_v) {
@@ -176,40 +825,39 @@ pub fn import_from_db(conn: &mut Connection, from_path: &std::path::Path) -> Res
                 }
             };
 
-            let row = HistoryRow {
+            let mut row = HistoryRow {
                 hist_id,
                 cmd,
                 epoch,
                 ppid,
                 pwd,
                 salt,
+                exit_code: value_to_i64(&exit_code_v),
+                duration_ms: value_to_i64(&duration_v),
+                host,
+                tty,
+                gitroot,
+                seq: value_to_i64(&seq_v),
+                env,
             };
+            let source_session = (row.salt, row.ppid);
+            // Overrides go in before hashing, so overridden rows dedup as new rows.
+            overrides.apply(&mut row);
 
-            let hash = row_hash(&row);
-
-            let exists: bool = conn.query_row(
-                "SELECT EXISTS(SELECT 1 FROM history_hash WHERE hash=?1)",
-                params![hash],
-                |r| r.get::<_, i64>(0),
-            )? == 1;
-
-            if exists {
-                continue;
+            if let Some(id) = insert_if_new(conn, &row, hash_version)? {
+                if value_to_i64(&synthetic_v).unwrap_or(0) != 0 {
+                    conn.execute(
+                        "UPDATE history SET synthetic = 1 WHERE id = ?1",
+                        params![id],
+                    )?;
+                }
+                outcome.inserted += 1;
+            } else {
+                *outcome
+                    .duplicates_by_session
+                    .entry(source_session)
+                    .or_default() += 1;
             }
-
-            conn.execute(
-                r#"
-                INSERT INTO history(hist_id, cmd, epoch, ppid, pwd, salt)
-                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
-                "#,
-                params![row.hist_id, row.cmd, row.epoch, row.ppid, row.pwd, row.salt],
-            )?;
-            let id = conn.last_insert_rowid();
-            conn.execute(
-                "INSERT OR IGNORE INTO history_hash(hash, history_id) VALUES (?1, ?2)",
-                params![hash, id],
-            )?;
-            inserted += 1;
         }
     }
 
@@ -222,7 +870,153 @@ pub fn import_from_db(conn: &mut Connection, from_path: &std::path::Path) -> Res
         );
     }
 
-    Ok((considered, inserted))
+    Ok(outcome)
+}
+
+/// Insert an imported row unless `history_hash` already has its hash. Returns
+/// the new row's id, or `None` for a duplicate.
+fn insert_if_new(
+    conn: &Connection,
+    row: &HistoryRow,
+    hash_version: HashVersion,
+) -> Result<Option<i64>> {
+    let hash = row_hash(row, hash_version);
+
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM history_hash WHERE hash=?1)",
+        params![hash],
+        |r| r.get::<_, i64>(0),
+    )? == 1;
+    if exists {
+        return Ok(None);
+    }
+
+    conn.execute(
+        r#"
+        INSERT INTO history(hist_id, cmd, epoch, ppid, pwd, salt, exit_code, duration_ms, host, tty, gitroot, seq, env, inserted_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, CAST(strftime('%s','now') AS INTEGER))
+        "#,
+        params![
+            row.hist_id,
+            row.cmd,
+            row.epoch,
+            row.ppid,
+            row.pwd,
+            row.salt,
+            row.exit_code,
+            row.duration_ms,
+            row.host,
+            row.tty,
+            row.gitroot,
+            row.seq,
+            row.env
+        ],
+    )?;
+    let id = conn.last_insert_rowid();
+    conn.execute(
+        "INSERT OR IGNORE INTO history_hash(hash, history_id) VALUES (?1, ?2)",
+        params![hash, id],
+    )?;
+    Ok(Some(id))
+}
+
+/// One line of `export` output (or a hand-written equivalent). Only `cmd`, `epoch`
+/// and `pwd` are required; `id` and `iso` are ignored, and missing or `null`
+/// session fields fall back to 0.
+#[derive(Debug, serde::Deserialize)]
+struct JsonlRecord {
+    cmd: String,
+    epoch: i64,
+    pwd: String,
+    #[serde(default)]
+    hist_id: Option<i64>,
+    #[serde(default)]
+    ppid: Option<i64>,
+    #[serde(default)]
+    salt: Option<i64>,
+    #[serde(default)]
+    exit_code: Option<i64>,
+    #[serde(default)]
+    duration_ms: Option<i64>,
+    #[serde(default)]
+    host: Option<String>,
+    #[serde(default)]
+    tty: Option<String>,
+    #[serde(default)]
+    gitroot: Option<String>,
+    #[serde(default)]
+    seq: Option<i64>,
+    #[serde(default)]
+    env: Option<std::collections::BTreeMap<String, String>>,
+}
+
+/// Merge `export` JSON Lines into the database with the same hash de-dup as
+/// `import_from_db`. Malformed lines are skipped and counted on stderr.
+pub fn import_from_jsonl(
+    conn: &mut Connection,
+    from_path: &std::path::Path,
+    hash_version: HashVersion,
+) -> Result<ImportOutcome> {
+    use std::io::BufRead;
+
+    let file = std::fs::File::open(from_path)
+        .with_context(|| format!("opening {}", from_path.display()))?;
+
+    let mut outcome = ImportOutcome::default();
+    let mut malformed: Vec<usize> = vec![];
+
+    let tx = conn.transaction()?;
+    for (n, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("reading {}", from_path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        outcome.considered += 1;
+
+        let record: JsonlRecord = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(_) => {
+                malformed.push(n + 1);
+                continue;
+            }
+        };
+        let row = HistoryRow {
+            hist_id: record.hist_id,
+            cmd: record.cmd,
+            epoch: record.epoch,
+            ppid: record.ppid.unwrap_or(0),
+            pwd: record.pwd,
+            salt: record.salt.unwrap_or(0),
+            exit_code: record.exit_code,
+            duration_ms: record.duration_ms,
+            host: record.host,
+            tty: record.tty,
+            gitroot: record.gitroot,
+            seq: record.seq,
+            env: record
+                .env
+                .map(|env| serde_json::to_string(&env))
+                .transpose()?,
+        };
+        if insert_if_new(&tx, &row, hash_version)?.is_some() {
+            outcome.inserted += 1;
+        } else {
+            *outcome
+                .duplicates_by_session
+                .entry((row.salt, row.ppid))
+                .or_default() += 1;
+        }
+    }
+    tx.commit()?;
+
+    if let Some(first) = malformed.first() {
+        eprintln!(
+            "import skipped {} malformed line(s) (first: line {first})",
+            malformed.len()
+        );
+    }
+
+    Ok(outcome)
 }
 
 fn value_to_i64(v: &Value) -> Option<i64> {
@@ -256,3 +1050,493 @@ fn value_to_i64(v: &Value) -> Option<i64> {
         Value::Blob(_) => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(hist_id: Option<i64>) -> HistoryRow {
+        HistoryRow {
+            hist_id,
+            cmd: "git status".to_string(),
+            epoch: 1700000000,
+            ppid: 123,
+            pwd: "/tmp".to_string(),
+            salt: 42,
+            exit_code: None,
+            duration_ms: None,
+            host: None,
+            tty: None,
+            gitroot: None,
+            seq: None,
+            env: None,
+        }
+    }
+
+    #[test]
+    fn tick_auto_optimize_runs_every_n_inserts() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        migrate(&conn).unwrap();
+        let has_epoch_index = |conn: &Connection| -> bool {
+            conn.query_row(
+                "SELECT count(*) FROM sqlite_master WHERE type='index' AND name='idx_history_epoch'",
+                [],
+                |r| r.get::<_, i64>(0),
+            )
+            .unwrap()
+                > 0
+        };
+
+        assert!(!tick_auto_optimize(&conn, 3).unwrap());
+        assert!(!tick_auto_optimize(&conn, 3).unwrap());
+        assert!(!has_epoch_index(&conn));
+        assert!(tick_auto_optimize(&conn, 3).unwrap());
+        assert!(has_epoch_index(&conn), "missing indexes are created");
+
+        // Counter starts over
+        assert!(!tick_auto_optimize(&conn, 3).unwrap());
+        let pending: String = conn
+            .query_row(
+                "SELECT value FROM meta WHERE key='inserts_since_optimize'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(pending, "1");
+    }
+
+    #[test]
+    fn apply_pragmas_sets_allowlisted_and_skips_others() {
+        let conn = Connection::open_in_memory().unwrap();
+        let before: i64 = conn
+            .query_row("PRAGMA synchronous", [], |r| r.get(0))
+            .unwrap();
+        apply_pragmas(
+            &conn,
+            &[
+                ("synchronous".to_string(), "OFF".to_string()),
+                ("cache_size".to_string(), "-4321".to_string()),
+            ],
+        );
+        let cache: i64 = conn
+            .query_row("PRAGMA cache_size", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(cache, -4321);
+        let after: i64 = conn
+            .query_row("PRAGMA synchronous", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(after, before);
+
+        let err = check_pragma("writable_schema", "1").unwrap_err();
+        assert!(err.to_string().contains("not allowed"));
+        let err = check_pragma("temp_store", "MEMORY; DROP TABLE x").unwrap_err();
+        assert!(err.to_string().contains("invalid value"));
+    }
+
+    #[test]
+    fn fts_matches_like_for_plain_word() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        migrate(&conn).unwrap();
+        {
+            let mut ins = conn
+                .prepare("INSERT INTO history(cmd, epoch, ppid, pwd, salt) VALUES (?1, ?2, 1, '/tmp', 1)")
+                .unwrap();
+            let verbs = [
+                "git status",
+                "cargo build",
+                "GIT log --oneline",
+                "ls -la",
+                "digit-count",
+            ];
+            for i in 0..10_000i64 {
+                let cmd = format!("{} {}", verbs[i as usize % verbs.len()], i);
+                ins.execute(params![cmd, i]).unwrap();
+            }
+        }
+
+        assert!(!has_fts(&conn).unwrap());
+        rebuild_fts(&conn).unwrap();
+        assert!(has_fts(&conn).unwrap());
+
+        // Rows written after the rebuild reach the index through the triggers
+        conn.execute(
+            "INSERT INTO history(cmd, epoch, ppid, pwd, salt) VALUES ('legit tool', 1, 1, '/tmp', 1)",
+            [],
+        )
+        .unwrap();
+        conn.execute("DELETE FROM history WHERE id = 1", [])
+            .unwrap();
+
+        let ids = |sql: &str| -> Vec<i64> {
+            let mut stmt = conn.prepare(sql).unwrap();
+            stmt.query_map([], |r| r.get(0))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap()
+        };
+        let like = ids("SELECT id FROM history WHERE cmd LIKE '%git%' ORDER BY id");
+        let fts = ids("SELECT id FROM history WHERE id IN \
+             (SELECT rowid FROM history_fts WHERE history_fts MATCH '\"git\"') ORDER BY id");
+        assert_eq!(like.len(), 6000);
+        assert_eq!(like, fts);
+
+        // Idempotent
+        rebuild_fts(&conn).unwrap();
+        assert_eq!(
+            fts,
+            ids("SELECT rowid FROM history_fts WHERE history_fts MATCH '\"git\"' ORDER BY rowid")
+        );
+    }
+
+    #[test]
+    fn redact_history_rewrites_cmd_and_hash() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        migrate(&conn).unwrap();
+        let cfg = DbConfig {
+            path: ":memory:".into(),
+            extra_paths: vec![],
+            hash_version: HashVersion::V1,
+            track_hash: true,
+            pragmas: vec![],
+        };
+        let mut leaked = row(Some(1));
+        leaked.cmd = "curl -H 'Authorization: Bearer s3cr3t' api".to_string();
+        insert_history(&mut conn, &leaked, &cfg).unwrap();
+        insert_history(&mut conn, &row(Some(2)), &cfg).unwrap();
+
+        let re = regex::Regex::new(r"Bearer \S+'").unwrap();
+        let preview = redact_history(&mut conn, &re, "Bearer ***'", HashVersion::V1, true).unwrap();
+        assert_eq!(preview.len(), 1);
+        let stored: String = conn
+            .query_row("SELECT cmd FROM history WHERE id = 1", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(stored, leaked.cmd, "dry run must roll back");
+
+        let changed =
+            redact_history(&mut conn, &re, "Bearer ***'", HashVersion::V1, false).unwrap();
+        assert_eq!(changed[0].2, "curl -H 'Authorization: Bearer ***' api");
+
+        let mut redacted = leaked.clone();
+        redacted.cmd = changed[0].2.clone();
+        let hash: String = conn
+            .query_row(
+                "SELECT hash FROM history_hash WHERE history_id = 1",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(hash, row_hash(&redacted, HashVersion::V1));
+        let hashes: i64 = conn
+            .query_row("SELECT count(*) FROM history_hash", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(hashes, 2);
+    }
+
+    #[test]
+    fn redact_history_scrubs_captured_env() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        migrate(&conn).unwrap();
+        let cfg = DbConfig {
+            path: ":memory:".into(),
+            extra_paths: vec![],
+            hash_version: HashVersion::V1,
+            track_hash: true,
+            pragmas: vec![],
+        };
+        let mut leaked = row(Some(1));
+        leaked.env = Some(r#"{"API_TOKEN":"s3cr3t","PWD":"/tmp"}"#.to_string());
+        insert_history(&mut conn, &leaked, &cfg).unwrap();
+        let hash_before: String = conn
+            .query_row("SELECT hash FROM history_hash", [], |r| r.get(0))
+            .unwrap();
+
+        let re = regex::Regex::new("s3cr3t").unwrap();
+        let changed = redact_history(&mut conn, &re, "***", HashVersion::V1, false).unwrap();
+        assert_eq!(changed.len(), 1);
+        assert!(
+            changed[0]
+                .2
+                .ends_with(r#"(env: {"API_TOKEN":"***","PWD":"/tmp"})"#)
+        );
+
+        let (cmd, env): (String, String) = conn
+            .query_row("SELECT cmd, env FROM history WHERE id = 1", [], |r| {
+                Ok((r.get(0)?, r.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(cmd, leaked.cmd);
+        assert_eq!(env, r#"{"API_TOKEN":"***","PWD":"/tmp"}"#);
+        // env is not hashed, so the dedup entry is untouched
+        let hash_after: String = conn
+            .query_row("SELECT hash FROM history_hash", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(hash_after, hash_before);
+    }
+
+    #[test]
+    fn row_hash_v1_includes_hist_id() {
+        assert_ne!(
+            row_hash(&row(Some(1)), HashVersion::V1),
+            row_hash(&row(Some(2)), HashVersion::V1)
+        );
+    }
+
+    #[test]
+    fn row_hash_separates_hosts() {
+        let mut laptop = row(Some(1));
+        laptop.host = Some("laptop".to_string());
+        let mut server = row(Some(1));
+        server.host = Some("server".to_string());
+        assert_ne!(
+            row_hash(&laptop, HashVersion::V2),
+            row_hash(&server, HashVersion::V2)
+        );
+        assert_ne!(
+            row_hash(&laptop, HashVersion::V2),
+            row_hash(&row(Some(1)), HashVersion::V2)
+        );
+
+        // tty is informational only
+        laptop.tty = Some("/dev/pts/1".to_string());
+        let mut same = laptop.clone();
+        same.tty = Some("/dev/pts/7".to_string());
+        assert_eq!(
+            row_hash(&laptop, HashVersion::V1),
+            row_hash(&same, HashVersion::V1)
+        );
+    }
+
+    #[test]
+    fn row_hash_ignores_exit_code() {
+        let mut failed = row(Some(1));
+        failed.exit_code = Some(1);
+        assert_eq!(
+            row_hash(&row(Some(1)), HashVersion::V1),
+            row_hash(&failed, HashVersion::V1)
+        );
+    }
+
+    #[test]
+    fn init_schema_adds_new_columns_idempotently() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE history (id INTEGER PRIMARY KEY AUTOINCREMENT, hist_id INTEGER, cmd TEXT, epoch INTEGER, ppid INTEGER, pwd TEXT, salt INTEGER);",
+        )
+        .unwrap();
+        init_schema(&conn).unwrap();
+        migrate(&conn).unwrap();
+        init_schema(&conn).unwrap();
+        migrate(&conn).unwrap();
+        assert!(has_column(&conn, "history", "exit_code").unwrap());
+        assert!(has_column(&conn, "history", "duration_ms").unwrap());
+        assert!(has_column(&conn, "history", "gitroot").unwrap());
+    }
+
+    #[test]
+    fn open_db_migrates_v1_database_idempotently() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("v1.sqlite");
+        {
+            // A version 1 database from an older build that already grew exit_code ad hoc
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE history (id INTEGER PRIMARY KEY AUTOINCREMENT, hist_id INTEGER, cmd TEXT, epoch INTEGER, ppid INTEGER, pwd TEXT, salt INTEGER, exit_code INTEGER);
+                 CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+                 INSERT INTO meta(key,value) VALUES('schema_version','1');
+                 INSERT INTO history(cmd, epoch, ppid, pwd, salt, exit_code) VALUES ('ls', 1, 1, '/', 1, 0);",
+            )
+            .unwrap();
+        }
+        let cfg = DbConfig {
+            path: path.clone(),
+            extra_paths: vec![],
+            hash_version: HashVersion::V1,
+            track_hash: true,
+            pragmas: vec![],
+        };
+        let version = |conn: &Connection| -> String {
+            conn.query_row(
+                "SELECT value FROM meta WHERE key='schema_version'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap()
+        };
+
+        let conn = open_db(&cfg).unwrap();
+        assert_eq!(version(&conn), SCHEMA_VERSION.to_string());
+        assert!(has_column(&conn, "history", "gitroot").unwrap());
+        drop(conn);
+
+        let conn = open_db(&cfg).unwrap();
+        assert_eq!(version(&conn), SCHEMA_VERSION.to_string());
+        let rows: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM history WHERE exit_code = 0",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(rows, 1);
+
+        // A corrupt version is an error, not an out-of-range skip
+        conn.execute("UPDATE meta SET value = '0' WHERE key='schema_version'", [])
+            .unwrap();
+        let err = open_db(&cfg).unwrap_err();
+        assert!(err.to_string().contains("schema versions start at 1"));
+
+        // A database from a newer build is refused rather than misread
+        conn.execute(
+            "UPDATE meta SET value = ?1 WHERE key='schema_version'",
+            params![(SCHEMA_VERSION + 1).to_string()],
+        )
+        .unwrap();
+        drop(conn);
+        let err = open_db(&cfg).unwrap_err();
+        assert!(err.to_string().contains("newer than this sdbh supports"));
+    }
+
+    #[test]
+    fn prune_older_than_removes_old_rows_and_hashes() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        migrate(&conn).unwrap();
+        let cfg = DbConfig {
+            path: ":memory:".into(),
+            extra_paths: vec![],
+            hash_version: HashVersion::V1,
+            track_hash: true,
+            pragmas: vec![],
+        };
+        for epoch in [100, 200, 300] {
+            let mut r = row(None);
+            r.epoch = epoch;
+            insert_history(&mut conn, &r, &cfg).unwrap();
+        }
+        let count = |conn: &Connection, table: &str| -> i64 {
+            conn.query_row(&format!("SELECT count(*) FROM {table}"), [], |r| r.get(0))
+                .unwrap()
+        };
+
+        assert_eq!(prune_older_than(&mut conn, 250, true).unwrap(), 2);
+        assert_eq!(count(&conn, "history"), 3);
+
+        assert_eq!(prune_older_than(&mut conn, 250, false).unwrap(), 2);
+        assert_eq!(count(&conn, "history"), 1);
+        assert_eq!(count(&conn, "history_hash"), 1);
+
+        assert_eq!(prune_older_than(&mut conn, 250, false).unwrap(), 0);
+    }
+
+    #[test]
+    fn dedup_history_keeps_lowest_id_and_rebuilds_tracked_hashes() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        migrate(&conn).unwrap();
+        let cfg = DbConfig {
+            path: ":memory:".into(),
+            extra_paths: vec![],
+            hash_version: HashVersion::V1,
+            track_hash: false,
+            pragmas: vec![],
+        };
+        for _ in 0..3 {
+            insert_history(&mut conn, &row(None), &cfg).unwrap();
+        }
+        let mut other = row(None);
+        other.cmd = "ls".to_string();
+        insert_history(&mut conn, &other, &cfg).unwrap();
+        let ids = |conn: &Connection, table: &str, column: &str| -> Vec<i64> {
+            conn.prepare(&format!("SELECT {column} FROM {table} ORDER BY {column}"))
+                .unwrap()
+                .query_map([], |r| r.get(0))
+                .unwrap()
+                .map(Result::unwrap)
+                .collect()
+        };
+
+        assert_eq!(dedup_history(&mut conn, &cfg, true).unwrap(), 2);
+        assert_eq!(ids(&conn, "history", "id"), vec![1, 2, 3, 4]);
+
+        // With track_hash off the hash table stays as it was (empty here)
+        assert_eq!(dedup_history(&mut conn, &cfg, false).unwrap(), 2);
+        assert_eq!(ids(&conn, "history", "id"), vec![1, 4]);
+        assert!(ids(&conn, "history_hash", "history_id").is_empty());
+
+        let tracked = DbConfig {
+            track_hash: true,
+            ..cfg
+        };
+        assert_eq!(dedup_history(&mut conn, &tracked, false).unwrap(), 0);
+        assert_eq!(ids(&conn, "history_hash", "history_id"), vec![1, 4]);
+    }
+
+    #[test]
+    fn row_hash_ignores_captured_env() {
+        let mut with_env = row(Some(1));
+        with_env.env = Some(r#"{"NODE_ENV":"production"}"#.to_string());
+        assert_eq!(
+            row_hash(&with_env, HashVersion::V1),
+            row_hash(&row(Some(1)), HashVersion::V1)
+        );
+    }
+
+    #[test]
+    fn row_hash_v2_ignores_hist_id() {
+        assert_eq!(
+            row_hash(&row(Some(1)), HashVersion::V2),
+            row_hash(&row(Some(2)), HashVersion::V2)
+        );
+        assert_eq!(
+            row_hash(&row(None), HashVersion::V2),
+            row_hash(&row(Some(7)), HashVersion::V2)
+        );
+    }
+
+    #[test]
+    fn switching_hash_version_rehashes_existing_rows() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        migrate(&conn).unwrap();
+        sync_hash_version(&conn, HashVersion::V1).unwrap();
+
+        let mut conn = conn;
+        let cfg = DbConfig {
+            path: ":memory:".into(),
+            extra_paths: vec![],
+            hash_version: HashVersion::V1,
+            track_hash: true,
+            pragmas: vec![],
+        };
+        insert_history(&mut conn, &row(Some(1)), &cfg).unwrap();
+        insert_history(&mut conn, &row(Some(2)), &cfg).unwrap();
+        let count = |conn: &Connection| -> i64 {
+            conn.query_row("SELECT count(*) FROM history_hash", [], |r| r.get(0))
+                .unwrap()
+        };
+        assert_eq!(count(&conn), 2);
+
+        // Under V2 both rows collapse onto one hash.
+        sync_hash_version(&conn, HashVersion::V2).unwrap();
+        assert_eq!(count(&conn), 1);
+        let stored: String = conn
+            .query_row("SELECT value FROM meta WHERE key='hash_version'", [], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert_eq!(stored, "2");
+        let v2 = row_hash(&row(Some(3)), HashVersion::V2);
+        let exists: i64 = conn
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM history_hash WHERE hash=?1)",
+                params![v2],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(exists, 1);
+    }
+}
@@ -9,18 +9,170 @@ pub struct HistoryRow {
     pub ppid: i64,
     pub pwd: String,
     pub salt: i64,
+    /// Exit status reported by the shell hook (not part of the dedup hash)
+    pub exit_code: Option<i64>,
+    /// Wall-clock runtime in milliseconds, when the shell integration measures it
+    pub duration_ms: Option<i64>,
+    /// Machine the command ran on (part of the dedup hash when set)
+    pub host: Option<String>,
+    /// Terminal device, e.g. `/dev/pts/3`
+    pub tty: Option<String>,
+    /// `git rev-parse --show-toplevel` of `pwd`, when `[log] track_gitroot` is on
+    pub gitroot: Option<String>,
+    /// Per-session command counter from the hook; breaks ties between equal epochs
+    pub seq: Option<i64>,
+    /// `log --capture-env` variables as a JSON object; not part of the dedup hash
+    pub env: Option<String>,
+}
+
+/// One history entry in JSON output. `list`, `search`, `last` and `export` all
+/// emit this shape, so consumers parse a single record type.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct HistoryRecord {
+    pub id: i64,
+    pub epoch: i64,
+    /// Local-time ISO-8601 rendering of `epoch`, with `--iso-dates`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iso: Option<String>,
+    pub pwd: String,
+    pub cmd: String,
+    /// Always present in `export`; elsewhere only with `--verbose-json`
+    #[serde(flatten)]
+    pub session: Option<SessionFields>,
+    /// Only in `export`, so `import-jsonl` can restore rows exactly
+    #[serde(flatten)]
+    pub details: Option<RowDetails>,
+}
+
+/// The stored columns of a history row beyond the session fields. Unknown values
+/// are `null` rather than left out.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct RowDetails {
+    pub exit_code: Option<i64>,
+    pub duration_ms: Option<i64>,
+    pub host: Option<String>,
+    pub tty: Option<String>,
+    pub gitroot: Option<String>,
+    pub seq: Option<i64>,
+    /// Variables recorded with `log --capture-env`
+    pub env: Option<std::collections::BTreeMap<String, String>>,
+}
+
+/// Every stored column of one history row, as printed by `show --format json`.
+/// Unknown values are `null` rather than left out, so the keys never vary.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct StoredRecord {
+    pub id: i64,
+    pub hist_id: Option<i64>,
+    pub epoch: i64,
+    pub ppid: i64,
+    pub pwd: String,
+    pub salt: i64,
+    pub cmd: String,
+    pub exit_code: Option<i64>,
+    pub duration_ms: Option<i64>,
+    pub host: Option<String>,
+    pub tty: Option<String>,
+    pub gitroot: Option<String>,
+    pub seq: Option<i64>,
+    /// Variables recorded with `log --capture-env`
+    pub env: Option<std::collections::BTreeMap<String, String>>,
+    /// When sdbh stored the row (unix seconds); `null` for rows from before it was tracked
+    pub inserted_at: Option<i64>,
+    /// Whether import-history made up `epoch`
+    pub synthetic: bool,
+}
+
+/// The shell-session columns of a history row.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct SessionFields {
+    pub hist_id: Option<i64>,
+    pub salt: i64,
+    pub ppid: i64,
 }
 
 #[derive(Debug, Clone)]
 pub struct DbConfig {
     pub path: PathBuf,
+    /// Additional databases from repeated `--db` (only read by aggregating stats)
+    pub extra_paths: Vec<PathBuf>,
+    /// Dedup hash scheme, from `[db] hash_version` in the config file
+    pub hash_version: HashVersion,
+    /// Whether inserts also write `history_hash` (`[db] track_hash`)
+    pub track_hash: bool,
+    /// Extra connection pragmas from `[db] pragmas`, applied by `open_db`
+    pub pragmas: Vec<(String, String)>,
+}
+
+/// Where a per-user sdbh file lives: the legacy `~/<legacy>` if it exists, else
+/// `$<xdg_var>/sdbh/<xdg_file>` when the variable holds an absolute path, else the
+/// legacy location. `None` only when there is neither a home nor an XDG directory.
+pub fn resolve_home_file(
+    home: Option<PathBuf>,
+    legacy: &str,
+    xdg_var: &str,
+    xdg_file: &str,
+) -> Option<PathBuf> {
+    let legacy_path = home.map(|h| h.join(legacy));
+    if legacy_path.as_ref().is_some_and(|p| p.exists()) {
+        return legacy_path;
+    }
+    match std::env::var_os(xdg_var).map(PathBuf::from) {
+        Some(base) if base.is_absolute() => Some(base.join("sdbh").join(xdg_file)),
+        _ => legacy_path,
+    }
+}
+
+/// Which fields feed the dedup hash stored in `history_hash`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashVersion {
+    /// epoch, ppid, salt, hist_id, pwd, cmd (original scheme)
+    #[default]
+    V1,
+    /// Same as V1 but without hist_id, which resets after `history -c`
+    V2,
+}
+
+impl HashVersion {
+    pub fn from_number(v: u32) -> Option<Self> {
+        match v {
+            1 => Some(Self::V1),
+            2 => Some(Self::V2),
+            _ => None,
+        }
+    }
+
+    pub fn number(self) -> u32 {
+        match self {
+            Self::V1 => 1,
+            Self::V2 => 2,
+        }
+    }
 }
 
 impl DbConfig {
+    /// `~/.sdbh.sqlite`, or `$XDG_DATA_HOME/sdbh/history.sqlite` when that is set
+    /// and no legacy database exists yet.
     pub fn default_path() -> PathBuf {
-        // Simple portable default (matches product decision)
         let home = std::env::var_os("HOME").unwrap_or_default();
-        PathBuf::from(home).join(".sdbh.sqlite")
+        resolve_home_file(
+            Some(PathBuf::from(home)),
+            ".sdbh.sqlite",
+            "XDG_DATA_HOME",
+            "history.sqlite",
+        )
+        .unwrap_or_else(|| PathBuf::from(".sdbh.sqlite"))
+    }
+
+    /// One single-database config per path, primary first.
+    pub fn each_db(&self) -> impl Iterator<Item = DbConfig> + '_ {
+        std::iter::once(&self.path)
+            .chain(&self.extra_paths)
+            .map(|path| DbConfig {
+                path: path.clone(),
+                extra_paths: vec![],
+                ..self.clone()
+            })
     }
 }
 
@@ -61,6 +213,21 @@ pub struct Variable {
     pub required: bool,
     /// Default value if not provided
     pub default: Option<String>,
+    /// Accepted values, e.g. `type = "int"` or `type = { choice = ["dev", "prod"] }`
+    /// (untyped variables accept any string)
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub var_type: Option<VarType>,
+}
+
+/// Value type of a template variable
+#[derive(Debug, Clone, PartialEq, Default, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VarType {
+    #[default]
+    String,
+    Int,
+    Choice(Vec<String>),
+    Path,
 }
 
 fn default_true() -> bool {
@@ -73,22 +240,73 @@ pub struct ResolvedTemplate {
     #[allow(dead_code)]
     pub template: Template,
     pub resolved_command: String,
-    #[allow(dead_code)]
     pub variables_used: HashMap<String, String>,
 }
 
 #[cfg(test)]
+#[allow(clippy::len_zero, clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     use std::env;
 
+    // One test for every env-dependent case: tests run in parallel and share the
+    // process environment.
     #[test]
     fn test_db_config_default_path() {
+        unsafe { env::remove_var("XDG_DATA_HOME") };
+
         // Test with HOME set
         unsafe { env::set_var("HOME", "/home/testuser") };
         let path = DbConfig::default_path();
         assert_eq!(path, PathBuf::from("/home/testuser/.sdbh.sqlite"));
 
+        // XDG_DATA_HOME wins when there is no legacy database
+        unsafe { env::set_var("XDG_DATA_HOME", "/home/testuser/.local/share") };
+        let path = DbConfig::default_path();
+        assert_eq!(
+            path,
+            PathBuf::from("/home/testuser/.local/share/sdbh/history.sqlite")
+        );
+
+        // ... but an existing ~/.sdbh.sqlite keeps being used
+        let home = tempfile::TempDir::new().unwrap();
+        std::fs::write(home.path().join(".sdbh.sqlite"), "").unwrap();
+        unsafe { env::set_var("HOME", home.path()) };
+        let path = DbConfig::default_path();
+        assert_eq!(path, home.path().join(".sdbh.sqlite"));
+
+        // Relative XDG paths are ignored, per the spec
+        unsafe { env::set_var("HOME", "/home/testuser") };
+        unsafe { env::set_var("XDG_DATA_HOME", "relative/share") };
+        let path = DbConfig::default_path();
+        assert_eq!(path, PathBuf::from("/home/testuser/.sdbh.sqlite"));
+        unsafe { env::remove_var("XDG_DATA_HOME") };
+
+        // Config: XDG_CONFIG_HOME, falling back to ~/.sdbh.toml
+        unsafe { env::set_var("XDG_CONFIG_HOME", "/home/testuser/.config") };
+        let config = |home: Option<&str>| {
+            resolve_home_file(
+                home.map(PathBuf::from),
+                ".sdbh.toml",
+                "XDG_CONFIG_HOME",
+                "config.toml",
+            )
+        };
+        assert_eq!(
+            config(Some("/home/testuser")),
+            Some(PathBuf::from("/home/testuser/.config/sdbh/config.toml"))
+        );
+        assert_eq!(
+            config(None),
+            Some(PathBuf::from("/home/testuser/.config/sdbh/config.toml"))
+        );
+        unsafe { env::remove_var("XDG_CONFIG_HOME") };
+        assert_eq!(
+            config(Some("/home/testuser")),
+            Some(PathBuf::from("/home/testuser/.sdbh.toml"))
+        );
+        assert_eq!(config(None), None);
+
         // Test with HOME unset (should use empty string)
         unsafe { env::remove_var("HOME") };
         let path = DbConfig::default_path();
@@ -111,6 +329,7 @@ mod tests {
                 description: Some("Message to echo".to_string()),
                 required: true,
                 default: Some("hello".to_string()),
+                var_type: None,
             }],
             defaults,
         };
@@ -137,6 +356,7 @@ mod tests {
             description: Some("A test variable".to_string()),
             required: false,
             default: Some("default_value".to_string()),
+            var_type: Some(VarType::Choice(vec!["a".to_string(), "b".to_string()])),
         };
 
         // Test TOML serialization
@@ -149,6 +369,23 @@ mod tests {
         assert_eq!(deserialized.description, variable.description);
         assert_eq!(deserialized.required, variable.required);
         assert_eq!(deserialized.default, variable.default);
+        assert_eq!(deserialized.var_type, variable.var_type);
+    }
+
+    #[test]
+    fn test_variable_type_from_toml() {
+        let int: Variable = toml::from_str("name = \"n\"\ntype = \"int\"").unwrap();
+        assert_eq!(int.var_type, Some(VarType::Int));
+
+        let choice: Variable =
+            toml::from_str("name = \"env\"\ntype = { choice = [\"dev\", \"prod\"] }").unwrap();
+        assert_eq!(
+            choice.var_type,
+            Some(VarType::Choice(vec!["dev".to_string(), "prod".to_string()]))
+        );
+
+        let untyped: Variable = toml::from_str("name = \"msg\"").unwrap();
+        assert_eq!(untyped.var_type, None);
     }
 
     #[test]
@@ -186,6 +423,13 @@ mod tests {
             ppid: 456,
             pwd: "/home/user".to_string(),
             salt: 789,
+            exit_code: Some(0),
+            duration_ms: Some(12),
+            host: Some("laptop".to_string()),
+            tty: Some("/dev/pts/0".to_string()),
+            gitroot: Some("/home/user".to_string()),
+            seq: None,
+            env: None,
         };
 
         // Test Debug formatting (implicitly tested by assert)
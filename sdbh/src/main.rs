@@ -8,5 +8,9 @@ use clap::Parser;
 
 fn main() -> Result<()> {
     let cli = cli::Cli::parse();
-    cli::run(cli)
+    match cli::run(cli) {
+        // The reader closed the pipe early; there's nobody left to report to.
+        Err(e) if cli::is_broken_pipe(&e) => Ok(()),
+        result => result,
+    }
 }
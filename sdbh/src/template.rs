@@ -1,10 +1,25 @@
-use crate::domain::{ResolvedTemplate, Template};
+use crate::domain::{ResolvedTemplate, Template, VarType, Variable};
 use anyhow::{Context, Result};
 use regex::Regex;
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+/// On-disk shape of a `template --export` bundle: one `[[template]]` entry per template.
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+struct TemplateBundle {
+    #[serde(default)]
+    template: Vec<Template>,
+}
+
+/// Outcome of [`TemplateEngine::import_bundle`]
+#[derive(Debug, Default)]
+pub struct BundleImport {
+    pub imported: Vec<String>,
+    /// Ids that already existed and were left alone (no `force`)
+    pub skipped: Vec<String>,
+}
+
 /// Template parsing and management engine
 #[derive(Debug)]
 pub struct TemplateEngine {
@@ -67,7 +82,7 @@ impl TemplateEngine {
         Ok(template)
     }
 
-    /// List all available templates
+    /// List all available templates, sorted by name
     pub fn list_templates(&self) -> Result<Vec<Template>> {
         let mut templates = Vec::new();
 
@@ -85,6 +100,9 @@ impl TemplateEngine {
             }
         }
 
+        // read_dir order is filesystem-dependent; keep listings stable
+        templates.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+
         Ok(templates)
     }
 
@@ -103,6 +121,39 @@ impl TemplateEngine {
         Ok(())
     }
 
+    /// Whether a template file exists for this id
+    pub fn template_exists(&self, template_id: &str) -> bool {
+        self.templates_dir
+            .join(format!("{}.toml", template_id))
+            .exists()
+    }
+
+    /// Move a template into `<templates_dir>/.trash` instead of unlinking it.
+    /// Returns the path of the trashed file.
+    pub fn trash_template(&self, template_id: &str) -> Result<PathBuf> {
+        let template_path = self.templates_dir.join(format!("{}.toml", template_id));
+
+        if !template_path.exists() {
+            anyhow::bail!("Template '{}' not found", template_id);
+        }
+
+        let trash_dir = self.templates_dir.join(".trash");
+        fs::create_dir_all(&trash_dir).with_context(|| {
+            format!("Failed to create trash directory: {}", trash_dir.display())
+        })?;
+
+        let trashed = trash_dir.join(format!("{}.toml", template_id));
+        fs::rename(&template_path, &trashed).with_context(|| {
+            format!(
+                "Failed to move template {} to {}",
+                template_path.display(),
+                trashed.display()
+            )
+        })?;
+
+        Ok(trashed)
+    }
+
     /// Delete a template
     pub fn delete_template(&self, template_id: &str) -> Result<()> {
         let template_path = self.templates_dir.join(format!("{}.toml", template_id));
@@ -121,6 +172,54 @@ impl TemplateEngine {
         Ok(())
     }
 
+    /// Write every template into one TOML bundle at `path`. Returns how many were written.
+    pub fn export_bundle(&self, path: &Path) -> Result<usize> {
+        let bundle = TemplateBundle {
+            template: self.list_templates()?,
+        };
+        let content =
+            toml::to_string_pretty(&bundle).context("Failed to serialize template bundle")?;
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write template bundle: {}", path.display()))?;
+        Ok(bundle.template.len())
+    }
+
+    /// Save every template from the bundle at `path`. Existing ids are skipped unless
+    /// `force` is set. All entries are validated first, so a bad entry writes nothing.
+    pub fn import_bundle(&self, path: &Path, force: bool) -> Result<BundleImport> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read template bundle: {}", path.display()))?;
+        let bundle: TemplateBundle = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse template bundle: {}", path.display()))?;
+
+        let mut seen = std::collections::HashSet::new();
+        for template in &bundle.template {
+            // The id becomes a file name; keep it inside the templates directory.
+            if template.id.starts_with('.') || template.id.contains(['/', '\\']) {
+                anyhow::bail!("Invalid template id '{}' in bundle", template.id);
+            }
+            self.validate_template(template)
+                .with_context(|| format!("Template '{}' in bundle is invalid", template.id))?;
+            if !seen.insert(template.id.as_str()) {
+                anyhow::bail!(
+                    "Template '{}' appears more than once in bundle",
+                    template.id
+                );
+            }
+        }
+
+        let mut outcome = BundleImport::default();
+        for template in &bundle.template {
+            if !force && self.template_exists(&template.id) {
+                outcome.skipped.push(template.id.clone());
+                continue;
+            }
+            self.save_template(template)?;
+            outcome.imported.push(template.id.clone());
+        }
+        Ok(outcome)
+    }
+
     /// Validate a template
     pub fn validate_template(&self, template: &Template) -> Result<()> {
         if template.id.is_empty() {
@@ -155,7 +254,7 @@ impl TemplateEngine {
             template.variables.iter().map(|v| v.name.clone()).collect();
 
         for var in command_vars {
-            if !defined_vars.contains(&var) {
+            if !defined_vars.contains(&var) && !BUILTIN_VARIABLES.contains(&var.as_str()) {
                 anyhow::bail!(
                     "Variable '{}' used in command but not defined in variables list",
                     var
@@ -189,7 +288,8 @@ impl TemplateEngine {
         for var in &template.variables {
             if var.required && !resolved_vars.contains_key(&var.name) {
                 if let Some(default) = &var.default {
-                    resolved_vars.insert(var.name.clone(), default.clone());
+                    let value = interpolate_default(default, &resolved_vars);
+                    resolved_vars.insert(var.name.clone(), value);
                 } else {
                     anyhow::bail!(
                         "Required variable '{}' not provided and no default available",
@@ -209,11 +309,63 @@ impl TemplateEngine {
         })
     }
 
-    /// Resolve a template with interactive prompting for missing variables
+    /// Where last-entered values are kept: `template_state.toml` next to the templates directory
+    fn state_path(&self) -> PathBuf {
+        self.templates_dir.with_file_name("template_state.toml")
+    }
+
+    fn load_state(&self) -> Result<HashMap<String, HashMap<String, String>>> {
+        let path = self.state_path();
+        if !path.exists() {
+            return Ok(HashMap::new());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read template state: {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse template state: {}", path.display()))
+    }
+
+    /// Values entered the last time this template was resolved
+    pub fn last_values(&self, template_id: &str) -> Result<HashMap<String, String>> {
+        Ok(self.load_state()?.remove(template_id).unwrap_or_default())
+    }
+
+    /// Store the values of the template's prompted variables (required, without a
+    /// `default`) so the next resolve can offer them
+    pub fn remember_values(
+        &self,
+        template: &Template,
+        values: &HashMap<String, String>,
+    ) -> Result<()> {
+        let remembered: HashMap<String, String> = template
+            .variables
+            .iter()
+            .filter(|v| v.required && v.default.is_none())
+            .filter_map(|v| Some((v.name.clone(), values.get(&v.name)?.clone())))
+            .collect();
+        if remembered.is_empty() {
+            return Ok(());
+        }
+
+        let mut state = self.load_state()?;
+        state.insert(template.id.clone(), remembered);
+        let path = self.state_path();
+        let content =
+            toml::to_string_pretty(&state).context("Failed to serialize template state")?;
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write template state: {}", path.display()))
+    }
+
+    /// Resolve a template with interactive prompting for missing variables.
+    ///
+    /// `remembered` values (see [`Self::last_values`]) are offered as the prompt default.
+    /// When stdin is not a terminal every variable must come from `--var` or a default:
+    /// a script should not silently run with whatever was entered last time.
     pub fn resolve_template_interactive(
         &self,
         template: &Template,
         provided_vars: &HashMap<String, String>,
+        remembered: &HashMap<String, String>,
     ) -> Result<ResolvedTemplate> {
         let mut resolved_vars = HashMap::new();
 
@@ -227,69 +379,69 @@ impl TemplateEngine {
             resolved_vars.insert(key.clone(), value.clone());
         }
 
-        // Apply defaults for variables that don't have values yet
-        for var in &template.variables {
-            if !resolved_vars.contains_key(&var.name)
-                && let Some(default) = &var.default
-            {
-                resolved_vars.insert(var.name.clone(), default.clone());
-            }
+        // Fail up front in non-interactive environments (like tests) rather than
+        // part-way through the prompts
+        let is_interactive = atty::is(atty::Stream::Stdin);
+        let missing_names: Vec<String> = template
+            .variables
+            .iter()
+            .filter(|v| v.required && v.default.is_none() && !resolved_vars.contains_key(&v.name))
+            .map(|v| v.name.clone())
+            .collect();
+        if !missing_names.is_empty() && !is_interactive {
+            let hint = if missing_names.iter().any(|n| remembered.contains_key(n)) {
+                " Remembered values are only offered at an interactive prompt."
+            } else {
+                ""
+            };
+            anyhow::bail!(
+                "Template '{}' requires variables that were not provided: {}. \
+                 Provide them using --var key=value flags.{hint}",
+                template.name,
+                missing_names.join(", ")
+            );
         }
 
-        // Collect missing required variables that need prompting
-        let mut missing_vars = Vec::new();
+        // Resolve in declaration order, so a default can refer to any earlier
+        // variable as `{name}`
+        let mut prompted = false;
         for var in &template.variables {
-            if var.required && !resolved_vars.contains_key(&var.name) {
-                missing_vars.push(var.clone());
+            if resolved_vars.contains_key(&var.name) {
+                continue;
             }
-        }
-
-        // Check if we're in an interactive environment
-        let is_interactive = atty::is(atty::Stream::Stdin);
-
-        // Prompt for missing variables interactively (only if interactive)
-        if !missing_vars.is_empty() {
-            if !is_interactive {
-                // In non-interactive environments (like tests), fail with a clear error
-                let missing_names: Vec<String> =
-                    missing_vars.iter().map(|v| v.name.clone()).collect();
-                anyhow::bail!(
-                    "Template '{}' requires variables that were not provided: {}. \
-                     Provide them using --var key=value flags.",
-                    template.name,
-                    missing_names.join(", ")
-                );
+            if let Some(default) = &var.default {
+                let value = interpolate_default(default, &resolved_vars);
+                resolved_vars.insert(var.name.clone(), value);
+            } else if var.required {
+                let last = remembered.get(&var.name);
+                if !prompted {
+                    println!(
+                        "Template '{}' requires the following variables:",
+                        template.name
+                    );
+                    println!();
+                    prompted = true;
+                }
+                let value = prompt_variable(var, last.map(String::as_str))?;
+                resolved_vars.insert(var.name.clone(), value);
             }
-
-            println!(
-                "Template '{}' requires the following variables:",
-                template.name
-            );
+        }
+        if prompted {
             println!();
+        }
 
-            for var in &missing_vars {
-                let prompt_text = if let Some(desc) = &var.description {
-                    format!("{} ({})", var.name, desc)
-                } else {
-                    var.name.clone()
-                };
-
-                let default_value = var.default.as_deref().unwrap_or("");
-
-                let value = if !default_value.is_empty() {
-                    dialoguer::Input::<String>::new()
-                        .with_prompt(&prompt_text)
-                        .default(default_value.to_string())
-                        .interact_text()?
-                } else {
-                    dialoguer::Input::<String>::new()
-                        .with_prompt(&prompt_text)
-                        .interact_text()?
-                };
-
-                resolved_vars.insert(var.name.clone(), value);
+        // Values from --var, defaults and prompts must all match the declared type
+        for var in &template.variables {
+            let Some(value) = resolved_vars.get(&var.name) else {
+                continue;
+            };
+            check_variable_value(var, value)?;
+            if var.var_type == Some(VarType::Path) && !Path::new(value).exists() {
+                eprintln!(
+                    "warning: path '{}' for variable '{}' does not exist",
+                    value, var.name
+                );
             }
-            println!();
         }
 
         // Perform variable substitution
@@ -321,6 +473,33 @@ pub fn extract_variables(command: &str) -> Result<Vec<String>> {
     Ok(variables)
 }
 
+/// Pseudo-variables filled in from context at resolve time. The `__name__` namespace is
+/// reserved for these: a template cannot define one, since variable names must start
+/// with a letter.
+pub const BUILTIN_VARIABLES: &[&str] = &["__pwd__", "__date__", "__epoch__"];
+
+/// Current value of a built-in pseudo-variable (`{__pwd__}`, `{__date__}`, `{__epoch__}`)
+fn builtin_variable(name: &str) -> Option<String> {
+    match name {
+        "__pwd__" => std::env::current_dir()
+            .ok()
+            .map(|dir| dir.to_string_lossy().into_owned()),
+        "__date__" => {
+            let now = time::OffsetDateTime::now_utc();
+            let offset = time::UtcOffset::local_offset_at(now).unwrap_or(time::UtcOffset::UTC);
+            let date = now.to_offset(offset).date();
+            Some(format!(
+                "{:04}-{:02}-{:02}",
+                date.year(),
+                u8::from(date.month()),
+                date.day()
+            ))
+        }
+        "__epoch__" => Some(time::OffsetDateTime::now_utc().unix_timestamp().to_string()),
+        _ => None,
+    }
+}
+
 /// Substitute variables in a command string
 pub fn substitute_variables(command: &str, variables: &HashMap<String, String>) -> Result<String> {
     let mut result = command.to_string();
@@ -330,6 +509,15 @@ pub fn substitute_variables(command: &str, variables: &HashMap<String, String>)
         result = result.replace(&pattern, var_value);
     }
 
+    for name in BUILTIN_VARIABLES {
+        let pattern = format!("{{{}}}", name);
+        if result.contains(&pattern)
+            && let Some(value) = builtin_variable(name)
+        {
+            result = result.replace(&pattern, &value);
+        }
+    }
+
     // Check for unsubstituted variables
     if let Some(pos) = result.find('{')
         && let Some(end_pos) = result[pos..].find('}')
@@ -341,6 +529,83 @@ pub fn substitute_variables(command: &str, variables: &HashMap<String, String>)
     Ok(result)
 }
 
+/// Fill `{name}` placeholders in a variable's default from the values resolved so far
+/// (and the built-ins). Unknown placeholders are left as they are.
+fn interpolate_default(default: &str, resolved: &HashMap<String, String>) -> String {
+    let mut value = default.to_string();
+    for (name, resolved_value) in resolved {
+        value = value.replace(&format!("{{{}}}", name), resolved_value);
+    }
+    for name in BUILTIN_VARIABLES {
+        let pattern = format!("{{{}}}", name);
+        if value.contains(&pattern)
+            && let Some(builtin) = builtin_variable(name)
+        {
+            value = value.replace(&pattern, &builtin);
+        }
+    }
+    value
+}
+
+/// Ask for a variable's value on the terminal, pre-filled with `default` when given
+fn prompt_variable(var: &Variable, default: Option<&str>) -> Result<String> {
+    let prompt_text = if let Some(desc) = &var.description {
+        format!("{} ({})", var.name, desc)
+    } else {
+        var.name.clone()
+    };
+
+    if let Some(VarType::Choice(choices)) = &var.var_type {
+        let selected = dialoguer::Select::new()
+            .with_prompt(&prompt_text)
+            .items(choices)
+            .default(
+                default
+                    .and_then(|d| choices.iter().position(|c| c == d))
+                    .unwrap_or(0),
+            )
+            .interact()?;
+        return Ok(choices[selected].clone());
+    }
+
+    let mut input = dialoguer::Input::<String>::new().with_prompt(&prompt_text);
+    if let Some(default) = default {
+        input = input.default(default.to_string());
+    }
+    if var.var_type == Some(VarType::Int) {
+        input = input
+            .validate_with(|v: &String| check_variable_value(var, v).map_err(|e| e.to_string()));
+    }
+    Ok(input.interact_text()?)
+}
+
+/// Reject a value that does not fit the variable's `type`. Paths are not checked
+/// here: a missing path only warrants a warning.
+fn check_variable_value(var: &Variable, value: &str) -> Result<()> {
+    // Leaving an optional variable blank is always allowed
+    if value.is_empty() && !var.required {
+        return Ok(());
+    }
+    match &var.var_type {
+        Some(VarType::Int) if value.trim().parse::<i64>().is_err() => {
+            anyhow::bail!(
+                "Variable '{}' must be an integer, got '{}'",
+                var.name,
+                value
+            )
+        }
+        Some(VarType::Choice(choices)) if !choices.iter().any(|c| c == value) => {
+            anyhow::bail!(
+                "Variable '{}' must be one of: {} (got '{}')",
+                var.name,
+                choices.join(", "),
+                value
+            )
+        }
+        _ => Ok(()),
+    }
+}
+
 /// Check if a variable name is valid
 fn is_valid_variable_name(name: &str) -> bool {
     !name.is_empty()
@@ -382,12 +647,14 @@ mod tests {
                     description: Some("The message to echo".to_string()),
                     required: true,
                     default: Some("hello".to_string()),
+                    var_type: None,
                 },
                 crate::domain::Variable {
                     name: "user".to_string(),
                     description: Some("The user name".to_string()),
                     required: true,
                     default: None,
+                    var_type: None,
                 },
             ],
             defaults: HashMap::new(),
@@ -639,6 +906,199 @@ mod tests {
         assert!(engine.load_template("test-template").is_err());
     }
 
+    #[test]
+    fn test_trash_template() {
+        let (engine, _temp) = create_test_engine();
+        engine.save_template(&create_sample_template()).unwrap();
+
+        let trashed = engine.trash_template("test-template").unwrap();
+
+        assert!(!engine.template_exists("test-template"));
+        assert!(trashed.ends_with(".trash/test-template.toml"));
+        assert!(trashed.exists());
+        // The trash directory is not picked up as a template
+        assert!(engine.list_templates().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_builtin_variables() {
+        let vars = HashMap::new();
+
+        let pwd = env::current_dir().unwrap();
+        assert_eq!(
+            substitute_variables("cd {__pwd__}", &vars).unwrap(),
+            format!("cd {}", pwd.display())
+        );
+
+        let date = substitute_variables("{__date__}", &vars).unwrap();
+        let parts: Vec<&str> = date.split('-').collect();
+        assert_eq!(parts.len(), 3, "{date}");
+        assert_eq!(
+            parts.iter().map(|p| p.len()).collect::<Vec<_>>(),
+            vec![4, 2, 2]
+        );
+        assert!(parts.iter().all(|p| p.chars().all(|c| c.is_ascii_digit())));
+
+        let before = time::OffsetDateTime::now_utc().unix_timestamp();
+        let epoch: i64 = substitute_variables("{__epoch__}", &vars)
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(epoch >= before && epoch <= before + 5);
+
+        // Unknown names in the reserved namespace are still errors
+        assert!(substitute_variables("{__nope__}", &vars).is_err());
+    }
+
+    #[test]
+    fn test_builtin_variables_need_no_definition() {
+        let (engine, _temp) = create_test_engine();
+        let mut template = create_sample_template();
+        template.command =
+            "echo {message} from {user} in {__pwd__} on {__date__} at {__epoch__}".to_string();
+        engine.validate_template(&template).unwrap();
+
+        template.command = "echo {__nope__}".to_string();
+        assert!(engine.validate_template(&template).is_err());
+
+        // Reserved names cannot be declared as regular variables
+        let mut declared = create_sample_template();
+        declared.variables[0].name = "__pwd__".to_string();
+        assert!(engine.validate_template(&declared).is_err());
+    }
+
+    #[test]
+    fn test_default_interpolates_earlier_variables() {
+        let (engine, _temp) = create_test_engine();
+        let var = |name: &str, default: Option<&str>| crate::domain::Variable {
+            name: name.to_string(),
+            description: None,
+            required: true,
+            default: default.map(str::to_string),
+            var_type: None,
+        };
+        let template = Template {
+            id: "deploy".to_string(),
+            name: "Deploy".to_string(),
+            description: None,
+            command: "deploy {env} {region} {tag}".to_string(),
+            category: None,
+            variables: vec![
+                var("env", None),
+                var("region", Some("eu")),
+                var("tag", Some("{env}-{region}-latest")),
+            ],
+            defaults: HashMap::new(),
+        };
+
+        let mut provided = HashMap::new();
+        provided.insert("env".to_string(), "prod".to_string());
+        let resolved = engine
+            .resolve_template_interactive(&template, &provided, &HashMap::new())
+            .unwrap();
+        assert_eq!(resolved.resolved_command, "deploy prod eu prod-eu-latest");
+
+        // An explicit value still wins over the interpolated default
+        provided.insert("tag".to_string(), "v1".to_string());
+        let resolved = engine
+            .resolve_template_interactive(&template, &provided, &HashMap::new())
+            .unwrap();
+        assert_eq!(resolved.resolved_command, "deploy prod eu v1");
+    }
+
+    #[test]
+    fn test_remembered_values_become_next_default() {
+        let (engine, temp) = create_test_engine();
+        let template = create_sample_template();
+        assert!(engine.last_values(&template.id).unwrap().is_empty());
+
+        let mut provided = HashMap::new();
+        provided.insert("user".to_string(), "alice".to_string());
+        let resolved = engine
+            .resolve_template_interactive(&template, &provided, &HashMap::new())
+            .unwrap();
+        engine
+            .remember_values(&template, &resolved.variables_used)
+            .unwrap();
+        assert!(temp.path().join("template_state.toml").exists());
+
+        // Only the prompted variable is stored, not `message`, which has a default
+        let last = engine.last_values(&template.id).unwrap();
+        assert_eq!(last.len(), 1);
+        assert_eq!(last.get("user").unwrap(), "alice");
+
+        // Without a terminal (as here) it is not used silently: --var is still required
+        let err = engine
+            .resolve_template_interactive(&template, &HashMap::new(), &last)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("not provided: user"), "{err}");
+        assert!(
+            err.contains("only offered at an interactive prompt"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_export_import_bundle_round_trip() {
+        let (engine, temp) = create_test_engine();
+        let mut template = create_sample_template();
+        template.variables[1].var_type = Some(VarType::Path);
+        template
+            .defaults
+            .insert("user".to_string(), "bob".to_string());
+        engine.save_template(&template).unwrap();
+        let mut other = create_sample_template();
+        other.id = "other".to_string();
+        other.name = "Other".to_string();
+        engine.save_template(&other).unwrap();
+
+        let bundle = temp.path().join("bundle.toml");
+        assert_eq!(engine.export_bundle(&bundle).unwrap(), 2);
+        assert!(
+            fs::read_to_string(&bundle)
+                .unwrap()
+                .contains("[[template]]")
+        );
+
+        // Existing ids are skipped unless forced
+        engine.delete_template("other").unwrap();
+        let outcome = engine.import_bundle(&bundle, false).unwrap();
+        assert_eq!(outcome.imported, vec!["other"]);
+        assert_eq!(outcome.skipped, vec!["test-template"]);
+        let outcome = engine.import_bundle(&bundle, true).unwrap();
+        assert_eq!(outcome.imported.len(), 2);
+
+        let loaded = engine.load_template("test-template").unwrap();
+        assert_eq!(loaded.variables[1].var_type, Some(VarType::Path));
+        assert_eq!(loaded.defaults.get("user").unwrap(), "bob");
+    }
+
+    #[test]
+    fn test_import_bundle_validates_before_writing() {
+        let (engine, temp) = create_test_engine();
+        let bundle = temp.path().join("bundle.toml");
+        fs::write(
+            &bundle,
+            r#"
+[[template]]
+id = "good"
+name = "Good"
+command = "echo ok"
+
+[[template]]
+id = "bad"
+name = "Bad"
+command = "echo {undefined}"
+"#,
+        )
+        .unwrap();
+
+        let err = engine.import_bundle(&bundle, false).unwrap_err();
+        assert!(format!("{err:#}").contains("Template 'bad' in bundle is invalid"));
+        assert!(!engine.template_exists("good"));
+    }
+
     #[test]
     fn test_delete_template_not_found() {
         let (engine, _temp) = create_test_engine();
@@ -683,6 +1143,80 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_resolve_template_interactive_enforces_types() {
+        let (engine, _temp) = create_test_engine();
+        let mut template = create_sample_template();
+        template.command = "scale --replicas {message} --env {user}".to_string();
+        template.variables[0].var_type = Some(VarType::Int);
+        template.variables[1].var_type =
+            Some(VarType::Choice(vec!["dev".to_string(), "prod".to_string()]));
+
+        let vars = |message: &str, user: &str| {
+            HashMap::from([
+                ("message".to_string(), message.to_string()),
+                ("user".to_string(), user.to_string()),
+            ])
+        };
+
+        let resolved = engine
+            .resolve_template_interactive(&template, &vars("3", "prod"), &HashMap::new())
+            .unwrap();
+        assert_eq!(resolved.resolved_command, "scale --replicas 3 --env prod");
+
+        let err = engine
+            .resolve_template_interactive(&template, &vars("three", "prod"), &HashMap::new())
+            .unwrap_err();
+        assert!(err.to_string().contains("must be an integer"));
+
+        let err = engine
+            .resolve_template_interactive(&template, &vars("3", "staging"), &HashMap::new())
+            .unwrap_err();
+        assert!(err.to_string().contains("must be one of: dev, prod"));
+
+        // A missing path is only a warning
+        template.variables[1].var_type = Some(VarType::Path);
+        assert!(
+            engine
+                .resolve_template_interactive(
+                    &template,
+                    &vars("3", "/no/such/dir"),
+                    &HashMap::new()
+                )
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_resolve_template_interactive_allows_blank_optional() {
+        let (engine, _temp) = create_test_engine();
+        let mut template = create_sample_template();
+        template.command = "scale --replicas {message} --env {user}".to_string();
+        template.variables[0].var_type = Some(VarType::Int);
+        template.variables[0].required = false;
+        template.variables[1].var_type =
+            Some(VarType::Choice(vec!["dev".to_string(), "prod".to_string()]));
+
+        let vars = |message: &str, user: &str| {
+            HashMap::from([
+                ("message".to_string(), message.to_string()),
+                ("user".to_string(), user.to_string()),
+            ])
+        };
+
+        // A blank optional variable skips the type check
+        let resolved = engine
+            .resolve_template_interactive(&template, &vars("", "dev"), &HashMap::new())
+            .unwrap();
+        assert_eq!(resolved.resolved_command, "scale --replicas  --env dev");
+
+        // A blank required variable is still checked
+        let err = engine
+            .resolve_template_interactive(&template, &vars("", ""), &HashMap::new())
+            .unwrap_err();
+        assert!(err.to_string().contains("must be one of: dev, prod"));
+    }
+
     #[test]
     fn test_extract_variables_complex() {
         // Test various edge cases
@@ -7,7 +7,10 @@ fn sdbh_cmd() -> Command {
     // Prefer the new macro API to avoid cargo build-dir issues.
     // (See deprecation notes in assert_cmd.)
     let exe = assert_cmd::cargo::cargo_bin!("sdbh");
-    Command::new(exe)
+    let mut cmd = Command::new(exe);
+    // Running the suite from a hooked shell must not scope commands to that session.
+    cmd.env_remove("SDBH_SALT").env_remove("SDBH_PPID");
+    cmd
 }
 
 fn conn(path: &std::path::Path) -> Connection {
@@ -762,15 +765,435 @@ fn export_with_invalid_session_env() {
         .assert()
         .success();
 
-    // Export with session filter but invalid env vars - should export all data (no filtering)
+    // Export with session filter but invalid env vars - should fail instead of exporting everything
     sdbh_cmd()
         .args(["--db", db.to_string_lossy().as_ref(), "export", "--session"])
-        .env_remove("SDBH_SALT")
+        .env("SDBH_SALT", "not-a-number")
         .env_remove("SDBH_PPID")
         .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--session requires SDBH_SALT and SDBH_PPID",
+        ))
+        .stdout(predicate::str::is_empty());
+}
+
+#[test]
+fn seq_orders_commands_that_share_an_epoch() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    // Logged out of order, e.g. by two backgrounded hook invocations racing
+    for (cmd, seq) in [("echo second", "2"), ("echo first", "1")] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                "1700000000",
+                "--ppid",
+                "1",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+                "--seq",
+                seq,
+            ])
+            .assert()
+            .success();
+    }
+
+    let run = |args: &[&str]| {
+        let out = sdbh_cmd()
+            .args(["--db", &db_arg])
+            .args(args)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let text = String::from_utf8(out).unwrap();
+        text.lines()
+            .map(|l| l.rsplit(" | ").next().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        run(&["list", "--all", "--no-headers"]),
+        ["echo first", "echo second"]
+    );
+    // search is newest first
+    assert_eq!(
+        run(&["search", "echo", "--all", "--no-headers"]),
+        ["echo second", "echo first"]
+    );
+
+    // Another session's seq counts independently and must not interleave
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "log",
+            "--cmd",
+            "echo other",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "1",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "2",
+            "--seq",
+            "1",
+        ])
+        .assert()
+        .success();
+    assert_eq!(
+        run(&["list", "--all", "--no-headers"]),
+        ["echo first", "echo second", "echo other"]
+    );
+    assert_eq!(
+        run(&["search", "echo", "--all", "--no-headers"]),
+        ["echo other", "echo second", "echo first"]
+    );
+}
+
+#[test]
+fn stats_top_trend_compares_window_halves() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let day = 86_400;
+
+    for (cmd, days_ago) in [
+        ("cargo test", 20),
+        ("cargo test", 5),
+        ("cargo test", 4),
+        ("cargo test", 3),
+        ("make", 25),
+        ("make", 24),
+        ("make", 2),
+    ] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &(now - days_ago * day).to_string(),
+                "--ppid",
+                "1",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "stats", "top", "--days", "30", "--trend"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("4 | ↑ +200%  | cargo test"))
+        .stdout(predicate::str::contains("3 | ↓ -50%   | make"));
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "stats", "top", "--days", "30"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("↑").not());
+}
+
+#[test]
+fn stats_new_lists_only_recently_first_seen_commands() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let day = 86_400;
+
+    for (cmd, epoch) in [
+        ("git status", now - 100 * day),
+        ("git status", now - day),
+        ("terraform plan", now - 3 * day),
+        ("terraform plan", now - 2 * day),
+        ("just test", now - day),
+    ] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &epoch.to_string(),
+                "--ppid",
+                "1",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+            ])
+            .assert()
+            .success();
+    }
+
+    let out = sdbh_cmd()
+        .args(["--db", &db_arg, "stats", "new", "--days", "7"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let out = String::from_utf8(out).unwrap();
+    let rows: Vec<Vec<&str>> = out
+        .lines()
+        .map(|l| l.split(" | ").map(str::trim).collect())
+        .collect();
+    assert_eq!(rows.len(), 2, "{out}");
+    assert_eq!(rows[0][1..], ["2", "terraform plan"]);
+    assert_eq!(rows[1][1..], ["1", "just test"]);
+}
+
+#[test]
+fn pwd_contains_matches_a_substring_anywhere_in_pwd() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+
+    for (cmd, pwd) in [
+        ("npm run build", "/a/node_modules/x"),
+        ("cargo build", "/b/src"),
+    ] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                "1700000000",
+                "--ppid",
+                "1",
+                "--pwd",
+                pwd,
+                "--salt",
+                "1",
+            ])
+            .assert()
+            .success();
+    }
+
+    for args in [
+        &["list", "--all"][..],
+        &["search", "build", "--all"],
+        &["summary", "--all"],
+    ] {
+        sdbh_cmd()
+            .args(["--db", &db_arg])
+            .args(args)
+            .args(["--pwd-contains", "node_modules"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("npm run build"))
+            .stdout(predicate::str::contains("cargo build").not());
+    }
+
+    // Composes with --under instead of conflicting
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "list",
+            "--all",
+            "--under",
+            "--pwd-override",
+            "/b",
+            "--pwd-contains",
+            "node_modules",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("build").not());
+}
+
+#[test]
+fn list_group_session_prints_a_header_per_session() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+
+    // Interleaved in time across two sessions
+    for (cmd, epoch, salt) in [
+        ("echo a1", "1700000000", "1"),
+        ("echo b1", "1700000010", "2"),
+        ("echo a2", "1700000020", "1"),
+        ("echo b2", "1700000030", "2"),
+    ] {
+        sdbh_cmd()
+            .args([
+                "--db", &db_arg, "log", "--cmd", cmd, "--epoch", epoch, "--ppid", "100", "--pwd",
+                "/tmp", "--salt", salt,
+            ])
+            .assert()
+            .success();
+    }
+
+    let out = sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "list",
+            "--all",
+            "--group-session",
+            "--no-headers",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 7, "{out}");
+    assert!(lines[0].starts_with("── session salt=1 ppid=100 · "));
+    assert!(lines[0].ends_with("2 command(s)"));
+    assert!(lines[1].ends_with("| echo a1"));
+    assert!(lines[2].ends_with("| echo a2"));
+    assert_eq!(lines[3], "");
+    assert!(lines[4].starts_with("── session salt=2 ppid=100 · "));
+    assert!(lines[5].ends_with("| echo b1"));
+    assert!(lines[6].ends_with("| echo b2"));
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "list",
+            "--group-session",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("only supports table output"));
+}
+
+#[test]
+fn list_group_session_orders_by_first_command_and_counts_shown_rows() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+
+    // Session salt=9 starts first even though its salt sorts last
+    for (cmd, epoch, salt) in [
+        ("echo z1", "1700000000", "9"),
+        ("echo a1", "1700000010", "1"),
+        ("echo a1", "1700000020", "1"),
+        ("echo a2", "1700000030", "1"),
+        ("echo z2", "1700000040", "9"),
+    ] {
+        sdbh_cmd()
+            .args([
+                "--db", &db_arg, "log", "--cmd", cmd, "--epoch", epoch, "--ppid", "100", "--pwd",
+                "/tmp", "--salt", salt,
+            ])
+            .assert()
+            .success();
+    }
+
+    let out = sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "list",
+            "--all",
+            "--group-session",
+            "--dedup-adjacent",
+            "--no-headers",
+        ])
+        .assert()
         .success()
-        .stdout(predicate::str::contains("echo test1"))
-        .stdout(predicate::str::contains("echo test2")); // Should export all data when env vars are missing
+        .get_output()
+        .stdout
+        .clone();
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 7, "{out}");
+    assert!(lines[0].starts_with("── session salt=9 ppid=100 · "));
+    assert!(lines[0].ends_with("2 command(s)"));
+    assert!(lines[4].starts_with("── session salt=1 ppid=100 · "));
+    assert!(lines[4].ends_with("2 command(s)"), "{out}");
+    assert!(lines[5].ends_with("| echo a1"));
+    assert!(lines[6].ends_with("| echo a2"));
+
+    // --limit keeps the session that started first
+    let out = sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "list",
+            "--limit",
+            "2",
+            "--group-session",
+            "--no-headers",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("echo z1") && out.contains("echo z2"), "{out}");
+    assert!(!out.contains("echo a"), "{out}");
+}
+
+#[test]
+fn list_session_without_hook_env_fails() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "log",
+            "--cmd",
+            "echo test1",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "100",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "1",
+        ])
+        .assert()
+        .success();
+
+    sdbh_cmd()
+        .args(["--db", db.to_string_lossy().as_ref(), "list", "--session"])
+        .env_remove("SDBH_SALT")
+        .env_remove("SDBH_PPID")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is the shell hook installed?"))
+        .stdout(predicate::str::contains("echo test1").not());
 }
 
 #[test]
@@ -810,7 +1233,7 @@ fn doctor_command_json_output() {
         ])
         .assert()
         .success()
-        .stdout(predicate::str::starts_with("["))
+        .stdout(predicate::str::starts_with("{\"checks\":["))
         .stdout(predicate::str::contains("\"check\""))
         .stdout(predicate::str::contains("\"status\""))
         .stdout(predicate::str::contains("\"detail\""));
@@ -912,6 +1335,7 @@ fn stats_top_with_limit_and_all_flags() {
 }
 
 #[test]
+#[allow(clippy::assertions_on_constants)]
 fn memory_bank_update() {
     // Update memory bank with current test coverage status
     // This is more of a documentation test, but ensures we track coverage improvements
@@ -1096,6 +1520,111 @@ fn fzf_multi_select_flag_parsing() {
 }
 
 #[test]
+fn fzf_timeout_parses_and_requires_fzf() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    for cmd in ["list", "search", "summary"] {
+        let mut args = vec!["--db", &db_arg, cmd];
+        if cmd == "search" {
+            args.push("git");
+        }
+        sdbh_cmd()
+            .env("HOME", tmp.path())
+            .env("PATH", "")
+            .args(&args)
+            .args(["--fzf", "--fzf-timeout", "5"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("fzf is not installed"));
+    }
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "list", "--fzf-timeout", "5"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--fzf"));
+
+    // --multi-select implies --fzf for templates, so it satisfies the requirement too
+    sdbh_cmd()
+        .env("HOME", tmp.path())
+        .env("PATH", "")
+        .args([
+            "--db",
+            &db_arg,
+            "template",
+            "--multi-select",
+            "--fzf-timeout",
+            "5",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("fzf is not installed"));
+}
+
+#[test]
+fn with_meta_accepts_multi_select_in_place_of_fzf() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+
+    // list/search go straight to fzf
+    for args in [
+        &["list", "--multi-select", "--with-meta"][..],
+        &["search", "git", "--multi-select", "--with-meta"],
+    ] {
+        sdbh_cmd()
+            .env("HOME", tmp.path())
+            .env("PATH", "")
+            .args(["--db", &db_arg])
+            .args(args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("fzf is not installed"));
+    }
+
+    // summary parses it but still wants an explicit --fzf
+    sdbh_cmd()
+        .args(["--db", &db_arg, "summary", "--multi-select", "--with-meta"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--multi-select requires --fzf flag",
+        ));
+}
+
+#[test]
+fn log_selection_accepts_multi_select_in_place_of_fzf() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+
+    sdbh_cmd()
+        .env("HOME", tmp.path())
+        .env("PATH", "")
+        .args(["--db", &db_arg, "list", "--multi-select", "--log-selection"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("fzf is not installed"));
+
+    // Accepted by the parser everywhere; summary and stats still want an explicit --fzf
+    for args in [
+        &["summary", "--multi-select", "--log-selection"][..],
+        &["stats", "by-pwd", "--multi-select", "--log-selection"],
+        &["stats", "top", "--multi-select", "--log-selection"],
+    ] {
+        sdbh_cmd()
+            .args(["--db", &db_arg])
+            .args(args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "--multi-select requires --fzf flag",
+            ));
+    }
+}
+
+#[test]
+#[allow(unused_imports)]
 fn fzf_multi_select_configuration() {
     // Test that multi-select flag can be parsed
     // This is a compile-time test to ensure the flag exists
@@ -1223,6 +1752,86 @@ fn search_supports_since_epoch_filter() {
     assert!(!stdout.contains("foo old"));
 }
 
+#[test]
+fn search_verbose_json_adds_session_fields() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "log",
+            "--cmd",
+            "kubectl get pods",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "123",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "42",
+            "--hist-id",
+            "7",
+        ])
+        .assert()
+        .success();
+
+    let json = |extra: &[&str]| -> serde_json::Value {
+        let mut args = vec![
+            "--db",
+            db.to_str().unwrap(),
+            "search",
+            "kubectl",
+            "--format",
+            "json",
+        ];
+        args.extend_from_slice(extra);
+        let out = sdbh_cmd()
+            .args(&args)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        serde_json::from_slice(&out).unwrap()
+    };
+
+    let lean = json(&[]);
+    let row = lean[0].as_object().unwrap();
+    for key in ["hist_id", "salt", "ppid"] {
+        assert!(
+            !row.contains_key(key),
+            "{key} present without --verbose-json"
+        );
+    }
+
+    let verbose = json(&["--verbose-json"]);
+    assert_eq!(verbose[0]["hist_id"], 7);
+    assert_eq!(verbose[0]["salt"], 42);
+    assert_eq!(verbose[0]["ppid"], 123);
+    assert_eq!(verbose[0]["cmd"], "kubectl get pods");
+
+    // Also on list, and the same fields export writes
+    let out = sdbh_cmd()
+        .args([
+            "--db",
+            db.to_str().unwrap(),
+            "list",
+            "--format",
+            "json",
+            "--verbose-json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let listed: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(listed[0]["salt"], 42);
+}
+
 #[test]
 fn search_json_output_is_valid_shape() {
     let tmp = TempDir::new().unwrap();
@@ -1576,6 +2185,99 @@ fn log_no_filter_allows_logging_noisy_commands() {
         .stdout(predicate::str::contains("| ls"));
 }
 
+#[test]
+fn log_runs_auto_optimize_every_n_inserts() {
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path();
+    let db = home.join("test.sqlite");
+    std::fs::write(home.join(".sdbh.toml"), "[db]\nauto_optimize_every = 3\n").unwrap();
+
+    let log = |i: i64| {
+        sdbh_cmd()
+            .env("HOME", home)
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--cmd",
+                &format!("echo {i}"),
+                "--epoch",
+                &(1700000000 + i).to_string(),
+                "--ppid",
+                "123",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "42",
+            ])
+            .assert()
+            .success();
+    };
+    let meta = |key: &str| -> Option<String> {
+        let conn = Connection::open(&db).unwrap();
+        conn.query_row("SELECT value FROM meta WHERE key = ?1", [key], |r| r.get(0))
+            .ok()
+    };
+
+    log(1);
+    log(2);
+    assert_eq!(meta("inserts_since_optimize").as_deref(), Some("2"));
+    assert_eq!(meta("last_auto_optimize"), None);
+
+    // Drop an index so the optimize pass has something to repair
+    Connection::open(&db)
+        .unwrap()
+        .execute_batch("DROP INDEX idx_history_pwd")
+        .unwrap();
+    log(3);
+    assert_eq!(meta("inserts_since_optimize").as_deref(), Some("0"));
+    assert!(meta("last_auto_optimize").is_some());
+    let conn = Connection::open(&db).unwrap();
+    let indexed: i64 = conn
+        .query_row(
+            "SELECT count(*) FROM sqlite_master WHERE type='index' AND name='idx_history_pwd'",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(indexed, 1);
+}
+
+#[test]
+fn db_pragmas_from_config_skip_bad_entries() {
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path();
+    let db = home.join("test.sqlite");
+
+    std::fs::write(
+        home.join(".sdbh.toml"),
+        "[db.pragmas]\ncache_size = -20000\ntemp_store = \"MEMORY\"\n",
+    )
+    .unwrap();
+    sdbh_cmd()
+        .env("HOME", home)
+        .args(["--db", db.to_string_lossy().as_ref(), "list", "--all"])
+        .assert()
+        .success();
+
+    std::fs::write(
+        home.join(".sdbh.toml"),
+        "[db.pragmas]\nwritable_schema = 1\nsynchronous = \"OFF\"\n",
+    )
+    .unwrap();
+    sdbh_cmd()
+        .env("HOME", home)
+        .args(["--db", db.to_string_lossy().as_ref(), "list", "--all"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "skipping writable_schema: not allowed",
+        ))
+        .stderr(predicate::str::contains(
+            "skipping synchronous: not allowed",
+        ));
+}
+
 #[test]
 fn log_respects_config_ignore_exact_in_home_sdbh_toml() {
     let tmp = TempDir::new().unwrap();
@@ -1730,6 +2432,54 @@ ignore_exact = ["ls"]
         .stdout(predicate::str::contains("| ls"));
 }
 
+#[test]
+fn default_db_follows_xdg_data_home_unless_legacy_db_exists() {
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path().join("home");
+    let data = tmp.path().join("data");
+    std::fs::create_dir(&home).unwrap();
+
+    let log = |cmd: &str| {
+        sdbh_cmd()
+            .env("HOME", &home)
+            .env("XDG_DATA_HOME", &data)
+            .env_remove("XDG_CONFIG_HOME")
+            .args([
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                "1700000000",
+                "--ppid",
+                "1",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+            ])
+            .assert()
+            .success();
+    };
+
+    log("echo xdg");
+    assert!(data.join("sdbh/history.sqlite").exists());
+    assert!(!home.join(".sdbh.sqlite").exists());
+
+    // An existing legacy database is kept in use
+    std::fs::remove_file(data.join("sdbh/history.sqlite")).unwrap();
+    sdbh_cmd()
+        .args([
+            "--db",
+            home.join(".sdbh.sqlite").to_string_lossy().as_ref(),
+            "db",
+            "health",
+        ])
+        .assert()
+        .success();
+    log("echo legacy");
+    assert!(!data.join("sdbh/history.sqlite").exists());
+}
+
 #[test]
 fn import_history_bash_assigns_synthetic_timestamps_and_dedups() {
     let tmp = TempDir::new().unwrap();
@@ -2145,14 +2895,73 @@ fn preview_shows_command_statistics() {
         ])
         .assert()
         .success()
-        .stdout(predicate::str::contains("🔍 Command Analysis: git status"))
+        .stdout(predicate::str::contains("Command Analysis: git status"))
+        .stdout(predicate::str::contains("\nCommand: git status\n"))
         .stdout(predicate::str::contains("Total uses: 3"))
         .stdout(predicate::str::contains("Directories: 3"))
         .stdout(predicate::str::contains(
-            "🕒 Recent Activity (Last 5 executions):",
+            "Recent Activity (Last 5 executions):",
         ));
 }
 
+#[test]
+fn preview_drops_decorations_unless_writing_to_a_preview_pane() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "log",
+            "--cmd",
+            "git status",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "1",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "1",
+        ])
+        .assert()
+        .success();
+
+    let preview = |extra: &[&str], pane: bool| {
+        let mut cmd = sdbh_cmd();
+        cmd.env_remove("NO_COLOR").env_remove("FZF_PREVIEW_COLUMNS");
+        if pane {
+            cmd.env("FZF_PREVIEW_COLUMNS", "80");
+        }
+        let out = cmd
+            .args(["--db", &db_arg, "preview", "git status"])
+            .args(extra)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        String::from_utf8(out).unwrap()
+    };
+
+    // Captured stdout is not a terminal
+    let plain = preview(&[], false);
+    assert!(plain.starts_with("Command Analysis: git status\n"));
+    assert!(plain.contains("Usage Statistics"));
+    for decoration in ["🔍", "📊", "🕒", "━"] {
+        assert!(!plain.contains(decoration), "unexpected {decoration}");
+    }
+
+    // fzf preview panes are pipes too, but keep the decorations
+    let pane = preview(&[], true);
+    assert!(pane.contains("🔍 Command Analysis: git status"));
+    assert!(pane.contains("━"));
+
+    assert!(!preview(&["--no-color"], true).contains("🔍"));
+}
+
 #[test]
 fn preview_command_not_found() {
     let tmp = TempDir::new().unwrap();
@@ -2340,30 +3149,135 @@ fn export_with_session_filter() {
 }
 
 #[test]
-fn doctor_detects_database_corruption() {
+fn export_defaults_to_current_session_unless_all() {
     let tmp = TempDir::new().unwrap();
-    let corrupted_db = tmp.path().join("corrupted.sqlite");
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
 
-    // Create a corrupted database file by writing invalid data
-    std::fs::write(&corrupted_db, b"not a valid sqlite database").unwrap();
+    for (cmd, salt, ppid) in [("echo mine", "1", "100"), ("echo theirs", "2", "200")] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                "1700000000",
+                "--ppid",
+                ppid,
+                "--pwd",
+                "/tmp",
+                "--salt",
+                salt,
+            ])
+            .assert()
+            .success();
+    }
 
     sdbh_cmd()
-        .args([
-            "--db",
-            corrupted_db.to_string_lossy().as_ref(),
-            "doctor",
-            "--no-spawn",
-        ])
+        .args(["--db", &db_arg, "export"])
+        .env("SDBH_SALT", "1")
+        .env("SDBH_PPID", "100")
         .assert()
         .success()
-        .stdout(predicate::str::contains("db.open"))
-        .stdout(predicate::str::contains("failed to open"));
-}
+        .stdout(predicate::str::contains("echo mine"))
+        .stdout(predicate::str::contains("echo theirs").not())
+        .stderr(predicate::str::contains(
+            "exporting only the current session (salt 1, ppid 100)",
+        ));
 
-#[test]
-fn config_file_parsing_errors() {
-    let tmp = TempDir::new().unwrap();
-    let db = tmp.path().join("test.sqlite");
+    sdbh_cmd()
+        .args(["--db", &db_arg, "export", "--all"])
+        .env("SDBH_SALT", "1")
+        .env("SDBH_PPID", "100")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("echo mine"))
+        .stdout(predicate::str::contains("echo theirs"))
+        .stderr(predicate::str::is_empty());
+
+    // Outside a hooked shell there is no session to default to
+    sdbh_cmd()
+        .args(["--db", &db_arg, "export"])
+        .env_remove("SDBH_SALT")
+        .env_remove("SDBH_PPID")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("echo theirs"));
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "export", "--all", "--session"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn export_no_synthetic_skips_imported_rows_without_timestamps() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+    let hist = tmp.path().join("zsh_history");
+
+    std::fs::write(&hist, ": 1700000000:0;echo stamped\n").unwrap();
+    let bash = tmp.path().join("bash_history");
+    std::fs::write(&bash, "echo unstamped\n").unwrap();
+
+    for (flag, path) in [("--zsh", &hist), ("--bash", &bash)] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "import-history",
+                "--pwd",
+                "/tmp",
+                flag,
+                path.to_string_lossy().as_ref(),
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "export", "--all"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("echo stamped"))
+        .stdout(predicate::str::contains("echo unstamped"));
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "export", "--all", "--no-synthetic"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("echo stamped"))
+        .stdout(predicate::str::contains("echo unstamped").not());
+}
+
+#[test]
+fn doctor_detects_database_corruption() {
+    let tmp = TempDir::new().unwrap();
+    let corrupted_db = tmp.path().join("corrupted.sqlite");
+
+    // Create a corrupted database file by writing invalid data
+    std::fs::write(&corrupted_db, b"not a valid sqlite database").unwrap();
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            corrupted_db.to_string_lossy().as_ref(),
+            "doctor",
+            "--no-spawn",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("db.open"))
+        .stdout(predicate::str::contains("failed to open"));
+}
+
+#[test]
+fn config_file_parsing_errors() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
 
     // Create database first
     sdbh_cmd()
@@ -2604,6 +3518,7 @@ fn very_long_command_handling() {
 }
 
 #[test]
+#[allow(clippy::needless_borrow)]
 fn preview_with_very_long_command() {
     let tmp = TempDir::new().unwrap();
     let db = tmp.path().join("test.sqlite");
@@ -2637,8 +3552,9 @@ fn preview_with_very_long_command() {
         .assert()
         .success()
         .stdout(predicate::str::contains(
-            "🔍 Command Analysis: very_long_command_name",
-        ));
+            "Command Analysis: very_long_command_name",
+        ))
+        .stdout(predicate::str::contains(format!("\nCommand: {long_cmd}\n")));
 }
 
 #[test]
@@ -2810,6 +3726,7 @@ fn database_file_permissions() {
 }
 
 #[test]
+#[allow(clippy::useless_vec)]
 fn extreme_timestamp_values() {
     let tmp = TempDir::new().unwrap();
     let db = tmp.path().join("test.sqlite");
@@ -3159,8 +4076,8 @@ fn preview_enhanced_context_aware_git() {
         .unwrap();
 
     let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(stdout.contains("🔍 Command Analysis: git status"));
-    assert!(stdout.contains("ℹ️  Context: Shows working directory status"));
+    assert!(stdout.contains("Command Analysis: git status"));
+    assert!(stdout.contains("Context: Shows working directory status"));
 }
 
 #[test]
@@ -3219,8 +4136,8 @@ fn preview_enhanced_context_aware_docker() {
         .unwrap();
 
     let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(stdout.contains("ℹ️  Context: Lists running containers"));
-    assert!(stdout.contains("🔗 Related Commands"));
+    assert!(stdout.contains("Context: Lists running containers"));
+    assert!(stdout.contains("Related Commands"));
     assert!(stdout.contains("docker build ."));
 }
 
@@ -3272,7 +4189,7 @@ fn preview_enhanced_recent_executions() {
         .unwrap();
 
     let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(stdout.contains("🕒 Recent Activity (Last 5 executions):"));
+    assert!(stdout.contains("Recent Activity (Last 5 executions):"));
     // Should show up to 5 recent executions
     assert!(stdout.contains("/tmp/project6"));
     assert!(stdout.contains("/tmp/project5"));
@@ -3318,13 +4235,14 @@ fn preview_enhanced_directory_usage() {
         .unwrap();
 
     let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(stdout.contains("📁 Directory Usage"));
+    assert!(stdout.contains("Directory Usage"));
     assert!(stdout.contains("/home/user/project"));
     assert!(stdout.contains("/tmp/build"));
     assert!(stdout.contains("/var/www"));
 }
 
 #[test]
+#[allow(unused_variables)]
 fn preview_enhanced_command_type_detection() {
     let tmp = TempDir::new().unwrap();
     let db = tmp.path().join("test.sqlite");
@@ -3373,7 +4291,7 @@ fn preview_enhanced_command_type_detection() {
         // The type is no longer explicitly shown in the preview output
         // We just verify the command is found and the preview works
         assert!(
-            stdout.contains("🔍 Command Analysis"),
+            stdout.contains("Command Analysis"),
             "Failed for command: {}",
             cmd
         );
@@ -3381,6 +4299,7 @@ fn preview_enhanced_command_type_detection() {
 }
 
 #[test]
+#[allow(clippy::useless_vec)]
 fn preview_enhanced_related_commands_by_directory() {
     let tmp = TempDir::new().unwrap();
     let db = tmp.path().join("test.sqlite");
@@ -3428,7 +4347,38 @@ fn preview_enhanced_related_commands_by_directory() {
 
     let stdout = String::from_utf8_lossy(&output.stdout);
     // Should not find related commands since echo hello was used in a different directory
-    assert!(!stdout.contains("🔗 Related Commands"));
+    assert!(!stdout.contains("Related Commands"));
+}
+
+#[test]
+fn preview_truncates_multibyte_related_commands() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+
+    // 'é' straddles byte 57, where a byte slice would panic
+    let long_cmd = format!("git commit -m {}é{}", "a".repeat(42), "b".repeat(10));
+    for (cmd, epoch) in [
+        ("git status", "1700000000"),
+        (long_cmd.as_str(), "1700000001"),
+    ] {
+        sdbh_cmd()
+            .args([
+                "--db", &db_arg, "log", "--cmd", cmd, "--epoch", epoch, "--ppid", "1", "--pwd",
+                "/proj", "--salt", "1",
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "preview", "git status"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Related Commands"))
+        .stdout(predicate::str::contains(format!(
+            "git commit -m {}é...",
+            "a".repeat(42)
+        )));
 }
 
 #[test]
@@ -3883,6 +4833,59 @@ fn preview_command_edge_cases() {
         .stdout(predicate::str::contains("not found in history"));
 }
 
+#[test]
+fn log_ignore_regex_filters_matching_commands() {
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path();
+    let db_arg = home.join("test.sqlite").to_string_lossy().to_string();
+
+    std::fs::write(
+        home.join(".sdbh.toml"),
+        r#"
+[log]
+ignore_regex = ["^kubectl .*--dry-run", "(unclosed"]
+"#,
+    )
+    .unwrap();
+
+    for cmd in [
+        "kubectl apply -f x.yaml --dry-run=client",
+        "kubectl apply -f x.yaml",
+    ] {
+        sdbh_cmd()
+            .env("HOME", home)
+            .env_remove("XDG_CONFIG_HOME")
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                "1700000000",
+                "--ppid",
+                "1",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+            ])
+            .assert()
+            .success()
+            .stderr(predicate::str::contains(
+                "invalid [log] ignore_regex \"(unclosed\"",
+            ));
+    }
+
+    sdbh_cmd()
+        .env("HOME", home)
+        .args(["--db", &db_arg, "list", "--all"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("kubectl apply -f x.yaml"))
+        .stdout(predicate::str::contains("--dry-run").not());
+}
+
 #[test]
 fn log_filter_config_edge_cases() {
     let tmp = TempDir::new().unwrap();
@@ -4098,6 +5101,49 @@ fn template_cli_delete_nonexistent() {
         .stderr(predicate::str::contains("Template 'nonexistent' not found"));
 }
 
+#[test]
+fn template_cli_delete_requires_confirmation() {
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path();
+    let templates_dir = home.join(".sdbh").join("templates");
+    std::fs::create_dir_all(&templates_dir).unwrap();
+    let content = "id = \"doomed\"\nname = \"Doomed\"\ncommand = \"echo hi\"\n";
+    std::fs::write(templates_dir.join("doomed.toml"), content).unwrap();
+    std::fs::write(
+        templates_dir.join("kept.toml"),
+        content.replace("doomed", "kept"),
+    )
+    .unwrap();
+
+    // Without a terminal and without --yes, nothing is removed
+    sdbh_cmd()
+        .env("HOME", home)
+        .args(["template", "--delete", "doomed"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("pass --yes"));
+    assert!(templates_dir.join("doomed.toml").exists());
+
+    sdbh_cmd()
+        .env("HOME", home)
+        .args(["template", "--delete", "doomed", "--yes"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Deleted template: doomed"));
+    assert!(!templates_dir.join("doomed.toml").exists());
+    assert!(!templates_dir.join(".trash").join("doomed.toml").exists());
+
+    sdbh_cmd()
+        .env("HOME", home)
+        .args(["template", "--delete", "kept", "--yes", "--backup"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Moved template kept"));
+    assert!(!templates_dir.join("kept.toml").exists());
+    let trashed = std::fs::read_to_string(templates_dir.join(".trash").join("kept.toml")).unwrap();
+    assert!(trashed.contains("id = \"kept\""));
+}
+
 #[test]
 fn template_cli_help() {
     // Test template command help
@@ -4419,6 +5465,7 @@ required = true
 }
 
 #[test]
+#[allow(clippy::useless_vec, unused_variables)]
 fn template_category_filtering() {
     let tmp = TempDir::new().unwrap();
     let home = tmp.path();
@@ -4616,6 +5663,7 @@ required = true
 }
 
 #[test]
+#[allow(clippy::useless_vec)]
 fn template_file_operations_error_handling() {
     let tmp = TempDir::new().unwrap();
     let home = tmp.path();
@@ -4812,3 +5860,4887 @@ required = true
         assert!(result.status.success());
     }
 }
+
+#[test]
+fn stats_top_canonical_by_program_merges_aliases() {
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path();
+    let db = home.join("test.sqlite");
+
+    std::fs::write(
+        home.join(".sdbh.toml"),
+        r#"
+[alias]
+g = "git"
+"#,
+    )
+    .unwrap();
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    for (i, cmd) in ["g status", "git status", "git push", "cargo build"]
+        .iter()
+        .enumerate()
+    {
+        sdbh_cmd()
+            .env("HOME", home)
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &(now - i as i64).to_string(),
+                "--ppid",
+                "123",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "42",
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .env("HOME", home)
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "stats",
+            "top",
+            "--canonical",
+            "--by-program",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("     3 | git\n"))
+        .stdout(predicate::str::contains("     1 | cargo\n"))
+        .stdout(predicate::str::contains("| g\n").not());
+
+    // Without --canonical the alias stays a separate program
+    sdbh_cmd()
+        .env("HOME", home)
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "stats",
+            "top",
+            "--by-program",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("     2 | git\n"))
+        .stdout(predicate::str::contains("     1 | g\n"));
+
+    // Full-command grouping merges `g status` into `git status`
+    sdbh_cmd()
+        .env("HOME", home)
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "summary",
+            "--canonical",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("     2 | git status"));
+}
+
+#[test]
+fn list_and_search_dedup_adjacent_collapses_repeats() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    // make test; make test; git status; make test
+    for (i, cmd) in ["make test", "make test", "git status", "make test"]
+        .iter()
+        .enumerate()
+    {
+        sdbh_cmd()
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &(1700000000 + i as i64).to_string(),
+                "--ppid",
+                "123",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "42",
+            ])
+            .assert()
+            .success();
+    }
+
+    let out = sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "list",
+            "--all",
+            "--dedup-adjacent",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let out = String::from_utf8(out).unwrap();
+    let cmds: Vec<&str> = out
+        .lines()
+        .map(|l| l.rsplit(" | ").next().unwrap())
+        .collect();
+    assert_eq!(cmds, ["make test", "git status", "make test"]);
+
+    let out = sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "search",
+            "make",
+            "--all",
+            "--dedup-adjacent",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.lines().count(), 1);
+
+    // Without the flag every run is shown
+    sdbh_cmd()
+        .args(["--db", db.to_string_lossy().as_ref(), "list", "--all"])
+        .assert()
+        .success()
+        .stdout(predicate::function(|s: &str| s.lines().count() == 4));
+}
+
+#[test]
+fn doctor_warns_when_prompt_hook_is_duplicated() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    sdbh_cmd()
+        .env("PROMPT_COMMAND", "__sdbh_prompt; __sdbh_prompt")
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "doctor",
+            "--no-spawn",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "{\"check\":\"bash.hook.env\",\"status\":\"warn\"",
+        ))
+        .stdout(predicate::str::contains("__sdbh_prompt 2 times"));
+
+    sdbh_cmd()
+        .env("PROMPT_COMMAND", "__sdbh_prompt")
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "doctor",
+            "--no-spawn",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(1 occurrence)"))
+        .stdout(predicate::str::contains("duplicate hook").not());
+}
+
+#[test]
+fn search_count_prints_number_of_matches() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    for (i, cmd) in ["git status", "make build", "git push"].iter().enumerate() {
+        sdbh_cmd()
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &(1700000000 + i as i64).to_string(),
+                "--ppid",
+                "123",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "42",
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "search",
+            "git",
+            "--count",
+        ])
+        .assert()
+        .success()
+        .stdout("2\n");
+
+    // Not capped by --limit
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "search",
+            "git",
+            "--count",
+            "--limit",
+            "1",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .stdout("{\"count\":2}\n");
+
+    // Filters still apply
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "list",
+            "git",
+            "--count",
+            "--here",
+            "--pwd-override",
+            "/elsewhere",
+        ])
+        .assert()
+        .success()
+        .stdout("0\n");
+
+    sdbh_cmd()
+        .args(["--db", db.to_string_lossy().as_ref(), "summary", "--count"])
+        .assert()
+        .success()
+        .stdout("3\n");
+}
+
+#[test]
+fn search_exclude_drops_each_substring() {
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path();
+    let db = home.join("test.sqlite");
+
+    for (i, cmd) in [
+        "git status",
+        "git push",
+        "git log --oneline",
+        "git commit -m 100%",
+        "make build",
+    ]
+    .iter()
+    .enumerate()
+    {
+        sdbh_cmd()
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &(1700000000 + i as i64).to_string(),
+                "--ppid",
+                "123",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "42",
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .env("HOME", home)
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "search",
+            "git",
+            "--exclude",
+            "status",
+            "--exclude",
+            "log",
+            "--all",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("git push"))
+        .stdout(predicate::str::contains("git commit"))
+        .stdout(predicate::str::contains("git status").not())
+        .stdout(predicate::str::contains("git log").not())
+        .stdout(predicate::str::contains("make build").not());
+
+    // Wildcards in an exclusion are literal
+    sdbh_cmd()
+        .env("HOME", home)
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "search",
+            "git",
+            "--exclude",
+            "t_s",
+            "--count",
+        ])
+        .assert()
+        .success()
+        .stdout("4\n");
+
+    // [search] default_exclude applies on top of --exclude
+    std::fs::write(
+        home.join(".sdbh.toml"),
+        "[search]\ndefault_exclude = [\"git status\"]\n",
+    )
+    .unwrap();
+    sdbh_cmd()
+        .env("HOME", home)
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "search",
+            "git",
+            "--exclude",
+            "push",
+            "--count",
+        ])
+        .assert()
+        .success()
+        .stdout("2\n");
+}
+
+#[test]
+fn search_sort_relevance_and_frequency() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    for (i, cmd) in [
+        "kubectl get pods",
+        "minikube start",
+        "minikube start",
+        "watch kubectl get pods",
+        "kubectl logs web",
+        "minikube start",
+    ]
+    .iter()
+    .enumerate()
+    {
+        sdbh_cmd()
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &(1700000000 + i as i64).to_string(),
+                "--ppid",
+                "123",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "42",
+            ])
+            .assert()
+            .success();
+    }
+
+    let cmds = |sort: &str, limit: &str| -> Vec<String> {
+        let out = sdbh_cmd()
+            .args([
+                "--db",
+                db.to_str().unwrap(),
+                "search",
+                "kube",
+                "--sort",
+                sort,
+                "--limit",
+                limit,
+                "--porcelain",
+            ])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| l.rsplit('\t').next().unwrap().to_string())
+            .collect()
+    };
+
+    assert_eq!(
+        cmds("recency", "2"),
+        vec!["minikube start", "kubectl logs web"]
+    );
+    // Ranked over every match, then limited
+    assert_eq!(
+        cmds("relevance", "3"),
+        vec![
+            "kubectl logs web",
+            "kubectl get pods",
+            "watch kubectl get pods"
+        ]
+    );
+    assert_eq!(cmds("frequency", "3"), vec!["minikube start"; 3]);
+}
+
+#[test]
+fn dirs_search_lists_matching_directories_with_counts() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    for (i, (cmd, pwd)) in [
+        ("cargo build", "/home/u/src/projectx"),
+        ("cargo test", "/home/u/src/projectx"),
+        ("git status", "/home/u/src/ProjectX/docs"),
+        ("vim notes.md", "/home/u/src/other"),
+        ("make", "/home/u/src/projectx"),
+    ]
+    .iter()
+    .enumerate()
+    {
+        sdbh_cmd()
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &(1700000000 + i as i64).to_string(),
+                "--ppid",
+                "123",
+                "--pwd",
+                pwd,
+                "--salt",
+                "42",
+            ])
+            .assert()
+            .success();
+    }
+
+    let out = sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "dirs",
+            "search",
+            "projectx",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let dirs: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(
+        dirs,
+        serde_json::json!([
+            {"pwd": "/home/u/src/projectx", "count": 3, "last_epoch": 1700000004},
+            {"pwd": "/home/u/src/ProjectX/docs", "count": 1, "last_epoch": 1700000002},
+        ])
+    );
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "dirs",
+            "search",
+            "other",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("     1 | "))
+        .stdout(predicate::str::contains("| /home/u/src/other"));
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "dirs",
+            "search",
+            "nowhere",
+            "--status",
+        ])
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn search_invert_excludes_matching_commands() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    for (i, cmd) in ["git status", "make build", "git push", "cargo test"]
+        .iter()
+        .enumerate()
+    {
+        sdbh_cmd()
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &(1700000000 + i as i64).to_string(),
+                "--ppid",
+                "123",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "42",
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "search",
+            "git",
+            "--invert",
+            "--all",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("make build"))
+        .stdout(predicate::str::contains("cargo test"))
+        .stdout(predicate::str::contains("git").not());
+
+    // Combines with location filters
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "search",
+            "git",
+            "--invert",
+            "--all",
+            "--here",
+            "--pwd-override",
+            "/elsewhere",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+}
+
+#[test]
+fn preview_width_config_drives_truncation() {
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path();
+    let db = home.join("test.sqlite");
+    let long_cmd = "echo abcdefghijklmnopqrstuvwxyz0123456789";
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "log",
+            "--cmd",
+            long_cmd,
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "123",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "42",
+        ])
+        .assert()
+        .success();
+
+    std::fs::write(home.join(".sdbh.toml"), "[preview]\nwidth = 40\n").unwrap();
+
+    // 40 columns leaves 15 for the command header: 12 chars + "..."
+    sdbh_cmd()
+        .env("HOME", home)
+        .env("COLUMNS", "200")
+        .args(["--db", db.to_string_lossy().as_ref(), "preview", long_cmd])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Command Analysis: echo abcdefg...\n",
+        ));
+
+    // --width overrides the config
+    sdbh_cmd()
+        .env("HOME", home)
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "preview",
+            long_cmd,
+            "--width",
+            "35",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Command Analysis: echo ab...\n"));
+}
+
+#[test]
+fn stats_since_until_bounds_the_window() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    // 2024-02-29 (before), 2024-03-10 and 2024-03-31 12:00 UTC (inside), 2024-04-01 (after)
+    for (cmd, epoch) in [
+        ("echo feb", 1709208000),
+        ("echo march", 1710072000),
+        ("echo march", 1711886400),
+        ("echo april", 1711972800),
+    ] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &epoch.to_string(),
+                "--ppid",
+                "123",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "42",
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "stats",
+            "top",
+            "--since",
+            "2024-03-01",
+            "--until",
+            "2024-03-31",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("     2 | echo march"))
+        .stdout(predicate::str::contains("feb").not())
+        .stdout(predicate::str::contains("april").not());
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "stats",
+            "by-pwd",
+            "--since",
+            "1711972800",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("     1 | /tmp | echo april"))
+        .stdout(predicate::str::contains("march").not());
+
+    // --days and an explicit window are mutually exclusive
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "stats",
+            "daily",
+            "--days",
+            "7",
+            "--since",
+            "2024-03-01",
+        ])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn db_info_prints_resolved_paths_to_stderr() {
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path();
+    let db = home.join("test.sqlite");
+
+    sdbh_cmd()
+        .env("HOME", home)
+        .env_remove("SDBH_SALT")
+        .env_remove("SDBH_PPID")
+        .args(["--db", db.to_string_lossy().as_ref(), "--db-info", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("db:").not())
+        .stderr(predicate::str::contains(format!(
+            "db: {} (from --db)",
+            db.display()
+        )))
+        .stderr(predicate::str::contains("config: none"))
+        .stderr(predicate::str::contains("session: none"));
+
+    std::fs::write(home.join(".sdbh.toml"), "[log]\nignore_exact = []\n").unwrap();
+
+    sdbh_cmd()
+        .env("HOME", home)
+        .env("SDBH_SALT", "42")
+        .env("SDBH_PPID", "123")
+        .args(["--db", db.to_string_lossy().as_ref(), "list", "--db-info"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(".sdbh.toml (loaded)"))
+        .stderr(predicate::str::contains("session: salt=42 ppid=123"));
+}
+
+#[test]
+fn template_list_is_sorted_and_shows_variables() {
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path();
+    let templates_dir = home.join(".sdbh").join("templates");
+    std::fs::create_dir_all(&templates_dir).unwrap();
+
+    std::fs::write(
+        templates_dir.join("zeta.toml"),
+        r#"
+id = "zeta"
+name = "Zeta Deploy"
+command = "deploy {env} --tag {tag}"
+
+[[variables]]
+name = "env"
+required = true
+
+[[variables]]
+name = "tag"
+required = false
+default = "latest"
+"#,
+    )
+    .unwrap();
+    std::fs::write(
+        templates_dir.join("alpha.toml"),
+        r#"
+id = "alpha"
+name = "Alpha Status"
+command = "git status"
+"#,
+    )
+    .unwrap();
+
+    let out = sdbh_cmd()
+        .env("HOME", home)
+        .args(["template", "--list"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let out = String::from_utf8(out).unwrap();
+
+    let alpha = out.find("Alpha Status").unwrap();
+    let zeta = out.find("Zeta Deploy").unwrap();
+    assert!(alpha < zeta, "templates should be listed by name:\n{out}");
+    assert!(out.contains("  Variables: none"));
+    assert!(out.contains("    - env (required)"));
+    assert!(out.contains("    - tag (optional, default: latest)"));
+}
+
+#[test]
+fn list_and_search_show_count_on_stderr() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    for (i, cmd) in ["git status", "git push", "make build"].iter().enumerate() {
+        sdbh_cmd()
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &(1700000000 + i as i64).to_string(),
+                "--ppid",
+                "123",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "42",
+            ])
+            .assert()
+            .success();
+    }
+
+    let out = sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "search",
+            "git",
+            "--all",
+            "--show-count",
+        ])
+        .assert()
+        .success()
+        .stderr("2 results\n")
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(String::from_utf8(out).unwrap().lines().count(), 2);
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "list",
+            "--all",
+            "--format",
+            "json",
+            "--show-count",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("results").not())
+        .stderr("3 results\n");
+}
+
+#[test]
+fn preview_shows_time_of_day_histogram() {
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path();
+    let db = home.join("test.sqlite");
+
+    // Two runs at 09:xx UTC and one at 14:xx UTC on 2023-11-14
+    for epoch in [1699952400, 1699952460, 1699970400] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--cmd",
+                "make deploy",
+                "--epoch",
+                &epoch.to_string(),
+                "--ppid",
+                "123",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "42",
+            ])
+            .assert()
+            .success();
+    }
+
+    let histogram = format!("  │{}█{}▄{}│", " ".repeat(9), " ".repeat(4), " ".repeat(9));
+    sdbh_cmd()
+        .env("HOME", home)
+        .env("TZ", "UTC")
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "preview",
+            "make deploy",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Time of Day:"))
+        .stdout(predicate::str::contains(histogram))
+        .stdout(predicate::str::contains("Usually run: 09h"));
+
+    // The section can be switched off
+    std::fs::write(home.join(".sdbh.toml"), "[preview]\nshow_hours = false\n").unwrap();
+    sdbh_cmd()
+        .env("HOME", home)
+        .env("TZ", "UTC")
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "preview",
+            "make deploy",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Time of Day").not());
+}
+
+#[test]
+fn exists_reports_presence_via_exit_code() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "log",
+            "--cmd",
+            "terraform apply",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "123",
+            "--pwd",
+            "/infra",
+            "--salt",
+            "42",
+        ])
+        .assert()
+        .success();
+
+    sdbh_cmd()
+        .args(["--db", db.to_string_lossy().as_ref(), "exists", "TERRAFORM"])
+        .assert()
+        .code(0)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::is_empty());
+
+    sdbh_cmd()
+        .args(["--db", db.to_string_lossy().as_ref(), "exists", "kubectl"])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::is_empty());
+
+    // Filters apply: the command exists, but not in this directory
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "exists",
+            "terraform",
+            "--here",
+            "--pwd-override",
+            "/tmp",
+        ])
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn export_escapes_control_characters_as_valid_json() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let cmd = "printf '\x1b[31mred\x1b[0m\x07'";
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "log",
+            "--cmd",
+            cmd,
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "123",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "42",
+        ])
+        .assert()
+        .success();
+
+    let out = sdbh_cmd()
+        .args(["--db", db.to_string_lossy().as_ref(), "export"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("\\u001b[31m"));
+
+    let line = out.lines().next().unwrap();
+    let value: serde_json::Value = serde_json::from_str(line).unwrap();
+    assert_eq!(value["cmd"], cmd);
+}
+
+#[test]
+fn failed_only_filters_on_logged_exit_code() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    for (i, (cmd, exit_code)) in [
+        ("make build", Some("0")),
+        ("make test", Some("2")),
+        ("make lint", None),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let epoch = (1700000000 + i as i64).to_string();
+        let mut args = vec![
+            "--db",
+            db.to_str().unwrap(),
+            "log",
+            "--cmd",
+            cmd,
+            "--epoch",
+            &epoch,
+            "--ppid",
+            "123",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "42",
+        ];
+        if let Some(code) = exit_code {
+            args.extend(["--exit-code", code]);
+        }
+        sdbh_cmd().args(args).assert().success();
+    }
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "list",
+            "--all",
+            "--failed-only",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("make test"))
+        .stdout(predicate::str::contains("make build").not())
+        .stdout(predicate::str::contains("make lint").not());
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "search",
+            "make",
+            "--all",
+            "--failed-only",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::function(|s: &str| {
+            s.lines().count() == 1 && s.contains("make test")
+        }));
+
+    let conn = Connection::open(&db).unwrap();
+    let code: Option<i64> = conn
+        .query_row(
+            "SELECT exit_code FROM history WHERE cmd = 'make lint'",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(code, None);
+}
+
+#[test]
+fn iso_dates_adds_iso_field_to_json_output() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "log",
+            "--cmd",
+            "echo iso",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "123",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "42",
+        ])
+        .assert()
+        .success();
+
+    for args in [
+        vec!["list", "--all", "--format", "json", "--iso-dates"],
+        vec!["search", "iso", "--format", "json", "--iso-dates"],
+        vec!["export", "--iso-dates"],
+    ] {
+        sdbh_cmd()
+            .env("TZ", "UTC")
+            .args(["--db", db.to_string_lossy().as_ref()])
+            .args(&args)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "\"epoch\":1700000000,\"iso\":\"2023-11-14T22:13:20Z\"",
+            ));
+    }
+
+    // Raw epoch only by default
+    sdbh_cmd()
+        .args(["--db", db.to_string_lossy().as_ref(), "export"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"iso\"").not());
+}
+
+#[test]
+fn stats_slow_orders_by_average_duration() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    for (i, (cmd, duration)) in [
+        ("cargo build", Some("3000")),
+        ("cargo build", Some("5000")),
+        ("git status", Some("20")),
+        ("make deploy", None),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let epoch = (now - 60 + i as i64).to_string();
+        let mut args = vec![
+            "--db",
+            db.to_str().unwrap(),
+            "log",
+            "--cmd",
+            cmd,
+            "--epoch",
+            &epoch,
+            "--ppid",
+            "123",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "42",
+        ];
+        if let Some(ms) = duration {
+            args.extend(["--duration-ms", ms]);
+        }
+        sdbh_cmd().args(args).assert().success();
+    }
+
+    let out = sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "stats",
+            "slow",
+            "--days",
+            "1",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "    4000 |      2 | cargo build",
+            "      20 |      1 | git status"
+        ]
+    );
+}
+
+#[test]
+fn log_without_hash_skips_history_hash_and_dedup() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src.sqlite");
+    let dst = tmp.path().join("dst.sqlite");
+
+    for (db, extra) in [(&src, None), (&dst, Some("--no-hash"))] {
+        let mut args = vec![
+            "--db",
+            db.to_str().unwrap(),
+            "log",
+            "--cmd",
+            "echo fast",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "123",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "42",
+        ];
+        args.extend(extra);
+        sdbh_cmd().args(args).assert().success();
+    }
+
+    let conn = Connection::open(&dst).unwrap();
+    let rows: i64 = conn
+        .query_row("SELECT count(*) FROM history", [], |r| r.get(0))
+        .unwrap();
+    let hashes: i64 = conn
+        .query_row("SELECT count(*) FROM history_hash", [], |r| r.get(0))
+        .unwrap();
+    assert_eq!((rows, hashes), (1, 0));
+
+    // The identical row from src is not recognised as a duplicate
+    sdbh_cmd()
+        .args([
+            "import",
+            "--from",
+            src.to_string_lossy().as_ref(),
+            "--to",
+            dst.to_string_lossy().as_ref(),
+        ])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("considered 1, inserted 1"));
+
+    // The config switch behaves the same way, and doctor mentions it
+    let home = tmp.path();
+    std::fs::write(home.join(".sdbh.toml"), "[db]\ntrack_hash = false\n").unwrap();
+    sdbh_cmd()
+        .env("HOME", home)
+        .args([
+            "--db",
+            dst.to_string_lossy().as_ref(),
+            "log",
+            "--cmd",
+            "echo config",
+            "--epoch",
+            "1700000001",
+            "--ppid",
+            "123",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "42",
+        ])
+        .assert()
+        .success();
+    let hashes: i64 = conn
+        .query_row("SELECT count(*) FROM history_hash", [], |r| r.get(0))
+        .unwrap();
+    assert_eq!(hashes, 1);
+
+    sdbh_cmd()
+        .env("HOME", home)
+        .args([
+            "--db",
+            dst.to_string_lossy().as_ref(),
+            "doctor",
+            "--no-spawn",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("dedup hashing disabled"));
+}
+
+#[test]
+fn delete_removes_rows_and_their_hashes() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    for (i, cmd) in [
+        "export TOKEN=secret123",
+        "curl -H 'Authorization: secret123'",
+        "git status",
+    ]
+    .iter()
+    .enumerate()
+    {
+        sdbh_cmd()
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &(1700000000 + i as i64).to_string(),
+                "--ppid",
+                "123",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "42",
+            ])
+            .assert()
+            .success();
+    }
+
+    // Non-interactive without --yes refuses
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "delete",
+            "--query",
+            "secret123",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("pass --yes"));
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "delete",
+            "--query",
+            "SECRET123",
+            "--yes",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Deleted 2 row(s)"));
+
+    let conn = Connection::open(&db).unwrap();
+    let count = |sql: &str| -> i64 { conn.query_row(sql, [], |r| r.get(0)).unwrap() };
+    assert_eq!(count("SELECT count(*) FROM history"), 1);
+    assert_eq!(count("SELECT count(*) FROM history_hash"), 1);
+
+    let id = count("SELECT id FROM history");
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "delete",
+            "--id",
+            &id.to_string(),
+            "--yes",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Deleted 1 row(s)"));
+    assert_eq!(count("SELECT count(*) FROM history"), 0);
+    assert_eq!(count("SELECT count(*) FROM history_hash"), 0);
+
+    // --id or --query is required
+    sdbh_cmd()
+        .args(["--db", db.to_string_lossy().as_ref(), "delete", "--yes"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn stats_top_and_daily_merge_multiple_databases() {
+    let tmp = TempDir::new().unwrap();
+    let db_a = tmp.path().join("a.sqlite");
+    let db_b = tmp.path().join("b.sqlite");
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    for (db, cmd, n) in [
+        (&db_a, "cargo test", 2),
+        (&db_a, "git status", 1),
+        (&db_b, "cargo test", 3),
+        (&db_b, "make build", 1),
+    ] {
+        for i in 0..n {
+            sdbh_cmd()
+                .args([
+                    "--db",
+                    db.to_string_lossy().as_ref(),
+                    "log",
+                    "--cmd",
+                    cmd,
+                    "--epoch",
+                    &(now - 10 - i).to_string(),
+                    "--ppid",
+                    "123",
+                    "--pwd",
+                    "/tmp",
+                    "--salt",
+                    "42",
+                ])
+                .assert()
+                .success();
+        }
+    }
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db_a.to_string_lossy().as_ref(),
+            "--db",
+            db_b.to_string_lossy().as_ref(),
+            "stats",
+            "top",
+            "--days",
+            "1",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("     5 | cargo test\n"))
+        .stdout(predicate::str::contains("     1 | git status"))
+        .stdout(predicate::str::contains("     1 | make build"));
+
+    // The limit applies after merging
+    sdbh_cmd()
+        .args([
+            "--db",
+            db_a.to_string_lossy().as_ref(),
+            "--db",
+            db_b.to_string_lossy().as_ref(),
+            "stats",
+            "top",
+            "--days",
+            "1",
+            "--limit",
+            "1",
+        ])
+        .assert()
+        .success()
+        .stdout("     5 | cargo test\n");
+
+    let out = sdbh_cmd()
+        .args([
+            "--db",
+            db_a.to_string_lossy().as_ref(),
+            "--db",
+            db_b.to_string_lossy().as_ref(),
+            "stats",
+            "daily",
+            "--days",
+            "2",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let total: i64 = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|l| l.rsplit('|').next().unwrap().trim().parse::<i64>().unwrap())
+        .sum();
+    assert_eq!(total, 7);
+
+    // Other commands only work against a single database
+    sdbh_cmd()
+        .args([
+            "--db",
+            db_a.to_string_lossy().as_ref(),
+            "--db",
+            db_b.to_string_lossy().as_ref(),
+            "list",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("multiple --db paths"));
+}
+
+#[test]
+fn db_prune_removes_old_rows() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    for (cmd, epoch) in [
+        ("echo ancient", now - 400 * 86400),
+        ("echo old", now - 366 * 86400),
+        ("echo recent", now - 10),
+    ] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &epoch.to_string(),
+                "--ppid",
+                "123",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "42",
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "db",
+            "prune",
+            "--older-than-days",
+            "365",
+            "--dry-run",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Would remove 2 row(s)"));
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "db",
+            "prune",
+            "--older-than-days",
+            "365",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Removed 2 row(s)"))
+        .stdout(predicate::str::contains("sdbh db optimize"));
+
+    sdbh_cmd()
+        .args(["--db", db.to_string_lossy().as_ref(), "list", "--all"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("echo recent"))
+        .stdout(predicate::str::contains("echo old").not());
+}
+
+#[test]
+fn db_dedup_removes_exact_duplicates() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    for cmd in ["make", "make", "make", "make test"] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                "1700000000",
+                "--ppid",
+                "123",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "42",
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "db", "dedup", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Would remove 2 duplicate row(s)"));
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "db", "dedup"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Removed 2 duplicate row(s)"));
+
+    let c = conn(&db);
+    let ids: Vec<i64> = c
+        .prepare("SELECT id FROM history ORDER BY id")
+        .unwrap()
+        .query_map([], |r| r.get(0))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(ids, vec![1, 4]);
+}
+
+#[test]
+fn output_exits_cleanly_when_the_reader_closes_the_pipe() {
+    use std::io::{BufRead, BufReader};
+
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "log",
+            "--cmd",
+            "seed",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "1",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "1",
+        ])
+        .assert()
+        .success();
+    // Far more output than a pipe buffer holds, so the writer hits EPIPE.
+    let mut c = conn(&db);
+    let tx = c.transaction().unwrap();
+    for i in 0..20_000 {
+        tx.execute(
+            "INSERT INTO history(hist_id, cmd, epoch, ppid, pwd, salt) VALUES (?1, ?2, ?3, 1, '/tmp', 1)",
+            rusqlite::params![i, format!("echo {i}"), 1700000001 + i],
+        )
+        .unwrap();
+    }
+    tx.commit().unwrap();
+
+    // Equivalent of `sdbh export --all | head -1`, and the same for the other row printers
+    for args in [
+        &["export", "--all"][..],
+        &["search", "echo", "--all"],
+        &["summary", "--all"],
+        &["stats", "top", "--all"],
+    ] {
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin!("sdbh"))
+            .args(["--db", &db_arg])
+            .args(args)
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut first = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut first)
+            .unwrap();
+        if args[0] == "export" {
+            assert!(first.contains(r#""cmd":"seed""#), "{first}");
+        }
+
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{args:?}: {:?}", output.status);
+        assert!(
+            output.stderr.is_empty(),
+            "{args:?}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+#[test]
+fn describe_export_matches_export_output() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "log",
+            "--cmd",
+            "git status",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "123",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "42",
+        ])
+        .assert()
+        .success();
+
+    let out = sdbh_cmd()
+        .args(["describe", "export"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let schema: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(schema["type"], "object");
+    let props = schema["properties"].as_object().unwrap();
+    let types: Vec<(&str, String)> = props
+        .iter()
+        .map(|(k, v)| (k.as_str(), v["type"].to_string()))
+        .collect();
+    assert_eq!(
+        types,
+        vec![
+            ("cmd", "\"string\"".to_string()),
+            ("duration_ms", "[\"integer\",\"null\"]".to_string()),
+            ("env", "[\"object\",\"null\"]".to_string()),
+            ("epoch", "\"integer\"".to_string()),
+            ("exit_code", "[\"integer\",\"null\"]".to_string()),
+            ("gitroot", "[\"string\",\"null\"]".to_string()),
+            ("hist_id", "[\"integer\",\"null\"]".to_string()),
+            ("host", "[\"string\",\"null\"]".to_string()),
+            ("id", "\"integer\"".to_string()),
+            ("iso", "\"string\"".to_string()),
+            ("ppid", "\"integer\"".to_string()),
+            ("pwd", "\"string\"".to_string()),
+            ("salt", "\"integer\"".to_string()),
+            ("seq", "[\"integer\",\"null\"]".to_string()),
+            ("tty", "[\"string\",\"null\"]".to_string()),
+        ]
+    );
+
+    // Every key the real export emits is described, and every required key is emitted
+    let out = sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "export",
+            "--iso-dates",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let line = String::from_utf8(out).unwrap();
+    let record: serde_json::Value = serde_json::from_str(line.lines().next().unwrap()).unwrap();
+    let record = record.as_object().unwrap();
+    for key in record.keys() {
+        assert!(props.contains_key(key), "undescribed export key {key}");
+    }
+    for key in schema["required"].as_array().unwrap() {
+        assert!(record.contains_key(key.as_str().unwrap()));
+    }
+}
+
+#[test]
+fn export_and_search_json_share_record_keys() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "log",
+            "--cmd",
+            "cargo build",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "123",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "42",
+        ])
+        .assert()
+        .success();
+
+    let keys = |args: &[&str]| -> Vec<String> {
+        let out = sdbh_cmd()
+            .args(["--db", &db_arg])
+            .args(args)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let text = String::from_utf8(out).unwrap();
+        let value: serde_json::Value = serde_json::from_str(text.lines().next().unwrap()).unwrap();
+        let record = match value {
+            serde_json::Value::Array(mut items) => items.remove(0),
+            record => record,
+        };
+        let mut keys: Vec<String> = record.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    };
+
+    let export = keys(&["export", "--all", "--iso-dates"]);
+    let search = keys(&[
+        "search",
+        "cargo",
+        "--all",
+        "--format",
+        "json",
+        "--iso-dates",
+        "--verbose-json",
+    ]);
+    // export additionally carries the remaining stored columns
+    let extra: Vec<&String> = export.iter().filter(|k| !search.contains(k)).collect();
+    assert_eq!(
+        extra,
+        [
+            "duration_ms",
+            "env",
+            "exit_code",
+            "gitroot",
+            "host",
+            "seq",
+            "tty"
+        ]
+    );
+    assert!(search.iter().all(|k| export.contains(k)));
+
+    // Without --verbose-json search emits a subset under the same names
+    let plain = keys(&["search", "cargo", "--all", "--format", "json"]);
+    assert_eq!(plain, ["cmd", "epoch", "id", "pwd"]);
+    assert!(plain.iter().all(|k| export.contains(k)));
+}
+
+#[test]
+fn describe_without_record_lists_every_kind() {
+    let out = sdbh_cmd()
+        .arg("describe")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let all: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    for kind in ["list", "search", "export", "doctor"] {
+        assert!(all.get(kind).is_some(), "missing {kind}");
+    }
+    assert_eq!(all["doctor"]["type"], "object");
+    assert_eq!(
+        all["doctor"]["properties"]["checks"]["items"]["required"][0],
+        "check"
+    );
+    assert_eq!(all["doctor"]["required"][1], "suggestions");
+}
+
+#[test]
+fn search_results_unchanged_after_reindex_fts() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    let log = |cmd: &str, epoch: &str| {
+        sdbh_cmd()
+            .args([
+                "--db", &db_arg, "log", "--cmd", cmd, "--epoch", epoch, "--ppid", "1", "--pwd",
+                "/tmp", "--salt", "1",
+            ])
+            .assert()
+            .success();
+    };
+    log("git status", "1700000000");
+    log("cargo build", "1700000001");
+    log("Git Log --oneline", "1700000002");
+    log("echo digits", "1700000003");
+
+    let search = |query: &str| {
+        let out = sdbh_cmd()
+            .args(["--db", &db_arg, "search", query, "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        String::from_utf8(out).unwrap()
+    };
+    let before = search("git");
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "db", "reindex-fts"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Full-text index built (4 rows)"));
+    assert_eq!(search("git"), before);
+
+    // New rows are indexed by triggers
+    log("legit push", "1700000004");
+    assert!(search("git").contains("legit push"));
+    assert!(search("it p").contains("legit push"));
+}
+
+#[test]
+fn csv_format_quotes_embedded_commas() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "log",
+            "--cmd",
+            "echo \"a,b\"",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "1",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "1",
+            "--hist-id",
+            "7",
+        ])
+        .assert()
+        .success();
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "list", "--format", "csv"])
+        .assert()
+        .success()
+        .stdout("id,epoch,pwd,cmd\n1,1700000000,/tmp,\"echo \"\"a,b\"\"\"\n");
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "search", "a,b", "--format", "csv"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "1,1700000000,/tmp,\"echo \"\"a,b\"\"\"",
+        ));
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "export", "--format", "csv"])
+        .assert()
+        .success()
+        .stdout(
+            "id,hist_id,epoch,ppid,pwd,salt,cmd\n1,7,1700000000,1,/tmp,1,\"echo \"\"a,b\"\"\"\n",
+        );
+}
+
+#[test]
+fn log_capture_env_is_shown_by_show() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+
+    for (cmd, capture) in [("npm run build", "FOO,UNSET_VAR"), ("npm test", "")] {
+        sdbh_cmd()
+            .env("FOO", "bar baz")
+            .env_remove("UNSET_VAR")
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                "1700000000",
+                "--ppid",
+                "1",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+                "--capture-env",
+                capture,
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "show", "--id", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("cmd          npm run build"))
+        .stdout(predicate::str::contains("env\n  FOO=bar baz\n"))
+        .stdout(predicate::str::contains("UNSET_VAR").not());
+
+    // An empty list (what the hooks pass when SDBH_CAPTURE_ENV is unset) records nothing
+    sdbh_cmd()
+        .args(["--db", &db_arg, "show", "--id", "2"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("env          -"));
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "show", "--id", "3"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no history entry with id 3"));
+}
+
+#[test]
+fn show_prints_every_column_untruncated() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+    let long_pwd = format!(
+        "/srv/{}/deeply/nested",
+        "very-long-directory-name-".repeat(8)
+    );
+    let long_cmd = format!("echo {}", "x".repeat(300));
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "log",
+            "--cmd",
+            &long_cmd,
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "4242",
+            "--pwd",
+            &long_pwd,
+            "--salt",
+            "77",
+            "--hist-id",
+            "9",
+            "--exit-code",
+            "2",
+            "--host",
+            "box",
+        ])
+        .assert()
+        .success();
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "show", "--id", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "cmd          {long_cmd}\n"
+        )))
+        .stdout(predicate::str::contains(format!(
+            "pwd          {long_pwd}\n"
+        )))
+        .stdout(predicate::str::contains("hist_id      9\n"))
+        .stdout(predicate::str::contains("epoch        1700000000 ("))
+        .stdout(predicate::str::contains("exit_code    2\n"))
+        .stdout(predicate::str::contains("duration_ms  -\n"));
+
+    let out = sdbh_cmd()
+        .args(["--db", &db_arg, "show", "--id", "1", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let row: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(row["cmd"], long_cmd.as_str());
+    assert_eq!(row["pwd"], long_pwd.as_str());
+    assert_eq!(row["salt"], 77);
+    assert_eq!(row["ppid"], 4242);
+    assert_eq!(row["host"], "box");
+    assert_eq!(row["duration_ms"], serde_json::Value::Null);
+    assert_eq!(row["synthetic"], false);
+
+    // The documented schema lists exactly the emitted keys
+    let schema = sdbh_cmd()
+        .args(["describe", "show"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let schema: serde_json::Value = serde_json::from_slice(&schema).unwrap();
+    let mut documented: Vec<&String> = schema["properties"].as_object().unwrap().keys().collect();
+    let mut emitted: Vec<&String> = row.as_object().unwrap().keys().collect();
+    documented.sort();
+    emitted.sort();
+    assert_eq!(documented, emitted);
+}
+
+/// Check `record` against one object schema from `sdbh describe`: no undocumented
+/// keys, every required key present, and each value of a declared type.
+fn assert_matches_schema(record: &serde_json::Value, schema: &serde_json::Value) {
+    let props = schema["properties"].as_object().unwrap();
+    let record = record.as_object().unwrap();
+    for (key, value) in record {
+        let Some(prop) = props.get(key) else {
+            panic!("undocumented key {key}");
+        };
+        let types: Vec<&str> = match &prop["type"] {
+            serde_json::Value::String(ty) => vec![ty.as_str()],
+            types => types
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|t| t.as_str().unwrap())
+                .collect(),
+        };
+        let actual = match value {
+            serde_json::Value::Null => "null",
+            serde_json::Value::Bool(_) => "boolean",
+            serde_json::Value::Number(n) if n.is_i64() => "integer",
+            serde_json::Value::Number(_) => "number",
+            serde_json::Value::String(_) => "string",
+            serde_json::Value::Array(_) => "array",
+            serde_json::Value::Object(_) => "object",
+        };
+        assert!(types.contains(&actual), "{key}: {actual} is not {types:?}");
+    }
+    for key in schema["required"].as_array().unwrap() {
+        let key = key.as_str().unwrap();
+        assert!(record.contains_key(key), "missing required key {key}");
+    }
+}
+
+#[test]
+fn json_output_matches_described_schemas() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+
+    // One row with every optional column set, one with none
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "log",
+            "--cmd",
+            "cargo build",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "1",
+            "--pwd",
+            "/src",
+            "--salt",
+            "2",
+            "--hist-id",
+            "7",
+            "--exit-code",
+            "0",
+            "--duration-ms",
+            "900",
+            "--host",
+            "box",
+            "--tty",
+            "/dev/pts/1",
+            "--gitroot",
+            "/src",
+            "--seq",
+            "1",
+            "--capture-env",
+            "SDBH_SCHEMA_VAR",
+        ])
+        .env("SDBH_SCHEMA_VAR", "x")
+        .assert()
+        .success();
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "log",
+            "--cmd",
+            "cargo test",
+            "--epoch",
+            "1700000001",
+            "--ppid",
+            "1",
+            "--pwd",
+            "/src",
+            "--salt",
+            "2",
+        ])
+        .assert()
+        .success();
+
+    let run = |args: &[&str]| -> String {
+        let out = sdbh_cmd()
+            .args(["--db", &db_arg])
+            .args(args)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        String::from_utf8(out).unwrap()
+    };
+    let schema = |kind: &str| -> serde_json::Value {
+        serde_json::from_str(&run(&["describe", kind])).unwrap()
+    };
+
+    for kind in ["list", "search"] {
+        let schema = schema(kind);
+        assert_eq!(schema["type"], "array");
+        let query: &[&str] = if kind == "search" { &["cargo"] } else { &[] };
+        for flags in [
+            &["--format", "json"][..],
+            &["--format", "json", "--verbose-json", "--iso-dates"],
+        ] {
+            let out = run(&[&[kind][..], query, flags].concat());
+            let rows: serde_json::Value = serde_json::from_str(&out).unwrap();
+            let rows = rows.as_array().unwrap();
+            assert_eq!(rows.len(), 2, "{kind} {flags:?}");
+            for row in rows {
+                assert_matches_schema(row, &schema["items"]);
+            }
+        }
+
+        // With every flag on, each documented key is emitted
+        let out = run(&[
+            &[kind][..],
+            query,
+            &["--format", "json", "--verbose-json", "--iso-dates"],
+        ]
+        .concat());
+        let rows: serde_json::Value = serde_json::from_str(&out).unwrap();
+        let mut emitted: Vec<&String> = rows[0].as_object().unwrap().keys().collect();
+        let mut documented: Vec<&String> = schema["items"]["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect();
+        emitted.sort();
+        documented.sort();
+        assert_eq!(emitted, documented, "{kind}");
+    }
+
+    let show_schema = schema("show");
+    for id in ["1", "2"] {
+        let out = run(&["show", "--id", id, "--format", "json"]);
+        assert_matches_schema(&serde_json::from_str(&out).unwrap(), &show_schema);
+    }
+
+    let schema = schema("export");
+    for flags in [&["--all"][..], &["--all", "--iso-dates"]] {
+        let out = run(&[&["export"][..], flags].concat());
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            assert_matches_schema(&serde_json::from_str(line).unwrap(), &schema);
+        }
+    }
+}
+
+#[test]
+fn tsv_format_flattens_embedded_tabs() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "log",
+            "--cmd",
+            "printf 'a\tb'",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "1",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "1",
+        ])
+        .assert()
+        .success();
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "list", "--format", "tsv"])
+        .assert()
+        .success()
+        .stdout("id\tepoch\tpwd\tcmd\n1\t1700000000\t/tmp\tprintf 'a b'\n");
+
+    // --no-header drops the header so outputs can be concatenated
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "search",
+            "printf",
+            "--format",
+            "tsv",
+            "--no-header",
+        ])
+        .assert()
+        .success()
+        .stdout("1\t1700000000\t/tmp\tprintf 'a b'\n");
+    sdbh_cmd()
+        .args(["--db", &db_arg, "list", "--format", "csv", "--no-header"])
+        .assert()
+        .success()
+        .stdout("1,1700000000,/tmp,printf 'a\tb'\n");
+}
+
+#[test]
+fn search_around_id_shows_window_in_time_order() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    // Different sessions: the window ignores session boundaries
+    for (i, cmd) in ["one", "two", "three", "four", "five"].iter().enumerate() {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &(1700000000 + i as i64 * 10).to_string(),
+                "--ppid",
+                &(100 + i).to_string(),
+                "--pwd",
+                "/tmp",
+                "--salt",
+                &i.to_string(),
+            ])
+            .assert()
+            .success();
+    }
+
+    let window = |context: &str| -> Vec<String> {
+        let out = sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "search",
+                "--around-id",
+                "3",
+                "--context",
+                context,
+                "--format",
+                "json",
+            ])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let rows: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        rows.as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["cmd"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    assert_eq!(window("1"), ["two", "three", "four"]);
+    assert_eq!(window("0"), ["three"]);
+    assert_eq!(window("10"), ["one", "two", "three", "four", "five"]);
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "search", "--around-id", "99"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no history entry with id 99"));
+
+    // A query and --around-id are mutually exclusive; one of them is required
+    sdbh_cmd()
+        .args(["--db", &db_arg, "search", "two", "--around-id", "3"])
+        .assert()
+        .failure();
+    sdbh_cmd()
+        .args(["--db", &db_arg, "search"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn bash_intercept_snippet_skips_non_interactive_shells() {
+    sdbh_cmd()
+        .args(["shell", "--bash", "--intercept"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#"[[ $- != *i* ]] && return"#))
+        .stdout(predicate::str::contains(
+            "(( BASH_SUBSHELL > 0 )) && return",
+        ))
+        .stdout(predicate::str::contains(
+            r#"if [[ -z "${SDBH_LOG_NONINTERACTIVE}" ]]; then"#,
+        ));
+}
+
+#[test]
+fn table_headers_only_with_flag_when_piped() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "log",
+            "--cmd",
+            "git status",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "1",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "1",
+        ])
+        .assert()
+        .success();
+
+    for cmd in [vec!["list"], vec!["search", "git"]] {
+        let mut args = vec!["--db", db_arg.as_str()];
+        args.extend(&cmd);
+
+        // stdout is a pipe here, so no header by default
+        sdbh_cmd()
+            .args(&args)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("DATE").not());
+
+        args.push("--headers");
+        let out = sdbh_cmd()
+            .args(&args)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "    ID | DATE                | PWD | CMD");
+        assert!(lines[1].chars().all(|c| c == '-'));
+        assert!(lines[2].ends_with("| /tmp | git status"));
+    }
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "summary", "--headers"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "    ID | LAST SEEN           |  COUNT | CMD\n",
+        ));
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "list", "--headers", "--format", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("[{"));
+}
+
+#[test]
+fn shell_nu_prints_nushell_hooks() {
+    sdbh_cmd()
+        .args(["shell", "--nu"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("# sdbh nushell hook mode"))
+        .stdout(predicate::str::contains("upsert hooks.pre_execution"))
+        .stdout(predicate::str::contains("upsert hooks.pre_prompt"))
+        .stdout(predicate::str::contains("(^sdbh log"))
+        .stdout(predicate::str::contains("# sdbh bash hook mode").not());
+
+    // Not part of the default output
+    sdbh_cmd()
+        .arg("shell")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("nushell").not());
+}
+
+#[test]
+fn replay_runs_session_in_order_and_reports_statuses() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let work = tmp.path().join("work");
+    std::fs::create_dir(&work).unwrap();
+
+    for (i, (cmd, salt)) in [
+        ("echo one", "7"),
+        ("echo other session", "8"),
+        ("sdbh list", "7"),
+        ("exit 3", "7"),
+        ("echo two in $(basename \"$PWD\")", "7"),
+    ]
+    .iter()
+    .enumerate()
+    {
+        sdbh_cmd()
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--no-filter",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &(1700000000 + i as i64).to_string(),
+                "--ppid",
+                "99",
+                "--pwd",
+                work.to_string_lossy().as_ref(),
+                "--salt",
+                salt,
+            ])
+            .assert()
+            .success();
+    }
+
+    let replay = |extra: &[&str]| -> String {
+        let mut args = vec![
+            "--db",
+            db.to_str().unwrap(),
+            "replay",
+            "--salt",
+            "7",
+            "--ppid",
+            "99",
+        ];
+        args.extend_from_slice(extra);
+        let out = sdbh_cmd()
+            .env("SHELL", "/bin/sh")
+            .args(&args)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        String::from_utf8(out).unwrap()
+    };
+
+    // Without --yes replay only prints, in order, without the sdbh entry
+    let dry = replay(&[]);
+    assert_eq!(dry, replay(&["--dry-run"]));
+    let cmds: Vec<&str> = dry
+        .lines()
+        .map(|l| l.rsplit(" | ").next().unwrap())
+        .collect();
+    assert_eq!(
+        cmds,
+        vec!["echo one", "exit 3", "echo two in $(basename \"$PWD\")"]
+    );
+    assert!(!dry.contains("exit 3:"));
+
+    let out = replay(&["--yes"]);
+    assert_eq!(
+        out.lines().collect::<Vec<_>>(),
+        vec![
+            "one",
+            "exit 0: echo one",
+            "exit 3: exit 3",
+            "two in work",
+            "exit 0: echo two in $(basename \"$PWD\")",
+            "Replayed 3 command(s), 1 failed",
+        ]
+    );
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            db.to_str().unwrap(),
+            "replay",
+            "--salt",
+            "1",
+            "--ppid",
+            "1",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no commands logged"));
+
+    // A dry run reports every missing directory instead of stopping at the first
+    let gone = tmp.path().join("gone");
+    for (i, (cmd, pwd)) in [
+        ("echo a", gone.join("a")),
+        ("echo b", work.clone()),
+        ("echo c", gone.join("c")),
+    ]
+    .iter()
+    .enumerate()
+    {
+        sdbh_cmd()
+            .args([
+                "--db",
+                db.to_str().unwrap(),
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                "1700000100",
+                "--seq",
+                &(3 - i).to_string(),
+                "--ppid",
+                "5",
+                "--pwd",
+                pwd.to_str().unwrap(),
+                "--salt",
+                "5",
+            ])
+            .assert()
+            .success();
+    }
+    let out = sdbh_cmd()
+        .args([
+            "--db",
+            db.to_str().unwrap(),
+            "replay",
+            "--salt",
+            "5",
+            "--ppid",
+            "5",
+        ])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "2 recorded directories no longer exist",
+        ))
+        .stderr(predicate::str::contains(
+            "pass --yes to run these 3 command(s)",
+        ))
+        .get_output()
+        .stdout
+        .clone();
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    // Same epoch: the session's seq decides the order
+    assert!(lines[0].ends_with("(missing) | echo c"), "{out}");
+    assert!(lines[1].ends_with(" | echo b"), "{out}");
+    assert!(lines[2].ends_with("(missing) | echo a"), "{out}");
+}
+
+#[test]
+fn replay_id_prints_or_runs_one_command() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+    let work = tmp.path().join("work");
+    std::fs::create_dir(&work).unwrap();
+
+    for cmd in ["echo first", "echo replayed"] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                "1700000000",
+                "--ppid",
+                "1",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+            ])
+            .assert()
+            .success();
+    }
+
+    let out = sdbh_cmd()
+        .args(["--db", &db_arg, "list", "--all", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let rows: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    let id = rows
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|r| r["cmd"] == "echo replayed")
+        .unwrap()["id"]
+        .as_i64()
+        .unwrap()
+        .to_string();
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "replay", "--id", &id])
+        .assert()
+        .success()
+        .stdout("echo replayed\n");
+
+    sdbh_cmd()
+        .current_dir(&work)
+        .env("SHELL", "/bin/sh")
+        .env_remove("SDBH_SALT")
+        .env_remove("SDBH_PPID")
+        .env("SDBH_CAPTURE_ENV", "SDBH_REPLAY_VAR")
+        .env("SDBH_REPLAY_VAR", "staging")
+        .args(["--db", &db_arg, "replay", "--id", &id, "--run"])
+        .assert()
+        .success()
+        .stdout("replayed\n");
+    // The run is logged as a fresh entry in the current directory, capturing env
+    // like the hook would
+    let c = conn(std::path::Path::new(&db_arg));
+    let (count, pwd, env): (i64, String, String) = c
+        .query_row(
+            "SELECT count(*), max(pwd), max(env) FROM history WHERE cmd = 'echo replayed'",
+            [],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(count, 2);
+    assert_eq!(pwd, work.canonicalize().unwrap().to_string_lossy());
+    assert_eq!(env, r#"{"SDBH_REPLAY_VAR":"staging"}"#);
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "replay", "--id", "999"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no history entry with id 999"));
+}
+
+#[test]
+fn shell_powershell_prints_prompt_hook() {
+    sdbh_cmd()
+        .args(["shell", "--powershell"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("# sdbh PowerShell hook mode"))
+        .stdout(predicate::str::contains("function global:prompt"))
+        .stdout(predicate::str::contains("Get-History -Count 1"))
+        .stdout(predicate::str::contains("--ppid $PID"))
+        .stdout(predicate::str::contains("--exit-code $code"))
+        .stdout(predicate::str::contains("# sdbh bash hook mode").not());
+
+    // Not part of the default output, and hook mode only
+    sdbh_cmd()
+        .arg("shell")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("PowerShell").not());
+    sdbh_cmd()
+        .args(["shell", "--powershell", "--intercept"])
+        .assert()
+        .failure();
+}
+
+#[cfg(unix)]
+#[test]
+fn doctor_nu_spawn_reports_sdbh_hook() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let bin = tmp.path().join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    let nu = bin.join("nu");
+    std::fs::write(
+        &nu,
+        "#!/bin/sh\necho '{pre_prompt: [\"{||\\n (^sdbh log --cmd $cmd) }\"]}'\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&nu, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    sdbh_cmd()
+        .env("PATH", &bin)
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "doctor",
+            "--spawn-only",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "nu.spawn           | ok    | config hooks contains sdbh log (1 occurrence)",
+        ));
+}
+
+#[test]
+fn host_and_tty_are_recorded_and_filterable() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    let log = |cmd: &str, host: &str, epoch: &str| {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                epoch,
+                "--ppid",
+                "1",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+                "--host",
+                host,
+                "--tty",
+                "/dev/pts/4",
+            ])
+            .assert()
+            .success();
+    };
+    // Same command/time/session on two machines: both rows are kept
+    log("make deploy", "laptop", "1700000000");
+    log("make deploy", "server", "1700000000");
+    log("uptime", "server", "1700000001");
+
+    let conn = Connection::open(&db).unwrap();
+    let hashes: i64 = conn
+        .query_row("SELECT count(*) FROM history_hash", [], |r| r.get(0))
+        .unwrap();
+    assert_eq!(hashes, 3);
+    let tty: String = conn
+        .query_row("SELECT tty FROM history WHERE id = 1", [], |r| r.get(0))
+        .unwrap();
+    assert_eq!(tty, "/dev/pts/4");
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "list", "--host", "laptop"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("make deploy"))
+        .stdout(predicate::str::contains("uptime").not());
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "search", "make", "--host", "server"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("make deploy"));
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "summary", "--host", "server"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("uptime"));
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "stats", "by-host", "--since", "1699999999"])
+        .assert()
+        .success()
+        .stdout("     2 | server\n     1 | laptop\n");
+}
+
+#[test]
+fn last_session_skips_other_sessions_and_sdbh_itself() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    let log = |cmd: &str, epoch: &str, salt: &str| {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                epoch,
+                "--ppid",
+                "100",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                salt,
+                // the default filter would drop the sdbh row before it is stored
+                "--no-filter",
+            ])
+            .assert()
+            .success();
+    };
+    log("cargo build", "1700000000", "7");
+    log("cargo test", "1700000010", "7");
+    log("vim notes.md", "1700000020", "8"); // another session, newer
+    log("sdbh last --session", "1700000030", "7");
+
+    sdbh_cmd()
+        .env("SDBH_SALT", "7")
+        .env("SDBH_PPID", "100")
+        .args(["--db", &db_arg, "last", "--session"])
+        .assert()
+        .success()
+        .stdout("cargo test\n");
+    let conn = Connection::open(&db).unwrap();
+    let sdbh_rows: i64 = conn
+        .query_row(
+            "SELECT count(*) FROM history WHERE cmd LIKE 'sdbh %'",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(sdbh_rows, 1);
+
+    // Without --session the newest non-sdbh command wins
+    sdbh_cmd()
+        .args(["--db", &db_arg, "last"])
+        .assert()
+        .success()
+        .stdout("vim notes.md\n");
+
+    sdbh_cmd()
+        .env_remove("SDBH_SALT")
+        .env_remove("SDBH_PPID")
+        .args(["--db", &db_arg, "last", "--session"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("SDBH_SALT"));
+}
+
+#[test]
+fn last_n_prints_newest_rows_oldest_first() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    for (cmd, epoch, pwd) in [
+        ("cargo build", "1700000000", "/work"),
+        ("cargo test", "1700000010", "/work"),
+        ("vim notes.md", "1700000020", "/home"),
+    ] {
+        sdbh_cmd()
+            .args([
+                "--db", &db_arg, "log", "--cmd", cmd, "--epoch", epoch, "--ppid", "100", "--pwd",
+                pwd, "--salt", "7",
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "last", "-n", "2"])
+        .assert()
+        .success()
+        .stdout("cargo test\nvim notes.md\n");
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "last",
+            "--count",
+            "5",
+            "--here",
+            "--pwd-override",
+            "/work",
+        ])
+        .assert()
+        .success()
+        .stdout("cargo build\ncargo test\n");
+
+    let out = sdbh_cmd()
+        .args(["--db", &db_arg, "last", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let rows: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(
+        rows,
+        serde_json::json!([{"id": 3, "epoch": 1700000020, "pwd": "/home", "cmd": "vim notes.md"}])
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn edit_rewrites_command_and_hash() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    for cmd in ["git status", "git stauts\x07"] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                "1700000000",
+                "--ppid",
+                "1",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+                "--host",
+                "laptop",
+            ])
+            .assert()
+            .success();
+    }
+
+    // Also records how the temp file it was handed looked
+    let editor = tmp.path().join("fix-typo");
+    let seen = tmp.path().join("seen");
+    std::fs::write(
+        &editor,
+        format!(
+            "#!/bin/sh\nls -l \"$1\" > '{0}'\nprintf '%s' \"$1\" > '{0}.path'\nprintf 'git log\\n' > \"$1\"\n",
+            seen.display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&editor, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    sdbh_cmd()
+        .env("EDITOR", &editor)
+        .args(["--db", &db_arg, "edit", "--id", "2"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Before: git stauts"))
+        .stdout(predicate::str::contains("After:  git log"));
+    let listing = std::fs::read_to_string(&seen).unwrap();
+    assert!(listing.starts_with("-rw-------"), "{listing}");
+    let edited = std::fs::read_to_string(seen.with_extension("path")).unwrap();
+    assert!(!std::path::Path::new(&edited).exists());
+
+    let conn = Connection::open(&db).unwrap();
+    let cmd: String = conn
+        .query_row("SELECT cmd FROM history WHERE id = 2", [], |r| r.get(0))
+        .unwrap();
+    assert_eq!(cmd, "git log");
+    let hashes: i64 = conn
+        .query_row(
+            "SELECT count(*) FROM history_hash WHERE history_id = 2",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(hashes, 1);
+
+    // Editing row 2 into row 1's command would give both the same hash
+    std::fs::write(&editor, "#!/bin/sh\nprintf 'git status\\n' > \"$1\"\n").unwrap();
+    sdbh_cmd()
+        .env("EDITOR", &editor)
+        .args(["--db", &db_arg, "edit", "--id", "2"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "identical entry already exists (id 1)",
+        ));
+    let cmd: String = conn
+        .query_row("SELECT cmd FROM history WHERE id = 2", [], |r| r.get(0))
+        .unwrap();
+    assert_eq!(cmd, "git log");
+
+    sdbh_cmd()
+        .env("EDITOR", &editor)
+        .args(["--db", &db_arg, "edit", "--id", "99"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no history entry with id 99"));
+}
+
+#[test]
+fn import_session_report_tallies_duplicates_per_source_session() {
+    let tmp = TempDir::new().unwrap();
+    let dest = tmp.path().join("dest.sqlite");
+    let older = tmp.path().join("older.sqlite");
+    let newer = tmp.path().join("newer.sqlite");
+
+    let log = |db: &std::path::Path, cmd: &str, epoch: i64, salt: i64, ppid: i64| {
+        sdbh_cmd()
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &epoch.to_string(),
+                "--ppid",
+                &ppid.to_string(),
+                "--pwd",
+                "/tmp",
+                "--salt",
+                &salt.to_string(),
+                "--host",
+                "box",
+            ])
+            .assert()
+            .success();
+    };
+
+    // The older dump has sessions 1 and 2; the newer one repeats part of both and adds session 3.
+    for (i, cmd) in ["make", "make test", "git push"].iter().enumerate() {
+        log(&older, cmd, 1700000000 + i as i64, 1, 100);
+        if i < 2 {
+            log(&newer, cmd, 1700000000 + i as i64, 1, 100);
+        }
+    }
+    log(&newer, "vim", 1700000100, 1, 100);
+    for (i, cmd) in ["top", "htop"].iter().enumerate() {
+        log(&older, cmd, 1700000200 + i as i64, 2, 200);
+    }
+    log(&newer, "top", 1700000200, 2, 200);
+    log(&newer, "df -h", 1700000300, 3, 300);
+
+    let dest_arg = dest.to_string_lossy().to_string();
+    sdbh_cmd()
+        .args(["import", "--to", &dest_arg, "--from"])
+        .arg(&older)
+        .assert()
+        .success();
+
+    sdbh_cmd()
+        .args(["import", "--session-report", "--to", &dest_arg, "--from"])
+        .arg(&newer)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("considered 5, inserted 2"))
+        .stderr(predicate::str::contains(
+            "  session salt=1 ppid=100: 2 duplicate(s)\n  session salt=2 ppid=200: 1 duplicate(s)\n",
+        ))
+        .stderr(predicate::str::contains("salt=3").not());
+}
+
+#[test]
+fn import_set_salt_overrides_imported_rows() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src.sqlite");
+    let dest = tmp.path().join("dest.sqlite");
+    let src_arg = src.to_string_lossy().to_string();
+    let dest_arg = dest.to_string_lossy().to_string();
+
+    for (i, cmd) in ["make", "make test"].iter().enumerate() {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &src_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &(1700000000 + i as i64).to_string(),
+                "--ppid",
+                "100",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .args(["import", "--to", &dest_arg, "--from", &src_arg])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("considered 2, inserted 2"));
+
+    // Overriding changes the dedup hash, so the same rows come in again under salt 7
+    sdbh_cmd()
+        .args([
+            "import",
+            "--to",
+            &dest_arg,
+            "--from",
+            &src_arg,
+            "--set-salt",
+            "7",
+            "--set-host",
+            "old-laptop",
+        ])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("considered 2, inserted 2"));
+
+    let c = conn(&dest);
+    let tagged: Vec<(String, i64, i64, Option<String>)> = c
+        .prepare("SELECT cmd, salt, ppid, host FROM history WHERE salt = 7 ORDER BY id")
+        .unwrap()
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(
+        tagged,
+        vec![
+            ("make".to_string(), 7, 100, Some("old-laptop".to_string())),
+            (
+                "make test".to_string(),
+                7,
+                100,
+                Some("old-laptop".to_string())
+            ),
+        ]
+    );
+    let untouched: i64 = c
+        .query_row("SELECT count(*) FROM history WHERE salt = 1", [], |r| {
+            r.get(0)
+        })
+        .unwrap();
+    assert_eq!(untouched, 2);
+}
+
+#[test]
+fn import_from_db_keeps_recorded_metadata() {
+    let tmp = TempDir::new().unwrap();
+    let src_arg = tmp.path().join("src.sqlite").to_string_lossy().to_string();
+    let dest_arg = tmp.path().join("dest.sqlite").to_string_lossy().to_string();
+
+    // Same epoch, logged out of order: only seq keeps "make" first
+    for (cmd, exit_code, duration, seq) in
+        [("make test", "2", "40", "2"), ("make", "0", "1500", "1")]
+    {
+        sdbh_cmd()
+            .env("SDBH_TEST_VAR", "staging")
+            .args([
+                "--db",
+                &src_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                "1700000000",
+                "--ppid",
+                "100",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+                "--exit-code",
+                exit_code,
+                "--duration-ms",
+                duration,
+                "--seq",
+                seq,
+                "--capture-env",
+                "SDBH_TEST_VAR",
+            ])
+            .assert()
+            .success();
+    }
+    conn(std::path::Path::new(&src_arg))
+        .execute("UPDATE history SET synthetic = 1 WHERE cmd = 'make'", [])
+        .unwrap();
+
+    sdbh_cmd()
+        .args(["import", "--to", &dest_arg, "--from", &src_arg])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("inserted 2"));
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            &dest_arg,
+            "list",
+            "--all",
+            "--failed-only",
+            "--no-headers",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("| make test"))
+        .stdout(predicate::str::contains("| make\n").not());
+
+    let c = conn(std::path::Path::new(&dest_arg));
+    let durations: Vec<Option<i64>> = c
+        .prepare("SELECT duration_ms FROM history ORDER BY id")
+        .unwrap()
+        .query_map([], |r| r.get(0))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(durations, [Some(40), Some(1500)]);
+
+    let out = sdbh_cmd()
+        .args(["--db", &dest_arg, "list", "--all", "--no-headers"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let cmds: Vec<String> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|l| l.rsplit(" | ").next().unwrap().to_string())
+        .collect();
+    assert_eq!(cmds, ["make", "make test"]);
+
+    sdbh_cmd()
+        .args(["--db", &dest_arg, "show", "--id", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("env\n  SDBH_TEST_VAR=staging\n"));
+    let synthetic: Vec<Option<i64>> = c
+        .prepare("SELECT synthetic FROM history ORDER BY id")
+        .unwrap()
+        .query_map([], |r| r.get(0))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(synthetic, [None, Some(1)]);
+}
+
+#[test]
+fn import_jsonl_round_trips_export() {
+    let tmp = TempDir::new().unwrap();
+    let src_arg = tmp.path().join("src.sqlite").to_string_lossy().to_string();
+    let dest_arg = tmp.path().join("dest.sqlite").to_string_lossy().to_string();
+
+    for (i, (cmd, extra)) in [
+        (
+            "make",
+            vec!["--hist-id", "3", "--exit-code", "2", "--seq", "1"],
+        ),
+        (
+            "make test",
+            vec!["--duration-ms", "1500", "--tty", "/dev/pts/1"],
+        ),
+        (
+            "git push",
+            vec!["--hist-id", "5", "--capture-env", "SDBH_TEST_VAR"],
+        ),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let epoch = (1700000000 + i as i64).to_string();
+        sdbh_cmd()
+            .args([
+                "--db", &src_arg, "log", "--cmd", cmd, "--epoch", &epoch, "--ppid", "100", "--pwd",
+                "/tmp", "--salt", "7",
+            ])
+            .args(extra)
+            .env("SDBH_TEST_VAR", "on")
+            .assert()
+            .success();
+    }
+
+    let export = |db: &str| -> String {
+        let out = sdbh_cmd()
+            .args(["--db", db, "export", "--all"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        String::from_utf8(out).unwrap()
+    };
+    let exported = export(&src_arg);
+    let dump = tmp.path().join("history.jsonl");
+    std::fs::write(&dump, &exported).unwrap();
+
+    // Merging an export back into its own database only finds duplicates
+    sdbh_cmd()
+        .args(["--db", &src_arg, "import-jsonl", "--from"])
+        .arg(&dump)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("considered 3, inserted 0"));
+    assert_eq!(export(&src_arg), exported);
+
+    // Hand-written lines: only the required fields, null session fields, then junk
+    std::fs::write(
+        &dump,
+        format!(
+            "{exported}\n{{\"cmd\":\"cargo build\",\"epoch\":1700000100,\"pwd\":\"/src\"}}\n\
+             {{\"cmd\":\"cargo test\",\"epoch\":1700000101,\"pwd\":\"/src\",\"hist_id\":null,\"ppid\":null,\"salt\":null}}\n\
+             not json\n{{\"cmd\":\"no epoch\"}}\n"
+        ),
+    )
+    .unwrap();
+
+    sdbh_cmd()
+        .args(["--db", &dest_arg, "import-jsonl", "--from"])
+        .arg(&dump)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("considered 7, inserted 5"))
+        .stderr(predicate::str::contains(
+            "import skipped 2 malformed line(s) (first: line 7)",
+        ));
+
+    // Same rows, every field (ids line up because the destination started empty)
+    let reimported = export(&dest_arg);
+    let lines: Vec<&str> = reimported.lines().collect();
+    assert_eq!(lines[..3].join("\n"), exported.trim_end());
+    assert!(lines[0].contains(r#""exit_code":2"#));
+    assert!(lines[1].contains(r#""hist_id":null"#));
+    assert!(lines[1].contains(r#""duration_ms":1500"#));
+    assert!(lines[2].contains(r#""env":{"SDBH_TEST_VAR":"on"}"#));
+    assert!(lines[3].contains(r#""cmd":"cargo build""#));
+    assert!(lines[4].contains(r#""salt":0"#));
+
+    // Importing again only finds duplicates
+    sdbh_cmd()
+        .args(["--db", &dest_arg, "import-jsonl", "--from"])
+        .arg(&dump)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("considered 7, inserted 0"));
+}
+
+#[test]
+fn redact_dry_run_previews_then_rewrites() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    for i in 0..7 {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                &format!("deploy --token=ghp_abc{i} --env prod"),
+                "--epoch",
+                &(1700000000 + i).to_string(),
+                "--ppid",
+                "1",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "redact",
+            "--pattern",
+            r"--token=\S+",
+            "--replacement",
+            "--token=***",
+            "--dry-run",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("7 row(s) would change"))
+        .stdout(predicate::str::contains(
+            "     1 | before: deploy --token=ghp_abc0 --env prod\n       | after:  deploy --token=*** --env prod\n",
+        ))
+        .stdout(predicate::str::contains("... and 2 more"));
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "search", "ghp_"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("ghp_abc6"));
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "redact", "--pattern", r"ghp_\w+"])
+        .assert()
+        .success()
+        .stdout("Redacted 7 row(s)\n");
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "search", "ghp_"])
+        .assert()
+        .success()
+        .stdout("");
+    sdbh_cmd()
+        .args(["--db", &db_arg, "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("deploy --token=*** --env prod"));
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "redact", "--pattern", "(unclosed"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid --pattern"));
+}
+
+#[test]
+fn list_and_search_since_until_use_half_open_local_dates() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    // 2024-03-01 00:00:00 UTC is 1709251200; 2024-03-02 00:00:00 UTC is 1709337600
+    for (cmd, epoch) in [
+        ("echo before", 1709251199i64),
+        ("echo start", 1709251200),
+        ("echo last", 1709337599),
+        ("echo end", 1709337600),
+    ] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &epoch.to_string(),
+                "--ppid",
+                "1",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+            ])
+            .assert()
+            .success();
+    }
+
+    for sub in ["list", "search"] {
+        let mut args = vec!["--db", &db_arg, sub];
+        if sub == "search" {
+            args.push("echo");
+        }
+        args.extend(["--since", "2024-03-01", "--until", "2024-03-02"]);
+        sdbh_cmd()
+            .env("TZ", "UTC")
+            .args(&args)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("echo start"))
+            .stdout(predicate::str::contains("echo last"))
+            .stdout(predicate::str::contains("echo before").not())
+            .stdout(predicate::str::contains("echo end").not());
+    }
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "search",
+            "echo",
+            "--since",
+            "2024-03-01",
+            "--days",
+            "7",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn stats_daily_limit_keeps_most_recent_days() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    for day in 0..4i64 {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                &format!("echo {day}"),
+                "--epoch",
+                &(1700000000 + day * 86400).to_string(),
+                "--ppid",
+                "1",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .env("TZ", "UTC")
+        .args([
+            "--db",
+            &db_arg,
+            "stats",
+            "daily",
+            "--since",
+            "1699990000",
+            "--limit",
+            "2",
+        ])
+        .assert()
+        .success()
+        .stdout("2023-11-16 |      1\n2023-11-17 |      1\n");
+
+    let out = sdbh_cmd()
+        .env("TZ", "UTC")
+        .args([
+            "--db",
+            &db_arg,
+            "stats",
+            "daily",
+            "--since",
+            "1699990000",
+            "--limit",
+            "2",
+            "--all",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(out.stdout).unwrap().lines().count(), 4);
+}
+
+#[test]
+fn stats_hourly_and_weekday_fill_every_bucket() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    // 1700000000 is Tuesday 2023-11-14 22:13:20 UTC
+    for epoch in [1700000000i64, 1700000060] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                &format!("echo {epoch}"),
+                "--epoch",
+                &epoch.to_string(),
+                "--ppid",
+                "1",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+            ])
+            .assert()
+            .success();
+    }
+
+    let out = sdbh_cmd()
+        .env("TZ", "UTC")
+        .args(["--db", &db_arg, "stats", "hourly", "--since", "1699990000"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 24);
+    assert!(stdout.contains("00h |      0 |\n"));
+    assert!(stdout.contains("22h |      2 | #"));
+
+    let out = sdbh_cmd()
+        .env("TZ", "UTC")
+        .args(["--db", &db_arg, "stats", "weekday", "--since", "1699990000"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 7);
+    assert!(stdout.starts_with("Sun |      0 |\n"));
+    assert!(stdout.contains("Tue |      2 | #"));
+}
+
+#[test]
+fn status_flag_exits_nonzero_when_nothing_matches() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "log",
+            "--cmd",
+            "git status",
+            "--epoch",
+            &now.to_string(),
+            "--ppid",
+            "1",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "1",
+        ])
+        .assert()
+        .success();
+
+    for (args, hit) in [
+        (vec!["search", "git"], true),
+        (vec!["search", "nomatch"], false),
+        (vec!["list", "--here", "--pwd-override", "/tmp"], true),
+        (
+            vec!["list", "--here", "--pwd-override", "/elsewhere"],
+            false,
+        ),
+        (vec!["summary", "--here", "--pwd-override", "/tmp"], true),
+        (
+            vec!["summary", "--here", "--pwd-override", "/elsewhere"],
+            false,
+        ),
+        (vec!["stats", "top"], true),
+        (vec!["stats", "top", "--since", "1"], true),
+        (vec!["stats", "daily", "--until", "1"], false),
+        (vec!["stats", "weekday", "--until", "1"], false),
+    ] {
+        let mut cmd = sdbh_cmd();
+        cmd.args(["--db", &db_arg]).args(&args).arg("--status");
+        let assert = cmd.assert();
+        if hit {
+            assert.success();
+        } else {
+            assert
+                .code(3)
+                .stdout(predicate::str::contains("git status").not());
+        }
+
+        // Without --status an empty result is still a success
+        sdbh_cmd()
+            .args(["--db", &db_arg])
+            .args(&args)
+            .assert()
+            .success();
+    }
+
+    // Errors keep exiting 1, so they can't be mistaken for "no results"
+    sdbh_cmd()
+        .args(["--db", &db_arg, "search", "--around-id", "99", "--status"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("no history entry with id 99"));
+}
+
+#[test]
+fn log_tracks_gitroot_and_stats_groups_by_repo() {
+    if std::process::Command::new("git")
+        .arg("--version")
+        .output()
+        .is_err()
+    {
+        return;
+    }
+
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path();
+    let db_arg = home.join("test.sqlite").to_string_lossy().to_string();
+    std::fs::write(home.join(".sdbh.toml"), "[log]\ntrack_gitroot = true\n").unwrap();
+
+    let repo = home.join("repo");
+    std::fs::create_dir_all(repo.join("src")).unwrap();
+    let outside = home.join("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+    assert!(
+        std::process::Command::new("git")
+            .args(["init", "-q"])
+            .current_dir(&repo)
+            .status()
+            .unwrap()
+            .success()
+    );
+    let repo_root = repo.canonicalize().unwrap().to_string_lossy().to_string();
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    for (i, pwd) in [repo.join("src"), repo.clone(), outside.clone()]
+        .iter()
+        .enumerate()
+    {
+        sdbh_cmd()
+            .env("HOME", home)
+            .env("GIT_CEILING_DIRECTORIES", home)
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                &format!("make {i}"),
+                "--epoch",
+                &(now - i as i64).to_string(),
+                "--ppid",
+                "1",
+                "--pwd",
+                pwd.to_string_lossy().as_ref(),
+                "--salt",
+                "1",
+            ])
+            .assert()
+            .success();
+    }
+
+    let conn = conn(&home.join("test.sqlite"));
+    let roots: Vec<Option<String>> = conn
+        .prepare("SELECT gitroot FROM history ORDER BY id")
+        .unwrap()
+        .query_map([], |r| r.get(0))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(
+        roots,
+        vec![Some(repo_root.clone()), Some(repo_root.clone()), None]
+    );
+
+    sdbh_cmd()
+        .env("HOME", home)
+        .args(["--db", &db_arg, "stats", "by-gitroot"])
+        .assert()
+        .success()
+        .stdout(format!("     2 | {repo_root}\n     1 | (no repo)\n"));
+
+    // Tracking is opt-in: without the config the column stays NULL
+    std::fs::remove_file(home.join(".sdbh.toml")).unwrap();
+    sdbh_cmd()
+        .env("HOME", home)
+        .args([
+            "--db",
+            &db_arg,
+            "log",
+            "--cmd",
+            "make untracked",
+            "--epoch",
+            &now.to_string(),
+            "--ppid",
+            "1",
+            "--pwd",
+            repo.to_string_lossy().as_ref(),
+            "--salt",
+            "1",
+        ])
+        .assert()
+        .success();
+    let root: Option<String> = conn
+        .query_row(
+            "SELECT gitroot FROM history WHERE cmd = 'make untracked'",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(root, None);
+}
+
+#[test]
+fn porcelain_output_is_tab_separated_with_escaped_tabs() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    for (cmd, epoch) in [
+        ("git commit -m 'two words'", 1700000000i64),
+        ("printf 'a\tb'", 1700000001),
+    ] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &epoch.to_string(),
+                "--ppid",
+                "1",
+                "--pwd",
+                "/home/me/My Projects",
+                "--salt",
+                "1",
+            ])
+            .assert()
+            .success();
+    }
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "list", "--porcelain", "--headers"])
+        .assert()
+        .failure();
+
+    // The literal tab inside the second command is escaped so it can't split fields
+    let expected = "1\t1700000000\t/home/me/My Projects\tgit commit -m 'two words'\n\
+                    2\t1700000001\t/home/me/My Projects\tprintf 'a\\tb'\n";
+    sdbh_cmd()
+        .args(["--db", &db_arg, "list", "--porcelain"])
+        .assert()
+        .success()
+        .stdout(expected);
+
+    let out = sdbh_cmd()
+        .args(["--db", &db_arg, "search", "commit", "--porcelain"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let fields: Vec<&str> = stdout.trim_end_matches('\n').split('\t').collect();
+    assert_eq!(
+        fields,
+        vec![
+            "1",
+            "1700000000",
+            "/home/me/My Projects",
+            "git commit -m 'two words'"
+        ]
+    );
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "summary", "--porcelain", "--pwd"])
+        .assert()
+        .success()
+        .stdout(
+            "2\t1700000001\t/home/me/My Projects\tprintf 'a\\tb'\n\
+             1\t1700000000\t/home/me/My Projects\tgit commit -m 'two words'\n",
+        );
+
+    // Without --pwd the field is still there, just empty
+    sdbh_cmd()
+        .args(["--db", &db_arg, "summary", "--porcelain"])
+        .assert()
+        .success()
+        .stdout(
+            "2\t1700000001\t\tprintf 'a\\tb'\n\
+             1\t1700000000\t\tgit commit -m 'two words'\n",
+        );
+}
+
+#[test]
+fn template_run_executes_and_logs_command() {
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path();
+    let db_arg = home.join("test.sqlite").to_string_lossy().to_string();
+    let templates_dir = home.join(".sdbh").join("templates");
+    std::fs::create_dir_all(&templates_dir).unwrap();
+    std::fs::write(
+        templates_dir.join("greet.toml"),
+        "id = \"greet\"\nname = \"Greet\"\ncommand = \"echo {msg}\"\n\n[[variables]]\nname = \"msg\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        templates_dir.join("fail.toml"),
+        "id = \"fail\"\nname = \"Fail\"\ncommand = \"exit 3\"\n",
+    )
+    .unwrap();
+
+    // Print-only by default, so $(sdbh template ...) keeps working
+    sdbh_cmd()
+        .env("HOME", home)
+        .args(["--db", &db_arg, "template", "greet", "--var", "msg=hi"])
+        .assert()
+        .success()
+        .stdout("echo hi\n");
+
+    sdbh_cmd()
+        .env("HOME", home)
+        .env("SHELL", "/bin/sh")
+        .args([
+            "--db", &db_arg, "template", "greet", "--var", "msg=hi", "--run",
+        ])
+        .assert()
+        .success()
+        .stdout("hi\n");
+
+    sdbh_cmd()
+        .env("HOME", home)
+        .env("SHELL", "/bin/sh")
+        .args(["--db", &db_arg, "template", "fail", "--run"])
+        .assert()
+        .code(3);
+
+    let conn = conn(&home.join("test.sqlite"));
+    let rows: Vec<(String, Option<i64>)> = conn
+        .prepare("SELECT cmd, exit_code FROM history ORDER BY id")
+        .unwrap()
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(
+        rows,
+        vec![
+            ("echo hi".to_string(), Some(0)),
+            ("exit 3".to_string(), Some(3))
+        ]
+    );
+}
+
+#[test]
+fn doctor_json_suggests_db_optimize_for_missing_indexes() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    // A database only ever opened by doctor has no performance indexes yet
+    let out = sdbh_cmd()
+        .env("SDBH_SALT", "1")
+        .env("SDBH_PPID", "1")
+        .args(["--db", &db_arg, "doctor", "--no-spawn", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let indexes = report["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|c| c["check"] == "db.indexes")
+        .unwrap();
+    assert_eq!(indexes["status"], "warn");
+    let suggestions = report["suggestions"].as_array().unwrap();
+    assert!(suggestions.contains(&serde_json::json!("db optimize")));
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "db", "optimize"])
+        .assert()
+        .success();
+    let out = sdbh_cmd()
+        .env("SDBH_SALT", "1")
+        .env("SDBH_PPID", "1")
+        .args(["--db", &db_arg, "doctor", "--no-spawn", "--format", "json"])
+        .output()
+        .unwrap();
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert!(
+        !report["suggestions"]
+            .as_array()
+            .unwrap()
+            .contains(&serde_json::json!("db optimize"))
+    );
+}
+
+#[test]
+fn template_export_import_bundle() {
+    let src = TempDir::new().unwrap();
+    let templates_dir = src.path().join(".sdbh").join("templates");
+    std::fs::create_dir_all(&templates_dir).unwrap();
+    std::fs::write(
+        templates_dir.join("greet.toml"),
+        "id = \"greet\"\nname = \"Greet\"\ncommand = \"echo {msg}\"\n\n[[variables]]\nname = \"msg\"\n",
+    )
+    .unwrap();
+    let bundle = src.path().join("bundle.toml");
+
+    sdbh_cmd()
+        .env("HOME", src.path())
+        .args(["template", "--export"])
+        .arg(&bundle)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Exported 1 template(s)"));
+    assert!(
+        std::fs::read_to_string(&bundle)
+            .unwrap()
+            .contains("[[template]]")
+    );
+
+    let dst = TempDir::new().unwrap();
+    sdbh_cmd()
+        .env("HOME", dst.path())
+        .args(["template", "--import"])
+        .arg(&bundle)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Imported 1 template(s)"));
+    sdbh_cmd()
+        .env("HOME", dst.path())
+        .args(["template", "greet", "--var", "msg=hi"])
+        .assert()
+        .success()
+        .stdout("echo hi\n");
+
+    // A second import skips the existing id unless --force
+    sdbh_cmd()
+        .env("HOME", dst.path())
+        .args(["template", "--import"])
+        .arg(&bundle)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Imported 0 template(s)"))
+        .stderr(predicate::str::contains("'greet' already exists"));
+    sdbh_cmd()
+        .env("HOME", dst.path())
+        .args(["template", "--import"])
+        .arg(&bundle)
+        .arg("--force")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Imported 1 template(s)"));
+}
+
+#[test]
+fn template_fzf_selects_and_resolves() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path();
+    let templates_dir = home.join(".sdbh").join("templates");
+    std::fs::create_dir_all(&templates_dir).unwrap();
+    std::fs::write(
+        templates_dir.join("greet.toml"),
+        "id = \"greet\"\nname = \"Greet\"\ncategory = \"demo\"\ncommand = \"echo {msg}\"\n\n[[variables]]\nname = \"msg\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        templates_dir.join("today.toml"),
+        "id = \"today\"\nname = \"Today\"\ndescription = \"Print the date\"\ncommand = \"date\"\n",
+    )
+    .unwrap();
+
+    // Without fzf on PATH the usual error is reported
+    sdbh_cmd()
+        .env("HOME", home)
+        .env("PATH", home.join("empty"))
+        .args(["template", "--fzf"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("fzf is not installed"));
+
+    // A stand-in fzf that records its input and picks the lines passed in $PICK
+    let bin = home.join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    let fzf = bin.join("fzf");
+    std::fs::write(
+        &fzf,
+        format!(
+            "#!/bin/sh\ntee {}/fzf-input | grep -E \"$PICK\"\n",
+            home.display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&fzf, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+
+    sdbh_cmd()
+        .env("HOME", home)
+        .env("PATH", &path)
+        .env("PICK", "^greet")
+        .args(["template", "--fzf", "--var", "msg=hi"])
+        .assert()
+        .success()
+        .stdout("echo hi\n");
+    assert_eq!(
+        std::fs::read_to_string(home.join("fzf-input")).unwrap(),
+        "greet  [demo] - Greet\ntoday  - Print the date\n"
+    );
+
+    sdbh_cmd()
+        .env("HOME", home)
+        .env("PATH", &path)
+        .env("PICK", ".")
+        .args(["template", "--multi-select", "--var", "msg=hi"])
+        .assert()
+        .success()
+        .stdout("echo hi\ndate\n");
+}
+
+#[test]
+fn doctor_checks_sdbh_binary_on_path() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+    let binary_check = |path: &std::ffi::OsStr| {
+        let out = sdbh_cmd()
+            .env("PATH", path)
+            .args(["--db", &db_arg, "doctor", "--no-spawn", "--format", "json"])
+            .output()
+            .unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+        report["checks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["check"] == "binary.path")
+            .unwrap()
+            .clone()
+    };
+
+    let empty = tmp.path().join("empty");
+    std::fs::create_dir_all(&empty).unwrap();
+    assert_eq!(binary_check(empty.as_os_str())["status"], "info");
+
+    // The binary under test is current
+    let built = assert_cmd::cargo::cargo_bin!("sdbh");
+    let check = binary_check(built.parent().unwrap().as_os_str());
+    assert_eq!(check["status"], "ok");
+
+    // A stale copy earlier in PATH is reported with its location
+    let stale = tmp.path().join("stale");
+    std::fs::create_dir_all(&stale).unwrap();
+    let script = stale.join("sdbh");
+    std::fs::write(&script, "#!/bin/sh\necho 'sdbh 0.0.1'\n").unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let check =
+        binary_check(&std::env::join_paths([stale.as_path(), built.parent().unwrap()]).unwrap());
+    assert_eq!(check["status"], "warn");
+    assert!(
+        check["detail"]
+            .as_str()
+            .unwrap()
+            .contains(&script.display().to_string())
+    );
+}
+
+#[test]
+fn search_near_shows_commands_within_window_of_last_match() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+
+    // Two rollouts: the window is taken around the most recent one
+    for (epoch, cmd) in [
+        (1700000000, "kubectl rollout restart deploy/api"),
+        (1700005000, "kubectl get pods"),
+        (1700005400, "vim values.yaml"),
+        (1700006000, "kubectl rollout restart deploy/web"),
+        (1700006300, "kubectl logs web"),
+        (1700006700, "git status"),
+    ] {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &epoch.to_string(),
+                "--ppid",
+                "1",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+            ])
+            .assert()
+            .success();
+    }
+
+    let near = |window: &str| -> Vec<String> {
+        let out = sdbh_cmd()
+            .args([
+                "--db", &db_arg, "search", "--near", "ROLLOUT", "--window", window, "--format",
+                "json",
+            ])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let rows: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        rows.as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["cmd"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    assert_eq!(
+        near("600"),
+        [
+            "vim values.yaml",
+            "kubectl rollout restart deploy/web",
+            "kubectl logs web"
+        ]
+    );
+    assert_eq!(near("0"), ["kubectl rollout restart deploy/web"]);
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "search", "--near", "helm"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no history entry matches"));
+    sdbh_cmd()
+        .args(["--db", &db_arg, "search", "kubectl", "--near", "rollout"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn import_history_drops_suspected_secrets_when_enabled() {
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path();
+    let hist = home.join("bash_history");
+    std::fs::write(&hist, "ssh admin@db1\nS3cr3t!Pass\nls -la\n").unwrap();
+
+    let import = |db: &std::path::Path| {
+        sdbh_cmd()
+            .env("HOME", home)
+            .args(["--db", db.to_string_lossy().as_ref(), "import-history"])
+            .arg("--bash")
+            .arg(&hist)
+            .args(["--pwd", "/tmp"])
+            .assert()
+            .success()
+    };
+    let cmds = |db: &std::path::Path| -> Vec<String> {
+        conn(db)
+            .prepare("SELECT cmd FROM history ORDER BY epoch")
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect()
+    };
+
+    // Off by default: every line is imported
+    let kept = home.join("kept.sqlite");
+    import(&kept).stderr(predicate::str::contains("inserted 3"));
+    assert_eq!(cmds(&kept), ["ssh admin@db1", "S3cr3t!Pass", "ls -la"]);
+
+    std::fs::write(
+        home.join(".sdbh.toml"),
+        "[import]\ndrop_suspected_secrets = true\n",
+    )
+    .unwrap();
+    let dropped = home.join("dropped.sqlite");
+    import(&dropped).stderr(predicate::str::contains(
+        "inserted 2, dropped 1 suspected secrets",
+    ));
+    assert_eq!(cmds(&dropped), ["ssh admin@db1", "ls -la"]);
+}
+
+#[test]
+fn doctor_checks_bash_histtimeformat() {
+    // doctor only inspects bash when it is installed
+    if std::process::Command::new("bash")
+        .args(["-c", "true"])
+        .status()
+        .is_err()
+    {
+        return;
+    }
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+    let check = |format: &str| {
+        let out = sdbh_cmd()
+            .env("HOME", tmp.path())
+            .env("HISTTIMEFORMAT", format)
+            .args(["--db", &db_arg, "doctor", "--format", "json"])
+            .output()
+            .unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+        report["checks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["check"] == "bash.histtimeformat")
+            .unwrap()
+            .clone()
+    };
+
+    assert_eq!(check("%s ")["status"], "ok");
+    let wrong = check("%F %T ");
+    assert_eq!(wrong["status"], "warn");
+    assert!(wrong["detail"].as_str().unwrap().contains("%F %T"));
+}
+
+#[test]
+fn db_reports_support_json_format() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "log",
+            "--cmd",
+            "echo hi",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "1",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "1",
+        ])
+        .assert()
+        .success();
+    let report = |sub: &str| -> serde_json::Value {
+        let out = sdbh_cmd()
+            .args(["--db", &db_arg, "db", sub, "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        serde_json::from_slice(&out).unwrap()
+    };
+
+    let health = report("health");
+    assert_eq!(health["integrity"], true);
+    assert_eq!(health["rows"], 1);
+    assert!(health["size_mb"].is_number());
+    assert!(health["free_mb"].is_number());
+    assert!(health["fragmentation"].is_number());
+    assert!(health["missing_indexes"].is_array());
+
+    let stats = report("stats");
+    assert_eq!(stats["rows"], 1);
+    assert!(stats["page_size"].as_i64().unwrap() > 0);
+    assert!(
+        stats["indexes"]
+            .as_array()
+            .unwrap()
+            .contains(&serde_json::json!("idx_history_epoch"))
+    );
+
+    let schema = report("schema");
+    let history = schema["tables"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|t| t["name"] == "history")
+        .unwrap();
+    let id = &history["columns"].as_array().unwrap()[0];
+    assert_eq!(id["name"], "id");
+    assert_eq!(id["pk"], true);
+    assert!(
+        schema["indexes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|i| i["name"] == "idx_history_epoch" && i["table"] == "history")
+    );
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "db", "health", "--format", "csv"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does not support --format csv"));
+}
+
+#[test]
+fn db_schema_json_describes_history_columns_and_indexes() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+
+    // Logging creates the query indexes
+    sdbh_cmd()
+        .args([
+            "--db",
+            &db_arg,
+            "log",
+            "--cmd",
+            "echo hi",
+            "--epoch",
+            "1700000000",
+            "--ppid",
+            "1",
+            "--pwd",
+            "/tmp",
+            "--salt",
+            "1",
+        ])
+        .assert()
+        .success();
+
+    let out = sdbh_cmd()
+        .args(["--db", &db_arg, "db", "schema", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let schema: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+    let history = schema["tables"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|t| t["name"] == "history")
+        .unwrap();
+    let columns = history["columns"].as_array().unwrap();
+    let column = |name: &str| columns.iter().find(|c| c["name"] == name).unwrap();
+    for name in [
+        "id",
+        "hist_id",
+        "cmd",
+        "epoch",
+        "ppid",
+        "pwd",
+        "salt",
+        "inserted_at",
+        "seq",
+    ] {
+        let c = column(name);
+        assert!(c["type"].is_string(), "{name} type");
+        assert!(c["notnull"].is_boolean(), "{name} notnull");
+        assert!(c["pk"].is_boolean(), "{name} pk");
+    }
+    assert_eq!(column("cmd")["type"], "TEXT");
+    assert_eq!(column("epoch")["type"], "INTEGER");
+    assert_eq!(column("epoch")["pk"], false);
+
+    let epoch_index = schema["indexes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|i| i["name"] == "idx_history_epoch")
+        .unwrap();
+    assert_eq!(epoch_index["table"], "history");
+    assert!(epoch_index["sql"].as_str().unwrap().contains("(epoch)"));
+}
+
+#[test]
+fn fzf_print0_flag_parsing() {
+    let tmp = TempDir::new().unwrap();
+    let db_arg = tmp.path().join("test.sqlite").to_string_lossy().to_string();
+    let empty_path = tmp.path().join("empty");
+
+    for args in [
+        vec!["list", "--fzf", "--multi-select", "--print0"],
+        vec!["search", "echo", "--fzf", "--multi-select", "--print0"],
+        vec!["summary", "--fzf", "--multi-select", "--print0"],
+        vec!["stats", "top", "--fzf", "--multi-select", "--print0"],
+        vec!["stats", "by-pwd", "--fzf", "--multi-select", "--print0"],
+        vec!["stats", "daily", "--fzf", "--multi-select", "--print0"],
+    ] {
+        sdbh_cmd()
+            .env("PATH", &empty_path)
+            .args(["--db", &db_arg])
+            .args(&args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("fzf is not installed"));
+    }
+
+    // --print0 only makes sense alongside --multi-select
+    sdbh_cmd()
+        .args(["--db", &db_arg, "list", "--fzf", "--print0"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--multi-select"));
+}
+
+#[test]
+fn db_compact_writes_smaller_copy_and_can_replace() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+    sdbh_cmd()
+        .args(["--db", &db_arg, "db", "health"])
+        .assert()
+        .success();
+
+    // Fill, then delete most rows so the file is mostly free pages
+    {
+        let conn = conn(&db);
+        let padding = "x".repeat(500);
+        for i in 0..2000 {
+            conn.execute(
+                "INSERT INTO history(hist_id, cmd, epoch, ppid, pwd, salt) VALUES (NULL, ?1, ?2, 1, '/tmp', 1)",
+                rusqlite::params![format!("echo {i} {padding}"), 1700000000 + i],
+            )
+            .unwrap();
+        }
+        conn.execute("DELETE FROM history WHERE epoch >= 1700000010", [])
+            .unwrap();
+    }
+    let row_count = |path: &std::path::Path| -> i64 {
+        conn(path)
+            .query_row("SELECT count(*) FROM history", [], |r| r.get(0))
+            .unwrap()
+    };
+    let size = |path: &std::path::Path| std::fs::metadata(path).unwrap().len();
+
+    let copy = tmp.path().join("compact.sqlite");
+    sdbh_cmd()
+        .args(["--db", &db_arg, "db", "compact"])
+        .arg(&copy)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Compacted"));
+    assert!(size(&copy) < size(&db));
+    assert_eq!(row_count(&copy), 10);
+    assert_eq!(row_count(&db), 10);
+
+    // The destination must be new
+    sdbh_cmd()
+        .args(["--db", &db_arg, "db", "compact"])
+        .arg(&copy)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already exists"));
+
+    let before = size(&db);
+    let swap = tmp.path().join("swap.sqlite");
+    // A connection that was open during --replace keeps writing to the live file
+    let held = conn(&db);
+    sdbh_cmd()
+        .args(["--db", &db_arg, "db", "compact", "--replace"])
+        .arg(&swap)
+        .assert()
+        .success();
+    assert!(!swap.exists());
+    assert!(size(&db) < before);
+    assert_eq!(row_count(&db), 10);
+    held.execute(
+        "INSERT INTO history(hist_id, cmd, epoch, ppid, pwd, salt) VALUES (NULL, 'late', 1800000000, 1, '/tmp', 1)",
+        [],
+    )
+    .unwrap();
+    assert_eq!(row_count(&db), 11);
+}
+
+#[test]
+fn db_backup_snapshots_database() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+    for (i, cmd) in ["echo one", "echo two"].iter().enumerate() {
+        sdbh_cmd()
+            .args([
+                "--db",
+                &db_arg,
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &(1700000000 + i as i64).to_string(),
+                "--ppid",
+                "1",
+                "--pwd",
+                "/tmp",
+                "--salt",
+                "1",
+            ])
+            .assert()
+            .success();
+    }
+
+    // A reader holding the database open doesn't get in the way
+    let held = conn(&db);
+    let backup = tmp.path().join("backup.sqlite");
+    sdbh_cmd()
+        .args(["--db", &db_arg, "db", "backup", "--to"])
+        .arg(&backup)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(2 rows)"))
+        .stdout(predicate::str::contains(backup.to_string_lossy().as_ref()));
+    drop(held);
+
+    let cmds: Vec<String> = conn(&backup)
+        .prepare("SELECT cmd FROM history ORDER BY epoch")
+        .unwrap()
+        .query_map([], |r| r.get(0))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(cmds, ["echo one", "echo two"]);
+
+    sdbh_cmd()
+        .args(["--db", &db_arg, "db", "backup", "--to"])
+        .arg(&backup)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--force"));
+    sdbh_cmd()
+        .args(["--db", &db_arg, "db", "backup", "--force", "--to"])
+        .arg(&backup)
+        .assert()
+        .success();
+
+    // Backing up onto the live database is refused and leaves it intact
+    sdbh_cmd()
+        .args(["--db", &db_arg, "db", "backup", "--force", "--to"])
+        .arg(tmp.path().join(".").join("test.sqlite"))
+        .timeout(std::time::Duration::from_secs(30))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("database being backed up"));
+    let rows: i64 = conn(&db)
+        .query_row("SELECT count(*) FROM history", [], |r| r.get(0))
+        .unwrap();
+    assert_eq!(rows, 2);
+    let leftovers = std::fs::read_dir(tmp.path())
+        .unwrap()
+        .filter(|e| {
+            e.as_ref()
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .starts_with(".sdbh-backup-")
+        })
+        .count();
+    assert_eq!(leftovers, 0);
+}
+
+#[test]
+fn list_unique_keeps_latest_run_of_each_command() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+
+    for (i, (cmd, pwd)) in [
+        ("make build", "/a"),
+        ("git status", "/a"),
+        ("make build", "/b"),
+        ("cargo test", "/a"),
+        ("make build", "/c"),
+    ]
+    .iter()
+    .enumerate()
+    {
+        sdbh_cmd()
+            .args([
+                "--db",
+                db.to_string_lossy().as_ref(),
+                "log",
+                "--cmd",
+                cmd,
+                "--epoch",
+                &(1700000000 + i as i64).to_string(),
+                "--ppid",
+                "123",
+                "--pwd",
+                pwd,
+                "--salt",
+                "42",
+            ])
+            .assert()
+            .success();
+    }
+
+    let out = sdbh_cmd()
+        .args([
+            "--db",
+            db.to_string_lossy().as_ref(),
+            "list",
+            "--unique",
+            "--porcelain",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let rows: Vec<Vec<String>> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|l| l.split('\t').map(str::to_string).collect())
+        .collect();
+    assert_eq!(rows.len(), 3);
+    // Chronological by each command's latest run, with that run's pwd
+    let summary: Vec<(&str, &str, &str)> = rows
+        .iter()
+        .map(|r| (r[1].as_str(), r[2].as_str(), r[3].as_str()))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("1700000001", "/a", "git status"),
+            ("1700000003", "/a", "cargo test"),
+            ("1700000004", "/c", "make build"),
+        ]
+    );
+}
+
+#[test]
+fn list_by_inserted_orders_by_record_time() {
+    let tmp = TempDir::new().unwrap();
+    let db = tmp.path().join("test.sqlite");
+    let db_arg = db.to_string_lossy().to_string();
+
+    // Logged in this order, but with command times the other way round
+    for (cmd, epoch) in [
+        ("first logged", "1700000200"),
+        ("second logged", "1700000100"),
+    ] {
+        sdbh_cmd()
+            .args([
+                "--db", &db_arg, "log", "--cmd", cmd, "--epoch", epoch, "--ppid", "1", "--pwd",
+                "/tmp", "--salt", "1",
+            ])
+            .assert()
+            .success();
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let inserted: Vec<Option<i64>> = conn(&db)
+        .prepare("SELECT inserted_at FROM history ORDER BY id")
+        .unwrap()
+        .query_map([], |r| r.get(0))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert!(
+        inserted
+            .iter()
+            .all(|t| t.is_some_and(|t| (now - 60..=now + 60).contains(&t)))
+    );
+
+    let list = |extra: &[&str]| -> Vec<String> {
+        let out = sdbh_cmd()
+            .args(["--db", &db_arg, "list", "--all", "--format", "json"])
+            .args(extra)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let rows: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        rows.as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["cmd"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(list(&[]), ["second logged", "first logged"]);
+    assert_eq!(list(&["--by-inserted"]), ["first logged", "second logged"]);
+
+    // The order follows inserted_at itself, not the row id
+    conn(&db)
+        .execute(
+            "UPDATE history SET inserted_at = inserted_at + 10 WHERE cmd = 'first logged'",
+            [],
+        )
+        .unwrap();
+    assert_eq!(list(&["--by-inserted"]), ["second logged", "first logged"]);
+}
+
+#[test]
+fn nul_bytes_in_commands_are_marked_and_export_stays_valid_json() {
+    let tmp = TempDir::new().unwrap();
+    let home = tmp.path();
+    // Command lines can't carry NUL through argv, but history files can
+    let hist = home.join("bash_history");
+    std::fs::write(&hist, "printf 'a\0b'\n").unwrap();
+
+    let import_and_export = |db: &std::path::Path| -> serde_json::Value {
+        let db_arg = db.to_string_lossy().to_string();
+        sdbh_cmd()
+            .env("HOME", home)
+            .args(["--db", &db_arg, "import-history", "--pwd", "/tmp", "--bash"])
+            .arg(&hist)
+            .assert()
+            .success();
+        let out = sdbh_cmd()
+            .env("HOME", home)
+            .args(["--db", &db_arg, "export", "--all"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let line = String::from_utf8(out).unwrap();
+        serde_json::from_str(line.trim_end()).unwrap()
+    };
+
+    let marked = home.join("marked.sqlite");
+    let row = import_and_export(&marked);
+    assert_eq!(row["cmd"], "printf 'a\\0b'");
+    let stored: String = conn(&marked)
+        .query_row("SELECT cmd FROM history", [], |r| r.get(0))
+        .unwrap();
+    assert!(!stored.contains('\0'));
+
+    // Opting out keeps the raw byte; JSON escapes it as \u0000
+    std::fs::write(home.join(".sdbh.toml"), "[log]\nstrip_nul = false\n").unwrap();
+    let raw = home.join("raw.sqlite");
+    let row = import_and_export(&raw);
+    assert_eq!(row["cmd"], "printf 'a\0b'");
+}